@@ -0,0 +1,140 @@
+//! Decoding benches. The `fixture` group runs over `tests/fixtures/sample.dex` and a generated dex of 2000 classes,
+//! plus every `.dex` and `.apk` of the directory in `DEXOMPILER_BENCH_DIR` when set
+use std::{env, fs, path::Path};
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use dex::DexReader;
+use dexompiler::{
+    analysis::parse_apk,
+    dex_parsing::{decode_method_lenient, get_blocks, parse_dexes, scan_opcodes, Coverage, Instruction, NamedDex},
+    testing::{sample_dex, SAMPLE_METHODS},
+    AnalysisOptions,
+    MethodCfg,
+};
+
+
+fn bench_instruction(c: &mut Criterion) {
+    let instructions: [(&str, &[u16]); 6] = [
+        ("invoke-super", &[8303, 921, 33]),
+        ("if-eq", &[45874, 102]),
+        ("new-instance", &[290, 648]),
+        ("goto", &[0x0528]),
+        ("const-wide", &[0x0018, 1, 2, 3, 4]),
+        ("packed-switch", &[0x002B, 6, 0]),
+    ];
+    let mut group = c.benchmark_group("instruction");
+    group.throughput(Throughput::Elements(1));
+    for (name, raw_bytecode) in instructions {
+        group.bench_function(name, |b| b.iter(|| Instruction::try_from_raw_bytecode(black_box(raw_bytecode), 0)));
+        group.bench_function(format!("{}/shallow", name), |b| b.iter(|| Instruction::try_opcode_from_raw_bytecode(black_box(raw_bytecode), 0)));
+    }
+    group.finish();
+}
+
+
+fn bench_method(c: &mut Criterion) {
+    let mut group = c.benchmark_group("method");
+    for (name, raw_bytecode) in SAMPLE_METHODS {
+        group.throughput(Throughput::Elements(decode_method_lenient(raw_bytecode).instructions.len() as u64));
+        group.bench_function(name, |b| b.iter(|| decode_method_lenient(black_box(raw_bytecode))));
+        group.bench_function(format!("{}/scan", name), |b| b.iter(|| scan_opcodes(black_box(raw_bytecode))));
+    }
+    group.finish();
+}
+
+
+fn bench_blocks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_blocks");
+    for (name, raw_bytecode) in SAMPLE_METHODS {
+        group.throughput(Throughput::Elements(decode_method_lenient(raw_bytecode).instructions.len() as u64));
+        group.bench_function(name, |b| b.iter(|| get_blocks(black_box(raw_bytecode))));
+    }
+    group.finish();
+}
+
+
+fn bench_dex(c: &mut Criterion) {
+    let bytes = sample_dex(100);
+    let instructions = parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes.clone()).unwrap()]), &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]).0.len();
+    let mut group = c.benchmark_group("dex");
+    group.throughput(Throughput::Elements(instructions as u64));
+    group.bench_function("parse_dexes", |b| b.iter_batched(
+        || NamedDex::multidex([DexReader::from_vec(bytes.clone()).unwrap()]),
+        |dexes| parse_dexes(dexes, &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]),
+        BatchSize::SmallInput,
+    ));
+    let shallow = AnalysisOptions::default().shallow(true);
+    group.bench_function("parse_dexes_shallow", |b| b.iter_batched(
+        || NamedDex::multidex([DexReader::from_vec(bytes.clone()).unwrap()]),
+        |dexes| parse_dexes(dexes, &shallow, &mut Coverage::default(), &mut vec![]),
+        BatchSize::SmallInput,
+    ));
+    group.finish();
+}
+
+
+/// Code of every method of a dex
+fn method_codes(bytes: Vec<u8>) -> Vec<Vec<u16>> {
+    let Ok(dex) = DexReader::from_vec(bytes) else { return vec![] };
+    dex.classes()
+        .filter_map(Result::ok)
+        .flat_map(|class| class.methods().filter_map(|method| method.code().map(|code| code.insns().to_vec())).collect::<Vec<_>>())
+        .collect()
+}
+
+
+/// Method codes of the checked-in sample, of a large generated dex and of the dexes and APKs of `DEXOMPILER_BENCH_DIR`,
+/// by name
+fn fixtures() -> Vec<(String, Vec<Vec<u16>>)> {
+    let mut fixtures = vec![
+        ("sample.dex".to_string(), method_codes(include_bytes!("../tests/fixtures/sample.dex").to_vec())),
+        ("generated-large".to_string(), method_codes(sample_dex(2000))),
+    ];
+    let Ok(dir) = env::var("DEXOMPILER_BENCH_DIR") else { return fixtures };
+    let mut paths = fs::read_dir(&dir).unwrap_or_else(|err| panic!("reading {}: {}", dir, err))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let codes = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("dex") => fs::read(&path).map(method_codes).unwrap_or_default(),
+            Some("apk") => apk_codes(&path),
+            _ => continue,
+        };
+        fixtures.push((name, codes));
+    }
+    fixtures
+}
+
+
+fn apk_codes(path: &Path) -> Vec<Vec<u16>> {
+    parse_apk(path).map_or(vec![], |contents| contents.dex_bytes.iter().flat_map(|bytes| method_codes(bytes.to_vec())).collect())
+}
+
+
+/// Instructions decoded per second and CFGs built per second, over every method of a fixture
+fn bench_fixture(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fixture");
+    group.sample_size(10);
+    for (name, codes) in fixtures() {
+        let instructions: usize = codes.iter().map(|code| decode_method_lenient(code).instructions.len()).sum();
+        group.throughput(Throughput::Elements(instructions as u64));
+        group.bench_function(format!("{}/decode", name), |b| b.iter(|| {
+            for code in &codes {
+                black_box(decode_method_lenient(black_box(code)));
+            }
+        }));
+        group.throughput(Throughput::Elements(codes.len() as u64));
+        group.bench_function(format!("{}/cfg", name), |b| b.iter(|| {
+            for code in &codes {
+                let _ = black_box(MethodCfg::build(black_box(code)));
+            }
+        }));
+    }
+    group.finish();
+}
+
+
+criterion_group!(benches, bench_instruction, bench_method, bench_blocks, bench_dex, bench_fixture);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+#![no_main]
+
+use dexompiler::Instruction;
+use libfuzzer_sys::fuzz_target;
+
+// Method code as little-endian code units, decoded one instruction at a time from every offset the decoder lands on.
+// Each step either decodes an instruction lying within the method, skips a payload or fails, and always moves forward
+fuzz_target!(|data: &[u8]| {
+    let raw_bytecode: Vec<u16> = data.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+    let mut offset = 0;
+    while offset < raw_bytecode.len() {
+        let next = match Instruction::try_from_raw_bytecode(&raw_bytecode, offset) {
+            Ok(Some((instruction, length))) => {
+                assert_eq!(*instruction.offset(), offset);
+                assert!(length > 0 && offset + length <= raw_bytecode.len());
+                let scanned = Instruction::try_opcode_from_raw_bytecode(&raw_bytecode, offset).unwrap();
+                assert_eq!(scanned, Some((*instruction.opcode(), length)));
+                offset + length
+            },
+            Ok(None) => offset + Instruction::payload_length(&raw_bytecode, offset).unwrap_or(1).max(1),
+            Err(err) => {
+                assert_eq!(err.offset(), offset);
+                offset + 1
+            },
+        };
+        assert!(next > offset);
+        offset = next;
+    }
+});
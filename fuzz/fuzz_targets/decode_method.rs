@@ -0,0 +1,11 @@
+#![no_main]
+
+use dexompiler::{decode_method, MethodCfg};
+use libfuzzer_sys::fuzz_target;
+
+// Method code as little-endian code units, through both the lenient decoder and the CFG builder
+fuzz_target!(|data: &[u8]| {
+    let raw_bytecode: Vec<u16> = data.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+    decode_method(&raw_bytecode);
+    let _ = MethodCfg::build(&raw_bytecode);
+});
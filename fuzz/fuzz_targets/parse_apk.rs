@@ -0,0 +1,10 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use dexompiler::analysis::parse_apk_from;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_apk_from(Cursor::new(data));
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use dexompiler::Manifest;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Manifest::parse(data.to_vec());
+});
@@ -0,0 +1,57 @@
+use serde::Serialize;
+
+
+const ACC_STATIC: u32 = 0x8;
+const ACC_BRIDGE: u32 = 0x40;
+const ACC_NATIVE: u32 = 0x100;
+const ACC_ABSTRACT: u32 = 0x400;
+const ACC_SYNTHETIC: u32 = 0x1000;
+const ACC_CONSTRUCTOR: u32 = 0x10000;
+
+
+/// Access flags telling what kind of declaration a method is, decoded from the `access_flags` of its encoded method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MethodFlags {
+    /// Without the implicit `this`, which instance methods receive in the first of their argument registers
+    pub r#static: bool,
+    /// Instance or static initializer, `<init>` or `<clinit>`
+    pub constructor: bool,
+    pub r#abstract: bool,
+    /// Implemented in a native library
+    pub native: bool,
+    /// Generated by the compiler, e.g. accessors of private members for inner classes
+    pub synthetic: bool,
+    /// Generated by the compiler to forward calls across generic type erasure
+    pub bridge: bool,
+}
+
+
+impl MethodFlags {
+    pub fn from_bits(access_flags: u32) -> Self {
+        Self {
+            r#static: access_flags & ACC_STATIC != 0,
+            constructor: access_flags & ACC_CONSTRUCTOR != 0,
+            r#abstract: access_flags & ACC_ABSTRACT != 0,
+            native: access_flags & ACC_NATIVE != 0,
+            synthetic: access_flags & ACC_SYNTHETIC != 0,
+            bridge: access_flags & ACC_BRIDGE != 0,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_method_flags() {
+        assert_eq!(MethodFlags::from_bits(0x1), MethodFlags::default());
+        let flags = MethodFlags::from_bits(0x1 | ACC_ABSTRACT | ACC_SYNTHETIC | ACC_BRIDGE);
+        assert_eq!(flags, MethodFlags { r#static: false, constructor: false, r#abstract: true, native: false, synthetic: true, bridge: true });
+        assert_eq!(serde_json::to_value(flags).unwrap()["abstract"], true);
+        let flags = MethodFlags::from_bits(ACC_STATIC | ACC_CONSTRUCTOR);
+        assert!(flags.r#static && flags.constructor);
+        assert_eq!(serde_json::to_value(flags).unwrap()["static"], true);
+    }
+}
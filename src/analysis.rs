@@ -0,0 +1,1444 @@
+use std::{borrow::Borrow, collections::BTreeMap, io::{self, Cursor, Read, Seek}, sync::Arc};
+#[cfg(feature = "fs")]
+use std::{fs::File, path::Path};
+
+use dex::{Dex, DexReader};
+use num_traits::FromPrimitive;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::Xxh3;
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{
+    api_sequence::{api_sequences, ApiSequence},
+    verify::{verify_dex, MethodVerifyErrors},
+    call_graph::{CallGraph, CallGraphMetrics},
+    duplicate_classes::{duplicate_classes, DuplicateClass},
+    debug_info::{debug_info_report, DebugInfoReport},
+    dex_parsing::{codeless_methods, parse_dexes, parse_dexes_dedup, CodelessMethod, Coverage, MethodReport, NamedDex, Opcode},
+    error::Error,
+    extension::{extensions, Extensions},
+    kotlin::{kotlin_report, KotlinReport},
+    manifest_parsing::{parse_permissions, Manifest},
+    network::{network_indicators, NetworkIndicator},
+    obfuscation::{string_decryptors, Obfuscation},
+    options::{AnalysisOptions, ReportField, Sampling},
+    packer::{Asset, PackerMatch},
+    permissions::{dead_api_calls, DeadApiCall},
+    sections::{dex_layout, DexSection, SectionAnomaly},
+    signature::Signatures,
+    fields::{fields, FieldRecord},
+    intents::{component_interactions, intents, ComponentInteractions, IntentSite},
+    string_pool::{string_pool, PoolString},
+    warning::{Warning, WarningKind},
+    watchlist::WatchlistHit,
+};
+#[cfg(feature = "parallel")]
+use crate::dex_parsing::parse_dexes_parallel;
+
+
+/// Magic bytes at the start of every dex file
+const DEX_MAGIC: &[u8] = b"dex\n";
+
+
+/// Number of times each opcode pair `(first, second)` occurs as consecutive opcodes of a method
+pub type BigramCounts = BTreeMap<(u8, u8), u32>;
+
+
+/// Opcode sequences of the analyzed methods
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sequences {
+    /// Opcode sequence of all methods and the report of every method in it
+    Flat {
+        op_seq: Vec<u8>,
+        methods: Vec<MethodReport>,
+    },
+    /// Distinct method sequences and, for every method, the index of its sequence
+    Deduplicated {
+        unique_sequences: Vec<Vec<u8>>,
+        methods: Vec<usize>,
+        /// Number of methods sharing every unique sequence
+        counts: Vec<usize>,
+        /// With `DedupScope::Run`, the run-wide id of the first unique sequence. The methods refer to run-wide ids, and
+        /// those below it to the sequences of earlier inputs
+        first_sequence: Option<usize>,
+    },
+}
+
+
+impl Sequences {
+    /// Deduplicated sequences with the duplicate counts derived from `methods`
+    pub fn deduplicated(unique_sequences: Vec<Vec<u8>>, methods: Vec<usize>) -> Self {
+        Self::run_deduplicated(unique_sequences, methods, None)
+    }
+
+    /// Deduplicated sequences whose methods refer to run-wide ids, the unique sequences starting at `first_sequence`.
+    /// The counts only cover the methods of these sequences
+    pub fn run_deduplicated(unique_sequences: Vec<Vec<u8>>, methods: Vec<usize>, first_sequence: Option<usize>) -> Self {
+        let first = first_sequence.unwrap_or(0);
+        let mut counts = vec![0; unique_sequences.len()];
+        for &sequence in &methods {
+            if let Some(count) = sequence.checked_sub(first).and_then(|index| counts.get_mut(index)) {
+                *count += 1;
+            }
+        }
+        Sequences::Deduplicated { unique_sequences, methods, counts, first_sequence }
+    }
+
+    /// Sequence of every method in `methods` of deduplicated sequences, `None` for the sequences of earlier inputs
+    fn method_sequences<'a>(unique_sequences: &'a [Vec<u8>], methods: &'a [usize], first_sequence: Option<usize>) -> impl Iterator<Item = Option<&'a Vec<u8>>> {
+        let first = first_sequence.unwrap_or(0);
+        methods.iter().map(move |&sequence| sequence.checked_sub(first).and_then(|index| unique_sequences.get(index)))
+    }
+
+    pub fn flat(op_seq: Vec<u8>, methods: Vec<MethodReport>) -> Self {
+        Sequences::Flat { op_seq, methods }
+    }
+
+    /// Number of opcodes of the sequences, those of a unique sequence counted once per method sharing it.
+    /// With `DedupScope::Run`, the methods sharing the sequences of earlier inputs count for none
+    pub fn opcode_count(&self) -> usize {
+        match self {
+            Sequences::Flat { op_seq, .. } => op_seq.len(),
+            Sequences::Deduplicated { unique_sequences, methods, first_sequence, .. } => Self::method_sequences(unique_sequences, methods, *first_sequence)
+                .flatten()
+                .map(Vec::len)
+                .sum(),
+        }
+    }
+
+    /// Number of methods of the sequences, including those sharing a unique sequence
+    pub fn method_count(&self) -> usize {
+        match self {
+            Sequences::Flat { methods, .. } => methods.len(),
+            Sequences::Deduplicated { methods, .. } => methods.len(),
+        }
+    }
+
+    /// Number of occurrences of every opcode byte in the sequences, those of a unique sequence counted once per method sharing it
+    pub fn opcode_histogram(&self) -> [u64; 256] {
+        let mut histogram = [0; 256];
+        match self {
+            Sequences::Flat { op_seq, .. } => op_seq.iter().for_each(|&opcode| histogram[opcode as usize] += 1),
+            Sequences::Deduplicated { unique_sequences, methods, first_sequence, .. } => for sequence in Self::method_sequences(unique_sequences, methods, *first_sequence).flatten() {
+                sequence.iter().for_each(|&opcode| histogram[opcode as usize] += 1);
+            },
+        }
+        histogram
+    }
+
+    /// Opcode bigrams of every method, by method index. Pairs never span two methods
+    pub fn method_bigrams(&self) -> Vec<(usize, BigramCounts)> {
+        match self {
+            Sequences::Flat { op_seq, methods, .. } => methods.iter()
+                .map(|method| bigrams(&op_seq[method.start()..method.end() + 1]))
+                .enumerate()
+                .collect(),
+            Sequences::Deduplicated { unique_sequences, methods, first_sequence, .. } => Self::method_sequences(unique_sequences, methods, *first_sequence)
+                .map(|sequence| sequence.map(|sequence| bigrams(sequence)).unwrap_or_default())
+                .enumerate()
+                .collect(),
+        }
+    }
+
+    /// Sequences of every run of methods of the same class, with the number of methods in each.
+    /// `None` for deduplicated sequences, which don't keep the class of their methods
+    pub fn by_class(&self) -> Option<Vec<(&str, usize, Sequences)>> {
+        let Sequences::Flat { op_seq, methods } = self else { return None };
+        let mut classes: Vec<(&str, usize, Sequences)> = vec![];
+        for method in methods {
+            let method_seq = &op_seq[method.start()..method.end() + 1];
+            match classes.last_mut() {
+                Some((class, count, Sequences::Flat { op_seq, methods, .. })) if *class == method.class() => {
+                    *count += 1;
+                    methods.push(method.moved_to(op_seq.len()));
+                    op_seq.extend_from_slice(method_seq);
+                },
+                _ => classes.push((method.class(), 1, Sequences::flat(method_seq.to_vec(), vec![method.moved_to(0)]))),
+            }
+        }
+        Some(classes)
+    }
+
+    /// Opcode sequence of every class, its methods concatenated in order, and the classes in walk order. A class defined
+    /// by several dexes is only walked in the first, so every class has the methods of one definition.
+    /// `None` for deduplicated sequences, which don't keep the class of their methods
+    pub fn class_sequences(&self) -> Option<Vec<(&str, Vec<u8>)>> {
+        let Sequences::Flat { op_seq, methods, .. } = self else { return None };
+        let mut classes: Vec<(&str, Vec<u8>)> = vec![];
+        for method in methods {
+            let method_seq = &op_seq[method.start()..method.end() + 1];
+            match classes.last_mut() {
+                Some((class, op_seq)) if *class == method.class() => op_seq.extend_from_slice(method_seq),
+                _ => classes.push((method.class(), method_seq.to_vec())),
+            }
+        }
+        Some(classes)
+    }
+
+    /// Sequence of every method on its own, along with its report.
+    /// `None` for deduplicated sequences, which don't keep the class of their methods
+    pub fn by_method(&self) -> Option<Vec<(&MethodReport, Sequences)>> {
+        let Sequences::Flat { op_seq, methods } = self else { return None };
+        Some(methods.iter()
+            .map(|method| (method, Sequences::flat(op_seq[method.start()..method.end() + 1].to_vec(), vec![method.moved_to(0)])))
+            .collect())
+    }
+
+    /// Hash of the sequences and of the methods referring to them, for inputs with other code to be sampled differently
+    pub fn identity(&self) -> u64 {
+        let mut hasher = Xxh3::new();
+        match self {
+            Sequences::Flat { op_seq, methods } => {
+                hasher.update(op_seq);
+                for method in methods {
+                    hasher.update(&(method.end() - method.start()).to_le_bytes());
+                }
+            },
+            Sequences::Deduplicated { unique_sequences, methods, .. } => {
+                for sequence in unique_sequences {
+                    hasher.update(&sequence.len().to_le_bytes());
+                    hasher.update(sequence);
+                }
+                for method in methods {
+                    hasher.update(&method.to_le_bytes());
+                }
+            },
+        }
+        hasher.digest()
+    }
+
+    /// Keeps the methods selected by `sampling`, the sequences of the others are dropped
+    pub fn sample(self, sampling: &Sampling) -> Self {
+        let input = self.identity();
+        match self {
+            Sequences::Flat { op_seq, methods, .. } => {
+                let mut sampled_seq = vec![];
+                let mut sampled_methods = vec![];
+                for (method, keep) in methods.iter().zip(sampling.select(methods.len(), input)) {
+                    if keep {
+                        sampled_methods.push(method.moved_to(sampled_seq.len()));
+                        sampled_seq.extend_from_slice(&op_seq[method.start()..method.end() + 1]);
+                    }
+                }
+                Sequences::flat(sampled_seq, sampled_methods)
+            },
+            // Run-wide ids are kept, later inputs may refer to any of the sequences
+            Sequences::Deduplicated { unique_sequences, methods, first_sequence: Some(first_sequence), .. } => {
+                let sampled_methods = methods.iter().zip(sampling.select(methods.len(), input)).filter(|(_, keep)| *keep).map(|(&sequence, _)| sequence).collect();
+                Sequences::run_deduplicated(unique_sequences, sampled_methods, Some(first_sequence))
+            },
+            Sequences::Deduplicated { mut unique_sequences, methods, .. } => {
+                // Sequences are renumbered in order of first use by the kept methods
+                let mut remapped = vec![None; unique_sequences.len()];
+                let mut sampled_sequences = vec![];
+                let mut sampled_methods = vec![];
+                for (&sequence, keep) in methods.iter().zip(sampling.select(methods.len(), input)) {
+                    if keep {
+                        let index = *remapped[sequence].get_or_insert_with(|| {
+                            sampled_sequences.push(std::mem::take(&mut unique_sequences[sequence]));
+                            sampled_sequences.len() - 1
+                        });
+                        sampled_methods.push(index);
+                    }
+                }
+                Sequences::deduplicated(sampled_sequences, sampled_methods)
+            },
+        }
+    }
+}
+
+
+impl Serialize for Sequences {
+    /// The fields of the variant, with the opcodes written as bytes
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FormattedSequences { sequences: self, mnemonics: false }.serialize(serializer)
+    }
+}
+
+
+/// Sequences serialized with the opcodes written as their mnemonics, e.g. `invoke-virtual`, when `mnemonics` is set,
+/// as bytes otherwise. `sequences` is either a `Sequences` or a reference to one
+pub struct FormattedSequences<S> {
+    pub sequences: S,
+    pub mnemonics: bool,
+}
+
+
+impl<T: Borrow<Sequences>> Serialize for FormattedSequences<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mnemonics = &self.mnemonics;
+        match self.sequences.borrow() {
+            Sequences::Flat { op_seq, methods } => {
+                let mut state = serializer.serialize_struct("Sequences", 2)?;
+                if *mnemonics {
+                    state.serialize_field("op_seq", &to_mnemonics(op_seq))?;
+                } else {
+                    state.serialize_field("op_seq", op_seq)?;
+                }
+                state.serialize_field("methods", methods)?;
+                state.end()
+            },
+            Sequences::Deduplicated { unique_sequences, methods, counts, first_sequence } => {
+                let mut state = serializer.serialize_struct("Sequences", 3 + first_sequence.is_some() as usize)?;
+                if let Some(first_sequence) = first_sequence {
+                    state.serialize_field("first_sequence", first_sequence)?;
+                }
+                if *mnemonics {
+                    let unique_sequences = unique_sequences.iter().map(|sequence| to_mnemonics(sequence)).collect::<Vec<_>>();
+                    state.serialize_field("unique_sequences", &unique_sequences)?;
+                } else {
+                    state.serialize_field("unique_sequences", unique_sequences)?;
+                }
+                state.serialize_field("methods", methods)?;
+                state.serialize_field("counts", counts)?;
+                state.end()
+            },
+        }
+    }
+}
+
+
+fn to_mnemonics(op_seq: &[u8]) -> Vec<&'static str> {
+    op_seq.iter().map(|&opcode| Opcode::from_u8(opcode).map_or("unused", |opcode| opcode.mnemonic())).collect()
+}
+
+
+fn bigrams(method_seq: &[u8]) -> BigramCounts {
+    let mut counts = BigramCounts::new();
+    for pair in method_seq.windows(2) {
+        *counts.entry((pair[0], pair[1])).or_default() += 1;
+    }
+    counts
+}
+
+
+/// Analysis of a whole APK, the sequences of its dexes are concatenated in archive order
+#[derive(Debug, Clone, Serialize)]
+pub struct ApkReport {
+    #[serde(flatten)]
+    pub sequences: Sequences,
+    /// Permissions requested by the manifest, without the `android.permission.` prefix
+    pub permissions: Option<Vec<String>>,
+    /// Calls of permission-guarded APIs whose permissions the manifest doesn't request, `None` without a manifest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dead_api_calls: Option<Vec<DeadApiCall>>,
+    /// Calls of watched reflection and dynamic loading APIs
+    pub watchlist: Vec<WatchlistHit>,
+    /// Native and abstract methods, which have no opcodes in the sequences
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub codeless_methods: Vec<CodelessMethod>,
+    /// How much of the code of all dexes could be decoded
+    pub coverage: Coverage,
+    /// Sizes read from the header of every dex
+    pub header_counts: Vec<HeaderCounts>,
+    /// Name and number of classes of every dex, in the order of the `dex` indices of the other sections
+    pub dexes: Vec<DexClasses>,
+    /// Classes defined by more than one dex, only the first copy of which is in the sequences
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub duplicate_classes: Vec<DuplicateClass>,
+    /// Lowercase hex SHA-256 of the archive, unknown when the report wasn't read from one. Identifies the input
+    /// whatever its path, e.g. when merging outputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Signature schemes and signers of the APK, unknown when the report wasn't read from an archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signatures: Option<Signatures>,
+    /// Strings of every dex, when enabled in the options and the report was read from an archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub string_pool: Option<Vec<PoolString>>,
+    /// Fields of the selected classes of every dex, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<FieldRecord>>,
+    /// Framework APIs invoked by the methods of the selected classes of every dex, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_sequences: Option<Vec<ApiSequence>>,
+    /// Intents created by the methods of the selected classes of every dex, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intents: Option<Vec<IntentSite>>,
+    /// Components of the manifest started by the `intents`, by class name. Needs the manifest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub components: Option<BTreeMap<String, ComponentInteractions>>,
+    /// URLs, addresses and suspicious domains in the strings of the selected classes of all dexes, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_indicators: Option<Vec<NetworkIndicator>>,
+    /// Kotlin usage and share of generated classes of all dexes, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kotlin: Option<KotlinReport>,
+    /// Source files of the classes and share of classes and methods with debug info of all dexes, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_info: Option<DebugInfoReport>,
+    /// Methods of the selected classes of every dex with registers outside their frame, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_errors: Option<Vec<MethodVerifyErrors>>,
+    /// Call graph metrics of every dex, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Vec<CallGraphMetrics>>,
+    /// Likely string decryption helpers of all dexes, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub obfuscation: Option<Obfuscation>,
+    /// Packers and obfuscators recognized by the packer rules, when enabled in the options and the report was read from an archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packer: Option<Vec<PackerMatch>>,
+    /// Outputs of the method analyses registered in the options, by analysis name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Extensions>,
+    /// Problems that didn't prevent the analysis, such as undecodable methods
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+}
+
+
+impl ApkReport {
+    /// The report as a JSON object, with the opcodes written as their mnemonics when `mnemonics` is set
+    pub fn to_value(&self, mnemonics: bool) -> serde_json::Result<serde_json::Value> {
+        let mut report = serde_json::to_value(self)?;
+        if mnemonics {
+            let sequences = serde_json::to_value(FormattedSequences { sequences: &self.sequences, mnemonics })?;
+            // The sequences are flattened into the report, their keys are replaced
+            if let (Some(report), serde_json::Value::Object(sequences)) = (report.as_object_mut(), sequences) {
+                report.extend(sequences);
+            }
+        }
+        Ok(report)
+    }
+}
+
+
+/// Analysis of a single dex
+#[derive(Debug, Clone, Serialize)]
+pub struct DexReport {
+    #[serde(flatten)]
+    pub sequences: Sequences,
+    pub watchlist: Vec<WatchlistHit>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub codeless_methods: Vec<CodelessMethod>,
+    pub coverage: Coverage,
+    pub header_counts: HeaderCounts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub string_pool: Option<Vec<PoolString>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sections: Option<Vec<DexSection>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomalies: Option<Vec<SectionAnomaly>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<FieldRecord>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_sequences: Option<Vec<ApiSequence>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intents: Option<Vec<IntentSite>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_indicators: Option<Vec<NetworkIndicator>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kotlin: Option<KotlinReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_info: Option<DebugInfoReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_errors: Option<Vec<MethodVerifyErrors>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<CallGraphMetrics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub obfuscation: Option<Obfuscation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Extensions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+}
+
+
+/// Size of a dex and of its id sections, read from its header without decoding anything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HeaderCounts {
+    /// Size of the whole dex in bytes
+    pub file_size: u32,
+    pub strings: u32,
+    pub methods: u32,
+    pub fields: u32,
+    pub classes: u32,
+}
+
+
+impl HeaderCounts {
+    pub fn from_dex<T: AsRef<[u8]>>(dex: &Dex<T>) -> Self {
+        let header = dex.header();
+        Self {
+            file_size: header.file_size(),
+            strings: header.string_ids_size(),
+            methods: header.method_ids_size(),
+            fields: header.field_ids_size(),
+            classes: header.class_defs_size(),
+        }
+    }
+}
+
+
+/// Name of a dex and the number of classes it defines
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DexClasses {
+    pub dex_name: String,
+    pub classes: u32,
+    /// Sections declared by the map_list, when enabled in the options and the report was read from an archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sections: Option<Vec<DexSection>>,
+    /// Anomalies of the layout of the sections, set along with `sections`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomalies: Option<Vec<SectionAnomaly>>,
+}
+
+
+impl DexClasses {
+    pub fn from_dex<T: AsRef<[u8]>>(dex: &NamedDex<T>) -> Self {
+        Self { dex_name: dex.name.clone(), classes: dex.dex.header().class_defs_size(), sections: None, anomalies: None }
+    }
+}
+
+
+/// Dexes, manifest and signatures read from an APK
+pub struct ApkContents {
+    /// Lowercase hex SHA-256 of the archive
+    pub sha256: String,
+    /// Dexes named after their entry in the archive
+    pub dexes: Vec<NamedDex<Arc<[u8]>>>,
+    /// Bytes of every dex of `dexes`, in the same order
+    pub dex_bytes: Vec<Arc<[u8]>>,
+    pub manifest: Option<Manifest>,
+    /// Entries of the `assets/` directory
+    pub assets: Vec<Asset>,
+    pub signatures: Signatures,
+    /// Dex entries and manifest that could not be parsed
+    pub warnings: Vec<Warning>,
+}
+
+
+/// Reads the dexes, the manifest and the signatures of the APK at `path`
+#[cfg(feature = "fs")]
+pub fn parse_apk(path: impl AsRef<Path>) -> Result<ApkContents, Error> {
+    parse_apk_from(File::open(path)?)
+}
+
+
+/// Reads the dexes, the manifest and the signatures of an APK from any seekable reader, e.g. an in-memory `Cursor`.
+/// Unreadable entries are skipped, dexes that fail to parse are skipped with a warning
+pub fn parse_apk_from(mut reader: impl Read + Seek) -> Result<ApkContents, Error> {
+    let mut hasher = Sha256::new();
+    reader.rewind()?;
+    io::copy(&mut reader, &mut hasher)?;
+    let sha256 = format!("{:x}", hasher.finalize());
+    let mut zip_handler = ZipArchive::new(reader)?;
+
+    let mut dexes = vec![];
+    let mut dex_bytes = vec![];
+    let mut manifest = None;
+    let mut assets = vec![];
+    let mut signatures = Signatures::default();
+    let mut warnings = vec![];
+    let mut central_directory_start = u64::MAX;
+
+    for i in 0..zip_handler.len() {
+        let Ok(mut current_file) = zip_handler.by_index(i) else { continue };
+        central_directory_start = central_directory_start.min(current_file.central_header_start());
+        signatures.add_entry(current_file.name());
+        let mut contents = Vec::new();
+        if current_file.read_to_end(&mut contents).is_err() {
+            continue;
+        }
+
+        if current_file.name().starts_with("assets/") {
+            assets.push(Asset::new(current_file.name(), &contents));
+        }
+        if current_file.name() == "AndroidManifest.xml" {
+            match Manifest::parse(contents) {
+                Ok(parsed) => manifest = Some(parsed),
+                Err(err) => warnings.push(Warning::from(err)),
+            }
+        } else if contents.starts_with(DEX_MAGIC) {
+            let bytes: Arc<[u8]> = contents.into();
+            match DexReader::from_vec(bytes.clone()) {
+                Ok(dex) => {
+                    dexes.push(NamedDex::new(current_file.name(), dex));
+                    dex_bytes.push(bytes);
+                },
+                Err(err) => warnings.push(Warning::new(WarningKind::InvalidDex, format!("{}: {}", current_file.name(), err))),
+            }
+        }
+    }
+
+    // The v2 and v3 signatures live outside the entries, in a block right before the central directory
+    if central_directory_start != u64::MAX {
+        signatures.read_signing_block(&mut zip_handler.into_inner(), central_directory_start)?;
+    }
+
+    Ok(ApkContents { sha256, dexes, dex_bytes, manifest, assets, signatures, warnings })
+}
+
+
+/// Reads only the manifest of the APK at `path`, see `read_manifest_from`
+#[cfg(feature = "fs")]
+pub fn read_manifest(path: impl AsRef<Path>) -> Result<Option<Manifest>, Error> {
+    read_manifest_from(File::open(path)?)
+}
+
+
+/// Reads only the manifest of an APK, the other entries are neither decompressed nor parsed.
+/// `None` for an archive without `AndroidManifest.xml`
+pub fn read_manifest_from(reader: impl Read + Seek) -> Result<Option<Manifest>, Error> {
+    manifest_contents(reader)?.map(Manifest::parse).transpose()
+}
+
+
+/// Reads only the requested permissions of the APK at `path`, see `read_permissions_from`
+#[cfg(feature = "fs")]
+pub fn read_permissions(path: impl AsRef<Path>) -> Result<Option<Vec<String>>, Error> {
+    read_permissions_from(File::open(path)?)
+}
+
+
+/// Reads only the requested permissions of an APK, without the `android.permission.` prefix, like `read_manifest_from`.
+/// `None` for an archive without `AndroidManifest.xml`
+pub fn read_permissions_from(reader: impl Read + Seek) -> Result<Option<Vec<String>>, Error> {
+    manifest_contents(reader)?.map(parse_permissions).transpose()
+}
+
+
+/// Decompressed `AndroidManifest.xml` of an APK, `None` for an archive without one
+fn manifest_contents(reader: impl Read + Seek) -> Result<Option<Vec<u8>>, Error> {
+    let mut zip_handler = ZipArchive::new(reader)?;
+    let mut entry = match zip_handler.by_name("AndroidManifest.xml") {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let mut contents = Vec::new();
+    entry.read_to_end(&mut contents)?;
+    Ok(Some(contents))
+}
+
+
+/// Analyzes the APK at `path`
+#[cfg(feature = "fs")]
+pub fn analyze_apk(path: impl AsRef<Path>, options: &AnalysisOptions) -> Result<ApkReport, Error> {
+    analyze_contents(parse_apk(path)?, options)
+}
+
+
+/// Analyzes an APK held in memory, e.g. streamed from object storage, the same way `analyze_apk` analyzes a file
+pub fn analyze_apk_bytes(data: &[u8], options: &AnalysisOptions) -> Result<ApkReport, Error> {
+    analyze_contents(parse_apk_from(Cursor::new(data))?, options)
+}
+
+
+/// Fails on the first class definition of the dex named `name` that can't be parsed, for `AnalysisOptions::strict_classes`
+fn check_class_defs(name: &str, dex: &Dex<impl AsRef<[u8]>>) -> Result<(), Error> {
+    match dex.classes().enumerate().find_map(|(index, class)| class.err().map(|source| (index, source))) {
+        Some((index, source)) => Err(Error::InvalidClass { dex: name.to_string(), index, source }),
+        None => Ok(()),
+    }
+}
+
+
+/// Analyzes the contents of an APK read with `parse_apk` or `parse_apk_from`, as `analyze_apk` does
+pub fn analyze_contents(contents: ApkContents, options: &AnalysisOptions) -> Result<ApkReport, Error> {
+    if options.strict_classes {
+        for dex in &contents.dexes {
+            check_class_defs(&dex.name, &dex.dex)?;
+        }
+    }
+    let ApkContents { sha256, dexes, dex_bytes, manifest, assets, signatures, mut warnings } = contents;
+    let (names, dexes) = split_names(dexes);
+    let string_pool = (options.string_pool && options.selects(ReportField::Strings)).then(|| dexes.iter().zip(&dex_bytes).enumerate()
+        .flat_map(|(index, (dex, bytes))| string_pool(index, bytes, dex, options.invalid_strings))
+        .collect());
+    let packer = options.packer.as_ref().filter(|_| options.selects(ReportField::Packer)).map(|rules| rules.detect(&dexes, manifest.as_ref(), &assets, options.max_cfg_depth, &mut warnings));
+    let decoded = decode_in_parallel(&names, &dex_bytes, options)?;
+    let mut report = analyze_named_dexes(names.into_iter().zip(dexes).map(|(name, dex)| NamedDex::new(name, dex)).collect(), &dex_bytes, manifest, options, decoded);
+    report.sha256 = Some(sha256);
+    report.signatures = options.selects(ReportField::Signatures).then_some(signatures);
+    report.string_pool = string_pool;
+    report.packer = packer;
+    if options.sections && options.selects(ReportField::Sections) {
+        for (dex, bytes) in report.dexes.iter_mut().zip(&dex_bytes) {
+            let layout = dex_layout(bytes);
+            dex.sections = Some(layout.sections);
+            dex.anomalies = Some(layout.anomalies);
+        }
+    }
+    warnings.append(&mut report.warnings);
+    report.warnings = warnings;
+    Ok(report)
+}
+
+
+/// Flat sequences decoded from the bytes of the dexes ahead of `analyze_named_dexes`, with the coverage and the warnings
+/// of the walk
+struct Decoded {
+    op_seq: Vec<u8>,
+    methods: Vec<MethodReport>,
+    coverage: Coverage,
+    warnings: Vec<Warning>,
+}
+
+
+/// Decodes the dexes of an APK in parallel when they are several, or through the `dex_cache` of the options, and the
+/// options allow it, that is when the sequences are needed, flat and without a cap shared by the dexes. `None` when the
+/// dexes are left to the sequential walk
+#[cfg(feature = "parallel")]
+fn decode_in_parallel(names: &[String], dex_bytes: &[Arc<[u8]>], options: &AnalysisOptions) -> Result<Option<Decoded>, Error> {
+    let needed = options.selects(ReportField::Opcodes) || options.selects(ReportField::Coverage);
+    if (dex_bytes.len() < 2 && options.dex_cache.is_none()) || !needed || options.dedup_methods || options.shares_caps() {
+        return Ok(None);
+    }
+    let dexes: Vec<(String, Arc<[u8]>)> = names.iter().cloned().zip(dex_bytes.iter().cloned()).collect();
+    let (mut coverage, mut warnings) = (Coverage::default(), vec![]);
+    let (op_seq, methods) = parse_dexes_parallel(&dexes, options, &mut coverage, &mut warnings)?;
+    Ok(Some(Decoded { op_seq, methods, coverage, warnings }))
+}
+
+
+#[cfg(not(feature = "parallel"))]
+fn decode_in_parallel(_names: &[String], _dex_bytes: &[Arc<[u8]>], _options: &AnalysisOptions) -> Result<Option<Decoded>, Error> {
+    Ok(None)
+}
+
+
+/// Analyzes already parsed dexes as the contents of one APK, the string pool needs the bytes of the dexes and is left out
+pub fn analyze_dexes(dexes: Vec<NamedDex<impl AsRef<[u8]>>>, manifest: Option<Manifest>, options: &AnalysisOptions) -> ApkReport {
+    analyze_named_dexes(dexes, &[], manifest, options, None)
+}
+
+
+/// `analyze_dexes` with the sequences already `decoded`, if any, and the bytes of the dexes when at hand
+fn analyze_named_dexes(dexes: Vec<NamedDex<impl AsRef<[u8]>>>, dex_bytes: &[Arc<[u8]>], manifest: Option<Manifest>, options: &AnalysisOptions, decoded: Option<Decoded>) -> ApkReport {
+    let selects = |field| options.selects(field);
+    let classes = if selects(ReportField::Dexes) { dexes.iter().map(DexClasses::from_dex).collect() } else { vec![] };
+    let duplicate_classes = if selects(ReportField::DuplicateClasses) { duplicate_classes(&dexes) } else { vec![] };
+    let metrics_enabled = options.call_graph_metrics && selects(ReportField::Metrics);
+    let decryptor_thresholds = options.string_decryptors.filter(|_| selects(ReportField::Obfuscation));
+    // The call graphs are shared by the metrics and the string decryptor heuristic
+    let graphs: Vec<CallGraph> = if metrics_enabled || decryptor_thresholds.is_some() {
+        dexes.iter().map(CallGraph::from_dex).collect()
+    } else {
+        vec![]
+    };
+    let (names, dexes) = split_names(dexes);
+    let watchlist = if selects(ReportField::Watchlist) { options.watchlist.scan(&dexes) } else { vec![] };
+    let codeless_methods = if selects(ReportField::CodelessMethods) { codeless_methods(&dexes, options) } else { vec![] };
+    // Read from the headers for free, and the run statistics of the command line tool need them
+    let header_counts = dexes.iter().map(HeaderCounts::from_dex).collect();
+    let fields = (options.fields && selects(ReportField::Fields)).then(|| dexes.iter().enumerate().flat_map(|(index, dex)| fields(index, dex, options)).collect());
+    let api_sequences = (options.api_sequences && selects(ReportField::ApiSequences)).then(|| dexes.iter().enumerate().flat_map(|(index, dex)| api_sequences(index, dex, options)).collect());
+    let intents: Option<Vec<IntentSite>> = (options.intents && selects(ReportField::Intents)).then(|| dexes.iter().enumerate().flat_map(|(index, dex)| intents(index, dex, dex_bytes.get(index).map(|bytes| &bytes[..]), options)).collect());
+    let components = intents.as_ref().zip(manifest.as_ref()).map(|(sites, manifest)| component_interactions(sites, manifest));
+    let network_indicators = (options.network_indicators && selects(ReportField::NetworkIndicators)).then(|| network_indicators(&dexes, options));
+    let kotlin = (options.kotlin && selects(ReportField::Kotlin)).then(|| kotlin_report(&dexes));
+    let debug_info = (options.debug_info && selects(ReportField::DebugInfo)).then(|| debug_info_report(&dexes));
+    let extensions = (!options.method_analyses.is_empty() && selects(ReportField::Extensions)).then(|| extensions(&dexes, options));
+    let dead_api_calls = manifest.as_ref().filter(|_| options.dead_api_calls && selects(ReportField::DeadApiCalls))
+        .map(|manifest| dexes.iter().enumerate().flat_map(|(index, dex)| dead_api_calls(index, dex, &manifest.permissions, options)).collect());
+    let mut warnings = vec![];
+    let verify_errors = (options.verify && selects(ReportField::VerifyErrors)).then(|| dexes.iter().enumerate().flat_map(|(index, dex)| verify_dex(index, dex, options, &mut warnings)).collect());
+    let metrics = metrics_enabled.then(|| graphs.iter().map(CallGraph::metrics).collect());
+    let obfuscation = decryptor_thresholds.map(|thresholds| Obfuscation {
+        string_decryptors: dexes.iter().zip(&graphs).enumerate()
+            .flat_map(|(index, (dex, graph))| string_decryptors(index, dex, graph, &thresholds))
+            .collect(),
+    });
+    let mut coverage = Coverage::default();
+    let dexes = names.into_iter().zip(dexes).map(|(name, dex)| NamedDex::new(name, dex)).collect();
+    let sequences = selected_sequences(dexes, decoded, options, &mut coverage, &mut warnings);
+    let permissions = manifest.filter(|_| selects(ReportField::Permissions)).map(|manifest| manifest.permissions);
+    ApkReport { sequences, permissions, dead_api_calls, watchlist, codeless_methods, coverage, header_counts, dexes: classes, duplicate_classes, sha256: None, signatures: None, string_pool: None, fields, api_sequences, intents, components, network_indicators, kotlin, debug_info, verify_errors, metrics, obfuscation, packer: None, extensions, warnings }
+}
+
+
+/// Analyzes the contents of a dex file
+pub fn analyze_dex(bytes: Vec<u8>, options: &AnalysisOptions) -> Result<DexReport, Error> {
+    let bytes: Arc<[u8]> = bytes.into();
+    let dex = DexReader::from_vec(bytes.clone())?;
+    if options.strict_classes {
+        check_class_defs("classes.dex", &dex)?;
+    }
+    let selects = |field| options.selects(field);
+    let string_pool = (options.string_pool && selects(ReportField::Strings)).then(|| string_pool(0, &bytes, &dex, options.invalid_strings));
+    let (sections, anomalies) = (options.sections && selects(ReportField::Sections)).then(|| dex_layout(&bytes)).map(|layout| (layout.sections, layout.anomalies)).unzip();
+    let watchlist = if selects(ReportField::Watchlist) { options.watchlist.scan(std::slice::from_ref(&dex)) } else { vec![] };
+    let codeless_methods = if selects(ReportField::CodelessMethods) { codeless_methods(std::slice::from_ref(&dex), options) } else { vec![] };
+    let header_counts = HeaderCounts::from_dex(&dex);
+    let fields = (options.fields && selects(ReportField::Fields)).then(|| fields(0, &dex, options));
+    let api_sequences = (options.api_sequences && selects(ReportField::ApiSequences)).then(|| api_sequences(0, &dex, options));
+    let intents = (options.intents && selects(ReportField::Intents)).then(|| intents(0, &dex, Some(&bytes), options));
+    let network_indicators = (options.network_indicators && selects(ReportField::NetworkIndicators)).then(|| network_indicators(std::slice::from_ref(&dex), options));
+    let kotlin = (options.kotlin && selects(ReportField::Kotlin)).then(|| kotlin_report(std::slice::from_ref(&dex)));
+    let debug_info = (options.debug_info && selects(ReportField::DebugInfo)).then(|| debug_info_report(std::slice::from_ref(&dex)));
+    let extensions = (!options.method_analyses.is_empty() && selects(ReportField::Extensions)).then(|| extensions(std::slice::from_ref(&dex), options));
+    let mut warnings = vec![];
+    let verify_errors = (options.verify && selects(ReportField::VerifyErrors)).then(|| verify_dex(0, &dex, options, &mut warnings));
+    let metrics_enabled = options.call_graph_metrics && selects(ReportField::Metrics);
+    let decryptor_thresholds = options.string_decryptors.filter(|_| selects(ReportField::Obfuscation));
+    let dex = NamedDex::new("classes.dex", dex);
+    let graph = (metrics_enabled || decryptor_thresholds.is_some()).then(|| CallGraph::from_dex(&dex));
+    let dex = dex.dex;
+    let metrics = graph.as_ref().filter(|_| metrics_enabled).map(CallGraph::metrics);
+    let obfuscation = decryptor_thresholds.zip(graph.as_ref())
+        .map(|(thresholds, graph)| Obfuscation { string_decryptors: string_decryptors(0, &dex, graph, &thresholds) });
+    let mut coverage = Coverage::default();
+    let sequences = selected_sequences(NamedDex::multidex([dex]), None, options, &mut coverage, &mut warnings);
+    Ok(DexReport { sequences, watchlist, codeless_methods, coverage, header_counts, string_pool, sections, anomalies, fields, api_sequences, intents, network_indicators, kotlin, debug_info, verify_errors, metrics, obfuscation, extensions, warnings })
+}
+
+
+/// Dexes without their names, in the same order as the names, for the analyses reporting dexes by index
+fn split_names<T>(dexes: Vec<NamedDex<T>>) -> (Vec<String>, Vec<Dex<T>>) {
+    dexes.into_iter().map(|NamedDex { name, dex }| (name, dex)).unzip()
+}
+
+
+/// Sequences of the dexes when the options select them, empty otherwise. The dexes are still walked for the coverage
+/// when it is selected, unless they were already `decoded`
+fn selected_sequences(dexes: Vec<NamedDex<impl AsRef<[u8]>>>, decoded: Option<Decoded>, options: &AnalysisOptions, coverage: &mut Coverage, warnings: &mut Vec<Warning>) -> Sequences {
+    if !options.selects(ReportField::Opcodes) && !options.selects(ReportField::Coverage) {
+        return Sequences::flat(vec![], vec![]);
+    }
+    let sequences = get_sequences(dexes, decoded, options, coverage, warnings);
+    if options.selects(ReportField::Opcodes) { sequences } else { Sequences::flat(vec![], vec![]) }
+}
+
+
+fn get_sequences(dexes: Vec<NamedDex<impl AsRef<[u8]>>>, decoded: Option<Decoded>, options: &AnalysisOptions, coverage: &mut Coverage, warnings: &mut Vec<Warning>) -> Sequences {
+    let sequences = if let Some(decoded) = decoded {
+        *coverage += decoded.coverage;
+        warnings.extend(decoded.warnings);
+        Sequences::flat(decoded.op_seq, decoded.methods)
+    } else if options.dedup_methods {
+        let (unique_sequences, methods, first_sequence) = parse_dexes_dedup(dexes, options, coverage, warnings);
+        Sequences::run_deduplicated(unique_sequences, methods, first_sequence)
+    } else {
+        let (op_seq, methods) = parse_dexes(dexes, options, coverage, warnings);
+        Sequences::flat(op_seq, methods)
+    };
+    match &options.sampling {
+        Some(sampling) => sequences.sample(sampling),
+        None => sequences,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use crate::{options::{CapStrategy, ClassFilter, DedupKey, DedupScope, Strictness}, signature::SigningScheme, testing::{sample_dex, DexBuilder, ClassDef, FieldDef, MethodDef, CodeDef, SAMPLE_METHODS, ACC_ABSTRACT, ACC_NATIVE, ACC_PUBLIC, ACC_STATIC}};
+    use crate::{access_flags::MethodFlags, dex_parsing::{CodelessKind, CodelessMethod, MethodDeduplicator}};
+    use super::*;
+
+    fn lenient() -> AnalysisOptions {
+        AnalysisOptions::default().strictness(Strictness::Lenient)
+    }
+
+    #[test]
+    fn test_analyze_dex_sample() {
+        let options = lenient().call_graph_metrics(true).build();
+        let report = analyze_dex(sample_dex(2), &options).unwrap();
+        let Sequences::Flat { op_seq, methods, .. } = report.sequences else { panic!("expected a flat report") };
+        assert_eq!(methods.len(), 2 * SAMPLE_METHODS.len());
+        assert_eq!(methods.last().unwrap().end() + 1, op_seq.len());
+        assert_eq!(report.metrics.unwrap().methods, 2 * SAMPLE_METHODS.len());
+    }
+
+    #[test]
+    fn test_report_fields_skip_strings() {
+        let read = || crate::string_pool::POOLS_READ.with(|read| read.get());
+        let options = lenient().string_pool(true);
+        let before = read();
+        let report = analyze_dex(sample_dex(1), &options.clone().report_fields([ReportField::Opcodes, ReportField::Coverage])).unwrap();
+        assert_eq!(read(), before);
+        assert!(serde_json::to_value(&report).unwrap().get("string_pool").is_none());
+        assert!(report.sequences.method_count() > 0);
+
+        let report = analyze_dex(sample_dex(1), &options.report_fields([ReportField::Strings, ReportField::Coverage])).unwrap();
+        assert_eq!(read(), before + 1);
+        assert!(report.string_pool.is_some_and(|strings| !strings.is_empty()));
+        assert_eq!(report.sequences.method_count(), 0);
+        assert!(report.coverage.methods > 0);
+    }
+
+    #[test]
+    fn test_analyze_dex_dedup() {
+        let options = lenient().dedup_methods(true).build();
+        let report = analyze_dex(sample_dex(3), &options).unwrap();
+        let Sequences::Deduplicated { unique_sequences, methods, counts, .. } = report.sequences else { panic!("expected a deduplicated report") };
+        assert_eq!(unique_sequences.len(), SAMPLE_METHODS.len());
+        assert_eq!(methods.len(), 3 * SAMPLE_METHODS.len());
+        assert_eq!(counts, vec![3; SAMPLE_METHODS.len()]);
+    }
+
+    #[test]
+    fn test_dedup_scope_and_key() {
+        let duplicated_dex = || {
+            let mut builder = DexBuilder::new();
+            // Twice const/4 v0, 0; return v0, then const/4 v1, 1; return v1
+            builder.class(ClassDef::new("Lcom/example/Main;")
+                .method(MethodDef::new("first", "I", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000F])))
+                .method(MethodDef::new("second", "I", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000F])))
+                .method(MethodDef::new("third", "I", &[]).code(CodeDef::new(2, 0, 0, &[0x1112, 0x010F]))));
+            DexReader::from_vec(builder.build()).unwrap()
+        };
+        let dedup = |options: AnalysisOptions| {
+            let report = analyze_dexes(NamedDex::multidex([duplicated_dex(), duplicated_dex()]), None, &options.dedup_methods(true).build());
+            let Sequences::Deduplicated { unique_sequences, methods, counts, .. } = report.sequences else { unreachable!() };
+            (unique_sequences.len(), methods, counts)
+        };
+        assert_eq!(dedup(AnalysisOptions::default()), (2, vec![0, 0, 1, 0, 0, 1], vec![4, 2]));
+        assert_eq!(dedup(AnalysisOptions::default().dedup_scope(DedupScope::Dex)), (4, vec![0, 0, 1, 2, 2, 3], vec![2, 1, 2, 1]));
+        assert_eq!(dedup(AnalysisOptions::default().dedup_key(DedupKey::Opcodes)), (1, vec![0; 6], vec![6]));
+
+        // Later inputs of the run only emit the bodies no earlier input had
+        let run = AnalysisOptions::default().dedup_methods(true).dedup_scope(DedupScope::Run).build();
+        let first = analyze_dexes(NamedDex::multidex([duplicated_dex()]), None, &run.clone());
+        let second = analyze_dexes(NamedDex::multidex([duplicated_dex()]), None, &run.clone());
+        let Sequences::Deduplicated { unique_sequences, methods, counts, first_sequence, .. } = first.sequences else { unreachable!() };
+        assert_eq!((unique_sequences.len(), methods, counts, first_sequence), (2, vec![0, 0, 1], vec![2, 1], Some(0)));
+        assert_eq!(second.sequences.method_count(), 3);
+        assert_eq!(second.sequences.opcode_count(), 0);
+        let json = serde_json::to_value(&second.sequences).unwrap();
+        assert_eq!(json, serde_json::json!({ "first_sequence": 2, "unique_sequences": [], "methods": [0, 0, 1], "counts": [] }));
+    }
+
+    #[test]
+    fn test_header_counts() {
+        let mut builder = DexBuilder::new();
+        builder.string("unused");
+        builder.method("Ljava/lang/Object;", "<init>", "V", &[]);
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .field(FieldDef::new("count", "I"))
+            .method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        builder.class(ClassDef::new("Lcom/example/Other;"));
+        let dex = builder.build();
+        let file_size = dex.len() as u32;
+        let report = analyze_dex(dex, &AnalysisOptions::default()).unwrap();
+        // unused, Ljava/lang/Object;, V, <init>, Lcom/example/Main;, I, count, run and Lcom/example/Other;
+        assert_eq!(report.header_counts, HeaderCounts { file_size, strings: 9, methods: 2, fields: 1, classes: 2 });
+    }
+
+    #[test]
+    fn test_sample_deduplicated() {
+        let sampling = Sampling { rate: 0.5, seed: 7 };
+        let methods: Vec<usize> = (0..64).map(|i| i % 4).collect();
+        let sequences = Sequences::deduplicated(vec![vec![0], vec![1], vec![2], vec![3]], methods.clone());
+        let input = sequences.identity();
+        let Sequences::Deduplicated { unique_sequences, methods: sampled, counts, .. } = sequences.sample(&sampling) else { unreachable!() };
+        assert_eq!(counts.iter().sum::<usize>(), sampled.len());
+        let expected: Vec<usize> = methods.into_iter().zip(sampling.select(64, input)).filter(|(_, keep)| *keep).map(|(i, _)| i).collect();
+        let resolved: Vec<usize> = sampled.iter().map(|&i| unique_sequences[i][0] as usize).collect();
+        assert_eq!(resolved, expected);
+    }
+
+    #[test]
+    fn test_analyze_dex_sampled() {
+        let options = lenient().sampling(Sampling { rate: 0.5, seed: 1 }).build();
+        let full = analyze_dex(sample_dex(10), &lenient()).unwrap();
+        let first = analyze_dex(sample_dex(10), &options).unwrap();
+        let second = analyze_dex(sample_dex(10), &options).unwrap();
+        assert_eq!(first.sequences, second.sequences);
+        let (Sequences::Flat { methods: all, .. }, Sequences::Flat { op_seq, methods, .. }) = (full.sequences, first.sequences) else { unreachable!() };
+        assert!(methods.len() < all.len());
+        assert_eq!(methods.last().unwrap().end() + 1, op_seq.len());
+    }
+
+    #[test]
+    fn test_sampling_depends_on_the_input() {
+        let sampling = Sampling { rate: 0.5, seed: 3 };
+        let first = Sequences::deduplicated(vec![vec![0x0E]], vec![0; 64]);
+        let second = Sequences::deduplicated(vec![vec![0x0F]], vec![0; 64]);
+        assert_ne!(first.identity(), second.identity());
+        assert_eq!(sampling.select(64, first.identity()), sampling.select(64, first.identity()));
+        assert_ne!(sampling.select(64, first.identity()), sampling.select(64, second.identity()));
+    }
+
+    #[test]
+    fn test_method_cap_truncates() {
+        let report = analyze_dex(sample_dex(4), &lenient().method_cap(5).build()).unwrap();
+        let Sequences::Flat { op_seq, methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!(methods.len(), 5);
+        assert_eq!(methods.last().unwrap().end() + 1, op_seq.len());
+        let report = analyze_dex(sample_dex(4), &lenient().method_cap(5).dedup_methods(true).build()).unwrap();
+        let Sequences::Deduplicated { methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!(methods.len(), 5);
+    }
+
+    #[test]
+    fn test_class_sequences() {
+        let class = |name, bodies: &[&[u16]]| bodies.iter().enumerate().fold(ClassDef::new(name), |class, (index, body)| {
+            class.method(MethodDef::new(["first", "second"][index], "V", &[]).code(CodeDef::new(1, 0, 0, body)))
+        });
+        let mut builder = DexBuilder::new();
+        // const/4 v0, 0; return-void, then nop; return-void
+        builder.class(class("Lcom/example/Main;", &[&[0x0012, 0x000E], &[0x0000, 0x000E]]));
+        builder.class(class("Lcom/example/Other;", &[&[0x000E]]));
+        let main = DexReader::from_vec(builder.build()).unwrap();
+        let mut builder = DexBuilder::new();
+        builder.class(class("Lcom/example/Other;", &[&[0x0012, 0x0012, 0x000E]]));
+        let second = DexReader::from_vec(builder.build()).unwrap();
+
+        // The second definition of Other is skipped, as the walk keeps the first definition of every class
+        let report = analyze_dexes(NamedDex::multidex([main, second]), None, &AnalysisOptions::default());
+        assert_eq!(report.sequences.class_sequences().unwrap(), [
+            ("Lcom/example/Main;", vec![0x12, 0x0E, 0x00, 0x0E]),
+            ("Lcom/example/Other;", vec![0x0E]),
+        ]);
+        let by_class = report.sequences.by_class().unwrap();
+        assert_eq!(by_class.iter().map(|(class, count, _)| (*class, *count)).collect::<Vec<_>>(), [("Lcom/example/Main;", 2), ("Lcom/example/Other;", 1)]);
+        assert!(Sequences::deduplicated(vec![vec![0x0E]], vec![0]).class_sequences().is_none());
+    }
+
+    #[test]
+    fn test_sequence_cap_mid_method() {
+        let mut builder = DexBuilder::new();
+        // const/4 v0, 0 three times; return-void, then the same body again
+        let body = [0x0012, 0x0012, 0x0012, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("first", "V", &[]).code(CodeDef::new(1, 0, 0, &body)))
+            .method(MethodDef::new("second", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000E])))
+            .method(MethodDef::new("third", "V", &[]).code(CodeDef::new(1, 0, 0, &body))));
+        let bytes = builder.build();
+
+        let report = analyze_dex(bytes.clone(), &AnalysisOptions::default().sequence_cap(5)).unwrap();
+        let Sequences::Flat { op_seq, methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!(op_seq.len(), 5);
+        assert_eq!(methods.iter().map(|method| (method.start(), method.end())).collect::<Vec<_>>(), [(0, 3), (4, 4)]);
+        let report = analyze_dex(bytes.clone(), &AnalysisOptions::default().sequence_cap(0)).unwrap();
+        let Sequences::Flat { op_seq, .. } = report.sequences else { unreachable!() };
+        assert_eq!(op_seq.len(), 10);
+
+        let report = analyze_dex(bytes, &AnalysisOptions::default().sequence_cap(5).dedup_methods(true)).unwrap();
+        let Sequences::Deduplicated { unique_sequences, methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!(unique_sequences.iter().map(Vec::len).collect::<Vec<_>>(), [4, 1]);
+        assert_eq!(methods, [0, 1]);
+    }
+
+    #[test]
+    fn test_cap_strategies() {
+        // The first dex has methods of 4 and 2 opcodes, the second two of 4 opcodes, 14 opcodes in all
+        let dex = |lengths: &[usize]| {
+            let mut builder = DexBuilder::new();
+            let mut class = ClassDef::new("Lcom/example/Main;");
+            for (i, &length) in lengths.iter().enumerate() {
+                let mut body = vec![0x0012; length - 1];
+                body.push(0x000E);
+                class = class.method(MethodDef::new(&format!("m{}", i), "V", &[]).code(CodeDef::new(1, 0, 0, &body)));
+            }
+            builder.class(class);
+            DexReader::from_vec(builder.build()).unwrap()
+        };
+        let bounds = |strategy: CapStrategy| {
+            let options = AnalysisOptions::default().sequence_cap(7).cap_strategy(strategy);
+            let report = analyze_dexes(NamedDex::multidex([dex(&[4, 2]), dex(&[4, 4])]), None, &options);
+            let Sequences::Flat { op_seq, methods, .. } = report.sequences else { unreachable!() };
+            assert_eq!(methods.last().unwrap().end() + 1, op_seq.len());
+            methods.iter().map(|method| (method.start(), method.end())).collect::<Vec<_>>()
+        };
+        // The first method of the second dex is cut to the 1 opcode left
+        assert_eq!(bounds(CapStrategy::TruncateExact), [(0, 3), (4, 5), (6, 6)]);
+        // It doesn't fit whole and is left out with the rest of the dex
+        assert_eq!(bounds(CapStrategy::TruncateMethods), [(0, 3), (4, 5)]);
+        // The second dex has a cap of its own and its second method is cut to 3 opcodes
+        assert_eq!(bounds(CapStrategy::PerDex), [(0, 3), (4, 5), (6, 9), (10, 12)]);
+    }
+
+    #[test]
+    fn test_empty_method_skipped() {
+        let mut builder = DexBuilder::new();
+        // Only an unused opcode, nothing decodes in lenient mode
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("garbage", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x003E])))
+            .method(MethodDef::new("fine", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let report = analyze_dex(builder.build(), &lenient()).unwrap();
+        let Sequences::Flat { op_seq, methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!(op_seq, [Opcode::ReturnVoid as u8]);
+        assert_eq!(methods.iter().map(|method| (method.start(), method.end())).collect::<Vec<_>>(), [(0, 0)]);
+        assert_eq!(report.coverage.methods, 2);
+    }
+
+    #[test]
+    fn test_class_filter_excludes() {
+        let filter = ClassFilter::default().exclude("Lorg/example/Sample1;");
+        let report = analyze_dex(sample_dex(3), &lenient().class_filter(filter).build()).unwrap();
+        let Sequences::Flat { methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!(methods.len(), 2 * SAMPLE_METHODS.len());
+    }
+
+    #[test]
+    fn test_exclude_synthetic() {
+        let mut builder = DexBuilder::new();
+        builder.type_idx("Lkotlin/Metadata;");
+        // return-void
+        builder.class(ClassDef::new("Lcom/example/Main;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        builder.class(ClassDef::new("Lcom/example/-$$Lambda$Main$1;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let bytes = builder.build();
+
+        let report = analyze_dex(bytes.clone(), &AnalysisOptions::default().kotlin(true)).unwrap();
+        assert_eq!(report.sequences.method_count(), 2);
+        let kotlin = serde_json::to_value(report.kotlin).unwrap();
+        assert_eq!(kotlin["detected"], true);
+        assert_eq!(kotlin["synthetic_class_ratio"], 0.5);
+        let report = analyze_dex(bytes, &AnalysisOptions::default().exclude_synthetic(true)).unwrap();
+        let Sequences::Flat { methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!(methods.iter().map(|method| method.class()).collect::<Vec<_>>(), ["Lcom/example/Main;"]);
+        assert!(report.kotlin.is_none());
+    }
+
+    #[test]
+    fn test_corrupt_method_warning() {
+        let mut builder = DexBuilder::new();
+        // const/4 v0, 0; unused opcode 0x3e; return-void
+        builder.class(ClassDef::new("Lcom/example/Corrupt;")
+            .method(MethodDef::new("broken", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x003E, 0x000E])))
+            .method(MethodDef::new("fine", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let bytes = builder.build();
+
+        for dedup_methods in [false, true] {
+            let report = analyze_dex(bytes.clone(), &AnalysisOptions::default().dedup_methods(dedup_methods)).unwrap();
+            assert_eq!(report.warnings, vec![Warning::new(WarningKind::InvalidInstruction, "Invalid instruction at offset 1: 62")
+                .class("Lcom/example/Corrupt;")
+                .method("broken")
+                .offset(1)]);
+        }
+        let report = analyze_dex(bytes, &lenient()).unwrap();
+        let Sequences::Flat { methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!((methods.len(), report.warnings.len()), (2, 1));
+    }
+
+    #[test]
+    fn test_coverage_one_bad_method() {
+        let mut builder = DexBuilder::new();
+        // const/4 v0, 0; unused opcode 0x3e; return-void
+        builder.class(ClassDef::new("Lcom/example/Corrupt;")
+            .method(MethodDef::new("broken", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x003E, 0x000E])))
+            .method(MethodDef::new("fine", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000E]))));
+        let bytes = builder.build();
+
+        for dedup_methods in [false, true] {
+            let coverage = analyze_dex(bytes.clone(), &AnalysisOptions::default().dedup_methods(dedup_methods)).unwrap().coverage;
+            assert_eq!((coverage.methods, coverage.decoded_methods, coverage.skipped_methods), (2, 1, 1));
+            assert_eq!((coverage.methods_pct(), coverage.code_units_pct()), (50.0, 40.0));
+
+            let coverage = analyze_dex(bytes.clone(), &lenient().dedup_methods(dedup_methods)).unwrap().coverage;
+            assert_eq!((coverage.decoded_methods, coverage.partial_methods), (1, 1));
+            assert_eq!((coverage.methods_pct(), coverage.code_units_pct()), (100.0, 80.0));
+        }
+    }
+
+    #[test]
+    fn test_all_classes_invalid() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/First;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        builder.class(ClassDef::new("Lcom/example/Second;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let mut bytes = builder.build();
+        // Point the class index and the class data of both class_defs past the end of the file
+        let class_defs_off = u32::from_le_bytes(bytes[0x64..0x68].try_into().unwrap()) as usize;
+        for class_def in bytes[class_defs_off..class_defs_off + 64].chunks_exact_mut(32) {
+            class_def[0..4].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+            class_def[24..28].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        }
+
+        for dedup_methods in [false, true] {
+            let report = analyze_dex(bytes.clone(), &lenient().dedup_methods(dedup_methods)).unwrap();
+            let kinds = report.warnings.iter().map(|warning| warning.kind).collect::<Vec<_>>();
+            assert_eq!(kinds, vec![WarningKind::InvalidClass, WarningKind::InvalidClass, WarningKind::NoValidClasses]);
+            assert_eq!(report.warnings[2].message, "None of the 2 class definitions of the dex could be parsed");
+        }
+        let report = analyze_dex(sample_dex(1), &lenient()).unwrap();
+        assert!(report.warnings.iter().all(|warning| warning.kind != WarningKind::NoValidClasses));
+    }
+
+    #[test]
+    fn test_strict_classes() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/First;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        builder.class(ClassDef::new("Lcom/example/Second;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let mut bytes = builder.build();
+        // Point the class index and the class data of the first class_def past the end of the file
+        let class_defs_off = u32::from_le_bytes(bytes[0x64..0x68].try_into().unwrap()) as usize;
+        bytes[class_defs_off..class_defs_off + 4].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        bytes[class_defs_off + 24..class_defs_off + 28].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+
+        let report = analyze_dex(bytes.clone(), &lenient()).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].kind, WarningKind::InvalidClass);
+        assert!(report.warnings[0].message.starts_with("Class definition 0: "));
+        assert_eq!(report.warnings[0].class_index, Some(0));
+        assert_eq!(serde_json::to_value(&report.warnings[0]).unwrap()["class_index"], 0);
+        assert_eq!(report.sequences.opcode_count(), 1);
+
+        let err = analyze_dex(bytes, &lenient().strict_classes(true)).unwrap_err();
+        assert!(matches!(err, Error::InvalidClass { ref dex, index: 0, .. } if dex == "classes.dex"));
+        assert!(analyze_dex(sample_dex(1), &lenient().strict_classes(true)).is_ok());
+    }
+
+    #[test]
+    fn test_codeless_methods() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Native;")
+            .method(MethodDef::new("decrypt", "[B", &["Ljava/lang/String;", "I"]).access_flags(ACC_PUBLIC | ACC_STATIC | ACC_NATIVE))
+            .method(MethodDef::new("run", "V", &[]).access_flags(ACC_PUBLIC | ACC_ABSTRACT))
+            .method(MethodDef::new("fine", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let report = analyze_dex(builder.build(), &AnalysisOptions::default()).unwrap();
+        assert_eq!(report.codeless_methods, vec![
+            CodelessMethod {
+                kind: CodelessKind::Native,
+                class: "Lcom/example/Native;".to_string(),
+                name: "decrypt".to_string(),
+                descriptor: "(Ljava/lang/String;I)[B".to_string(),
+                flags: MethodFlags { native: true, ..MethodFlags::default() },
+            },
+            CodelessMethod {
+                kind: CodelessKind::Abstract,
+                class: "Lcom/example/Native;".to_string(),
+                name: "run".to_string(),
+                descriptor: "()V".to_string(),
+                flags: MethodFlags { r#abstract: true, ..MethodFlags::default() },
+            },
+        ]);
+        let Sequences::Flat { methods, .. } = report.sequences else { unreachable!() };
+        assert_eq!(methods.len(), 1);
+        let json = serde_json::to_value(&report.codeless_methods[0]).unwrap();
+        assert_eq!(json["kind"], "native");
+    }
+
+    #[test]
+    fn test_method_bigrams() {
+        let mut builder = DexBuilder::new();
+        // const/4 v0, 0; const/4 v0, 0; return-void
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("first", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x0012, 0x000E])))
+            // return-void
+            .method(MethodDef::new("second", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let report = analyze_dex(builder.build(), &AnalysisOptions::default()).unwrap();
+        let (const4, return_void) = (Opcode::Const4 as u8, Opcode::ReturnVoid as u8);
+        assert_eq!(report.sequences.method_bigrams(), vec![
+            (0, BigramCounts::from([((const4, const4), 1), ((const4, return_void), 1)])),
+            // No (return-void, return-void) pair across the method boundary
+            (1, BigramCounts::new()),
+        ]);
+    }
+
+    #[test]
+    fn test_mnemonics() {
+        // Lorg/fdroid/fdroid/views/main/MainActivity;onStart
+        let raw_bytecode = [4207, 743, 2, 96, 57, 275, 33, 4148, 15, 26, 21033, 8305, 855, 2, 266, 312, 7, 8532, 22998, 8302, 714, 1, 14];
+        let mut deduplicator = MethodDeduplicator::new(Strictness::Strict);
+        deduplicator.add(&raw_bytecode).unwrap();
+        let (unique_sequences, methods) = deduplicator.into_parts();
+        let sequences = Sequences::flat(unique_sequences[0].clone(), vec![]);
+        assert_eq!(serde_json::to_value(&sequences).unwrap()["op_seq"][0], Opcode::InvokeSuper as u8);
+        assert_eq!(serde_json::to_value(FormattedSequences { sequences: &sequences, mnemonics: false }).unwrap(), serde_json::to_value(&sequences).unwrap());
+        let mnemonics = [
+            "invoke-super", "sget", "const/16", "if-lt", "const-string", "invoke-static",
+            "move-result", "if-eqz", "iget-object", "invoke-virtual", "return-void",
+        ];
+        assert_eq!(serde_json::to_value(FormattedSequences { sequences, mnemonics: true }).unwrap()["op_seq"], serde_json::json!(mnemonics));
+        let sequences = Sequences::deduplicated(unique_sequences, methods);
+        assert_eq!(serde_json::to_value(FormattedSequences { sequences, mnemonics: true }).unwrap()["unique_sequences"], serde_json::json!([mnemonics]));
+    }
+
+    #[test]
+    fn test_method_bigrams_deduplicated() {
+        let sequences = Sequences::deduplicated(vec![vec![1, 2, 1, 2], vec![3]], vec![1, 0, 1]);
+        let bigrams = sequences.method_bigrams();
+        assert_eq!(bigrams.len(), 3);
+        assert_eq!(bigrams[1], (1, BigramCounts::from([((1, 2), 2), ((2, 1), 1)])));
+        assert!(bigrams[0].1.is_empty() && bigrams[2].1.is_empty());
+    }
+
+    #[test]
+    fn test_opcode_histogram() {
+        let sequences = Sequences::deduplicated(vec![vec![1, 2, 1], vec![3]], vec![0, 1, 0]);
+        let histogram = sequences.opcode_histogram();
+        assert_eq!((histogram[1], histogram[2], histogram[3], histogram[4]), (4, 2, 1, 0));
+        assert_eq!(sequences.method_count(), 3);
+        let histogram = Sequences::flat(vec![0x0E, 0x0E, 0x12], vec![]).opcode_histogram();
+        assert_eq!((histogram[0x0E], histogram[0x12]), (2, 1));
+    }
+
+    #[test]
+    fn test_analyze_dex_invalid() {
+        let err = analyze_dex(b"not a dex".to_vec(), &AnalysisOptions::default()).err().unwrap();
+        assert!(matches!(err, Error::Dex(_)));
+        assert!(std::error::Error::source(&err).unwrap().downcast_ref::<dex::Error>().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_analyze_apk_missing() {
+        assert!(matches!(analyze_apk("/nonexistent/app.apk", &AnalysisOptions::default()), Err(Error::Io(_))));
+    }
+
+    fn zip_with(entries: &[&str]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for name in entries {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(b"contents").unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Inserts an APK Signing Block with a v2 block of `signers` signers before the central directory
+    fn with_signing_block(apk: Vec<u8>, signers: usize) -> Vec<u8> {
+        let eocd = apk.len() - 22;
+        let central_directory_start = u32::from_le_bytes(apk[eocd + 16..eocd + 20].try_into().unwrap()) as usize;
+
+        let mut signer_seq = vec![];
+        for _ in 0..signers {
+            signer_seq.extend(4u32.to_le_bytes());
+            signer_seq.extend(b"sign");
+        }
+        let mut value = 0x7109871Au32.to_le_bytes().to_vec();
+        value.extend((signer_seq.len() as u32).to_le_bytes());
+        value.extend(signer_seq);
+        let mut pairs = (value.len() as u64).to_le_bytes().to_vec();
+        pairs.extend(value);
+        let block_size = (pairs.len() + 24) as u64;
+        let mut block = block_size.to_le_bytes().to_vec();
+        block.extend(pairs);
+        block.extend(block_size.to_le_bytes());
+        block.extend(b"APK Sig Block 42");
+
+        let mut signed = apk[..central_directory_start].to_vec();
+        signed.extend(&block);
+        signed.extend(&apk[central_directory_start..]);
+        let eocd = signed.len() - 22;
+        signed[eocd + 16..eocd + 20].copy_from_slice(&((central_directory_start + block.len()) as u32).to_le_bytes());
+        signed
+    }
+
+    #[test]
+    fn test_parse_apk_unsigned() {
+        let contents = parse_apk_from(Cursor::new(zip_with(&["META-INF/MANIFEST.MF", "res/raw/key.rsa"]))).unwrap();
+        assert!(!contents.signatures.signed);
+        assert_eq!(contents.signatures.signer_count, 0);
+        assert!(contents.signatures.schemes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_apk_v1_signed() {
+        let contents = parse_apk_from(Cursor::new(zip_with(&["META-INF/MANIFEST.MF", "META-INF/CERT.SF", "META-INF/CERT.RSA"]))).unwrap();
+        assert!(contents.signatures.signed);
+        assert_eq!(contents.signatures.signer_count, 1);
+        assert_eq!(contents.signatures.schemes, vec![SigningScheme::V1]);
+        assert_eq!(contents.signatures.v1_files, vec!["META-INF/CERT.RSA"]);
+    }
+
+    #[test]
+    fn test_parse_apk_signing_block() {
+        let apk = with_signing_block(zip_with(&["META-INF/CERT.RSA", "classes.txt"]), 2);
+        let contents = parse_apk_from(Cursor::new(apk)).unwrap();
+        assert!(contents.signatures.signed);
+        assert_eq!(contents.signatures.signer_count, 2);
+        assert_eq!(contents.signatures.schemes, vec![SigningScheme::V1, SigningScheme::V2]);
+    }
+
+    #[test]
+    fn test_read_manifest_skips_dexes() {
+        // The dex entry isn't a dex at all, reading the manifest never looks at it
+        assert_eq!(read_manifest_from(Cursor::new(zip_with(&["classes.dex", "res/layout/main.xml"]))).unwrap(), None);
+        assert!(matches!(read_manifest_from(Cursor::new(zip_with(&["AndroidManifest.xml", "classes.dex"]))), Err(Error::Manifest(_))));
+        // A bare dex is not an archive
+        assert!(matches!(read_manifest_from(Cursor::new(sample_dex(1))), Err(Error::Zip(_))));
+    }
+
+    #[test]
+    fn test_read_permissions_skips_dexes() {
+        // Neither entry is what it claims to be: only the manifest is parsed, the dex is never decoded
+        assert_eq!(read_permissions_from(Cursor::new(zip_with(&["classes.dex", "classes2.dex"]))).unwrap(), None);
+        assert!(matches!(read_permissions_from(Cursor::new(zip_with(&["AndroidManifest.xml", "classes.dex"]))), Err(Error::Manifest(_))));
+        assert!(matches!(read_permissions_from(Cursor::new(sample_dex(1))), Err(Error::Zip(_))));
+    }
+
+    #[test]
+    fn test_dex_names_two_dexes() {
+        let dex = |class: &str| {
+            let mut builder = DexBuilder::new();
+            builder.class(ClassDef::new(class).method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+            builder.build()
+        };
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, class) in [("classes.dex", "Lcom/example/Main;"), ("classes2.dex", "Lcom/example/Injected;")] {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(&dex(class)).unwrap();
+        }
+        let contents = parse_apk_from(Cursor::new(writer.finish().unwrap().into_inner())).unwrap();
+        assert_eq!(contents.dexes.iter().map(|dex| dex.name.as_str()).collect::<Vec<_>>(), ["classes.dex", "classes2.dex"]);
+
+        let report = analyze_dexes(contents.dexes, None, &AnalysisOptions::default().call_graph_metrics(true));
+        let Sequences::Flat { methods, .. } = &report.sequences else { unreachable!() };
+        let attributed = methods.iter().map(|method| (method.class(), method.dex_name())).collect::<Vec<_>>();
+        assert_eq!(attributed, [("Lcom/example/Main;", "classes.dex"), ("Lcom/example/Injected;", "classes2.dex")]);
+        assert_eq!(report.dexes, [
+            DexClasses { dex_name: "classes.dex".to_string(), classes: 1, sections: None, anomalies: None },
+            DexClasses { dex_name: "classes2.dex".to_string(), classes: 1, sections: None, anomalies: None },
+        ]);
+        let metrics = report.metrics.unwrap();
+        assert_eq!(metrics.iter().map(|metrics| metrics.dex_name.as_str()).collect::<Vec<_>>(), ["classes.dex", "classes2.dex"]);
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_analyze_apk_bytes_matches_file() {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        writer.start_file("classes.dex", FileOptions::default()).unwrap();
+        writer.write_all(&sample_dex(2)).unwrap();
+        let apk = writer.finish().unwrap().into_inner();
+        let path = std::env::temp_dir().join(format!("dexompiler-apk-bytes-{}.apk", std::process::id()));
+        std::fs::write(&path, &apk).unwrap();
+        let options = AnalysisOptions::default().strictness(Strictness::Lenient).string_pool(true);
+        let from_file = analyze_apk(&path, &options);
+        std::fs::remove_file(&path).unwrap();
+        let from_bytes = analyze_apk_bytes(&apk, &options).unwrap();
+        assert_eq!(serde_json::to_value(from_bytes).unwrap(), serde_json::to_value(from_file.unwrap()).unwrap());
+        assert!(matches!(analyze_apk_bytes(b"not an archive", &options), Err(Error::Zip(_))));
+    }
+
+    #[test]
+    fn test_multidex_apk_decodes_like_the_dexes() {
+        let mut second = DexBuilder::new();
+        // The first dex defines Sample too, this definition is skipped either way
+        second.class(ClassDef::new("Lorg/example/Sample0;").method(MethodDef::new("first", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        second.class(ClassDef::new("Lcom/example/Helper;")
+            .method(MethodDef::new("help", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x0012, 0x000E]))));
+        let dexes = [sample_dex(2), second.build()];
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, dex) in ["classes.dex", "classes2.dex"].iter().zip(&dexes) {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(dex).unwrap();
+        }
+        let apk = writer.finish().unwrap().into_inner();
+        // Decoded in parallel from the APK unless a cap is shared by the dexes
+        for options in [lenient(), lenient().sequence_cap(8).cap_strategy(CapStrategy::PerDex), lenient().method_cap(3)] {
+            let from_apk = analyze_apk_bytes(&apk, &options).unwrap();
+            let from_dexes = analyze_dexes(NamedDex::multidex(dexes.clone().map(|dex| DexReader::from_vec(dex).unwrap())), None, &options);
+            assert_eq!(from_apk.sequences, from_dexes.sequences);
+            assert_eq!(from_apk.coverage, from_dexes.coverage);
+        }
+    }
+
+    #[test]
+    fn test_appended_data_in_apk_entry() {
+        let apk = |dex: &[u8]| {
+            let mut writer = ZipWriter::new(Cursor::new(vec![]));
+            writer.start_file("classes.dex", FileOptions::default()).unwrap();
+            writer.write_all(dex).unwrap();
+            writer.finish().unwrap().into_inner()
+        };
+        let options = lenient().sections(true);
+        let dex = sample_dex(1);
+        let report = analyze_apk_bytes(&apk(&dex), &options).unwrap();
+        assert_eq!(report.dexes[0].anomalies, Some(vec![]));
+        assert!(report.dexes[0].sections.as_ref().is_some_and(|sections| sections.iter().any(|section| section.kind == "code_item")));
+
+        let mut padded = dex.clone();
+        padded.extend([0xAB; 32]);
+        let report = analyze_apk_bytes(&apk(&padded), &options).unwrap();
+        assert_eq!(report.dexes[0].anomalies, Some(vec![SectionAnomaly::AppendedData { offset: dex.len() as u32, size: 32 }]));
+    }
+}
@@ -0,0 +1,98 @@
+use dex::Dex;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    extension::{for_each_method, MethodAnalysis, MethodContext},
+    options::AnalysisOptions,
+};
+
+
+/// Descriptor prefixes of the classes whose methods make up API sequences
+pub const FRAMEWORK_PREFIXES: &[&str] = &["Landroid/", "Ljava/", "Ljavax/", "Lkotlin/"];
+
+
+/// Framework APIs invoked by a method
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiSequence {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub name: String,
+    /// Invoked framework methods in bytecode order, e.g. `Landroid/telephony/TelephonyManager;->getDeviceId`
+    pub calls: Vec<String>,
+}
+
+
+/// API sequence of every method of the selected classes of `dex` invoking at least one framework method
+pub fn api_sequences<T: AsRef<[u8]>>(dex_index: usize, dex: &Dex<T>, options: &AnalysisOptions) -> Vec<ApiSequence> {
+    let mut sequences = vec![];
+    for_each_method(dex_index, dex, options, |ctx| {
+        let calls = ApiCalls::calls(ctx);
+        if !calls.is_empty() {
+            sequences.push(ApiSequence { dex: dex_index, class: ctx.class().to_string(), name: ctx.name().to_string(), calls });
+        }
+    });
+    sequences
+}
+
+
+/// The API sequences as a method analysis, reported under `extensions.api_calls`
+pub struct ApiCalls;
+
+
+impl ApiCalls {
+    /// Invoked framework methods in bytecode order, e.g. `Landroid/telephony/TelephonyManager;->getDeviceId`
+    pub fn calls(ctx: &MethodContext) -> Vec<String> {
+        ctx.instructions().iter()
+            .filter(|inst| inst.opcode().is_invoke())
+            .filter_map(|inst| ctx.dex().resolve_method((*inst.reference())?))
+            .filter(|method| FRAMEWORK_PREFIXES.iter().any(|prefix| method.class.starts_with(prefix)))
+            .map(|method| format!("{}->{}", method.class, method.name))
+            .collect()
+    }
+}
+
+
+impl MethodAnalysis for ApiCalls {
+    fn name(&self) -> &str {
+        "api_calls"
+    }
+
+    fn run(&self, ctx: &MethodContext) -> Value {
+        let calls = Self::calls(ctx);
+        if calls.is_empty() { Value::Null } else { Value::from(calls) }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{DexBuilder, ClassDef, MethodDef, CodeDef};
+    use super::*;
+
+    #[test]
+    fn test_api_sequences() {
+        let mut builder = DexBuilder::new();
+        let log = builder.method("Landroid/util/Log;", "d", "I", &["Ljava/lang/String;", "Ljava/lang/String;"]) as u16;
+        let length = builder.method("Ljava/lang/String;", "length", "I", &[]) as u16;
+        let helper = builder.method("Lcom/example/Main;", "helper", "V", &[]) as u16;
+        // invoke-static Log.d; invoke-static helper; invoke-virtual String.length; invoke-static helper; return-void
+        let body = [0x2071, log, 0x0010, 0x0071, helper, 0x0000, 0x106E, length, 0x0000, 0x0071, helper, 0x0000, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("run", "V", &[]).code(CodeDef::new(2, 0, 2, &body)))
+            .method(MethodDef::new("helper", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0071, helper, 0x0000, 0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let sequences = api_sequences(0, &dex, &AnalysisOptions::default());
+        // helper only calls a local method and has no sequence
+        assert_eq!(sequences, [ApiSequence {
+            dex: 0,
+            class: "Lcom/example/Main;".to_string(),
+            name: "run".to_string(),
+            calls: vec!["Landroid/util/Log;->d".to_string(), "Ljava/lang/String;->length".to_string()],
+        }]);
+    }
+}
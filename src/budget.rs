@@ -0,0 +1,103 @@
+use std::sync::{Condvar, Mutex, PoisonError};
+
+
+/// Limits the total size of the inputs held in memory at the same time.
+/// Small inputs proceed in parallel while large ones wait until enough of the budget is released
+pub struct ByteBudget {
+    capacity: u64,
+    in_flight: Mutex<u64>,
+    released: Condvar,
+}
+
+
+/// Share of a `ByteBudget` held while an input is processed, released on drop
+pub struct BudgetPermit<'a> {
+    budget: &'a ByteBudget,
+    bytes: u64,
+}
+
+
+impl ByteBudget {
+    pub fn new(capacity: u64) -> Self {
+        Self { capacity, in_flight: Mutex::new(0), released: Condvar::new() }
+    }
+
+    /// Blocks until `bytes` fit in the budget.
+    /// Inputs larger than the whole budget are admitted once nothing else is in flight
+    pub fn acquire(&self, bytes: u64) -> BudgetPermit<'_> {
+        let bytes = bytes.min(self.capacity);
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(PoisonError::into_inner);
+        while *in_flight + bytes > self.capacity {
+            in_flight = self.released.wait(in_flight).unwrap_or_else(PoisonError::into_inner);
+        }
+        *in_flight += bytes;
+        BudgetPermit { budget: self, bytes }
+    }
+
+    /// Number of bytes currently held by permits
+    pub fn in_flight(&self) -> u64 {
+        *self.in_flight.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+
+impl Drop for BudgetPermit<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.budget.in_flight.lock().unwrap_or_else(PoisonError::into_inner);
+        *in_flight -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{atomic::{AtomicU64, Ordering}, mpsc}, thread, time::Duration};
+
+    use super::ByteBudget;
+
+    #[test]
+    fn test_large_input_waits_for_release() {
+        let budget = ByteBudget::new(10);
+        let first = budget.acquire(8);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _second = budget.acquire(5);
+                sender.send(budget.in_flight()).unwrap();
+            });
+            assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+            assert_eq!(budget.in_flight(), 8);
+            drop(first);
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), 5);
+        });
+        assert_eq!(budget.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_small_inputs_stay_within_budget() {
+        let budget = ByteBudget::new(10);
+        let peak = AtomicU64::new(0);
+        thread::scope(|scope| {
+            for size in [3, 4, 2, 5, 1, 3, 4, 2] {
+                let (budget, peak) = (&budget, &peak);
+                scope.spawn(move || {
+                    let _permit = budget.acquire(size);
+                    peak.fetch_max(budget.in_flight(), Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                });
+            }
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 10);
+        assert_eq!(budget.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_oversized_input_is_admitted_alone() {
+        let budget = ByteBudget::new(10);
+        let permit = budget.acquire(100);
+        assert_eq!(budget.in_flight(), 10);
+        drop(permit);
+        assert_eq!(budget.in_flight(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{fmt, fs, io, path::PathBuf, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+
+use dexompiler::{AnalysisOptions, DexCache, DexWalk};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{progress::eprintln_above, stats::RunStats};
+
+
+/// Entry of the cache, the walk of a dex along with the settings it was walked with
+#[derive(Serialize, Deserialize)]
+struct Entry<S, W> {
+    settings: S,
+    walk: W,
+}
+
+
+/// Walks of the dexes of earlier inputs and runs for `--cache-dir`, one bincode file per dex named after its
+/// `DexWalk::key`. Entries walked with other options or by another version are misses, and overwritten
+pub struct Cache {
+    dir: PathBuf,
+    /// SHA-256 of the version and of the walk fingerprint of the options
+    settings: String,
+    /// Suffix of the temporary files, unique across the workers of a run
+    writes: AtomicU64,
+    /// Counts the lookups
+    stats: Arc<RunStats>,
+}
+
+
+impl Cache {
+    /// Opens the cache in `dir`, created if missing
+    pub fn open(dir: &str, options: &AnalysisOptions, stats: Arc<RunStats>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut settings = Sha256::new();
+        settings.update(env!("CARGO_PKG_VERSION"));
+        settings.update([0]);
+        settings.update(options.walk_fingerprint());
+        Ok(Self { dir: PathBuf::from(dir), settings: format!("{:x}", settings.finalize()), writes: AtomicU64::new(0), stats })
+    }
+
+    /// Walk stored under `key` with the same settings, `None` when missing, stale or unreadable
+    fn read(&self, key: &str) -> Option<DexWalk> {
+        let entry: Entry<String, DexWalk> = bincode::deserialize(&fs::read(self.path(key)).ok()?).ok()?;
+        (entry.settings == self.settings).then_some(entry.walk)
+    }
+
+    /// Stores `walk` under `key`. It is written to a temporary file first, so that readers never see half an entry
+    fn write(&self, key: &str, walk: &DexWalk) -> io::Result<()> {
+        let entry = bincode::serialize(&Entry { settings: &self.settings, walk }).map_err(io::Error::other)?;
+        let temporary = self.dir.join(format!("{}.{}-{}.tmp", key, std::process::id(), self.writes.fetch_add(1, Ordering::Relaxed)));
+        fs::write(&temporary, entry)?;
+        fs::rename(&temporary, self.path(key))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.bin", key))
+    }
+}
+
+
+impl DexCache for Cache {
+    fn get(&self, key: &str) -> Option<DexWalk> {
+        let walk = self.read(key);
+        self.stats.cache_lookup(walk.is_some());
+        walk
+    }
+
+    fn put(&self, key: &str, walk: &DexWalk) {
+        if let Err(err) = self.write(key, walk) {
+            eprintln_above!("Error caching the dex {}: {}", key, err);
+        }
+    }
+}
+
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache").field("dir", &self.dir).field("settings", &self.settings).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use dexompiler::{process_dexes_parallel, testing::sample_dex, Strictness, DEX_BUFFER};
+
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dexompiler-cache-{}-{}", std::process::id(), name))
+    }
+
+    /// Opcodes of the methods of the sample dex, walked through `cache`
+    fn walk(cache: Cache, options: &AnalysisOptions) -> Vec<Vec<u8>> {
+        let dexes = vec![("classes.dex".to_string(), sample_dex(1).into())];
+        let mut opcodes = vec![];
+        process_dexes_parallel(&dexes, &options.clone().dex_cache(cache), DEX_BUFFER, |method| opcodes.push(method.opcodes)).unwrap();
+        opcodes
+    }
+
+    #[test]
+    fn test_get_and_put() {
+        let dir = scratch("entries");
+        let options = AnalysisOptions::default().strictness(Strictness::Lenient);
+        let stats = Arc::new(RunStats::new());
+        let open = |options: &AnalysisOptions| Cache::open(dir.to_str().unwrap(), options, stats.clone()).unwrap();
+        let decoded = walk(open(&options), &options);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(walk(open(&options), &options), decoded);
+        assert_eq!((stats.summary().cache_hits, stats.summary().cache_misses), (Some(1), Some(1)));
+
+        // The same entry is stale under other walk settings, and overwritten
+        let capped = options.clone().sequence_cap(1).cap_strategy(dexompiler::CapStrategy::PerDex);
+        assert_eq!(walk(open(&capped), &capped), [decoded[0][..1].to_vec()]);
+        assert_eq!(walk(open(&capped), &capped), [decoded[0][..1].to_vec()]);
+        assert_eq!((stats.summary().cache_hits, stats.summary().cache_misses), (Some(2), Some(2)));
+        assert_eq!(walk(open(&options), &options), decoded);
+        assert_eq!((stats.summary().cache_hits, stats.summary().cache_misses), (Some(2), Some(3)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
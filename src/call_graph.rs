@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::{dex_parsing::{decode_method_lenient, unreachable_instructions, NamedDex}, reference::{resolve_method, MethodRef}};
+
+
+/// Callbacks invoked by the Android framework or the runtime, which have no callers in the dex by design
+pub const LIFECYCLE_CALLBACKS: &[&str] = &[
+    "<clinit>", "onCreate", "onStart", "onRestart", "onResume", "onPause", "onStop", "onDestroy",
+    "onReceive", "onBind", "onUnbind", "onRebind", "onStartCommand", "onHandleIntent",
+    "onActivityResult", "onNewIntent", "onSaveInstanceState", "onRestoreInstanceState",
+    "onCreateView", "onViewCreated", "onAttach", "onDetach", "attachBaseContext",
+    "query", "insert", "update", "delete", "getType",
+];
+
+/// Minimum number of distinct callers for a method to be reported as a hub
+pub const HUB_MIN_IN_DEGREE: usize = 3;
+
+/// Maximum number of hubs reported per dex
+pub const MAX_HUBS: usize = 16;
+
+
+/// Calls between the methods defined in a dex.
+/// Out-degrees count every distinct callee, in-degrees the distinct callers defined in the dex
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    /// Name of the dex the graph was built from, e.g. `classes2.dex`
+    dex_name: String,
+    methods: BTreeMap<u32, MethodRef>,
+    callees: HashMap<u32, HashSet<u32>>,
+    callers: HashMap<u32, HashSet<u32>>,
+    /// Number of invoke instructions targeting every method, repeated calls included
+    call_sites: HashMap<u32, usize>,
+    /// Number of instructions no control flow reaches in every method with some
+    unreachable: BTreeMap<u32, usize>,
+}
+
+
+/// Distribution of the degrees of the methods defined in a dex
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DegreeStats {
+    pub max: usize,
+    pub mean: f64,
+    /// Number of methods for every degree
+    pub histogram: BTreeMap<usize, usize>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Hub {
+    #[serde(flatten)]
+    pub method: MethodRef,
+    pub in_degree: usize,
+}
+
+
+/// Method with instructions that no path from its entry or its catch handlers reaches
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnreachableCode {
+    #[serde(flatten)]
+    pub method: MethodRef,
+    pub instructions: usize,
+}
+
+
+/// Structural metrics of the call graph of one dex
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CallGraphMetrics {
+    /// Name of the dex defining every method of the graph
+    pub dex_name: String,
+    pub methods: usize,
+    pub calls: usize,
+    pub in_degree: DegreeStats,
+    pub out_degree: DegreeStats,
+    /// Methods with the most callers, in decreasing order of in-degree
+    pub hubs: Vec<Hub>,
+    /// Methods without callers, lifecycle callbacks excluded
+    pub entry_points: Vec<MethodRef>,
+    /// Methods with unreachable instructions, methods reachable throughout are left out
+    pub unreachable_code: Vec<UnreachableCode>,
+}
+
+
+impl CallGraph {
+    /// Builds the graph from the invoke instructions of every method with code, and counts their unreachable instructions
+    pub fn from_dex<T: AsRef<[u8]>>(dex: &NamedDex<T>) -> Self {
+        let mut graph = Self { dex_name: dex.name.clone(), ..Self::default() };
+        let dex = &dex.dex;
+        for class in dex.classes().flatten() {
+            for method in class.methods() {
+                let caller = method.id() as u32;
+                if let Some(method_ref) = resolve_method(dex, caller) {
+                    graph.add_method(caller, method_ref);
+                }
+                let Some(code) = method.code() else { continue };
+                for inst in decode_method_lenient(code.insns()).instructions {
+                    if let (Some(callee), true) = (*inst.reference(), inst.opcode().is_invoke()) {
+                        graph.add_call(caller, callee);
+                    }
+                }
+                let handlers = code.tries().iter().flat_map(|try_block| try_block.catch_handlers().iter().map(|handler| handler.addr() as usize));
+                graph.add_unreachable(caller, unreachable_instructions(code.insns(), handlers, 0).map_or(0, |(unreachable, _)| unreachable));
+            }
+        }
+        graph
+    }
+
+    /// Registers a method defined in the dex
+    pub fn add_method(&mut self, idx: u32, method: MethodRef) {
+        self.methods.insert(idx, method);
+    }
+
+    /// Registers a call, recursion is not counted and repeated calls only count as call sites
+    pub fn add_call(&mut self, caller: u32, callee: u32) {
+        if caller == callee {
+            return;
+        }
+        *self.call_sites.entry(callee).or_default() += 1;
+        self.callees.entry(caller).or_default().insert(callee);
+        self.callers.entry(callee).or_default().insert(caller);
+    }
+
+    /// Registers the number of unreachable instructions of a method, methods without any are not kept
+    pub fn add_unreachable(&mut self, idx: u32, instructions: usize) {
+        if instructions > 0 {
+            self.unreachable.insert(idx, instructions);
+        }
+    }
+
+    pub fn out_degree(&self, idx: u32) -> usize {
+        self.callees.get(&idx).map_or(0, HashSet::len)
+    }
+
+    pub fn in_degree(&self, idx: u32) -> usize {
+        self.callers.get(&idx).map_or(0, |callers| callers.iter().filter(|caller| self.methods.contains_key(caller)).count())
+    }
+
+    /// Number of invoke instructions targeting the method, outside of the method itself
+    pub fn call_sites(&self, idx: u32) -> usize {
+        self.call_sites.get(&idx).copied().unwrap_or(0)
+    }
+
+    /// Number of distinct classes defining a caller of the method
+    pub fn caller_classes(&self, idx: u32) -> usize {
+        self.callers.get(&idx).map_or(0, |callers| {
+            callers.iter().filter_map(|caller| self.methods.get(caller)).map(|method| &method.class).collect::<HashSet<_>>().len()
+        })
+    }
+
+    pub fn metrics(&self) -> CallGraphMetrics {
+        let in_degrees: Vec<usize> = self.methods.keys().map(|&idx| self.in_degree(idx)).collect();
+        let out_degrees: Vec<usize> = self.methods.keys().map(|&idx| self.out_degree(idx)).collect();
+
+        let mut hubs: Vec<Hub> = self.methods.values().zip(&in_degrees)
+            .filter(|(_, &in_degree)| in_degree >= HUB_MIN_IN_DEGREE)
+            .map(|(method, &in_degree)| Hub { method: method.clone(), in_degree })
+            .collect();
+        hubs.sort_by_key(|hub| std::cmp::Reverse(hub.in_degree));
+        hubs.truncate(MAX_HUBS);
+
+        let entry_points = self.methods.values().zip(&in_degrees)
+            .filter(|(method, &in_degree)| in_degree == 0 && !LIFECYCLE_CALLBACKS.contains(&method.name.as_str()))
+            .map(|(method, _)| method.clone())
+            .collect();
+
+        let unreachable_code = self.unreachable.iter()
+            .filter_map(|(idx, &instructions)| Some(UnreachableCode { method: self.methods.get(idx)?.clone(), instructions }))
+            .collect();
+
+        CallGraphMetrics {
+            dex_name: self.dex_name.clone(),
+            methods: self.methods.len(),
+            calls: self.callees.values().map(HashSet::len).sum(),
+            in_degree: DegreeStats::new(&in_degrees),
+            out_degree: DegreeStats::new(&out_degrees),
+            hubs,
+            entry_points,
+            unreachable_code,
+        }
+    }
+}
+
+
+impl DegreeStats {
+    fn new(degrees: &[usize]) -> Self {
+        let mut histogram = BTreeMap::new();
+        for &degree in degrees {
+            *histogram.entry(degree).or_insert(0) += 1;
+        }
+        let mean = if degrees.is_empty() { 0.0 } else { degrees.iter().sum::<usize>() as f64 / degrees.len() as f64 };
+        Self { max: degrees.iter().copied().max().unwrap_or(0), mean, histogram }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{ClassDef, CodeDef, DexBuilder, MethodDef, TryDef};
+    use super::*;
+
+    fn method(name: &str) -> MethodRef {
+        MethodRef { class: "Lcom/example/Main;".to_string(), name: name.to_string(), params: vec![], return_type: "V".to_string() }
+    }
+
+    #[test]
+    fn test_metrics_known_hub() {
+        let mut graph = CallGraph::default();
+        for (idx, name) in ["onCreate", "a", "b", "c", "log", "unused"].into_iter().enumerate() {
+            graph.add_method(idx as u32, method(name));
+        }
+        // onCreate -> a, b, c; a, b, c -> log; a -> log twice; log -> external method 100
+        for (caller, callee) in [(0, 1), (0, 2), (0, 3), (1, 4), (1, 4), (2, 4), (3, 4), (4, 100), (4, 4)] {
+            graph.add_call(caller, callee);
+        }
+        let metrics = graph.metrics();
+        assert_eq!(metrics.methods, 6);
+        assert_eq!(metrics.calls, 7);
+        assert_eq!(metrics.hubs, vec![Hub { method: method("log"), in_degree: 3 }]);
+        assert_eq!(metrics.entry_points, vec![method("unused")]);
+        assert_eq!(metrics.in_degree.max, 3);
+        assert_eq!(metrics.in_degree.histogram, BTreeMap::from([(0, 2), (1, 3), (3, 1)]));
+        assert_eq!(metrics.out_degree.histogram, BTreeMap::from([(0, 1), (1, 4), (3, 1)]));
+        assert_eq!(metrics.out_degree.mean, 7.0 / 6.0);
+        // a calls log twice, the recursive call of log is left out
+        assert_eq!((graph.call_sites(4), graph.in_degree(4), graph.caller_classes(4)), (4, 3, 1));
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            // return-void; const/4 v0, 1; return v0, deliberately unreachable after the return
+            .method(MethodDef::new("dead", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E, 0x1012, 0x000F])))
+            // return-void; move-exception v0; throw v0, reached by the exception edge of the try block
+            .method(MethodDef::new("handled", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E, 0x000D, 0x0027])
+                .try_block(TryDef::new(0, 1).catch_all(1))))
+            .method(MethodDef::new("clean", "V", &[]).code(CodeDef::new(0, 0, 0, &[0x000E]))));
+        let dex = NamedDex::new("classes.dex", DexReader::from_vec(builder.build()).unwrap());
+        let metrics = CallGraph::from_dex(&dex).metrics();
+        assert_eq!(metrics.unreachable_code, [UnreachableCode { method: method("dead"), instructions: 2 }]);
+    }
+}
@@ -0,0 +1,213 @@
+use std::{collections::{BTreeMap, BTreeSet}, io::{self, Write}};
+
+use serde::Serialize;
+
+use crate::{dex_parsing::{decode_method_lenient, NamedDex}, reference::resolve_referenced_class};
+
+
+/// References between classes: an edge from A to B counts the instructions of A referencing a type, field or method
+/// defined by B. Coarser and cheaper than the `CallGraph` of the methods
+#[derive(Debug, Default)]
+pub struct ClassGraph {
+    /// Classes defined in the dexes, the other nodes are external, e.g. framework classes
+    defined: BTreeSet<String>,
+    /// Number of references from a class to another, a class referencing itself is left out
+    edges: BTreeMap<(String, String), usize>,
+}
+
+
+/// Class of the graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClassNode<'a> {
+    /// Descriptor of the class, e.g. `Lcom/example/Main;`
+    pub class: &'a str,
+    /// Whether the class is only referenced, not defined in the dexes
+    pub external: bool,
+}
+
+
+/// Weighted edge of the graph, a line of the edge list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClassEdge<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    /// Number of referencing instructions
+    pub weight: usize,
+    /// Whether the referenced class isn't defined in the dexes
+    pub external: bool,
+}
+
+
+impl ClassGraph {
+    /// Builds the graph from the instructions of every method with code of the dexes
+    pub fn from_dexes<T: AsRef<[u8]>>(dexes: &[NamedDex<T>]) -> Self {
+        let mut graph = Self::default();
+        for dex in dexes.iter().map(|named| &named.dex) {
+            for class in dex.classes().flatten() {
+                let descriptor = class.jtype().type_descriptor().to_string();
+                for code in class.methods().filter_map(|method| method.code()) {
+                    for inst in decode_method_lenient(code.insns()).instructions {
+                        if let Some(referenced) = resolve_referenced_class(dex, &inst) {
+                            graph.add_reference(&descriptor, referenced);
+                        }
+                    }
+                }
+                graph.add_class(descriptor);
+            }
+        }
+        graph
+    }
+
+    /// Registers a class defined in the dexes
+    pub fn add_class(&mut self, class: impl Into<String>) {
+        self.defined.insert(class.into());
+    }
+
+    /// Registers an instruction of `from` referencing `to`
+    pub fn add_reference(&mut self, from: &str, to: impl Into<String>) {
+        let to = to.into();
+        if from != to {
+            *self.edges.entry((from.to_string(), to)).or_default() += 1;
+        }
+    }
+
+    /// Number of instructions of `from` referencing `to`
+    pub fn weight(&self, from: &str, to: &str) -> usize {
+        self.edges.get(&(from.to_string(), to.to_string())).copied().unwrap_or(0)
+    }
+
+    pub fn is_external(&self, class: &str) -> bool {
+        !self.defined.contains(class)
+    }
+
+    /// Defined classes, then the referenced classes that aren't defined, each in descriptor order
+    pub fn nodes(&self) -> Vec<ClassNode<'_>> {
+        let external: BTreeSet<&str> = self.edges.keys().map(|(_, to)| to.as_str()).filter(|to| self.is_external(to)).collect();
+        self.defined.iter().map(|class| ClassNode { class, external: false })
+            .chain(external.into_iter().map(|class| ClassNode { class, external: true }))
+            .collect()
+    }
+
+    /// Edges in the order of their source and target descriptors
+    pub fn edges(&self) -> impl Iterator<Item = ClassEdge<'_>> {
+        self.edges.iter().map(|((from, to), &weight)| ClassEdge { from, to, weight, external: self.is_external(to) })
+    }
+
+    /// Writes the graph in the DOT language of Graphviz, external classes are dashed
+    pub fn write_dot(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "digraph classes {{")?;
+        for node in self.nodes() {
+            let style = if node.external { " [style=dashed]" } else { "" };
+            writeln!(out, "    \"{}\"{};", escape_dot(node.class), style)?;
+        }
+        for edge in self.edges() {
+            writeln!(out, "    \"{}\" -> \"{}\" [weight={}];", escape_dot(edge.from), escape_dot(edge.to), edge.weight)?;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Writes the graph as GraphML, with an `external` attribute on the nodes and a `weight` attribute on the edges
+    pub fn write_graphml(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(out, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+        writeln!(out, r#"  <key id="external" for="node" attr.name="external" attr.type="boolean"/>"#)?;
+        writeln!(out, r#"  <key id="weight" for="edge" attr.name="weight" attr.type="int"/>"#)?;
+        writeln!(out, r#"  <graph id="classes" edgedefault="directed">"#)?;
+        for node in self.nodes() {
+            writeln!(out, r#"    <node id="{}"><data key="external">{}</data></node>"#, escape_xml(node.class), node.external)?;
+        }
+        for edge in self.edges() {
+            writeln!(out, r#"    <edge source="{}" target="{}"><data key="weight">{}</data></edge>"#, escape_xml(edge.from), escape_xml(edge.to), edge.weight)?;
+        }
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</graphml>")
+    }
+
+    /// Writes the edge list, one JSON `ClassEdge` per line
+    pub fn write_ndjson(&self, out: &mut impl Write) -> io::Result<()> {
+        for edge in self.edges() {
+            serde_json::to_writer(&mut *out, &edge)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn escape_dot(id: &str) -> String {
+    id.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{ClassDef, CodeDef, DexBuilder, MethodDef};
+    use super::*;
+
+    #[test]
+    fn test_class_graph_from_dex() {
+        let mut builder = DexBuilder::new();
+        let helper = builder.type_idx("Lcom/example/Helper;") as u16;
+        let run = builder.method("Lcom/example/Helper;", "run", "V", &[]) as u16;
+        let length = builder.method("Ljava/lang/String;", "length", "I", &[]) as u16;
+        let field = builder.field("Lcom/example/Helper;", "count", "I") as u16;
+        // new-instance v0, Helper; invoke-static {}, Helper.run; iget v1, v0, Helper.count; invoke-virtual {v0}, String.length; return-void
+        let main = [0x0022, helper, 0x0071, run, 0x0000, 0x0152, field, 0x106E, length, 0x0000, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Main;").method(MethodDef::new("main", "V", &[]).code(CodeDef::new(2, 0, 1, &main))));
+        builder.class(ClassDef::new("Lcom/example/Helper;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(0, 0, 0, &[0x000E]))));
+        let dex = NamedDex::new("classes.dex", DexReader::from_vec(builder.build()).unwrap());
+
+        let graph = ClassGraph::from_dexes(&[dex]);
+        assert_eq!(graph.weight("Lcom/example/Main;", "Lcom/example/Helper;"), 3);
+        assert_eq!(graph.weight("Lcom/example/Main;", "Ljava/lang/String;"), 1);
+        assert_eq!(graph.nodes(), [
+            ClassNode { class: "Lcom/example/Helper;", external: false },
+            ClassNode { class: "Lcom/example/Main;", external: false },
+            ClassNode { class: "Ljava/lang/String;", external: true },
+        ]);
+    }
+
+    #[test]
+    fn test_class_graph_formats() {
+        let mut graph = ClassGraph::default();
+        graph.add_class("Lcom/example/Main;");
+        graph.add_class("Lcom/example/Helper;");
+        graph.add_reference("Lcom/example/Main;", "Lcom/example/Helper;");
+        graph.add_reference("Lcom/example/Main;", "Lcom/example/Helper;");
+        graph.add_reference("Lcom/example/Main;", "Lcom/example/Main;");
+        graph.add_reference("Lcom/example/Helper;", "Ljava/lang/Object;");
+
+        let mut dot = vec![];
+        graph.write_dot(&mut dot).unwrap();
+        assert_eq!(String::from_utf8(dot).unwrap(), "\
+digraph classes {
+    \"Lcom/example/Helper;\";
+    \"Lcom/example/Main;\";
+    \"Ljava/lang/Object;\" [style=dashed];
+    \"Lcom/example/Helper;\" -> \"Ljava/lang/Object;\" [weight=1];
+    \"Lcom/example/Main;\" -> \"Lcom/example/Helper;\" [weight=2];
+}
+");
+
+        let mut graphml = vec![];
+        graph.write_graphml(&mut graphml).unwrap();
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert!(graphml.contains(r#"<node id="Ljava/lang/Object;"><data key="external">true</data></node>"#));
+        assert!(graphml.contains(r#"<edge source="Lcom/example/Main;" target="Lcom/example/Helper;"><data key="weight">2</data></edge>"#));
+
+        let mut ndjson = vec![];
+        graph.write_ndjson(&mut ndjson).unwrap();
+        let edges: Vec<serde_json::Value> = ndjson.split(|&byte| byte == b'\n').filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(edges[0], serde_json::json!({"from": "Lcom/example/Helper;", "to": "Ljava/lang/Object;", "weight": 1, "external": true}));
+        assert_eq!(edges.len(), 2);
+    }
+}
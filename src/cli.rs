@@ -1,22 +1,673 @@
-use clap::Parser;
+use std::{fs, io};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use dexompiler::{AnalysisOptions, CapStrategy, ClassFilter, DecodeMode, DedupKey, DedupScope, InvalidStrings, Normalization, obfuscation::DecryptorThresholds, opcode_map::OpcodeMap, packer::PackerRules, ReportField, Sampling, Strictness, watchlist::Watchlist};
 use num_cpus;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Json,
+    Ndjson,
+    /// One row of opcode counts per input, for spreadsheets. Only for apk records of opcode sequences
+    Csv,
+}
+
+
+/// What a record of the output holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    /// The whole report of an input
+    Apk,
+    /// The concatenated sequences of the methods of a class, with their number
+    Class,
+    /// The sequence of a single method
+    Method,
+}
+
+
+/// Optional sections added to the report of every input
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Emit {
+    /// Degree statistics, hubs and entry points of the call graph of every dex, and the methods with unreachable code
+    Metrics,
+    /// Every string of every dex with its index, byte length and number of referencing instructions. In ndjson output, and
+    /// at class and method granularity, a record per string follows the records of the input
+    StringPool,
+    /// Size and offset of the sections of every dex from its map_list, and layout anomalies such as link data, data
+    /// appended past the end of the dex or overlapping sections
+    Sections,
+    /// Every field of every selected class with its type, access flags and the initial value of static finals, as records
+    /// of their own like the string pool
+    Fields,
+    /// Framework APIs (android, java, javax, kotlin) invoked by every method of every selected class, in bytecode order
+    ApiSeq,
+    /// Calls of framework APIs guarded by a permission the manifest doesn't request, with the calling method, the
+    /// offset of the invoke and the missing permissions
+    DeadApiCalls,
+    /// Intents created by every method of every selected class, with their action or target component and the call starting them
+    Intents,
+    /// URLs, IP addresses and suspicious domains in the strings and static final fields of every selected class, with
+    /// the networking APIs (HttpURLConnection, OkHttp, WebView) invoked by the methods loading them
+    NetworkIndicators,
+    /// Whether the APK references the Kotlin metadata annotation, and the share of its classes generated by the compiler
+    Kotlin,
+    /// Source file of every class, the share of classes naming one and of methods with debug info, and the source file extensions
+    DebugInfo,
+    /// Likely string decryption helpers: static methods returning strings with a decryption loop, called from many classes
+    Obfuscation,
+    /// Packers and obfuscators recognized by their fingerprints, with the evidence of every match
+    Packer,
+    /// Opcode mnemonic, offset and branch target of every emitted instruction, as `instructions` in the record of every method
+    InstructionsLite,
+    /// Only the manifest of every APK, without decoding the dexes. The other sections and the granularity are ignored
+    Manifest,
+}
+
+
+/// Alphabet of the emitted opcode sequences
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Normalize {
+    /// Opcode bytes as they are
+    None,
+    /// Every invoke-* becomes invoke-virtual
+    InvokeMerged,
+    /// One symbol per opcode category: moves, constants, field accesses, invokes, branches...
+    Category,
+}
+
+
+/// Built-in opcode vocabularies
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// The ordinal of the opcode category of every opcode
+    Kinds,
+    /// Every opcode as its own byte
+    Full,
+}
+
+
+/// Methods sharing a table of unique sequences
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Scope {
+    /// Every method of an input
+    Input,
+    /// The methods of each dex
+    Dex,
+    /// Every method of the run: a body is emitted by the first input having it, later inputs refer to it by its id
+    Run,
+}
+
+
+/// What makes two method bodies duplicates
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Key {
+    /// Identical code units
+    Exact,
+    /// Identical normalized opcodes, whatever the operands
+    Opcodes,
+}
+
+
+/// Which code units of a method are decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Decode {
+    /// Every code unit from the start to the end of the method
+    Linear,
+    /// Only the code reachable from the entry and the catch handlers, skipping data embedded between instructions
+    Recursive,
+}
+
+
+/// How the string pool writes the strings that aren't valid MUTF-8
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// Hex of the bytes
+    Hex,
+    /// The bytes with those outside printable ASCII escaped as `\xNN`
+    Escaped,
+    /// Standard base64 of the bytes
+    Base64,
+}
+
+
+/// How the opcode sequence of an input is cut at the sequence cap
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Cap {
+    /// Keep only the methods fitting whole under the cap
+    TruncateMethods,
+    /// Truncate the method reaching the cap to fill it exactly
+    TruncateExact,
+    /// Cap every dex on its own, the output may be up to one cap per dex long
+    PerDex,
+}
+
+
+/// Format of the class reference graph of `dexompiler inspect`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT, external classes dashed
+    Dot,
+    Graphml,
+    /// One JSON edge per line, with its weight and whether the target is external
+    Ndjson,
+}
+
+
+/// Arguments of `dexompiler inspect`
+#[derive(clap::Args, Debug)]
+pub struct InspectArgs {
+    /// APK to disassemble
+    pub input: String,
+
+    /// Only list the methods of the class with this descriptor, e.g. `Lcom/foo/Bar;`
+    #[arg(long)]
+    pub class: Option<String>,
+
+    /// Only list the methods with this name
+    #[arg(long)]
+    pub method: Option<String>,
+
+    /// Print the graph of the references between classes instead, weighted by the number of referencing instructions.
+    /// Referenced classes that the APK doesn't define, e.g. framework classes, are external nodes
+    #[arg(long, value_enum, conflicts_with_all = ["class", "method"])]
+    pub class_graph: Option<GraphFormat>,
+}
+
+
+/// Which records `dexompiler merge` keeps of an input found in several outputs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Conflict {
+    /// Those of the first output listing it
+    First,
+    /// Those of the last output listing it
+    Last,
+    /// Fail the merge
+    Error,
+}
+
+
+/// Arguments of `dexompiler merge`
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+    /// Outputs of earlier runs to merge, in any format. They must share the same meta header
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+
+    /// Merged output file
+    #[arg(short, long)]
+    pub output: String,
+
+    /// Format of the merged output. CSV outputs can only be merged into CSV
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    pub format: Format,
+
+    /// Which records are kept of an input found in several outputs, inputs being told apart by their path, or the hash
+    /// of the APK for stdin inputs
+    #[arg(long, value_enum, default_value_t = Conflict::First)]
+    pub on_conflict: Conflict,
+}
+
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print a smali-like listing of the matched methods of one APK, or an index of its classes and methods without filters
+    Inspect(InspectArgs),
+    /// Merge the outputs of several runs, e.g. shards of a corpus, into one output with a single summary footer
+    Merge(MergeArgs),
+}
+
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Output file
-    #[arg(short, long)]
-    pub output: String,
+    #[arg(short, long, required = true)]
+    pub output: Option<String>,
     
-    /// Max opcode sequence length to parse
+    /// Output format: a single JSON object with the records keyed by input path under `inputs`, or one JSON record per
+    /// line streamed as inputs complete. Both start with a `meta` header recording the granularity. CSV only holds the opcode histogram of every input
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    pub format: Format,
+
+    /// How the sequences are grouped into records. Class and method records leave out the other sections of the report
+    #[arg(long, value_enum, default_value_t = Granularity::Apk, conflicts_with = "dedup_methods")]
+    pub granularity: Granularity,
+
+    /// Add a record with an empty sequence and the access flags of every abstract and native method to class and method records,
+    /// and the list of these methods as `codeless_methods` to APK records
+    #[arg(long, default_value_t = false)]
+    pub include_codeless: bool,
+
+    /// Number of records a worker buffers before handing them to the writer in ndjson mode
+    #[arg(long, default_value_t = 64)]
+    pub batch_records: usize,
+
+    /// Max opcode sequence length of every input, 0 for no limit
     #[arg(short, long, default_value_t = 0)]
     pub sequence_cap: usize,
+
+    /// What happens to the method reaching the sequence cap, and whether the cap covers all dexes or each one
+    #[arg(long, value_enum, default_value_t = Cap::TruncateExact)]
+    pub cap_strategy: Cap,
     
+    /// Max number of methods to emit per input, 0 for no limit
+    #[arg(long, default_value_t = 0)]
+    pub method_cap: usize,
+
+    /// Only emit the methods of classes whose descriptor starts with this prefix, may be repeated
+    #[arg(long)]
+    pub include_class: Vec<String>,
+
+    /// Skip the methods of classes whose descriptor starts with this prefix, may be repeated
+    #[arg(long)]
+    pub exclude_class: Vec<String>,
+
+    /// Skip the methods of lambdas, numbered anonymous classes, Kotlin helper classes and synthetic classes
+    #[arg(long, default_value_t = false)]
+    pub exclude_synthetic: bool,
+
+    /// Decode byte-identical method bodies once and emit a table of unique sequences with the number of methods sharing each
+    #[arg(long, default_value_t = false)]
+    pub dedup_methods: bool,
+
+    /// Methods sharing a table of unique sequences with --dedup-methods
+    #[arg(long, value_enum, default_value_t = Scope::Input)]
+    pub dedup_scope: Scope,
+
+    /// What makes two method bodies duplicates with --dedup-methods
+    #[arg(long, value_enum, default_value_t = Key::Exact)]
+    pub dedup_key: Key,
+
+    /// Decode only opcodes and instruction lengths, skipping operands, for faster opcode-only runs
+    #[arg(long, default_value_t = false, conflicts_with = "dedup_methods")]
+    pub shallow: bool,
+
+    /// Decode every code unit of a method in order, or only the code reachable by branches, switches and fall-through
+    #[arg(long, value_enum, default_value_t = Decode::Linear, conflicts_with = "dedup_methods")]
+    pub decode_mode: Decode,
+
+    /// Report the code unit offset of every emitted opcode, as `offsets` in the record of every method
+    #[arg(long, default_value_t = false, conflicts_with = "dedup_methods")]
+    pub with_offsets: bool,
+
+    /// Report the invoke counts by kind, const-string, new-instance, throw and monitor-enter counts, the number of
+    /// distinct invoked methods and the register move counts, as `op_stats` in the record of every method, and the
+    /// distribution of move densities in the summary. Methods are then decoded fully
+    #[arg(long, default_value_t = false, conflicts_with = "dedup_methods")]
+    pub with_op_stats: bool,
+
+    /// Emit opcodes as mnemonic strings, e.g. `invoke-virtual`, instead of bytes. Ignored with `--normalize category` and vocabularies other than `--preset full`
+    #[arg(long, default_value_t = false)]
+    pub mnemonics: bool,
+
+    /// Skip payloads and undecodable instructions instead of dropping the whole method
+    #[arg(long, default_value_t = false)]
+    pub lenient: bool,
+
+    /// Check the register operands of every instruction against the register count of its method, reporting the
+    /// methods with registers outside their frame under `verify_errors`, and warn about branches into the middle of an instruction
+    #[arg(long, default_value_t = false)]
+    pub verify: bool,
+
+    /// Only decode every method of every input, whatever the class filter, and write the code units that can't be
+    /// decoded with their dex, class, method, offset and opcode byte instead of any sequence. Exits with 1 if any fails
+    #[arg(long, default_value_t = false, conflicts_with_all = ["emit", "isolate", "only_permissions"])]
+    pub verify_only: bool,
+
+    /// Fail an input on the first class definition that can't be parsed, instead of skipping the class with a warning
+    /// giving its index
+    #[arg(long, default_value_t = false)]
+    pub strict_classes: bool,
+
+    /// Also print the warnings of every input to stderr
+    #[arg(long, default_value_t = false)]
+    pub echo_warnings: bool,
+
+    /// Maximum total size in bytes of the input files processed at the same time
+    #[arg(long, default_value_t = 4 * 1024 * 1024 * 1024)]
+    pub memory_budget: u64,
+
+    /// File with extra APIs to watch for, one `Lclass;->method` per line
+    #[arg(long)]
+    pub watchlist: Option<String>,
+
+    /// TOML file with extra packer fingerprints, in the format of the embedded rules
+    #[arg(long)]
+    pub packer_rules: Option<String>,
+
+    /// Fraction of the decoded methods to emit, chosen at random
+    #[arg(long, default_value_t = 1.0, value_parser = parse_rate)]
+    pub sample_rate: f64,
+
+    /// Seed of the method sampling, the same seed selects the same methods of the same input, and other methods of other inputs
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Remap the opcodes before emitting them
+    #[arg(long, value_enum, default_value_t = Normalize::None)]
+    pub normalize: Normalize,
+
+    /// TOML or JSON file mapping opcode mnemonics or bytes to output tokens under `[map]`, with an optional `default` token
+    /// of the unmapped opcodes, which are passed through without it
+    #[arg(long, conflicts_with_all = ["normalize", "preset"])]
+    pub opcode_map: Option<String>,
+
+    /// Built-in opcode vocabulary
+    #[arg(long, value_enum, conflicts_with = "normalize")]
+    pub preset: Option<Preset>,
+
+    /// Max depth of the traversals of the control flow of a method, deeper methods are left out and reported. 0 for no limit
+    #[arg(long, default_value_t = 0)]
+    pub max_cfg_depth: usize,
+
+    /// Extra sections to emit, may be repeated
+    #[arg(long, value_enum)]
+    pub emit: Vec<Emit>,
+
+    /// Comma-separated sections of the APK records to compute and write, e.g. `opcodes,permissions,coverage`, all by
+    /// default. Sections turned on by --emit still need it
+    #[arg(long, value_delimiter = ',', value_parser = parse_report_field)]
+    pub fields: Vec<ReportField>,
+
+    /// How --emit string-pool writes the strings that aren't valid MUTF-8, whose bytes can't be written as text
+    #[arg(long, value_enum, default_value_t = Encoding::Hex)]
+    pub invalid_strings: Encoding,
+
+    /// Only the requested permissions of every APK, as a map of paths to permissions, without decoding the dexes.
+    /// Inputs that aren't APKs are skipped
+    #[arg(long, default_value_t = false, conflicts_with_all = ["emit", "isolate"])]
+    pub only_permissions: bool,
+
+    /// Minimum share of decryption opcodes, array accesses, int xors and adds and char conversions, of a string decryptor's body
+    #[arg(long, default_value_t = DecryptorThresholds::default().min_score, value_parser = parse_rate)]
+    pub decryptor_min_score: f64,
+
+    /// Minimum number of distinct classes calling a string decryptor
+    #[arg(long, default_value_t = DecryptorThresholds::default().min_caller_classes)]
+    pub decryptor_min_callers: usize,
+
+    /// Also print the summary of the run, counters, throughput and peak memory, every this many seconds
+    #[arg(long)]
+    pub progress_stats: Option<u64>,
+
+    /// Print a plain progress line every 10 seconds instead of the progress bars, the default when stderr isn't a terminal
+    #[arg(long, default_value_t = false)]
+    pub no_progress: bool,
+
     /// Number of threads to use
     #[arg(short, long, default_value_t = num_cpus::get())]
     pub threads: usize,
     
-    /// Input files
+    /// Input files, `-` for an APK read from stdin, or http(s) URLs with the http feature
     #[arg(short, long, num_args = 1..=2097152)]
-    pub input: Vec<String>
-}
\ No newline at end of file
+    pub input: Vec<String>,
+
+    /// Key of the record of the APK read from stdin, the hex xxh3-128 hash of its bytes by default
+    #[arg(long)]
+    pub stdin_name: Option<String>,
+
+    /// File listing more input files or URLs, one per line, taken literally without glob expansion. Blank lines and
+    /// lines starting with `#` are skipped
+    #[arg(long)]
+    pub input_list: Option<String>,
+
+    /// Max size in bytes of a downloaded input, larger ones are abandoned
+    #[arg(long, default_value_t = 512 * 1024 * 1024)]
+    pub max_download_size: u64,
+
+    /// Max number of inputs downloaded at the same time, whatever the number of threads
+    #[arg(long, default_value_t = 8)]
+    pub download_concurrency: usize,
+
+    /// Analyze every input in a child process, so that a crash only loses that input, which is added to the quarantine list
+    #[arg(long, default_value_t = false)]
+    pub isolate: bool,
+
+    /// Number of times an input crashing its child process is retried before being quarantined, with --isolate
+    #[arg(long, default_value_t = 1)]
+    pub isolate_retries: usize,
+
+    /// File the inputs crashing their child process are appended to, one per line, `<output>.quarantine` by default
+    #[arg(long)]
+    pub quarantine: Option<String>,
+
+    /// Directory keeping the decoded methods of every dex, keyed by the SHA-256 of the dex, so that a dex found in several
+    /// inputs, in this run or a later one, is only decoded once. Entries decoded with other options are decoded again.
+    /// Unused with --dedup-methods, --method-cap, --emit instructions-lite, and a sequence cap without --cap-strategy per-dex
+    #[arg(long, conflicts_with_all = ["verify_only", "only_permissions"])]
+    pub cache_dir: Option<String>,
+
+    /// Analyze only this input and print its records and totals as JSON on stdout, run by --isolate
+    #[arg(long, hide = true)]
+    pub worker_single: Option<String>,
+}
+
+impl Args {
+    /// Option the CSV output can't be combined with, it has a column per opcode and a row per input
+    pub fn csv_conflict(&self) -> Option<&'static str> {
+        if self.format != Format::Csv {
+            None
+        } else if self.granularity != Granularity::Apk {
+            Some("--granularity")
+        } else if self.only_permissions {
+            Some("--only-permissions")
+        } else if self.emit.contains(&Emit::Manifest) {
+            Some("--emit manifest")
+        } else if self.isolate {
+            Some("--isolate")
+        } else if self.verify_only {
+            Some("--verify-only")
+        } else if !self.fields.is_empty() && !self.fields.contains(&ReportField::Opcodes) {
+            Some("--fields without opcodes")
+        } else if self.normalize == Normalize::Category || self.preset == Some(Preset::Kinds) || self.opcode_map.is_some() {
+            Some("a vocabulary other than opcodes")
+        } else {
+            None
+        }
+    }
+
+    /// Inputs given on the command line followed by those of the input list, if any.
+    /// Glob patterns of the command line are expanded, plain paths, URLs and the listed inputs are kept as they are
+    pub fn resolve_inputs(&self) -> io::Result<Vec<String>> {
+        let listed: Vec<String> = match &self.input_list {
+            Some(path) => fs::read_to_string(path)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+            None => vec![],
+        };
+        let expanded = self.input.iter().flat_map(|pattern| {
+            if !is_url(pattern) && pattern.contains(['*', '?', '[']) {
+                match glob::glob(pattern) {
+                    Ok(paths) => paths.filter_map(Result::ok).map(|path| path.to_string_lossy().into_owned()).collect(),
+                    Err(_) => vec![]
+                }
+            } else {
+                vec![pattern.clone()]
+            }
+        });
+        Ok(expanded.chain(listed).collect())
+    }
+
+    /// Options of the analysis of every input, loading the user watchlist, opcode map and packer rules if given
+    pub fn analysis_options(&self) -> io::Result<AnalysisOptions> {
+        let mut watchlist = Watchlist::default();
+        if let Some(path) = &self.watchlist {
+            watchlist.extend_from_file(path)?;
+        }
+        let class_filter = self.include_class.iter().fold(ClassFilter::default(), |filter, prefix| filter.include(prefix));
+        let class_filter = self.exclude_class.iter().fold(class_filter, |filter, prefix| filter.exclude(prefix));
+        let mut options = AnalysisOptions::default()
+            .sequence_cap(self.sequence_cap)
+            .cap_strategy(match self.cap_strategy {
+                Cap::TruncateMethods => CapStrategy::TruncateMethods,
+                Cap::TruncateExact => CapStrategy::TruncateExact,
+                Cap::PerDex => CapStrategy::PerDex,
+            })
+            .method_cap(self.method_cap)
+            .class_filter(class_filter)
+            .strictness(if self.lenient { Strictness::Lenient } else { Strictness::Strict })
+            .dedup_methods(self.dedup_methods)
+            .dedup_scope(match self.dedup_scope {
+                Scope::Input => DedupScope::Input,
+                Scope::Dex => DedupScope::Dex,
+                Scope::Run => DedupScope::Run,
+            })
+            .dedup_key(match self.dedup_key {
+                Key::Exact => DedupKey::Bytecode,
+                Key::Opcodes => DedupKey::Opcodes,
+            })
+            .shallow(self.shallow)
+            .decode_mode(match self.decode_mode {
+                Decode::Linear => DecodeMode::Linear,
+                Decode::Recursive => DecodeMode::Recursive,
+            })
+            .with_offsets(self.with_offsets)
+            .op_stats(self.with_op_stats)
+            .mnemonics(self.mnemonics)
+            .max_cfg_depth(self.max_cfg_depth)
+            .call_graph_metrics(self.emit.contains(&Emit::Metrics))
+            .string_pool(self.emit.contains(&Emit::StringPool))
+            .invalid_strings(match self.invalid_strings {
+                Encoding::Hex => InvalidStrings::Hex,
+                Encoding::Escaped => InvalidStrings::Escaped,
+                Encoding::Base64 => InvalidStrings::Base64,
+            })
+            .sections(self.emit.contains(&Emit::Sections))
+            .fields(self.emit.contains(&Emit::Fields))
+            .api_sequences(self.emit.contains(&Emit::ApiSeq))
+            .dead_api_calls(self.emit.contains(&Emit::DeadApiCalls))
+            .intents(self.emit.contains(&Emit::Intents))
+            .network_indicators(self.emit.contains(&Emit::NetworkIndicators))
+            .kotlin(self.emit.contains(&Emit::Kotlin))
+            .debug_info(self.emit.contains(&Emit::DebugInfo))
+            .exclude_synthetic(self.exclude_synthetic)
+            .instructions_lite(self.emit.contains(&Emit::InstructionsLite))
+            .verify(self.verify)
+            .strict_classes(self.strict_classes)
+            .normalization(match (&self.opcode_map, self.preset) {
+                (Some(path), _) => Normalization::Map(Box::new(OpcodeMap::from_file(path)?)),
+                (None, Some(Preset::Kinds)) => Normalization::Map(Box::new(OpcodeMap::kinds())),
+                (None, Some(Preset::Full)) => Normalization::Map(Box::new(OpcodeMap::full())),
+                (None, None) => match self.normalize {
+                    Normalize::None => Normalization::None,
+                    Normalize::InvokeMerged => Normalization::InvokeMerged,
+                    Normalize::Category => Normalization::Category,
+                },
+            })
+            .watchlist(watchlist);
+        if self.emit.contains(&Emit::Obfuscation) {
+            options = options.string_decryptors(DecryptorThresholds { min_score: self.decryptor_min_score, min_caller_classes: self.decryptor_min_callers });
+        }
+        if self.emit.contains(&Emit::Packer) {
+            let mut rules = PackerRules::default();
+            if let Some(path) = &self.packer_rules {
+                rules.extend_from_file(path)?;
+            }
+            options = options.packer(rules);
+        }
+        if !self.fields.is_empty() {
+            options = options.report_fields(self.fields.iter().copied());
+        }
+        if self.sample_rate < 1.0 {
+            options = options.sampling(Sampling { rate: self.sample_rate, seed: self.seed });
+        }
+        Ok(options.build())
+    }
+}
+
+
+/// Whether an input is fetched over HTTP(S) rather than read from a file
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+
+fn parse_report_field(value: &str) -> Result<ReportField, String> {
+    ReportField::from_name(value).ok_or_else(|| {
+        let names: Vec<_> = ReportField::ALL.iter().map(|field| field.name()).collect();
+        format!("unknown field {}, expected one of: {}", value, names.join(", "))
+    })
+}
+
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("{} is not a rate between 0 and 1", value)),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_inputs_empty_glob() {
+        let args = Args::parse_from(["dexompiler", "-o", "out.json", "-i", "/nonexistent/*.apk"]);
+        assert!(args.resolve_inputs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sample_rate_range() {
+        assert!(Args::try_parse_from(["dexompiler", "-o", "out.json", "-i", "app.apk", "--sample-rate", "1.5"]).is_err());
+        let args = Args::parse_from(["dexompiler", "-o", "out.json", "-i", "app.apk", "--sample-rate", "0.1"]);
+        assert_eq!(args.sample_rate, 0.1);
+    }
+
+    #[test]
+    fn test_analysis_options_missing_watchlist() {
+        let args = Args::parse_from(["dexompiler", "-o", "out.json", "-i", "app.apk", "--watchlist", "/nonexistent/watchlist.txt"]);
+        assert!(args.analysis_options().is_err());
+        let args = Args::parse_from(["dexompiler", "-o", "out.json", "-i", "app.apk", "--exclude-class", "Landroidx/"]);
+        assert!(args.analysis_options().is_ok());
+    }
+
+    #[test]
+    fn test_resolve_inputs_plain_path() {
+        let args = Args::parse_from(["dexompiler", "-o", "out.json", "-i", "app.apk"]);
+        assert_eq!(args.resolve_inputs().unwrap(), vec!["app.apk".to_string()]);
+        let args = Args::parse_from(["dexompiler", "-o", "out.json", "-i", "https://example.com/app.apk?sig=a*b"]);
+        assert_eq!(args.resolve_inputs().unwrap(), ["https://example.com/app.apk?sig=a*b"]);
+    }
+
+    #[test]
+    fn test_resolve_inputs_list() {
+        let list = std::env::temp_dir().join(format!("dexompiler-input-list-{}.txt", std::process::id()));
+        fs::write(&list, "# corpus\nfirst.apk\n\n  second.apk  \n#skipped.apk\nthird.apk\n/nonexistent/[v2]*.apk\n").unwrap();
+        let args = Args::parse_from(["dexompiler", "-o", "out.json", "-i", "app.apk", "--input-list", list.to_str().unwrap()]);
+        let inputs = args.resolve_inputs();
+        fs::remove_file(&list).unwrap();
+        // Listed paths are never patterns, even with glob characters
+        assert_eq!(inputs.unwrap(), ["app.apk", "first.apk", "second.apk", "third.apk", "/nonexistent/[v2]*.apk"]);
+        let args = Args::parse_from(["dexompiler", "-o", "out.json", "--input-list", "/nonexistent/inputs.txt"]);
+        assert!(args.resolve_inputs().is_err());
+    }
+
+    #[test]
+    fn test_inspect_subcommand() {
+        let args = Args::parse_from(["dexompiler", "inspect", "app.apk", "--class", "Lcom/foo/Bar;", "--method", "baz"]);
+        let Some(Command::Inspect(inspect)) = args.command else { panic!("expected the inspect subcommand") };
+        assert_eq!(inspect.input, "app.apk");
+        assert_eq!(inspect.class.as_deref(), Some("Lcom/foo/Bar;"));
+        assert_eq!(inspect.method.as_deref(), Some("baz"));
+        assert!(Args::try_parse_from(["dexompiler", "-i", "app.apk"]).is_err());
+    }
+
+    #[test]
+    fn test_merge_subcommand() {
+        let args = Args::parse_from(["dexompiler", "merge", "a.ndjson", "b.ndjson", "-o", "out.ndjson", "--format", "ndjson", "--on-conflict", "last"]);
+        let Some(Command::Merge(merge)) = args.command else { panic!("expected the merge subcommand") };
+        assert_eq!(merge.inputs, ["a.ndjson", "b.ndjson"]);
+        assert_eq!((merge.format, merge.on_conflict), (Format::Ndjson, Conflict::Last));
+        assert!(Args::try_parse_from(["dexompiler", "merge", "a.ndjson"]).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeSet;
+
+use dex::Dex;
+use serde::Serialize;
+
+
+/// Source file a class was compiled from, as named by its class definition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClassSourceFile {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub source_file: String,
+}
+
+
+/// How much debug info the classes of an APK kept, which tells whether decompiling it gives line numbers and names back
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DebugInfoReport {
+    /// Number of class definitions of all dexes
+    pub classes: usize,
+    pub classes_with_source_file: usize,
+    /// `classes_with_source_file` over `classes`, 0 without classes
+    pub source_file_ratio: f64,
+    /// Number of methods with code of all dexes
+    pub methods: usize,
+    /// Methods with code pointing to a debug_info_item, which holds their line numbers and parameter names
+    pub methods_with_debug_info: usize,
+    /// `methods_with_debug_info` over `methods`, 0 without methods
+    pub debug_info_ratio: f64,
+    /// Extensions of the source file names, e.g. `java` and `kt`. Names without one, such as the `SourceFile` of
+    /// minified builds, are left out
+    pub source_file_extensions: BTreeSet<String>,
+    pub source_files: Vec<ClassSourceFile>,
+}
+
+
+/// Reads the source file of every class and whether every method has debug info, from the class definitions and
+/// code items only: the debug info itself isn't decoded. Classes that fail to parse are left out
+pub fn debug_info_report<T: AsRef<[u8]>>(dexes: &[Dex<T>]) -> DebugInfoReport {
+    let mut report = DebugInfoReport::default();
+    for (index, dex) in dexes.iter().enumerate() {
+        for class in dex.classes().flatten() {
+            report.classes += 1;
+            if let Some(source_file) = class.source_file().as_ref().map(|source_file| source_file.to_string()) {
+                report.classes_with_source_file += 1;
+                if let Some((_, extension)) = source_file.rsplit_once('.').filter(|(name, extension)| !name.is_empty() && !extension.is_empty()) {
+                    report.source_file_extensions.insert(extension.to_string());
+                }
+                report.source_files.push(ClassSourceFile { dex: index, class: class.jtype().type_descriptor().to_string(), source_file });
+            }
+            for code in class.methods().filter_map(|method| method.code()) {
+                report.methods += 1;
+                if code.debug_info_offset() != 0 {
+                    report.methods_with_debug_info += 1;
+                }
+            }
+        }
+    }
+    if report.classes > 0 {
+        report.source_file_ratio = report.classes_with_source_file as f64 / report.classes as f64;
+    }
+    if report.methods > 0 {
+        report.debug_info_ratio = report.methods_with_debug_info as f64 / report.methods as f64;
+    }
+    report
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{ClassDef, CodeDef, DexBuilder, MethodDef};
+    use super::*;
+
+    /// The same two classes, with the debug info and source files of a debug build or stripped as by a release build
+    fn fixture(debug: bool) -> Dex<Vec<u8>> {
+        let code = || if debug { CodeDef::new(1, 0, 0, &[0x000E]).debug_info(10) } else { CodeDef::new(1, 0, 0, &[0x000E]) };
+        let class = |descriptor: &str, source_file: &str| {
+            let class = ClassDef::new(descriptor)
+                .method(MethodDef::new("run", "V", &[]).code(code()))
+                .method(MethodDef::new("stop", "V", &[]).code(code()));
+            if debug { class.source_file(source_file) } else { class }
+        };
+        let mut builder = DexBuilder::new();
+        builder.class(class("Lcom/example/Main;", "Main.kt"));
+        builder.class(class("Lcom/example/Legacy;", "Legacy.java"));
+        DexReader::from_vec(builder.build()).unwrap()
+    }
+
+    #[test]
+    fn test_debug_build() {
+        let report = debug_info_report(&[fixture(true)]);
+        assert_eq!((report.classes, report.classes_with_source_file, report.source_file_ratio), (2, 2, 1.0));
+        assert_eq!((report.methods, report.methods_with_debug_info, report.debug_info_ratio), (4, 4, 1.0));
+        assert_eq!(report.source_file_extensions, BTreeSet::from(["java".to_string(), "kt".to_string()]));
+        assert_eq!(report.source_files[0], ClassSourceFile { dex: 0, class: "Lcom/example/Main;".to_string(), source_file: "Main.kt".to_string() });
+    }
+
+    #[test]
+    fn test_release_build() {
+        let report = debug_info_report(&[fixture(false)]);
+        assert_eq!((report.classes, report.classes_with_source_file, report.source_file_ratio), (2, 0, 0.0));
+        assert_eq!((report.methods, report.methods_with_debug_info, report.debug_info_ratio), (4, 0, 0.0));
+        assert!(report.source_file_extensions.is_empty() && report.source_files.is_empty());
+    }
+}
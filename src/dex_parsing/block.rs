@@ -2,7 +2,7 @@ use std::{rc::Rc, cell::RefCell, fmt, sync::{Mutex, Arc}, collections::HashSet};
 
 use super::instruction::Instruction;
 
-pub(crate) struct BasicBlock {
+pub struct BasicBlock {
     prev: Vec<Rc<RefCell<BasicBlock>>>,
     instructions: Vec<Instruction>,
     succ: Vec<Rc<RefCell<BasicBlock>>>,
@@ -19,7 +19,7 @@ impl fmt::Debug for BasicBlock {
     }
 }
 
-pub(crate) type BlockPtr = Rc<RefCell<BasicBlock>>;
+pub type BlockPtr = Rc<RefCell<BasicBlock>>;
 
 impl BasicBlock {
 
@@ -32,6 +32,10 @@ impl BasicBlock {
         &self.instructions
     }
 
+    pub fn succ(&self) -> &Vec<BlockPtr> {
+        &self.succ
+    }
+
     pub fn add_prev(&mut self, block: BlockPtr) {
         self.prev.push(block);
     }
@@ -44,18 +48,26 @@ impl BasicBlock {
         self.instructions.push(instruction);
     }
 
+    /// Marks this block and the blocks reachable from it as visited, adding the opcodes of each to `accumulator`.
+    /// The traversal keeps its own stack, so deep graphs can't overflow the call stack
     pub fn visit(&mut self, accumulator: &Arc<Mutex<HashSet<String>>>) {
+        let mut acc = accumulator.lock().unwrap();
         self.visited = true;
-        {
-            let mut acc = accumulator.lock().unwrap();
-            acc.insert(self.instructions.iter().map(|i| format!("{}", *i.opcode() as u8)).collect::<Vec<_>>().join(" "));
-        }
-        for succ in self.succ.iter() {
-            if let Ok(mut succ) = succ.try_borrow_mut() {
-                if !succ.visited {
-                    succ.visit(accumulator);
-                }
+        acc.insert(self.opcodes());
+        let mut pending = self.succ.clone();
+        while let Some(block) = pending.pop() {
+            // This block is already borrowed by the caller and was visited first
+            let Ok(mut block) = block.try_borrow_mut() else { continue };
+            if block.visited {
+                continue;
             }
+            block.visited = true;
+            acc.insert(block.opcodes());
+            pending.extend(block.succ.iter().cloned());
         }
     }
+
+    fn opcodes(&self) -> String {
+        self.instructions.iter().map(|i| format!("{}", *i.opcode() as u8)).collect::<Vec<_>>().join(" ")
+    }
 }
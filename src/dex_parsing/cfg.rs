@@ -0,0 +1,185 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::error::Error;
+
+use super::{get_blocks, BasicBlock, BlockPtr};
+
+
+/// Control flow graph of a method, as the basic blocks of its code in offset order
+#[derive(Debug)]
+pub struct MethodCfg {
+    blocks: Vec<BlockPtr>,
+}
+
+
+impl MethodCfg {
+    /// Splits the code of a method into basic blocks linked by their branches
+    pub fn build(raw_bytecode: &[u16]) -> Result<Self, Error> {
+        Ok(Self { blocks: get_blocks(raw_bytecode)? })
+    }
+
+    /// Block at offset 0, `None` for a method without instructions
+    pub fn entry(&self) -> Option<&BlockPtr> {
+        self.blocks.first()
+    }
+
+    pub fn blocks(&self) -> &[BlockPtr] {
+        &self.blocks
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Indices in `blocks` of the blocks reachable from the entry, in depth-first preorder.
+    /// Blocks more than `max_depth` blocks away from the entry are left out, 0 for no limit
+    pub fn depth_first(&self, max_depth: usize) -> Traversal {
+        let index_of = index_of(&self.blocks);
+        let roots = if self.blocks.is_empty() { vec![] } else { vec![0] };
+        depth_first(self.blocks.len(), roots, max_depth, |index| {
+            self.blocks[index].borrow().succ().iter().map(|succ| index_of[&Rc::as_ptr(succ)]).collect()
+        })
+    }
+}
+
+
+/// Index of every block in `blocks`, by address
+fn index_of(blocks: &[BlockPtr]) -> HashMap<*const RefCell<BasicBlock>, usize> {
+    blocks.iter().enumerate().map(|(index, block)| (Rc::as_ptr(block), index)).collect()
+}
+
+
+/// Indices of the blocks reachable from the first one, each after all of its successors but those reached through a back edge.
+/// Backward data-flow passes such as liveness converge fastest visiting blocks in this order. Unreachable blocks are left out
+pub fn postorder(blocks: &[BlockPtr]) -> Vec<usize> {
+    let index_of = index_of(blocks);
+    let mut visited = vec![false; blocks.len()];
+    let mut order = Vec::with_capacity(blocks.len());
+    // Blocks being explored along with the index of their next successor to explore, kept on the heap as in `depth_first`
+    let mut pending: Vec<(usize, usize)> = vec![];
+    if !blocks.is_empty() {
+        visited[0] = true;
+        pending.push((0, 0));
+    }
+    while let Some(&(block, next)) = pending.last() {
+        let succ = blocks[block].borrow().succ().get(next).map(|succ| index_of[&Rc::as_ptr(succ)]);
+        match succ {
+            Some(succ) => {
+                pending.last_mut().expect("the explored block is pending").1 += 1;
+                if !std::mem::replace(&mut visited[succ], true) {
+                    pending.push((succ, 0));
+                }
+            },
+            None => {
+                order.push(block);
+                pending.pop();
+            },
+        }
+    }
+    order
+}
+
+
+/// Reverse of `postorder`: every block comes before its successors, back edges aside.
+/// The order of forward data-flow passes and dominator computations. Unreachable blocks are left out
+pub fn reverse_postorder(blocks: &[BlockPtr]) -> Vec<usize> {
+    let mut order = postorder(blocks);
+    order.reverse();
+    order
+}
+
+
+/// Nodes reached by a depth-first traversal
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Traversal {
+    /// Reached nodes in preorder
+    pub order: Vec<usize>,
+    /// Whether some nodes were only found past the max depth and left unexplored
+    pub truncated: bool,
+}
+
+
+/// Depth-first traversal of a graph of `len` nodes from `roots`, `successors` giving the edges of every node.
+/// The traversal keeps its own stack instead of recursing, so long paths can't overflow the call stack.
+/// Roots are at depth 1 and nodes only found deeper than `max_depth` are not visited, 0 for no limit
+pub fn depth_first(len: usize, roots: impl IntoIterator<Item = usize>, max_depth: usize, mut successors: impl FnMut(usize) -> Vec<usize>) -> Traversal {
+    let max_depth = if max_depth > 0 { max_depth } else { usize::MAX };
+    let mut visited = vec![false; len];
+    let mut order = vec![];
+    let mut cut = vec![];
+    let mut pending: Vec<(usize, usize)> = roots.into_iter().filter(|&root| root < len).map(|root| (root, 1)).collect();
+    pending.reverse();
+    while let Some((node, depth)) = pending.pop() {
+        if std::mem::replace(&mut visited[node], true) {
+            continue;
+        }
+        order.push(node);
+        // Pushed in reverse so the first successor is explored first, as a recursive traversal would
+        for succ in successors(node).into_iter().rev().filter(|&succ| succ < len && !visited[succ]) {
+            if depth < max_depth {
+                pending.push((succ, depth + 1));
+            } else {
+                cut.push(succ);
+            }
+        }
+    }
+    // A node cut on one path may still be reached through a shorter one
+    let truncated = cut.into_iter().any(|node| !visited[node]);
+    Traversal { order, truncated }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::testing::SAMPLE_METHODS;
+    use super::*;
+
+    #[test]
+    fn test_depth_first() {
+        // 0 -> 1 -> 3, 0 -> 2 -> 3, 4 unreachable
+        let edges = [vec![1, 2], vec![3], vec![3], vec![], vec![0]];
+        let traversal = depth_first(5, [0], 0, |node| edges[node].clone());
+        assert_eq!(traversal, Traversal { order: vec![0, 1, 3, 2], truncated: false });
+        assert_eq!(depth_first(5, [0], 2, |node| edges[node].clone()), Traversal { order: vec![0, 1, 2], truncated: true });
+        // 3 is cut below 1 and 2, but it is also a root
+        assert_eq!(depth_first(5, [0, 3], 2, |node| edges[node].clone()), Traversal { order: vec![0, 1, 2, 3], truncated: false });
+    }
+
+    #[test]
+    fn test_reverse_postorder() {
+        // The method of `test_get_blocks1`: 0 -> 1 | 2, 2 -> 3 | 4, and 1, 3 and 4 return or throw
+        let (_, get_request_time) = SAMPLE_METHODS[1];
+        let cfg = MethodCfg::build(get_request_time).unwrap();
+        assert_eq!(postorder(cfg.blocks()), [1, 3, 4, 2, 0]);
+        assert_eq!(reverse_postorder(cfg.blocks()), [0, 2, 4, 3, 1]);
+
+        // 0 -> 1 -> 0 and 2 -> 1, 2 is unreachable from the entry
+        let blocks = [BasicBlock::new(), BasicBlock::new(), BasicBlock::new()];
+        for (from, to) in [(0, 1), (1, 0), (2, 1)] {
+            blocks[from].borrow_mut().add_succ(blocks[to].clone());
+        }
+        assert_eq!(postorder(&blocks), [1, 0]);
+        assert_eq!(reverse_postorder(&blocks), [0, 1]);
+        assert!(postorder(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_deeply_nested_cfg() {
+        // 100000 nested if-eqz v0, +2 followed by a return-void: every if starts a block falling through to the next one,
+        // deep enough to overflow the stack of a recursive traversal
+        let depth = 100_000;
+        let mut raw_bytecode = [0x0038, 0x0002].repeat(depth);
+        raw_bytecode.push(0x000E);
+        let cfg = MethodCfg::build(&raw_bytecode).unwrap();
+        let traversal = cfg.depth_first(0);
+        assert_eq!(traversal.order.len(), cfg.len());
+        assert!(!traversal.truncated);
+        let traversal = cfg.depth_first(1000);
+        assert_eq!(traversal.order.len(), 1000);
+        assert!(traversal.truncated);
+    }
+}
@@ -0,0 +1,109 @@
+use std::ops::AddAssign;
+
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+
+use crate::options::Strictness;
+
+
+/// How much of the code of an input could be decoded, counted over the methods with code that were walked.
+/// Deserializes from its serialized form, the percentages being ignored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Coverage {
+    pub methods: usize,
+    /// Methods decoded without a problem
+    pub decoded_methods: usize,
+    /// Methods kept with some undecodable code units skipped, only in lenient mode
+    pub partial_methods: usize,
+    /// Methods dropped because of an undecodable instruction, only in strict mode
+    pub skipped_methods: usize,
+    pub code_units: usize,
+    /// Code units of the decoded and partially decoded methods, without the undecodable ones
+    pub decoded_code_units: usize,
+}
+
+
+impl Coverage {
+    /// Records a method of `code_units` code units, `errors` of which couldn't be decoded
+    pub(crate) fn add_method(&mut self, code_units: usize, errors: usize, strictness: Strictness) {
+        self.methods += 1;
+        self.code_units += code_units;
+        if errors == 0 {
+            self.decoded_methods += 1;
+            self.decoded_code_units += code_units;
+        } else if strictness == Strictness::Lenient {
+            self.partial_methods += 1;
+            self.decoded_code_units += code_units.saturating_sub(errors);
+        } else {
+            self.skipped_methods += 1;
+        }
+    }
+
+    /// Percentage of the methods decoded at least partially, 100 when there are no methods
+    pub fn methods_pct(&self) -> f64 {
+        percentage(self.decoded_methods + self.partial_methods, self.methods)
+    }
+
+    /// Percentage of the code units decoded, 100 when there is no code
+    pub fn code_units_pct(&self) -> f64 {
+        percentage(self.decoded_code_units, self.code_units)
+    }
+}
+
+
+fn percentage(part: usize, total: usize) -> f64 {
+    if total == 0 { 100.0 } else { part as f64 / total as f64 * 100.0 }
+}
+
+
+impl AddAssign for Coverage {
+    fn add_assign(&mut self, other: Self) {
+        self.methods += other.methods;
+        self.decoded_methods += other.decoded_methods;
+        self.partial_methods += other.partial_methods;
+        self.skipped_methods += other.skipped_methods;
+        self.code_units += other.code_units;
+        self.decoded_code_units += other.decoded_code_units;
+    }
+}
+
+
+impl Serialize for Coverage {
+    /// The counters followed by `methods_pct` and `code_units_pct`
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Coverage", 8)?;
+        state.serialize_field("methods", &self.methods)?;
+        state.serialize_field("decoded_methods", &self.decoded_methods)?;
+        state.serialize_field("partial_methods", &self.partial_methods)?;
+        state.serialize_field("skipped_methods", &self.skipped_methods)?;
+        state.serialize_field("code_units", &self.code_units)?;
+        state.serialize_field("decoded_code_units", &self.decoded_code_units)?;
+        state.serialize_field("methods_pct", &self.methods_pct())?;
+        state.serialize_field("code_units_pct", &self.code_units_pct())?;
+        state.end()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_coverage_percentages() {
+        let mut strict = Coverage::default();
+        strict.add_method(30, 0, Strictness::Strict);
+        strict.add_method(10, 2, Strictness::Strict);
+        assert_eq!((strict.decoded_methods, strict.skipped_methods, strict.decoded_code_units), (1, 1, 30));
+        assert_eq!((strict.methods_pct(), strict.code_units_pct()), (50.0, 75.0));
+
+        let mut lenient = Coverage::default();
+        lenient.add_method(10, 2, Strictness::Lenient);
+        assert_eq!((lenient.partial_methods, lenient.methods_pct(), lenient.code_units_pct()), (1, 100.0, 80.0));
+
+        strict += lenient;
+        assert_eq!((strict.methods, strict.code_units, strict.decoded_code_units), (3, 50, 38));
+        assert_eq!(Coverage::default().code_units_pct(), 100.0);
+        let json = serde_json::to_value(strict).unwrap();
+        assert_eq!(json["code_units_pct"], 76.0);
+    }
+}
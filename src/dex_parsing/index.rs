@@ -0,0 +1,78 @@
+use super::{instruction::Instruction, MethodDecode};
+
+
+/// Instructions of a method by the code unit offset they start at, to resolve branch targets and annotate listings
+#[derive(Debug, Clone)]
+pub struct InstructionIndex<'a> {
+    instructions: &'a [Instruction],
+    /// Position in `instructions` of the instruction starting at every offset, `NONE` inside instructions, payloads and undecoded code
+    positions: Vec<u32>,
+}
+
+
+const NONE: u32 = u32::MAX;
+
+
+impl<'a> InstructionIndex<'a> {
+    /// Indexes instructions in code order, as decoded from a single method
+    pub fn new(instructions: &'a [Instruction]) -> Self {
+        let len = instructions.last().map_or(0, |inst| *inst.offset() + 1);
+        let mut positions = vec![NONE; len];
+        for (position, inst) in instructions.iter().enumerate() {
+            positions[*inst.offset()] = position as u32;
+        }
+        Self { instructions, positions }
+    }
+
+    /// Instruction starting at `offset`, `None` when no decoded instruction starts there
+    pub fn get(&self, offset: usize) -> Option<&'a Instruction> {
+        self.position(offset).map(|position| &self.instructions[position])
+    }
+
+    /// Position in the indexed instructions of the instruction starting at `offset`
+    pub fn position(&self, offset: usize) -> Option<usize> {
+        self.positions.get(offset).filter(|&&position| position != NONE).map(|&position| position as usize)
+    }
+
+    /// Instruction spanning `offset`, whether it starts there or further up
+    pub fn containing(&self, offset: usize) -> Option<&'a Instruction> {
+        let position = self.instructions.partition_point(|inst| *inst.offset() <= offset).checked_sub(1)?;
+        let inst = &self.instructions[position];
+        (offset < *inst.offset() + inst.opcode().format().units()).then_some(inst)
+    }
+
+    pub fn instructions(&self) -> &'a [Instruction] {
+        self.instructions
+    }
+}
+
+
+impl MethodDecode {
+    /// Index of the decoded instructions by offset
+    pub fn index(&self) -> InstructionIndex<'_> {
+        InstructionIndex::new(&self.instructions)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::dex_parsing::{decode_method_lenient, Opcode};
+
+    #[test]
+    fn test_branch_target_instruction() {
+        // const/4 v0, 0; if-eqz v0, +4; const/4 v0, 1; nop; return v0
+        let decoded = decode_method_lenient(&[0x0012, 0x0038, 0x0004, 0x1012, 0x0000, 0x000F]);
+        let index = decoded.index();
+        let branch = index.get(1).unwrap();
+        assert_eq!(*branch.opcode(), Opcode::IfEqz);
+        let target = index.get(branch.branch_target().unwrap()).unwrap();
+        assert_eq!((*target.opcode(), *target.offset()), (Opcode::Return, 5));
+        assert_eq!(index.position(5), Some(4));
+        // The second code unit of the if-eqz and past the end
+        assert!(index.get(2).is_none());
+        assert!(index.get(6).is_none());
+        assert_eq!(*index.containing(2).unwrap().offset(), 1);
+        assert!(index.containing(6).is_none());
+    }
+}
@@ -2,8 +2,9 @@ use core::fmt;
 use std::error::Error;
 
 use num_traits::FromPrimitive;
+use serde::Serialize;
 
-use super::opcode::Opcode;
+use super::{opcode::Opcode, registers::Registers};
 
 
 #[macro_export]
@@ -34,8 +35,36 @@ pub struct InstructionParsingError {
     offset: usize,
 }
 
+impl InstructionParsingError {
+    pub(crate) fn new(byte: u8, offset: usize) -> Self {
+        Self { byte, offset }
+    }
+
+    /// Error for the code unit at `offset`, naming its low byte, 0 past the end of the method
+    pub(crate) fn at(raw_bytecode: &[u16], offset: usize) -> Self {
+        Self::new(word(raw_bytecode, offset).map_or(0, |unit| (unit & 0xFF) as u8), offset)
+    }
+
+    /// Offending opcode byte
+    pub fn byte(&self) -> u8 {
+        self.byte
+    }
+
+    /// Offset of the offending code unit in the method bytecode
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
 impl Error for InstructionParsingError {}
 
+
+/// Code unit `index` of a method, an error at `index` when the method ends before it.
+/// Every read of the code units while decoding goes through it, so that truncated methods can't panic
+pub(crate) fn word(raw_bytecode: &[u16], index: usize) -> Result<u16, InstructionParsingError> {
+    raw_bytecode.get(index).copied().ok_or(InstructionParsingError { byte: 0, offset: index })
+}
+
 impl fmt::Display for InstructionParsingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Invalid instruction at offset {}: {}", self.offset, self.byte)
@@ -43,77 +72,155 @@ impl fmt::Display for InstructionParsingError {
 }
 
 
-#[derive(Debug, PartialEq)]
+/// Decoded instruction. Serializes as its lightweight form: the opcode mnemonic, the offset and the branch target
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Instruction {
     /// The opcode of the instruction
     opcode: Opcode,
     /// The offset of the instruction in the method bytecode
     offset: usize,
-    /// Branch target of the instruction
+    /// Absolute offset of the branch target or payload, which may lie past the end of the method
     branch_target: Option<usize>,
+    /// Constant pool index referenced by the instruction: string, type, field, method, call site, method handle or proto
+    #[serde(skip)]
+    reference: Option<u32>,
+    /// Register operands, in the order of the instruction format
+    #[serde(skip)]
+    registers: Registers,
 }
 
 
 impl Instruction {
     pub fn try_from_raw_bytecode(raw_bytecode: &[u16], offset: usize) -> Result<Option<(Self, usize)>, InstructionParsingError>  {
-        let raw_bytecode = &raw_bytecode[offset..];
-        let (opcode_byte, immediate_args) = split_word!(raw_bytecode[0]);
+        let (opcode, length) = match Self::try_opcode_from_raw_bytecode(raw_bytecode, offset)? {
+            Some(decoded) => decoded,
+            None => return Ok(None),
+        };
+        let (opcode_byte, _): (u8, u8) = split_word!(word(raw_bytecode, offset)?);
+        // Operands past the end of the method make the whole instruction invalid
+        let invalid = |_| InstructionParsingError { byte: opcode_byte, offset };
+        let operand = |unit: usize| word(raw_bytecode, offset + unit).map_err(invalid);
+        let branch_target = Self::decode_relative_offset(raw_bytecode, offset)?.map(|relative| (offset as i64 + relative as i64) as usize);
+        let reference = match opcode_byte {
+            0x1A | 0x1C | 0x1F | 0x20 | 0x22..=0x25 | 0x52..=0x72 | 0x74..=0x78 | 0xFA..=0xFF => Some(operand(1)? as u32),
+            0x1B => Some(concat_words!(operand(1)?, operand(2)?)),
+            _ => None
+        };
+        let registers = Registers::decode(opcode.format(), raw_bytecode, offset).map_err(invalid)?;
+        Ok(Some((Instruction { opcode, offset, branch_target, reference, registers }, length)))
+    }
+
+    /// Decodes only the opcode and the length in code units of the instruction at `offset`, skipping its operands
+    /// except for the checks of `InstructionFormat::accepts` and the branch targets, which must not lie before the
+    /// start of the method
+    pub fn try_opcode_from_raw_bytecode(raw_bytecode: &[u16], offset: usize) -> Result<Option<(Opcode, usize)>, InstructionParsingError> {
+        let first_unit = word(raw_bytecode, offset)?;
+        let (opcode_byte, immediate_args) = split_word!(first_unit);
         let opcode: Opcode = FromPrimitive::from_u8(opcode_byte).ok_or(InstructionParsingError { byte: opcode_byte, offset: offset })?;
 
-        let (length, branch_target) = match opcode_byte {
-            0x0 => {
-                if (1..=3).contains(&immediate_args) {
-                    return Ok(None);
-                }
-                (1, None)
-            },
-            0x01 | 0x04 | 0x07 | 0x0A..=0x12 | 0x1D | 0x1E | 0x21 | 0x27 | 0x7B..=0x8F | 0xB0..=0xCF => (1, None),
-            0x02 | 0x05 | 0x08 | 0x13 | 0x15 | 0x16 | 0x19 | 0x1A | 0x1C | 0x1F | 0x20 | 0x22 | 0x23 | 0x2D..=0x31 | 0x44..=0x6D | 0x90..=0xAF | 0xD0..=0xE2 | 0xFE | 0xFF => {
-                if raw_bytecode.len() < 2 {
-                    return Err(InstructionParsingError { byte: opcode_byte, offset: offset });
-                }
-                (2, None)
-            },
-            0x03 | 0x06 | 0x09 | 0x14 | 0x17 | 0x1B | 0x24..=0x26 | 0x6E..=0x72 | 0x74..=0x78 | 0xFC | 0xFD => {
-                if raw_bytecode.len() < 3 {
-                    return Err(InstructionParsingError { byte: opcode_byte, offset: offset });
-                }
-                (3, None)
-            },
-            0xFA | 0xFB => (4, None),
-            0x18 => (5, None),
-            0x28 => (1, Some(immediate_args as i8 as i32)),
-            0x29 => (2, Some(immediate_args as i16 as i32)),
-            0x2A => {
-                if raw_bytecode.len() < 3 {
-                    return Err(InstructionParsingError { byte: opcode_byte, offset: offset });
-                }
-                (3, Some(concat_words!(raw_bytecode[1], raw_bytecode[2]) as i32))
-            },
-            0x2B | 0x2C => {
-                if raw_bytecode.len() < 3 {
-                    return Err(InstructionParsingError { byte: opcode_byte, offset: offset });
-                }
-                (3, Some(concat_words!(raw_bytecode[1], raw_bytecode[2]) as i32))
-            },
-            0x32..=0x3D => {
-                if raw_bytecode.len() < 2 {
-                    return Err(InstructionParsingError { byte: opcode_byte, offset: offset });
-                }
-                (2, Some(raw_bytecode[1] as i16 as i32))
-            },
-            0x3e..=0x43 | 0x73 | 0x79..=0x7a | 0xe3..=0xf9 => {
-                return Err(InstructionParsingError { byte: opcode_byte, offset: offset });
-            }
-        };
-        if length > raw_bytecode.len() {
+        // nop doubles as the header of the payload pseudo-instructions
+        if opcode == Opcode::Nop && (1..=3).contains(&immediate_args) {
+            return Ok(None);
+        }
+        let format = opcode.format();
+        if !format.accepts(first_unit) {
             return Err(InstructionParsingError { byte: opcode_byte, offset: offset });
         }
-        let branch_target = match branch_target {
-            Some(target) => Some((target + offset as i32) as usize),
-            None => None
+        let length = format.units();
+        if offset + length > raw_bytecode.len() {
+            return Err(InstructionParsingError { byte: opcode_byte, offset: offset });
+        }
+        Self::decode_relative_offset(raw_bytecode, offset)?;
+        Ok(Some((opcode, length)))
+    }
+
+    /// Offset of the branch target or payload of the instruction at `offset`, relative to it. Targets before the start
+    /// of the method can't be represented and make the instruction invalid
+    fn decode_relative_offset(raw_bytecode: &[u16], offset: usize) -> Result<Option<i32>, InstructionParsingError> {
+        let (opcode_byte, immediate_args): (u8, u8) = split_word!(word(raw_bytecode, offset)?);
+        let invalid = |_| InstructionParsingError { byte: opcode_byte, offset };
+        let operand = |unit: usize| word(raw_bytecode, offset + unit).map_err(invalid);
+        // 10t, 20t and 21t store 8 and 16 bit offsets, 30t and 31t 32 bit ones, the low word first
+        let relative_offset = match opcode_byte {
+            0x28 => immediate_args as i8 as i32,
+            0x29 | 0x32..=0x3D => operand(1)? as i16 as i32,
+            0x26 | 0x2A..=0x2C => concat_words!(operand(1)?, operand(2)?) as i32,
+            _ => return Ok(None),
+        };
+        if (offset as i64) + (relative_offset as i64) < 0 {
+            return Err(InstructionParsingError { byte: opcode_byte, offset });
+        }
+        Ok(Some(relative_offset))
+    }
+
+    /// Length in code units of the payload pseudo-instruction at `offset`, `None` if there is no payload there
+    pub fn payload_length(raw_bytecode: &[u16], offset: usize) -> Option<usize> {
+        let raw_bytecode = raw_bytecode.get(offset..)?;
+        match *raw_bytecode.first()? {
+            // packed-switch-payload: ident, size, first_key (2 units), targets (size * 2 units)
+            0x0100 => Some(*raw_bytecode.get(1)? as usize * 2 + 4),
+            // sparse-switch-payload: ident, size, keys (size * 2 units), targets (size * 2 units)
+            0x0200 => Some(*raw_bytecode.get(1)? as usize * 4 + 2),
+            // fill-array-data-payload: ident, element_width, size (2 units), data (size * element_width bytes)
+            0x0300 => {
+                let element_width = *raw_bytecode.get(1)? as usize;
+                let size = concat_words!(*raw_bytecode.get(2)?, *raw_bytecode.get(3)?) as usize;
+                Some((size * element_width + 1) / 2 + 4)
+            },
+            _ => None
+        }
+    }
+
+    /// Absolute offsets of the case targets of a `packed-switch` or `sparse-switch`, read from its payload in the method bytecode.
+    /// `None` for other instructions, or when the payload is missing, truncated or has a target before the method start
+    pub fn switch_targets(&self, raw_bytecode: &[u16]) -> Option<Vec<usize>> {
+        let payload_offset = (*self.branch_target())?;
+        let payload = raw_bytecode.get(payload_offset..)?;
+        let size = *payload.get(1)? as usize;
+        let targets = match (self.opcode, *payload.first()?) {
+            (Opcode::PackedSwitch, 0x0100) => payload.get(4..4 + size * 2)?,
+            (Opcode::SparseSwitch, 0x0200) => payload.get(2 + size * 2..2 + size * 4)?,
+            _ => return None,
         };
-        Ok(Some((Instruction { opcode, offset, branch_target }, length)))
+        targets.chunks_exact(2)
+            .map(|target| usize::try_from(self.offset as i64 + concat_words!(target[0], target[1]) as i32 as i64).ok())
+            .collect()
+    }
+
+    /// Argument registers of a 35c instruction, `invoke-kind`, `filled-new-array`, `invoke-custom` or `invoke-polymorphic`, in argument order.
+    /// The format is `A|G|op BBBB F|E|D|C` with A the argument count: the first four registers are the nibbles of the third
+    /// code unit from the lowest, and the fifth one is G, the low nibble of the byte after the opcode.
+    /// `None` for other instructions, or when the count is above 5 or the code units are missing
+    pub fn invocation_registers(&self, raw_bytecode: &[u16]) -> Option<Vec<u8>> {
+        if !matches!(self.opcode as u8, 0x24 | 0x6E..=0x72 | 0xFA | 0xFC) {
+            return None;
+        }
+        let (_, immediate_args): (u8, u8) = split_word!(*raw_bytecode.get(self.offset)?);
+        let registers = *raw_bytecode.get(self.offset + 2)?;
+        let count = immediate_args >> 4;
+        if count > 5 {
+            return None;
+        }
+        Some((0..count)
+            .map(|i| match i {
+                4 => immediate_args & 0xF,
+                _ => (registers >> (i * 4) & 0xF) as u8,
+            })
+            .collect())
+    }
+
+    /// Constant pool the reference of the instruction indexes, as named in smali: `string`, `type`, `field`, `method`, `call_site`, `method_handle` or `proto`
+    pub fn reference_kind(&self) -> Option<&'static str> {
+        self.reference?;
+        Some(match self.opcode as u8 {
+            0x1A | 0x1B => "string",
+            0x52..=0x6D => "field",
+            0x6E..=0x72 | 0x74..=0x78 | 0xFA | 0xFB => "method",
+            0xFC | 0xFD => "call_site",
+            0xFE => "method_handle",
+            0xFF => "proto",
+            _ => "type",
+        })
     }
 
     pub fn opcode(&self) -> &Opcode {
@@ -124,22 +231,143 @@ impl Instruction {
         &self.offset
     }
 
+    /// Offset of the branch target of a goto, if-* or switch, or of the payload of a switch or fill-array-data,
+    /// relative to the instruction. Negative for backward branches
+    pub fn relative_offset(&self) -> Option<i32> {
+        self.branch_target.map(|target| (target as i64 - self.offset as i64) as i32)
+    }
+
+    /// Absolute offset of the branch target or payload, which may lie past the end of the method
     pub fn branch_target(&self) -> &Option<usize> {
         &self.branch_target
     }
+
+    /// Absolute offset of the branch target or payload, `None` when it lies past the end of a method of `method_len`
+    /// code units
+    pub fn absolute_target(&self, method_len: usize) -> Option<usize> {
+        self.branch_target.filter(|&target| target < method_len)
+    }
+
+    pub fn reference(&self) -> &Option<u32> {
+        &self.reference
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub(super) fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+}
+
+
+impl fmt::Display for Instruction {
+    /// The mnemonic followed by the absolute branch target or the constant pool reference, e.g. `if-eqz 0x0012` or `invoke-virtual method@714`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.opcode)?;
+        if let Some(target) = self.branch_target {
+            write!(f, " {:#06x}", target)?;
+        }
+        if let (Some(reference), Some(kind)) = (self.reference, self.reference_kind()) {
+            write!(f, " {}@{}", kind, reference)?;
+        }
+        Ok(())
+    }
 }
 
 
 #[cfg(test)]
 mod test {
+    use crate::{dex_parsing::{decode_method_lenient, decode_opcodes, InstructionFormat}, options::Normalization};
     use super::*;
 
+    #[test]
+    fn test_switch_targets() {
+        let raw_bytecode = [
+            // nop; packed-switch v0, +8; sparse-switch v0, +14
+            0x0000, 0x002B, 8, 0, 0x002C, 14, 0,
+            // return-void; nop
+            0x000E, 0x0000,
+            // packed-switch-payload, first key 10, targets +6, +5
+            0x0100, 2, 10, 0, 6, 0, 5, 0,
+            // nop; sparse-switch-payload, keys 1 and 1000, targets +3, -4
+            0x0000, 0x0200, 2, 1, 0, 1000, 0, 3, 0, 0xFFFC, 0xFFFF,
+        ];
+        let hand_computed = |inst: &Instruction| {
+            let payload = inst.branch_target().unwrap();
+            let size = raw_bytecode[payload + 1] as usize;
+            let targets = if *inst.opcode() == Opcode::PackedSwitch { &raw_bytecode[payload + 4..] } else { &raw_bytecode[payload + 2 + size * 2..] };
+            (0..size).map(|i| (*inst.offset() as i32 + concat_words!(targets[i * 2], targets[i * 2 + 1]) as i32) as usize).collect::<Vec<_>>()
+        };
+
+        let (packed, _) = Instruction::try_from_raw_bytecode(&raw_bytecode, 1).unwrap().unwrap();
+        assert_eq!(packed.switch_targets(&raw_bytecode), Some(hand_computed(&packed)));
+        assert_eq!(packed.switch_targets(&raw_bytecode), Some(vec![7, 6]));
+        let (sparse, _) = Instruction::try_from_raw_bytecode(&raw_bytecode, 4).unwrap().unwrap();
+        assert_eq!(sparse.switch_targets(&raw_bytecode), Some(hand_computed(&sparse)));
+        assert_eq!(sparse.switch_targets(&raw_bytecode), Some(vec![7, 0]));
+
+        assert_eq!(packed.switch_targets(&raw_bytecode[..12]), None);
+        let (return_void, _) = Instruction::try_from_raw_bytecode(&raw_bytecode, 7).unwrap().unwrap();
+        assert_eq!(return_void.switch_targets(&raw_bytecode), None);
+    }
+
+    #[test]
+    fn test_invocation_registers() {
+        // invoke-virtual {v1, v2, v3, v4, v5}, method@7: A = 5, G = 5, F|E|D|C = 4|3|2|1
+        let raw_bytecode = [0x556E, 7, 0x4321];
+        let (invoke, _) = Instruction::try_from_raw_bytecode(&raw_bytecode, 0).unwrap().unwrap();
+        assert_eq!(invoke.invocation_registers(&raw_bytecode), Some(vec![1, 2, 3, 4, 5]));
+        // The fifth register comes from G alone, not from the upper nibble holding the count
+        assert_eq!(invoke.invocation_registers(&[0x5A6E, 7, 0x4321]), Some(vec![1, 2, 3, 4, 10]));
+
+        // invoke-static {v7}: a single argument is C, the unused G and F|E|D nibbles are ignored
+        let raw_bytecode = [0x1F71, 3, 0xFFF7];
+        let (invoke, _) = Instruction::try_from_raw_bytecode(&raw_bytecode, 0).unwrap().unwrap();
+        assert_eq!(invoke.invocation_registers(&raw_bytecode), Some(vec![7]));
+        assert_eq!(invoke.invocation_registers(&[0x0071, 3, 0x0000]), Some(vec![]));
+
+        assert_eq!(invoke.invocation_registers(&[0x6071, 3, 0x4321]), None);
+        assert_eq!(invoke.invocation_registers(&[0x1F71, 3]), None);
+
+        // filled-new-array {v0, v1}, type@2
+        let raw_bytecode = [0x2024, 2, 0x0010];
+        let (filled, _) = Instruction::try_from_raw_bytecode(&raw_bytecode, 0).unwrap().unwrap();
+        assert_eq!(filled.invocation_registers(&raw_bytecode), Some(vec![0, 1]));
+        // invoke-virtual/range uses the 3rc format
+        let raw_bytecode = [0x0374, 7, 0x0010];
+        let (range, _) = Instruction::try_from_raw_bytecode(&raw_bytecode, 0).unwrap().unwrap();
+        assert_eq!(range.invocation_registers(&raw_bytecode), None);
+    }
+
+    #[test]
+    fn test_format_operands() {
+        assert_eq!(Opcode::InvokeVirtual.format(), InstructionFormat::F35c);
+        assert_eq!((InstructionFormat::F35c.units(), InstructionFormat::F35c.max_registers()), (3, Some(5)));
+        assert_eq!((Opcode::MoveFrom16.format().id(), Opcode::MoveFrom16.format().max_registers()), ("22x", Some(2)));
+        assert_eq!(Opcode::InvokeVirtualRange.format().max_registers(), None);
+
+        // invoke-virtual declaring 7 argument registers, which a 35c instruction can't hold
+        let raw_bytecode = [0x706E, 7, 0x4321, 0x000E];
+        let err = Instruction::try_from_raw_bytecode(&raw_bytecode, 0).unwrap_err();
+        assert_eq!((err.byte(), err.offset()), (0x6E, 0));
+        assert!(Instruction::try_opcode_from_raw_bytecode(&raw_bytecode, 0).is_err());
+        // Strict decoding drops the method, lenient decoding steps over the invoke
+        assert!(decode_opcodes(&raw_bytecode, &mut vec![], &Normalization::None).is_err());
+        assert_eq!(decode_method_lenient(&raw_bytecode).undecoded[0], 0);
+        // The same invoke with 5 registers is fine
+        assert!(Instruction::try_from_raw_bytecode(&[0x556E, 7, 0x4321], 0).unwrap().is_some());
+        // return-void with a non-zero unused byte
+        assert!(Instruction::try_from_raw_bytecode(&[0x010E], 0).is_err());
+    }
+
     #[test]
     fn test_try_from_raw_bytecode0() {
         let raw_bytecode = [8303, 921, 33];
         let (instruction, length) = Instruction::try_from_raw_bytecode(&raw_bytecode, 0).unwrap().expect("Failed to parse instruction");
         assert!(length == 3);
-        assert_eq!(instruction, Instruction { opcode: Opcode::InvokeSuper, offset: 0, branch_target: None });
+        assert_eq!(instruction, Instruction { opcode: Opcode::InvokeSuper, offset: 0, branch_target: None, reference: Some(921), registers: Registers::List { registers: [1, 2, 0, 0, 0], len: 2 } });
     }
 
     #[test]
@@ -147,7 +375,7 @@ mod test {
         let raw_bytecode = [45874, 102];
         let (instruction, length) = Instruction::try_from_raw_bytecode(&raw_bytecode, 0).unwrap().expect("Failed to parse instruction");
         assert_eq!(length, 2);
-        assert_eq!(instruction, Instruction { opcode: Opcode::IfEq, offset: 0, branch_target: Some(102) });
+        assert_eq!(instruction, Instruction { opcode: Opcode::IfEq, offset: 0, branch_target: Some(102), reference: None, registers: Registers::List { registers: [3, 11, 0, 0, 0], len: 2 } });
     }
 
     #[test]
@@ -155,6 +383,145 @@ mod test {
         let raw_bytecode = [290, 648];
         let (instruction, length) = Instruction::try_from_raw_bytecode(&raw_bytecode, 0).unwrap().expect("Failed to parse instruction");
         assert_eq!(length, 2);
-        assert_eq!(instruction, Instruction { opcode: Opcode::NewInstance, offset: 0, branch_target: None });
+        assert_eq!(instruction, Instruction { opcode: Opcode::NewInstance, offset: 0, branch_target: None, reference: Some(648), registers: Registers::List { registers: [1, 0, 0, 0, 0], len: 1 } });
+    }
+
+    #[test]
+    fn test_try_from_raw_bytecode_move16() {
+        // move/16 v300, v400; move-wide/16 v300, v400; move-object/16 v300, v400; return-void
+        let raw_bytecode = [0x0003, 300, 400, 0x0006, 300, 400, 0x0009, 300, 400, 0x000E];
+        let moved = Registers::List { registers: [300, 400, 0, 0, 0], len: 2 };
+        let none = Registers::List { registers: [0; 5], len: 0 };
+        let expected = [(Opcode::Move16, 0, moved), (Opcode::MoveWide16, 3, moved), (Opcode::MoveObject16, 6, moved), (Opcode::ReturnVoid, 9, none)];
+        let mut offset = 0;
+        for (opcode, expected_offset, registers) in expected {
+            let (instruction, length) = Instruction::try_from_raw_bytecode(&raw_bytecode, offset).unwrap().expect("Failed to parse instruction");
+            assert_eq!(instruction, Instruction { opcode, offset: expected_offset, branch_target: None, reference: None, registers });
+            offset += length;
+        }
+        assert_eq!(offset, raw_bytecode.len());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_try_from_raw_bytecode_goto16() {
+        // nop; goto/16 +5, the offset is in the second code unit
+        let (instruction, length) = Instruction::try_from_raw_bytecode(&[0x0000, 0x0029, 5], 1).unwrap().unwrap();
+        assert_eq!((length, *instruction.branch_target()), (2, Some(6)));
+    }
+
+    #[test]
+    fn test_try_from_raw_bytecode_goto_wide_targets() {
+        // Five nops; goto/16 -5, back to the first nop
+        let raw_bytecode = [0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0029, 0xFFFB];
+        let (instruction, length) = Instruction::try_from_raw_bytecode(&raw_bytecode, 5).unwrap().unwrap();
+        assert_eq!((instruction.opcode, length, *instruction.branch_target()), (Opcode::Goto16, 2, Some(0)));
+        // The same goto/16 -5 one code unit earlier lands before the method instead of wrapping around
+        assert!(Instruction::try_from_raw_bytecode(&raw_bytecode[1..], 4).is_err());
+        // Shallow decoding rejects it as well
+        assert_eq!(Instruction::try_opcode_from_raw_bytecode(&raw_bytecode, 5).unwrap(), Some((Opcode::Goto16, 2)));
+        assert!(Instruction::try_opcode_from_raw_bytecode(&raw_bytecode[1..], 4).is_err());
+
+        // nop; nop; goto/32 +70000, the low word first
+        let raw_bytecode = [0x0000, 0x0000, 0x002A, (70000 & 0xFFFF) as u16, (70000 >> 16) as u16];
+        let (instruction, length) = Instruction::try_from_raw_bytecode(&raw_bytecode, 2).unwrap().unwrap();
+        assert_eq!((instruction.opcode, length, *instruction.branch_target()), (Opcode::Goto32, 3, Some(70002)));
+        // goto/32 -70000 from the same place
+        let backward = (-70000i32) as u32;
+        assert!(Instruction::try_from_raw_bytecode(&[0x0000, 0x0000, 0x002A, backward as u16, (backward >> 16) as u16], 2).is_err());
+        assert!(Instruction::try_opcode_from_raw_bytecode(&[0x0000, 0x0000, 0x002A, backward as u16, (backward >> 16) as u16], 2).is_err());
+        // goto -1 as the first instruction
+        assert!(Instruction::try_opcode_from_raw_bytecode(&[0xFF28], 0).is_err());
+    }
+
+    #[test]
+    fn test_relative_and_absolute_targets() {
+        let targets = |raw_bytecode: &[u16], offset: usize| {
+            let (instruction, _) = Instruction::try_from_raw_bytecode(raw_bytecode, offset).unwrap().unwrap();
+            (instruction.relative_offset(), *instruction.branch_target(), instruction.absolute_target(raw_bytecode.len()))
+        };
+        // nop; nop; if-eqz v0, -2; return-void
+        assert_eq!(targets(&[0x0000, 0x0000, 0x0038, 0xFFFE, 0x000E], 2), (Some(-2), Some(0), Some(0)));
+        // return-void; goto -1
+        assert_eq!(targets(&[0x000E, 0xFF28], 1), (Some(-1), Some(0), Some(0)));
+        // goto +10; return-void
+        assert_eq!(targets(&[0x0A28, 0x000E], 0), (Some(10), Some(10), None));
+        // fill-array-data v0, +4; return-void; fill-array-data-payload of two bytes
+        let raw_bytecode = [0x0026, 4, 0, 0x000E, 0x0300, 1, 2, 0, 0x0201];
+        assert_eq!(targets(&raw_bytecode, 0), (Some(4), Some(4), Some(4)));
+        // packed-switch v0, +3 and sparse-switch v0, +3 pointing at their payloads
+        assert_eq!(targets(&[0x002B, 3, 0, 0x0100, 0, 0, 0, 0x000E], 0), (Some(3), Some(3), Some(3)));
+        assert_eq!(targets(&[0x0000, 0x002C, 0xFFFF, 0xFFFF, 0x000E], 1), (Some(-1), Some(0), Some(0)));
+        // A return-void carries no target
+        assert_eq!(targets(&[0x000E], 0), (None, None, None));
+    }
+
+    #[test]
+    fn test_try_from_raw_bytecode_branch_out_of_method() {
+        // nop; goto/32 +0x7fffffff, which overflows an i32 once added to the offset
+        let raw_bytecode = [0x0000, 0x002A, 0xFFFF, 0x7FFF];
+        let (instruction, _) = Instruction::try_from_raw_bytecode(&raw_bytecode, 1).unwrap().unwrap();
+        assert_eq!(*instruction.branch_target(), Some(0x8000_0000));
+        assert_eq!(instruction.absolute_target(raw_bytecode.len()), None);
+        // goto -5 at the start of the method
+        assert!(Instruction::try_from_raw_bytecode(&[0xFB28], 0).is_err());
+    }
+
+    #[test]
+    fn test_try_from_raw_bytecode_move16_truncated() {
+        // move-object/16 missing its source register word
+        let raw_bytecode = [0x0009, 300];
+        assert!(Instruction::try_from_raw_bytecode(&raw_bytecode, 0).is_err());
+    }
+
+    #[test]
+    fn test_offsets_past_the_end() {
+        assert_eq!(word(&[0x000E], 0).unwrap(), 0x000E);
+        assert_eq!(word(&[0x000E], 1).unwrap_err().offset(), 1);
+        for offset in [1, 2, usize::MAX] {
+            assert!(Instruction::try_from_raw_bytecode(&[0x000E], offset).is_err());
+            assert!(Instruction::try_opcode_from_raw_bytecode(&[0x000E], offset).is_err());
+        }
+        // const-string missing its string index, at the end of a method
+        let err = Instruction::try_from_raw_bytecode(&[0x000E, 0x001A], 1).unwrap_err();
+        assert_eq!((err.byte(), err.offset()), (0x1A, 1));
+    }
+
+    #[test]
+    fn test_display() {
+        // invoke-super method@921; if-eqz +102; const-string string@5; new-instance type@648; return-void
+        let raw_bytecode = [8303, 921, 33, 0x0038, 102, 0x001A, 5, 290, 648, 0x000E];
+        let mut offset = 0;
+        let mut listing = vec![];
+        while offset < raw_bytecode.len() {
+            let (instruction, length) = Instruction::try_from_raw_bytecode(&raw_bytecode, offset).unwrap().expect("Failed to parse instruction");
+            listing.push(instruction.to_string());
+            offset += length;
+        }
+        assert_eq!(listing, vec!["invoke-super method@921", "if-eqz 0x0069", "const-string string@5", "new-instance type@648", "return-void"]);
+        assert_eq!(Opcode::InvokeStaticRange.to_string(), "invoke-static/range");
+        assert_eq!(Opcode::AddInt2Addr.mnemonic(), "add-int/2addr");
+        assert_eq!(Opcode::MoveWideFrom16.mnemonic(), "move-wide/from16");
+    }
+
+    #[test]
+    fn test_is_invoke() {
+        assert!(Opcode::InvokeVirtual.is_invoke() && Opcode::InvokeInterface.is_invoke() && Opcode::InvokeStaticRange.is_invoke());
+        assert!(!Opcode::InvokePolymorphic.is_invoke() && !Opcode::InvokeCustom.is_invoke());
+        assert!(!Opcode::FilledNewArray.is_invoke() && !Opcode::ReturnVoid.is_invoke());
+    }
+
+    #[test]
+    fn test_serialize_lite_snapshot() {
+        let (_, on_start) = crate::testing::SAMPLE_METHODS[0];
+        let decoded = decode_method_lenient(on_start);
+        assert_eq!(serde_json::to_string(&decoded.instructions[..4]).unwrap(), concat!(
+            r#"[{"opcode":"invoke-super","offset":0,"branch_target":null},"#,
+            r#"{"opcode":"sget","offset":3,"branch_target":null},"#,
+            r#"{"opcode":"const/16","offset":5,"branch_target":null},"#,
+            r#"{"opcode":"if-lt","offset":7,"branch_target":22}]"#,
+        ));
+        let lines = decoded.instructions.iter().map(|inst| serde_json::to_string(inst).unwrap()).collect::<Vec<_>>();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[10], r#"{"opcode":"return-void","offset":22,"branch_target":null}"#);
+    }
+}
@@ -0,0 +1,339 @@
+use std::sync::Arc;
+
+use dex::{class::Class, code::{CodeItem, ExceptionType}, method::Method};
+use serde::{Deserialize, Serialize};
+
+use crate::access_flags::MethodFlags;
+use super::{instruction::Instruction, op_stats::OpStats, visitor::MethodInfo};
+
+
+/// Per-method record: where the method lies in the emitted opcode sequence and the layout of its register frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MethodReport {
+    /// Name of the dex defining the method, e.g. `classes2.dex`
+    dex_name: Arc<str>,
+    /// Index of the method's first opcode in the sequence
+    start: usize,
+    /// Index of the method's last opcode in the sequence
+    end: usize,
+    /// Number of registers used by the method
+    registers_size: u16,
+    /// Number of registers holding the incoming arguments, the last `ins_size` of the frame
+    ins_size: u16,
+    /// Number of registers of the outgoing arguments of the method's invokes
+    outs_size: u16,
+    /// Code unit offset of every opcode of the method in its bytecode, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offsets: Option<Vec<u32>>,
+    /// Invoke, allocation and other opcode counts of the whole method, whatever the sequence cap, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    op_stats: Option<OpStats>,
+    /// Lightweight form of every instruction of the method, parallel to `offsets`, when enabled in the options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instructions: Option<Vec<Instruction>>,
+    /// Try blocks of the method, in the order of its code item
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tries: Vec<TryRegion>,
+    /// Raw `access_flags` of the encoded method, e.g. `0x9` for public static
+    access_flags: u32,
+    /// Descriptor of the declaring class, left out of the report as grouped outputs key records by it
+    #[serde(skip)]
+    class: String,
+    #[serde(skip)]
+    name: String,
+    /// Parameter and return types, e.g. `(Ljava/lang/String;I)V`
+    #[serde(skip)]
+    descriptor: String,
+    #[serde(skip)]
+    flags: MethodFlags,
+}
+
+
+/// Every field of a `MethodReport` but its instructions, for a `DexWalk` to store it. Unlike the report, it serializes
+/// the same fields whatever their values, as formats that aren't self-describing need
+#[derive(Serialize, Deserialize)]
+pub(crate) struct StoredReport {
+    dex_name: Arc<str>,
+    start: usize,
+    end: usize,
+    registers_size: u16,
+    ins_size: u16,
+    outs_size: u16,
+    offsets: Option<Vec<u32>>,
+    op_stats: Option<OpStats>,
+    tries: Vec<TryRegion>,
+    access_flags: u32,
+    class: String,
+    name: String,
+    descriptor: String,
+}
+
+
+impl From<MethodReport> for StoredReport {
+    fn from(report: MethodReport) -> Self {
+        let MethodReport { dex_name, start, end, registers_size, ins_size, outs_size, offsets, op_stats, instructions: _, tries, access_flags, class, name, descriptor, flags: _ } = report;
+        Self { dex_name, start, end, registers_size, ins_size, outs_size, offsets, op_stats, tries, access_flags, class, name, descriptor }
+    }
+}
+
+
+impl From<StoredReport> for MethodReport {
+    fn from(stored: StoredReport) -> Self {
+        let StoredReport { dex_name, start, end, registers_size, ins_size, outs_size, offsets, op_stats, tries, access_flags, class, name, descriptor } = stored;
+        Self { dex_name, start, end, registers_size, ins_size, outs_size, offsets, op_stats, instructions: None, tries, access_flags, class, name, descriptor, flags: MethodFlags::from_bits(access_flags) }
+    }
+}
+
+
+/// Range of code units covered by a try block and the handlers it jumps to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TryRegion {
+    /// Code unit offset of the first covered instruction
+    pub start_addr: u32,
+    /// Number of code units covered
+    pub insn_count: u16,
+    pub handlers: Vec<CatchHandler>,
+}
+
+
+/// Handler of a try block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CatchHandler {
+    /// Descriptor of the caught exception, e.g. `Ljava/io/IOException;`, `None` for the catch-all handler
+    pub exception_type: Option<String>,
+    /// Code unit offset of the handler
+    pub addr: u32,
+}
+
+
+impl MethodReport {
+    pub(crate) fn new(start: usize, end: usize, dex_name: Arc<str>, method: &MethodInfo, code: &CodeItem) -> Self {
+        let tries = code.tries().iter()
+            .map(|try_block| TryRegion {
+                start_addr: try_block.start_addr(),
+                insn_count: try_block.insn_count(),
+                handlers: try_block.catch_handlers().iter()
+                    .map(|handler| CatchHandler {
+                        exception_type: match handler.exception() {
+                            ExceptionType::Ty(jtype) => Some(jtype.type_descriptor().to_string()),
+                            ExceptionType::BaseException => None,
+                        },
+                        addr: handler.addr() as u32,
+                    })
+                    .collect(),
+            })
+            .collect();
+        let access_flags = method.method().access_flags().bits() as u32;
+        Self {
+            dex_name,
+            start,
+            end,
+            registers_size: code.registers_size(),
+            ins_size: code.ins_size(),
+            outs_size: code.outs_size(),
+            offsets: None,
+            op_stats: None,
+            instructions: None,
+            tries,
+            access_flags,
+            class: method.class().jtype().type_descriptor().to_string(),
+            name: method.method().name().to_string(),
+            descriptor: descriptor(method.method()),
+            flags: MethodFlags::from_bits(access_flags),
+        }
+    }
+
+    pub(crate) fn with_offsets(mut self, offsets: Vec<u32>) -> Self {
+        self.offsets = Some(offsets);
+        self
+    }
+
+    pub(crate) fn with_op_stats(mut self, op_stats: OpStats) -> Self {
+        self.op_stats = Some(op_stats);
+        self
+    }
+
+    pub(crate) fn with_instructions(mut self, instructions: Vec<Instruction>) -> Self {
+        self.instructions = Some(instructions);
+        self
+    }
+
+    /// Same method placed at `start` in another sequence
+    pub(crate) fn moved_to(&self, start: usize) -> Self {
+        Self { start, end: start + self.end - self.start, ..self.clone() }
+    }
+
+    pub fn dex_name(&self) -> &str {
+        &self.dex_name
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn registers_size(&self) -> u16 {
+        self.registers_size
+    }
+
+    pub fn ins_size(&self) -> u16 {
+        self.ins_size
+    }
+
+    pub fn outs_size(&self) -> u16 {
+        self.outs_size
+    }
+
+    /// Offsets of the method's opcodes in its bytecode, parallel to the opcodes from `start` to `end`
+    pub fn offsets(&self) -> Option<&[u32]> {
+        self.offsets.as_deref()
+    }
+
+    pub fn op_stats(&self) -> Option<&OpStats> {
+        self.op_stats.as_ref()
+    }
+
+    /// Instructions of the method, parallel to the opcodes from `start` to `end`
+    pub fn instructions(&self) -> Option<&[Instruction]> {
+        self.instructions.as_deref()
+    }
+
+    pub fn tries(&self) -> &[TryRegion] {
+        &self.tries
+    }
+
+    /// Descriptors of the exceptions the handlers of the method catch, e.g. `Ljava/lang/SecurityException;`, without
+    /// duplicates and in the order of the try blocks
+    pub fn caught_types(&self) -> Vec<&str> {
+        let mut caught: Vec<&str> = vec![];
+        for exception_type in self.tries.iter().flat_map(|try_block| &try_block.handlers).filter_map(|handler| handler.exception_type.as_deref()) {
+            if !caught.contains(&exception_type) {
+                caught.push(exception_type);
+            }
+        }
+        caught
+    }
+
+    /// Whether a try block of the method has a catch-all handler, as compiled for `finally` and `synchronized` blocks
+    pub fn catches_all(&self) -> bool {
+        self.tries.iter().flat_map(|try_block| &try_block.handlers).any(|handler| handler.exception_type.is_none())
+    }
+
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub fn class(&self) -> &str {
+        &self.class
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn descriptor(&self) -> &str {
+        &self.descriptor
+    }
+
+    pub fn flags(&self) -> MethodFlags {
+        self.flags
+    }
+
+    pub fn access_flags(&self) -> u32 {
+        self.access_flags
+    }
+
+    /// Whether the method has no `this`, otherwise held by the first register of the arguments
+    pub fn is_static(&self) -> bool {
+        self.flags.r#static
+    }
+
+    pub fn is_constructor(&self) -> bool {
+        self.flags.constructor
+    }
+
+    /// Whether the compiler generated the method, e.g. the body of a desugared lambda or an accessor
+    pub fn is_synthetic(&self) -> bool {
+        self.flags.synthetic
+    }
+
+    /// Always false for a reported method, as only methods with code are reported
+    pub fn is_abstract(&self) -> bool {
+        self.flags.r#abstract
+    }
+
+    /// Always false for a reported method, as only methods with code are reported
+    pub fn is_native(&self) -> bool {
+        self.flags.native
+    }
+
+    /// Number of registers holding locals, the registers below the arguments
+    pub fn locals_size(&self) -> u16 {
+        self.registers_size.saturating_sub(self.ins_size)
+    }
+}
+
+
+/// Opcodes of a method handed over by `process_dex_with`, along with the class and method they come from
+pub struct MethodSequence<'a> {
+    pub info: &'a MethodInfo<'a>,
+    /// Opcodes of the method, normalized and truncated to the sequence cap
+    pub opcodes: &'a [u8],
+    /// Where the method would lie in the sequence built by `parse_dexes`
+    pub report: MethodReport,
+}
+
+
+/// Why a method has no code item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodelessKind {
+    /// Implemented in a native library, a common sign of packing or obfuscation
+    Native,
+    Abstract,
+}
+
+
+/// Method declared without code, which leaves only its signature to go by
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CodelessMethod {
+    pub kind: CodelessKind,
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub name: String,
+    /// Parameter and return types, e.g. `(Ljava/lang/String;I)V`
+    pub descriptor: String,
+    pub flags: MethodFlags,
+}
+
+
+impl CodelessMethod {
+    /// `None` for methods with code, or without code but neither native nor abstract
+    pub(crate) fn new(class: &Class, method: &Method) -> Option<Self> {
+        if method.code().is_some() {
+            return None;
+        }
+        let flags = MethodFlags::from_bits(method.access_flags().bits() as u32);
+        let kind = if flags.native {
+            CodelessKind::Native
+        } else if flags.r#abstract {
+            CodelessKind::Abstract
+        } else {
+            return None;
+        };
+        Some(Self {
+            kind,
+            class: class.jtype().type_descriptor().to_string(),
+            name: method.name().to_string(),
+            descriptor: descriptor(method),
+            flags,
+        })
+    }
+}
+
+
+/// Parameter and return types of `method`, e.g. `(Ljava/lang/String;I)V`
+fn descriptor(method: &Method) -> String {
+    let params = method.params().iter().map(|param| param.type_descriptor().as_str()).collect::<String>();
+    format!("({}){}", params, method.return_type().type_descriptor().as_str())
+}
@@ -1,19 +1,82 @@
-use std::collections::{HashSet, HashMap};
+use std::{collections::{BTreeSet, HashSet, HashMap}, cell::RefCell, fmt, ops::ControlFlow, sync::{Arc, Mutex, PoisonError}};
+#[cfg(feature = "parallel")]
+use std::{any::Any, panic::{self, AssertUnwindSafe}, sync::{atomic::{AtomicBool, Ordering}, mpsc}};
 
-use dex::Dex;
+use dex::{Dex, class::Class};
+#[cfg(feature = "parallel")]
+use dex::DexReader;
+use xxhash_rust::xxh3::xxh3_64;
 mod instruction;
 mod opcode;
 mod block;
-use crate::concat_words;
+mod method;
+mod cfg;
+mod visitor;
+mod coverage;
+mod registers;
+mod index;
+mod op_stats;
+#[cfg(feature = "parallel")]
+mod walk_cache;
+use crate::{error::{CfgError, Error}, kotlin::synthetic_kind, options::{AnalysisOptions, CapStrategy, DecodeMode, DedupKey, DedupScope, Normalization, Strictness}, warning::{Warning, WarningKind}};
 
-use self::{instruction::Instruction, block::{BlockPtr, BasicBlock}, opcode::Opcode};
+pub use self::{instruction::{Instruction, InstructionParsingError}, block::{BlockPtr, BasicBlock}, opcode::{InstructionFormat, Opcode, OpcodeCategory}, method::{MethodReport, MethodSequence, CodelessMethod, CodelessKind, TryRegion, CatchHandler}, cfg::{depth_first, postorder, reverse_postorder, MethodCfg, Traversal},
+    visitor::{InstructionVisitor, ClassInfo, MethodInfo, DecodedInstruction, walk_dex}, coverage::Coverage, registers::{normalize_registers, Registers}, index::InstructionIndex, op_stats::OpStats};
+#[cfg(feature = "parallel")]
+pub use self::walk_cache::{DexCache, DexWalk};
+use self::op_stats::OpStatsCounter;
 
-pub(crate) fn parse_dexes(dexes: Vec<Dex<impl AsRef<[u8]>>>, sequence_cap: usize) -> (Vec<u8>, Vec<(usize, usize)>) {
+
+thread_local! {
+    /// Opcodes of the method currently being decoded, reused across methods and calls on the same worker thread
+    static METHOD_SEQ: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+}
+
+/// Dex along with the name of the archive entry it was read from, e.g. `classes2.dex`
+pub struct NamedDex<T> {
+    pub name: String,
+    pub dex: Dex<T>,
+}
+
+
+impl<T: AsRef<[u8]>> NamedDex<T> {
+    pub fn new(name: impl Into<String>, dex: Dex<T>) -> Self {
+        Self { name: name.into(), dex }
+    }
+
+    /// Names dexes read outside of an archive after the entries of a multidex APK: `classes.dex`, `classes2.dex`...
+    pub fn multidex(dexes: impl IntoIterator<Item = Dex<T>>) -> Vec<Self> {
+        dexes.into_iter()
+            .enumerate()
+            .map(|(index, dex)| match index {
+                0 => Self::new("classes.dex", dex),
+                _ => Self::new(format!("classes{}.dex", index + 1), dex),
+            })
+            .collect()
+    }
+}
+
+
+/// Concatenated opcode sequences of the methods of all dexes, the decoded methods are counted in `coverage`
+/// and problems met along the way are pushed to `warnings`. A class defined by several dexes is only taken from the first
+pub fn parse_dexes(dexes: Vec<NamedDex<impl AsRef<[u8]>>>, options: &AnalysisOptions, coverage: &mut Coverage, warnings: &mut Vec<Warning>) -> (Vec<u8>, Vec<MethodReport>) {
     let mut op_seq = vec![]; 
     let mut method_bounds = vec![];
-    let mut pos = 0;
+    let mut walked = Walked::default();
+    let mut caps = Caps::new(options);
     for dex in dexes {
-        let (curr_op_seq, curr_method_bounds) = get_op_seq(dex, &mut pos, sequence_cap);
+        if caps.methods == 0 || caps.opcodes == 0 {
+            break;
+        }
+        let (curr_op_seq, curr_method_bounds, capped) = get_op_seq(dex, &mut walked, caps, options, coverage, warnings);
+        caps.methods -= curr_method_bounds.len();
+        // With a per-dex cap every dex starts with the whole cap, otherwise the dexes share it
+        if options.cap_strategy != CapStrategy::PerDex {
+            if capped {
+                break;
+            }
+            caps.opcodes -= curr_op_seq.len();
+        }
         op_seq.extend(curr_op_seq);
         method_bounds.extend(curr_method_bounds);
     }
@@ -21,160 +84,919 @@ pub(crate) fn parse_dexes(dexes: Vec<Dex<impl AsRef<[u8]>>>, sequence_cap: usize
 }
 
 
-fn get_op_seq(dex: Dex<impl AsRef<[u8]>>, pos: &mut usize, sequence_cap: usize) -> (Vec<u8>, Vec<(usize, usize)>) {
+fn get_op_seq(dex: NamedDex<impl AsRef<[u8]>>, walked: &mut Walked, caps: Caps, options: &AnalysisOptions, coverage: &mut Coverage, warnings: &mut Vec<Warning>) -> (Vec<u8>, Vec<MethodReport>, bool) {
     let mut op_seq = vec![];
     let mut m_bounds = vec![];
-    for class in dex.classes() {
-        if let Ok(class) = class {
-            for method in class.methods() {
-                if let Some(code) = method.code() {
-                    let raw_bytecode = code.insns();
-                    let mut offset = 0;
-                    let mut current_method_seq = vec![];
-                    let mut do_extend = true;
-                    let start = *pos;
-                    while offset < raw_bytecode.len() {
-                        if sequence_cap > 0 && op_seq.len() + current_method_seq.len() >= sequence_cap {
-                            extend(&mut op_seq, current_method_seq, &mut m_bounds, pos, start);
-                            return (op_seq, m_bounds);
-                        }
-                        match Instruction::try_from_raw_bytecode(raw_bytecode, offset) {
-                            Ok(Some((inst, length))) => {
-                                offset += length;
-                                current_method_seq.push(*inst.opcode() as u8);
-                            },
-                            Ok(None) => break,
-                            Err(_) => {
-                                // eprintln!("Error parsing: {}::{}", class.jtype().to_java_type(), method.name());
-                                do_extend = false;
-                                break;
-                            },
+    let capped = walk_sequences(&dex, walked, caps, options, coverage, warnings, |method| {
+        op_seq.extend_from_slice(method.opcodes);
+        m_bounds.push(method.report);
+    });
+    (op_seq, m_bounds, capped)
+}
+
+
+/// Progress of the walks over the dexes of an APK
+#[derive(Debug, Default)]
+struct Walked {
+    /// Position of the next opcode in the concatenated sequences
+    pos: usize,
+    /// Descriptors of the classes walked so far, later definitions of which are skipped
+    classes: HashSet<String>,
+}
+
+
+/// Methods and opcodes a walk may still emit, `usize::MAX` for no limit
+#[derive(Debug, Clone, Copy)]
+struct Caps {
+    methods: usize,
+    opcodes: usize,
+}
+
+impl Caps {
+    fn new(options: &AnalysisOptions) -> Self {
+        let unlimited_if_zero = |cap: usize| if cap > 0 { cap } else { usize::MAX };
+        Self { methods: unlimited_if_zero(options.method_cap), opcodes: unlimited_if_zero(options.sequence_cap) }
+    }
+}
+
+
+/// Decodes the methods of a dex one at a time and hands each to `f` as soon as it is decoded, instead of building
+/// the whole sequence as `parse_dexes` does. Options, warnings and positions are the same as with `parse_dexes`.
+/// Returns the coverage of the dex
+pub fn process_dex_with<F: FnMut(MethodSequence)>(dex: &NamedDex<impl AsRef<[u8]>>, options: &AnalysisOptions, warnings: &mut Vec<Warning>, f: F) -> Coverage {
+    let mut coverage = Coverage::default();
+    walk_sequences(dex, &mut Walked::default(), Caps::new(options), options, &mut coverage, warnings, f);
+    coverage
+}
+
+
+/// Method handed over by `process_dexes_parallel`, owning its opcodes so that it can leave the thread decoding it
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedMethod {
+    /// Index of the dex the method comes from among the given dexes
+    pub dex: usize,
+    /// Opcodes of the method, normalized and truncated to the sequence cap
+    pub opcodes: Vec<u8>,
+    /// Positions start over at every dex
+    pub report: MethodReport,
+}
+
+
+/// Decoded methods of a dex that `parse_dexes_parallel` lets wait for the caller to get to the dex
+#[cfg(feature = "parallel")]
+pub const DEX_BUFFER: usize = 256;
+
+
+/// Coverage and warnings of a dex walked by `process_dexes_parallel`
+#[cfg(feature = "parallel")]
+type DexOutcome = Result<(Coverage, Vec<Warning>), Error>;
+
+
+/// Decodes the dexes of an APK concurrently on the rayon thread pool, one dex per task, and hands each method to `f`
+/// on the calling thread as soon as it is decoded, as `process_dex_with` does for a single dex. Methods are handed
+/// over in dex order: the methods of a dex decoded before the caller gets to it wait in a channel of `buffered`
+/// methods, its task blocking once the channel is full. A dex no task has started yet when the caller gets to it is
+/// walked on the calling thread. Classes defined by several dexes are only walked in the first one, as with
+/// `parse_dexes`, while the method and sequence caps apply to every dex on its own.
+///
+/// Nothing is accumulated: besides the dex bytes, memory holds the method being decoded by each task and at most
+/// `buffered` decoded methods per dex. With a `dex_cache` in the options, the walks of the dexes are looked up there
+/// first, and a dex missing from it is held whole until stored.
+///
+/// A panic while walking a dex fails the whole call with `Error::DecodePanicked`. Otherwise returns the coverage summed
+/// over the dexes and their warnings in dex order
+#[cfg(feature = "parallel")]
+pub fn process_dexes_parallel<F: FnMut(DecodedMethod)>(dexes: &[(String, Arc<[u8]>)], options: &AnalysisOptions, buffered: usize, mut f: F) -> Result<(Coverage, Vec<Warning>), Error> {
+    let skipped = later_definitions(dexes);
+    // Whether a task or the caller took each dex, and the coverage and warnings of the dexes the tasks walked
+    let claimed: Vec<AtomicBool> = dexes.iter().map(|_| AtomicBool::new(false)).collect();
+    let walked: Vec<Mutex<Option<DexOutcome>>> = dexes.iter().map(|_| Mutex::new(None)).collect();
+    let (senders, receivers): (Vec<_>, Vec<_>) = dexes.iter().map(|_| mpsc::sync_channel(buffered)).unzip();
+    rayon::in_place_scope(|scope| {
+        // The first dex is left to the caller
+        for (index, sender) in senders.into_iter().enumerate().skip(1) {
+            let (claimed, walked, skipped) = (&claimed, &walked, &skipped);
+            scope.spawn(move |_| {
+                if claimed[index].swap(true, Ordering::AcqRel) {
+                    return;
+                }
+                // Sending only fails once the caller gave up, on an error or a panic of `f`
+                let result = walk_dex_streaming(index, &dexes[index], &skipped[index], options, |method| { let _ = sender.send(method); });
+                *walked[index].lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+            });
+        }
+        let mut coverage = Coverage::default();
+        let mut warnings = vec![];
+        for (index, receiver) in receivers.into_iter().enumerate() {
+            let result = if claimed[index].swap(true, Ordering::AcqRel) {
+                // The sender is dropped once the task stored its result
+                receiver.iter().for_each(&mut f);
+                walked[index].lock().unwrap_or_else(PoisonError::into_inner).take().expect("walked dex")
+            } else {
+                walk_dex_streaming(index, &dexes[index], &skipped[index], options, &mut f)
+            };
+            let (dex_coverage, dex_warnings) = result?;
+            coverage += dex_coverage;
+            warnings.extend(dex_warnings);
+        }
+        Ok((coverage, warnings))
+    })
+}
+
+
+/// Walks the dex at `index` for `process_dexes_parallel`, through its `dex_cache` if any, and hands its methods to `f`
+#[cfg(feature = "parallel")]
+fn walk_dex_streaming(index: usize, (name, bytes): &(String, Arc<[u8]>), skipped: &HashSet<String>, options: &AnalysisOptions, mut f: impl FnMut(DecodedMethod)) -> DexOutcome {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut sink = |opcodes, report| f(DecodedMethod { dex: index, opcodes, report });
+        let Some(cache) = options.dex_cache.as_deref().filter(|_| !options.instructions_lite) else {
+            return walk_dex_bytes(name, bytes, skipped.clone(), options, &mut sink);
+        };
+        let key = DexWalk::key(name, bytes, skipped);
+        let walk = cache.get(&key).unwrap_or_else(|| {
+            let mut methods = vec![];
+            let (coverage, warnings) = walk_dex_bytes(name, bytes, skipped.clone(), options, |opcodes, report| methods.push((opcodes, report)));
+            let walk = DexWalk { methods, coverage, warnings };
+            cache.put(&key, &walk);
+            walk
+        });
+        walk.methods.into_iter().for_each(|(opcodes, report)| sink(opcodes, report));
+        (walk.coverage, walk.warnings)
+    })).map_err(|payload| Error::DecodePanicked { dex: name.clone(), message: panic_message(&*payload) })
+}
+
+
+/// Walks a dex from its bytes for `process_dexes_parallel`, skipping the classes of an earlier dex, and hands the
+/// opcodes and report of each method to `sink`. Returns the coverage and warnings of the dex
+#[cfg(feature = "parallel")]
+fn walk_dex_bytes(name: &str, bytes: &Arc<[u8]>, skipped: HashSet<String>, options: &AnalysisOptions, mut sink: impl FnMut(Vec<u8>, MethodReport)) -> (Coverage, Vec<Warning>) {
+    let mut coverage = Coverage::default();
+    let mut warnings = vec![];
+    // `Dex` cannot be shared between threads, so each task reads its own from the bytes
+    match DexReader::from_vec(bytes.clone()) {
+        Ok(dex) => {
+            let mut walked = Walked { pos: 0, classes: skipped };
+            walk_sequences(&NamedDex::new(name, dex), &mut walked, Caps::new(options), options, &mut coverage, &mut warnings, |method| {
+                sink(method.opcodes.to_vec(), method.report);
+            });
+        }
+        Err(err) => warnings.push(Warning::new(WarningKind::InvalidDex, format!("{}: {}", name, err))),
+    }
+    (coverage, warnings)
+}
+
+
+/// Same as `parse_dexes` for the bytes of the dexes, decoded in parallel by `process_dexes_parallel`.
+/// Only for options whose caps aren't shared by the dexes, see `AnalysisOptions::shares_caps`
+#[cfg(feature = "parallel")]
+pub fn parse_dexes_parallel(dexes: &[(String, Arc<[u8]>)], options: &AnalysisOptions, coverage: &mut Coverage, warnings: &mut Vec<Warning>) -> Result<(Vec<u8>, Vec<MethodReport>), Error> {
+    let mut op_seq = vec![];
+    let mut methods = vec![];
+    let (dex_coverage, dex_warnings) = process_dexes_parallel(dexes, options, DEX_BUFFER, |method| {
+        // Positions start over at every dex
+        methods.push(method.report.moved_to(op_seq.len()));
+        op_seq.extend(method.opcodes);
+    })?;
+    *coverage += dex_coverage;
+    warnings.extend(dex_warnings);
+    Ok((op_seq, methods))
+}
+
+
+/// Message of a caught panic, as given to `panic!`
+#[cfg(feature = "parallel")]
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+
+/// Descriptors of the classes of each dex that an earlier dex already defines, for `process_dexes_parallel` to skip
+#[cfg(feature = "parallel")]
+fn later_definitions(dexes: &[(String, Arc<[u8]>)]) -> Vec<HashSet<String>> {
+    let mut defined = HashSet::new();
+    dexes.iter()
+        .map(|(_, bytes)| match DexReader::from_vec(bytes.clone()) {
+            Ok(dex) => dex.classes()
+                .flatten()
+                .map(|class| class.jtype().type_descriptor().to_string())
+                .filter(|descriptor| !defined.insert(descriptor.clone()))
+                .collect(),
+            Err(_) => HashSet::new(),
+        })
+        .collect()
+}
+
+
+/// Walks the methods of a dex for `parse_dexes` and `process_dex_with`, returns whether the opcode cap was reached.
+/// Classes already walked in an earlier dex are skipped
+fn walk_sequences<F: FnMut(MethodSequence)>(dex: &NamedDex<impl AsRef<[u8]>>, walked: &mut Walked, caps: Caps, options: &AnalysisOptions, coverage: &mut Coverage, warnings: &mut Vec<Warning>, sink: F) -> bool {
+    METHOD_SEQ.with(|current_method_seq| {
+        let mut current_method_seq = current_method_seq.borrow_mut();
+        current_method_seq.clear();
+        let mut visitor = OpSeqVisitor {
+            options,
+            dex_name: dex.name.as_str().into(),
+            caps,
+            capped: false,
+            walked,
+            emitted: 0,
+            methods: 0,
+            current_method_seq: &mut current_method_seq,
+            current_offsets: vec![],
+            current_op_stats: OpStatsCounter::default(),
+            current_instructions: vec![],
+            error: None,
+            errors: 0,
+            classes: ClassCounts::default(),
+            coverage,
+            warnings,
+            sink,
+        };
+        walk_dex(&dex.dex, &mut visitor);
+        visitor.classes.warn_if_all_failed(visitor.warnings);
+        visitor.capped
+    })
+}
+
+
+/// Decodes the opcode sequences of the methods of a dex for `walk_sequences`
+struct OpSeqVisitor<'a, F> {
+    options: &'a AnalysisOptions,
+    /// Name of the walked dex, shared by the reports of its methods
+    dex_name: Arc<str>,
+    caps: Caps,
+    /// Whether the opcode cap was reached, which ends the walk
+    capped: bool,
+    walked: &'a mut Walked,
+    /// Number of opcodes handed to `sink` so far
+    emitted: usize,
+    /// Number of methods handed to `sink` so far
+    methods: usize,
+    current_method_seq: &'a mut Vec<u8>,
+    /// Code unit offsets of the opcodes of the current method, when enabled in the options
+    current_offsets: Vec<u32>,
+    /// Op stats of the current method, when enabled in the options
+    current_op_stats: OpStatsCounter,
+    /// Instructions of the current method, when enabled in the options
+    current_instructions: Vec<Instruction>,
+    /// First undecodable instruction of the current method, which is dropped in strict mode
+    error: Option<Warning>,
+    /// Number of undecodable code units of the current method
+    errors: usize,
+    classes: ClassCounts,
+    coverage: &'a mut Coverage,
+    warnings: &'a mut Vec<Warning>,
+    sink: F,
+}
+
+impl<F: FnMut(MethodSequence)> InstructionVisitor for OpSeqVisitor<'_, F> {
+    fn visit_class(&mut self, class: &ClassInfo) -> ControlFlow<()> {
+        self.classes.parsed += 1;
+        if !self.walked.classes.insert(class.class().jtype().type_descriptor().to_string()) {
+            return ControlFlow::Break(());
+        }
+        if is_selected(class.class(), self.options) { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+    }
+
+    fn visit_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        if self.methods >= self.caps.methods {
+            // Only reached with a zero method cap, as `leave_method` ends the walk at the cap
+            return ControlFlow::Break(());
+        }
+        self.current_method_seq.clear();
+        self.current_offsets.clear();
+        self.current_op_stats.finish();
+        self.current_instructions.clear();
+        self.error = None;
+        self.errors = 0;
+        if method.code().is_some() { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+    }
+
+    fn visit_instruction(&mut self, inst: &DecodedInstruction) {
+        self.visit_opcode(*inst.instruction.opcode(), *inst.instruction.offset());
+        if self.options.op_stats {
+            self.current_op_stats.add(&inst.instruction);
+        }
+        if self.options.instructions_lite {
+            self.current_instructions.push(inst.instruction.clone());
+        }
+    }
+
+    fn visit_opcode(&mut self, opcode: Opcode, offset: usize) {
+        self.current_method_seq.push(self.options.normalization.apply(opcode));
+        if self.options.with_offsets {
+            self.current_offsets.push(offset as u32);
+        }
+    }
+
+    fn visit_class_error(&mut self, err: &dex::Error) {
+        self.classes.invalid(err, self.warnings);
+    }
+
+    fn visit_error(&mut self, err: &InstructionParsingError) {
+        self.errors += 1;
+        self.error.get_or_insert_with(|| Warning::new(WarningKind::InvalidInstruction, err).offset(err.offset()));
+    }
+
+    fn leave_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        // `visit_method` skips the methods without code
+        let Some(code) = method.code() else { return ControlFlow::Continue(()) };
+        self.coverage.add_method(code.insns().len(), self.errors, self.options.strictness);
+        if let Some(warning) = self.error.take() {
+            self.warnings.push(warning.class(method.class().jtype().type_descriptor().as_str()).method(method.method().name().as_str()));
+            if !self.options.lenient() {
+                return ControlFlow::Continue(());
+            }
+        }
+        // An empty method has no bounds in the sequence, e.g. a lenient one made only of undecodable code units
+        if self.current_method_seq.is_empty() {
+            return ControlFlow::Continue(());
+        }
+        // The method reaching the cap ends the walk, truncated to fill the cap exactly or left out if it doesn't fit whole
+        let room = self.caps.opcodes - self.emitted;
+        self.capped = self.current_method_seq.len() >= room;
+        if self.current_method_seq.len() > room {
+            if self.options.cap_strategy == CapStrategy::TruncateMethods {
+                return ControlFlow::Break(());
+            }
+            self.current_method_seq.truncate(room);
+            self.current_offsets.truncate(room);
+            self.current_instructions.truncate(room);
+        }
+        let start = self.walked.pos;
+        self.walked.pos += self.current_method_seq.len();
+        self.emitted += self.current_method_seq.len();
+        self.methods += 1;
+        let mut report = MethodReport::new(start, self.walked.pos - 1, self.dex_name.clone(), method, code);
+        if self.options.with_offsets {
+            report = report.with_offsets(self.current_offsets.clone());
+        }
+        if self.options.op_stats {
+            report = report.with_op_stats(self.current_op_stats.finish());
+        }
+        if self.options.instructions_lite {
+            report = report.with_instructions(std::mem::take(&mut self.current_instructions));
+        }
+        (self.sink)(MethodSequence { info: method, opcodes: self.current_method_seq, report });
+        self.current_method_seq.clear();
+        if self.capped || self.methods >= self.caps.methods { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    }
+
+    fn strictness(&self) -> Strictness {
+        self.options.strictness
+    }
+
+    /// Op stats and instructions need the operands, which only a full decode has
+    fn shallow(&self) -> bool {
+        self.options.shallow && !self.options.op_stats && !self.options.instructions_lite
+    }
+
+    fn decode_mode(&self) -> DecodeMode {
+        self.options.decode_mode
+    }
+}
+
+
+/// Native and abstract methods of the selected classes of all dexes
+pub fn codeless_methods(dexes: &[Dex<impl AsRef<[u8]>>], options: &AnalysisOptions) -> Vec<CodelessMethod> {
+    let mut visitor = CodelessVisitor { options, methods: vec![] };
+    for dex in dexes {
+        walk_dex(dex, &mut visitor);
+    }
+    visitor.methods
+}
+
+
+/// Collects the methods without code for `codeless_methods`, the code of the other methods is never walked
+struct CodelessVisitor<'a> {
+    options: &'a AnalysisOptions,
+    methods: Vec<CodelessMethod>,
+}
+
+impl InstructionVisitor for CodelessVisitor<'_> {
+    fn visit_class(&mut self, class: &ClassInfo) -> ControlFlow<()> {
+        if is_selected(class.class(), self.options) { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+    }
+
+    fn visit_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        self.methods.extend(CodelessMethod::new(method.class(), method.method()));
+        ControlFlow::Break(())
+    }
+
+    fn visit_instruction(&mut self, _inst: &DecodedInstruction) {}
+}
+
+
+/// Class definitions of a dex that could and couldn't be parsed
+#[derive(Debug, Default, Clone, Copy)]
+struct ClassCounts {
+    parsed: usize,
+    failed: usize,
+}
+
+impl ClassCounts {
+    /// Counts a class definition that failed to parse and reports it with its index in the dex
+    fn invalid(&mut self, err: &dex::Error, warnings: &mut Vec<Warning>) {
+        let index = self.parsed + self.failed;
+        warnings.push(Warning::new(WarningKind::InvalidClass, format!("Class definition {}: {}", index, err)).class_index(index));
+        self.failed += 1;
+    }
+
+    /// Tells a broken dex from an empty one, when every class definition failed to parse
+    fn warn_if_all_failed(&self, warnings: &mut Vec<Warning>) {
+        if self.parsed == 0 && self.failed > 0 {
+            warnings.push(Warning::new(WarningKind::NoValidClasses, format!("None of the {} class definitions of the dex could be parsed", self.failed)));
+        }
+    }
+}
+
+
+pub(crate) fn is_selected(class: &Class, options: &AnalysisOptions) -> bool {
+    let descriptor = class.jtype().type_descriptor();
+    (options.class_filter.is_empty() || options.class_filter.matches(descriptor))
+        && !(options.exclude_synthetic && synthetic_kind(descriptor, class.access_flags().bits() as u32).is_some())
+}
+
+/// Decodes the opcodes of a whole method into `method_seq`, stopping at the first payload pseudo-instruction
+fn decode_opcodes(raw_bytecode: &[u16], method_seq: &mut Vec<u8>, normalization: &Normalization) -> Result<(), InstructionParsingError> {
+    let mut offset = 0;
+    while offset < raw_bytecode.len() {
+        match Instruction::try_from_raw_bytecode(raw_bytecode, offset)? {
+            Some((inst, length)) => {
+                offset += length;
+                method_seq.push(normalization.apply(*inst.opcode()));
+            },
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+
+/// Opcode bytes and lengths in code units of the instructions of a whole method, stopping at the first payload
+/// pseudo-instruction like the full decoder. Operands are never decoded, see `Instruction::try_opcode_from_raw_bytecode`
+pub fn scan_opcodes(raw_bytecode: &[u16]) -> Result<Vec<(u8, usize)>, InstructionParsingError> {
+    let mut opcodes = Vec::with_capacity(raw_bytecode.len());
+    let mut offset = 0;
+    while offset < raw_bytecode.len() {
+        match Instruction::try_opcode_from_raw_bytecode(raw_bytecode, offset)? {
+            Some((opcode, length)) => {
+                offset += length;
+                opcodes.push((opcode as u8, length));
+            },
+            None => break,
+        }
+    }
+    Ok(opcodes)
+}
+
+
+/// Outcome of decoding a method without giving up on the first problem
+#[derive(Debug, Default)]
+pub struct MethodDecode {
+    /// Successfully decoded instructions, in code order
+    pub instructions: Vec<Instruction>,
+    /// Offsets of the code units that could not be decoded
+    pub undecoded: Vec<usize>,
+}
+
+
+/// Decodes a whole method, skipping over payload pseudo-instructions and stepping one code unit past
+/// anything that can't be decoded, so decoding always reaches the end of the method
+pub fn decode_method_lenient(raw_bytecode: &[u16]) -> MethodDecode {
+    let mut decoded = MethodDecode { instructions: Vec::with_capacity(raw_bytecode.len()), undecoded: vec![] };
+    let mut offset = 0;
+    while offset < raw_bytecode.len() {
+        match Instruction::try_from_raw_bytecode(raw_bytecode, offset) {
+            Ok(Some((inst, length))) => {
+                offset += length;
+                decoded.instructions.push(inst);
+            },
+            Ok(None) => match Instruction::payload_length(raw_bytecode, offset) {
+                Some(length) => offset += length,
+                None => {
+                    decoded.undecoded.push(offset);
+                    offset += 1;
+                }
+            },
+            Err(_) => {
+                decoded.undecoded.push(offset);
+                offset += 1;
+            }
+        }
+    }
+    decoded
+}
+
+
+/// Decodes the method with index `method_idx` in the method ids of `dex`, skipping payload pseudo-instructions.
+/// Fails with `MethodNotFound` when no class of the dex defines the method, with `NoCode` for abstract and native
+/// methods, and with `InstructionDecode` on the first code unit that can't be decoded
+pub fn decode_method_by_index<T: AsRef<[u8]>>(dex: &Dex<T>, method_idx: u32) -> Result<Vec<Instruction>, Error> {
+    if method_idx >= dex.header().method_ids_size() {
+        return Err(Error::MethodNotFound { method_idx });
+    }
+    let item = dex.get_method_item(method_idx as u64)?;
+    // Only the class the method id names can define it
+    let class = dex.find_class_by_type(item.class_idx() as u32)?.ok_or(Error::MethodNotFound { method_idx })?;
+    let method = class.methods().find(|method| method.id() == method_idx as u64).ok_or(Error::MethodNotFound { method_idx })?;
+    let code = method.code().ok_or_else(|| Error::NoCode { class: class.jtype().type_descriptor().to_string(), method: method.name().to_string() })?;
+    let decoded = decode_method_lenient(code.insns());
+    match decoded.undecoded.first() {
+        Some(&offset) => Err(Error::from(InstructionParsingError::at(code.insns(), offset))
+            .in_method(class.jtype().type_descriptor(), method.name())),
+        None => Ok(decoded.instructions),
+    }
+}
+
+
+/// Decodes the instructions reachable from the entry of a method and from the catch `handlers`, following branches,
+/// switch cases and fall-through, so that data laid between the instructions is never decoded as code.
+/// Instructions are in code order, reachable offsets that can't be decoded or hold a payload are in `MethodDecode::undecoded`
+pub fn decode_method_recursive(raw_bytecode: &[u16], handlers: impl IntoIterator<Item = usize>) -> MethodDecode {
+    let mut decoded = MethodDecode::default();
+    let mut visited = vec![false; raw_bytecode.len()];
+    let mut pending: Vec<usize> = std::iter::once(0).chain(handlers).collect();
+    while let Some(offset) = pending.pop() {
+        if offset >= raw_bytecode.len() || visited[offset] {
+            continue;
+        }
+        visited[offset] = true;
+        let Ok(Some((inst, length))) = Instruction::try_from_raw_bytecode(raw_bytecode, offset) else {
+            decoded.undecoded.push(offset);
+            continue;
+        };
+        let category = inst.opcode().category();
+        if !matches!(category, OpcodeCategory::Return | OpcodeCategory::Throw | OpcodeCategory::Goto) {
+            pending.push(offset + length);
+        }
+        match category {
+            OpcodeCategory::Goto | OpcodeCategory::If => pending.extend(inst.absolute_target(raw_bytecode.len())),
+            OpcodeCategory::Switch => pending.extend(inst.switch_targets(raw_bytecode).into_iter().flatten()),
+            _ => (),
+        }
+        decoded.instructions.push(inst);
+    }
+    decoded.instructions.sort_by_key(|inst| *inst.offset());
+    decoded.undecoded.sort_unstable();
+    decoded
+}
+
+
+/// Number of the instructions decoded by a linear sweep of a method that no path from its entry or its catch `handlers`
+/// reaches, dead code left in by obfuscators or data mistaken for code, and of all its decoded instructions.
+/// `None` when a path is longer than `max_depth` instructions, 0 for no limit
+pub fn unreachable_instructions(raw_bytecode: &[u16], handlers: impl IntoIterator<Item = usize>, max_depth: usize) -> Option<(usize, usize)> {
+    let decoded = decode_method_lenient(raw_bytecode);
+    let by_offset = decoded.index();
+    let instructions = by_offset.instructions();
+    let roots = std::iter::once(0).chain(handlers).filter_map(|offset| by_offset.position(offset));
+    let traversal = depth_first(instructions.len(), roots, max_depth, |index| {
+        let inst = &instructions[index];
+        let category = inst.opcode().category();
+        let mut targets: Vec<usize> = inst.absolute_target(raw_bytecode.len()).into_iter().collect();
+        if category == OpcodeCategory::Switch {
+            targets.extend(inst.switch_targets(raw_bytecode).into_iter().flatten());
+        }
+        let mut successors: Vec<usize> = targets.into_iter().filter_map(|offset| by_offset.position(offset)).collect();
+        if !matches!(category, OpcodeCategory::Return | OpcodeCategory::Throw | OpcodeCategory::Goto) && index + 1 < instructions.len() {
+            successors.push(index + 1);
+        }
+        successors
+    });
+    (!traversal.truncated).then_some((instructions.len() - traversal.order.len(), instructions.len()))
+}
+
+
+/// Decodes every distinct method body once, bodies that are duplicates under its key share a single opcode sequence
+#[derive(Default)]
+pub struct MethodDeduplicator {
+    /// Whether bodies with the same opcodes but different operands share a sequence too
+    key: DedupKey,
+    /// Decode with `decode_method_lenient` instead of dropping methods with undecodable instructions
+    strictness: Strictness,
+    /// Alphabet of the decoded sequences
+    normalization: Normalization,
+    /// Hash of a method's code units to the index of its sequence in `unique_sequences` and its number of undecodable code units
+    seen: HashMap<u64, (usize, usize)>,
+    /// Hash of a decoded sequence to its index in `unique_sequences`, only filled with `DedupKey::Opcodes`
+    seen_sequences: HashMap<u64, usize>,
+    /// Opcode sequences of the distinct method bodies
+    unique_sequences: Vec<Vec<u8>>,
+    /// Id of `unique_sequences[0]`, the sequences handed out by `next_input` come first
+    first_id: usize,
+    /// Id of the sequence of every decoded method, in dex order
+    methods: Vec<usize>,
+    /// Total length of `unique_sequences`
+    unique_len: usize,
+    /// Reused buffer holding the little-endian bytes of the method being hashed
+    bytes: Vec<u8>,
+    coverage: Coverage,
+}
+
+impl MethodDeduplicator {
+    pub fn new(strictness: Strictness) -> Self {
+        Self { strictness, ..Default::default() }
+    }
+
+    /// Remaps the decoded opcodes, identical bodies still share a sequence
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// What makes two bodies duplicates, byte-identical bodies by default
+    pub fn key(mut self, key: DedupKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Forgets the bodies seen so far, the next ones get their own sequences even if they were seen before
+    pub fn forget(&mut self) {
+        self.seen.clear();
+        self.seen_sequences.clear();
+    }
+
+    /// Hands out the sequences and methods added so far and starts over for another input, whose methods still share
+    /// the sequences seen before. Returns the id of the first sequence handed out, the unique sequences and the methods
+    pub fn next_input(&mut self) -> (usize, Vec<Vec<u8>>, Vec<usize>) {
+        let first_id = self.first_id;
+        self.first_id += self.unique_sequences.len();
+        self.unique_len = 0;
+        self.coverage = Coverage::default();
+        (first_id, std::mem::take(&mut self.unique_sequences), std::mem::take(&mut self.methods))
+    }
+
+    /// Records a method body, decoding it only if it has not been seen before.
+    /// Returns the id of the method's sequence, its index in the unique sequence table before any `next_input`
+    pub fn add(&mut self, raw_bytecode: &[u16]) -> Result<usize, InstructionParsingError> {
+        self.bytes.clear();
+        self.bytes.extend(raw_bytecode.iter().flat_map(|word| word.to_le_bytes()));
+        let hash = xxh3_64(&self.bytes);
+        let (id, errors) = match self.seen.get(&hash) {
+            Some(&seen) => seen,
+            None => {
+                let mut method_seq = vec![];
+                let mut errors = 0;
+                if self.strictness == Strictness::Lenient {
+                    let decoded = decode_method_lenient(raw_bytecode);
+                    method_seq.extend(decoded.instructions.iter().map(|inst| self.normalization.apply(*inst.opcode())));
+                    errors = decoded.undecoded.len();
+                } else if let Err(err) = decode_opcodes(raw_bytecode, &mut method_seq, &self.normalization) {
+                    self.coverage.add_method(raw_bytecode.len(), 1, self.strictness);
+                    return Err(err);
+                }
+                let sequence_hash = (self.key == DedupKey::Opcodes).then(|| xxh3_64(&method_seq));
+                let id = match sequence_hash.and_then(|sequence_hash| self.seen_sequences.get(&sequence_hash)) {
+                    Some(&id) => id,
+                    None => {
+                        self.unique_len += method_seq.len();
+                        self.unique_sequences.push(method_seq);
+                        let id = self.first_id + self.unique_sequences.len() - 1;
+                        if let Some(sequence_hash) = sequence_hash {
+                            self.seen_sequences.insert(sequence_hash, id);
                         }
+                        id
                     }
-                    if do_extend {
-                        extend(&mut op_seq, current_method_seq, &mut m_bounds, pos, start)
+                };
+                self.seen.insert(hash, (id, errors));
+                (id, errors)
+            }
+        };
+        self.coverage.add_method(raw_bytecode.len(), errors, self.strictness);
+        self.methods.push(id);
+        Ok(id)
+    }
+
+    /// Truncates the newest unique sequence so the unique sequences total at most `sequence_cap` opcodes. A truncated
+    /// sequence is forgotten, so that the later inputs sharing the table decode its body again in full
+    fn truncate_to(&mut self, sequence_cap: usize) {
+        if let (Some(excess @ 1..), Some(newest)) = (self.unique_len.checked_sub(sequence_cap), self.unique_sequences.last_mut()) {
+            newest.truncate(newest.len().saturating_sub(excess));
+            self.unique_len = sequence_cap;
+            let truncated = self.first_id + self.unique_sequences.len() - 1;
+            self.seen.retain(|_, (id, _)| *id != truncated);
+            self.seen_sequences.retain(|_, id| *id != truncated);
+        }
+    }
+
+    /// Coverage of every method added so far, duplicates included
+    pub fn coverage(&self) -> Coverage {
+        self.coverage
+    }
+
+    pub fn into_parts(self) -> (Vec<Vec<u8>>, Vec<usize>) {
+        (self.unique_sequences, self.methods)
+    }
+}
+
+
+/// Table of unique sequences shared by the inputs analyzed with `DedupScope::Run` and the same options or their clones
+#[derive(Default)]
+pub struct DedupTable(Mutex<Option<MethodDeduplicator>>);
+
+impl fmt::Debug for DedupTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let first_id = self.0.lock().unwrap_or_else(PoisonError::into_inner).as_ref().map_or(0, |deduplicator| deduplicator.first_id);
+        f.debug_struct("DedupTable").field("sequences", &first_id).finish()
+    }
+}
+
+
+/// Same as `parse_dexes`, but identical method bodies are decoded and emitted once.
+/// Returns the unique sequence table and, for every method, the index of its sequence.
+/// With `DedupScope::Run`, the table only has the sequences no earlier input of the run emitted, and the methods refer
+/// to run-wide ids, those of the table starting at the id returned along. Inputs sharing the table are parsed one at a time.
+/// The sequence cap bounds the total length of the unique sequences of all dexes, the sequence reaching it is truncated to fill it exactly
+pub fn parse_dexes_dedup(dexes: Vec<NamedDex<impl AsRef<[u8]>>>, options: &AnalysisOptions, coverage: &mut Coverage, warnings: &mut Vec<Warning>) -> (Vec<Vec<u8>>, Vec<usize>, Option<usize>) {
+    let (sequence_cap, method_cap) = (options.sequence_cap, options.method_cap);
+    let mut table = (options.dedup_scope == DedupScope::Run).then(|| options.dedup_table.0.lock().unwrap_or_else(PoisonError::into_inner));
+    let mut deduplicator = table.as_mut().and_then(|table| table.take()).unwrap_or_else(|| {
+        MethodDeduplicator::new(options.strictness).normalization(options.normalization.clone()).key(options.dedup_key)
+    });
+    let mut defined = HashSet::new();
+    'dexes: for dex in dexes {
+        if options.dedup_scope == DedupScope::Dex {
+            deduplicator.forget();
+        }
+        let mut classes = ClassCounts::default();
+        for class in dex.dex.classes() {
+            let class = match class {
+                Ok(class) => class,
+                Err(err) => {
+                    classes.invalid(&err, warnings);
+                    continue;
+                }
+            };
+            classes.parsed += 1;
+            if !defined.insert(class.jtype().type_descriptor().to_string()) || !is_selected(&class, options) {
+                continue;
+            }
+            for method in class.methods() {
+                if sequence_cap > 0 && deduplicator.unique_len >= sequence_cap {
+                    break 'dexes;
+                }
+                if method_cap > 0 && deduplicator.methods.len() >= method_cap {
+                    break 'dexes;
+                }
+                if let Some(code) = method.code() {
+                    // Undecodable methods are dropped, as in `get_op_seq`
+                    match deduplicator.add(code.insns()) {
+                        Ok(_) if sequence_cap > 0 => deduplicator.truncate_to(sequence_cap),
+                        Ok(_) => {},
+                        Err(err) => warnings.push(Warning::new(WarningKind::InvalidInstruction, &err)
+                            .class(class.jtype().type_descriptor().as_str())
+                            .method(method.name().as_str())
+                            .offset(err.offset())),
                     }
                 }
             }
         }
-        if sequence_cap > 0 && op_seq.len() >= sequence_cap {
-            return (op_seq, m_bounds);
-        }
+        classes.warn_if_all_failed(warnings);
+    }
+    *coverage += deduplicator.coverage();
+    match table {
+        Some(mut table) => {
+            let (first_id, unique_sequences, methods) = deduplicator.next_input();
+            *table = Some(deduplicator);
+            (unique_sequences, methods, Some(first_id))
+        },
+        None => {
+            let (unique_sequences, methods) = deduplicator.into_parts();
+            (unique_sequences, methods, None)
+        },
     }
-    (op_seq, m_bounds)
 }
 
-fn extend(op_seq: &mut Vec<u8>, current_method_seq: Vec<u8>, m_bounds: &mut Vec<(usize, usize)>, pos: &mut usize, start: usize) {
-    *pos += current_method_seq.len();
-    m_bounds.push((start, *pos - 1));
-    op_seq.extend(current_method_seq);
-}
 
-pub(crate) fn into_blocks(dex: Dex<impl AsRef<[u8]>>) -> Vec<BlockPtr> {
+/// Entry blocks of the methods of a dex, methods whose blocks can't be built are counted as skipped in `coverage`
+/// and reported in `warnings`
+pub fn into_blocks(dex: Dex<impl AsRef<[u8]>>, coverage: &mut Coverage, warnings: &mut Vec<Warning>) -> Vec<BlockPtr> {
     let mut blocks = vec![];
+    let mut classes = ClassCounts::default();
     for class in dex.classes() {
-        if let Ok(class) = class {
-            for method in class.methods() {
-                if let Some(code) = method.code() {
-                    if let Ok(b) = get_blocks(code.insns()) {
-                        if let Some(block) = b.first() {
-                            blocks.push(block.clone());
+        match class {
+            Ok(class) => {
+                classes.parsed += 1;
+                // Resolved on the first error only and shared by the remaining methods of the class
+                let mut class_name = None;
+                for method in class.methods() {
+                    if let Some(code) = method.code() {
+                        let blocks_or_err = get_blocks(code.insns());
+                        coverage.add_method(code.insns().len(), blocks_or_err.is_err() as usize, Strictness::Strict);
+                        match blocks_or_err {
+                            Ok(b) => if let Some(block) = b.first() {
+                                blocks.push(block.clone());
+                            },
+                            Err(err) => {
+                                let class_name: &String = class_name.get_or_insert_with(|| class.jtype().type_descriptor().to_string());
+                                warnings.push(Warning::from(err).class(class_name.as_str()).method(method.name().as_str()));
+                            }
                         }
-                    } else {
-                        eprintln!("Error parsing: {}::{}", class.jtype().to_java_type(), method.name());
                     }
                 }
-            }
+            },
+            Err(err) => classes.invalid(&err, warnings),
         }
     }
+    classes.warn_if_all_failed(warnings);
     blocks
 }
 
-fn get_blocks(raw_bytecode: &[u16]) -> Result<Vec<BlockPtr>, String> {
-    let mut instructions: Vec<Instruction> = vec![];
-    let mut block_starts = vec![0 as usize];
-    let mut edges = vec![];
+/// Offsets starting the basic blocks of a method decoded into `instructions`, in order: 0, the targets of its branches
+/// and switches and the instructions following a conditional branch. Targets that can't be resolved are left out
+pub(crate) fn block_leaders(raw_bytecode: &[u16], instructions: &[Instruction]) -> BTreeSet<usize> {
+    let mut leaders = BTreeSet::from([0]);
+    let mut after_if = false;
+    for inst in instructions {
+        if std::mem::take(&mut after_if) {
+            leaders.insert(*inst.offset());
+        }
+        match inst.opcode().category() {
+            OpcodeCategory::If => {
+                after_if = true;
+                leaders.extend(inst.absolute_target(raw_bytecode.len()));
+            },
+            OpcodeCategory::Goto => leaders.extend(inst.absolute_target(raw_bytecode.len())),
+            OpcodeCategory::Switch => leaders.extend(inst.switch_targets(raw_bytecode).into_iter().flatten()),
+            _ => {},
+        }
+    }
+    leaders
+}
+
+
+/// Splits the code of a method into basic blocks linked by their branches, in offset order
+pub fn get_blocks(raw_bytecode: &[u16]) -> Result<Vec<BlockPtr>, Error> {
+    let mut instructions: Vec<Instruction> = Vec::with_capacity(raw_bytecode.len());
     let mut offset = 0;
     while offset < raw_bytecode.len() {
-        match Instruction::try_from_raw_bytecode(raw_bytecode, offset) {
-            Ok(Some((inst, length))) => {
+        match Instruction::try_from_raw_bytecode(raw_bytecode, offset)? {
+            Some((inst, length)) => {
                 offset += length;
-                match *inst.opcode() as u8 {
-                    0x32..=0x3D => {
-                        let current_block_start = *block_starts.last().unwrap();
-                        edges.push((current_block_start, offset));
-                        edges.push((current_block_start, inst.branch_target().unwrap()));
-                        block_starts.push(offset);
-                        block_starts.push(inst.branch_target().unwrap());
-                        
-                    },
-                    0x28..=0x2A => {
-                        let current_block_start = *block_starts.last().unwrap();
-                        edges.push((current_block_start, inst.branch_target().unwrap()));
-                        block_starts.push(inst.branch_target().unwrap());
-                    },
-                    0x2B | 0x2C => {
-                        let jump_target = inst.branch_target().unwrap();
-                        if jump_target + 1 > raw_bytecode.len() {
-                            return Err(format!("Jump target out of bounds: {}", jump_target).to_string());
-                        }
-                        let size = raw_bytecode[jump_target + 1];
-                        let current_offset = *inst.offset();
-                        let current_block_start = *block_starts.last().unwrap();
-                        let targets = if inst.opcode() == &Opcode::PackedSwitch {
-                            &raw_bytecode[jump_target + 4..]
-                        } else {
-                            &raw_bytecode[jump_target + 2 + size as usize * 2..]
-                        };
-                        for i in (0..(size as usize * 2)).step_by(2) {
-                            let relative_target = concat_words!(targets[i], targets[i+1]) as i32;
-                            let target = (current_offset as i32 + relative_target) as u32;
-                            block_starts.push(target as usize);
-                            edges.push((current_block_start, target as usize));
-                        }
-                    },
-                    _ => ()
-                }
                 instructions.push(inst);
             },
-            Ok(None) => break,
-            Err(_) => return Err(format!("Error parsing instruction at offset: {}", offset).to_string()),
+            None => break,
         }
     }
-    let block_starts = block_starts.into_iter().collect::<HashSet<usize>>();
-    let mut blocks: Vec<BlockPtr> = vec![];
-    let mut index_mapping = HashMap::new();
-    for inst in instructions.into_iter() {
-        if block_starts.contains(inst.offset()) {
-            blocks.push(BasicBlock::new());
-            index_mapping.insert(*inst.offset(), blocks.len() - 1);
+    let leaders = block_leaders(raw_bytecode, &instructions);
+    let index = InstructionIndex::new(&instructions);
+    // Block of the instruction at every position, and the edges between blocks as (block, target offset)
+    let mut block_of = Vec::with_capacity(instructions.len());
+    let mut edges = vec![];
+    for inst in &instructions {
+        if leaders.contains(inst.offset()) {
+            block_of.push(block_of.last().map_or(0, |&block| block + 1));
+        } else {
+            // The first instruction is at offset 0, which always starts a block
+            block_of.push(*block_of.last().ok_or(CfgError::MissingSource(*inst.offset()))?);
+        }
+        let block = block_of[block_of.len() - 1];
+        // Branches out of the method have nowhere to go
+        let target = || inst.absolute_target(raw_bytecode.len()).ok_or(CfgError::JumpTargetOutOfBounds(inst.branch_target().unwrap()));
+        match inst.opcode().category() {
+            OpcodeCategory::If => {
+                edges.push((block, *inst.offset() + inst.opcode().format().units()));
+                edges.push((block, target()?));
+            },
+            OpcodeCategory::Goto => edges.push((block, target()?)),
+            OpcodeCategory::Switch => {
+                let targets = inst.switch_targets(raw_bytecode)
+                    .ok_or(CfgError::JumpTargetOutOfBounds(inst.branch_target().unwrap()))?;
+                edges.extend(targets.into_iter().map(|target| (block, target)));
+            },
+            _ => {},
         }
-        let mut current_block = blocks.last().expect("No current block").borrow_mut();
-        current_block.push(inst);
     }
-    for (src, dst) in edges.into_iter() {
-        let src_index = match index_mapping.get(&src) {
-            Some(&index) => index,
-            None => return Err(format!("No source index {}", src).to_string()),
-        };
-        let dst_index = match index_mapping.get(&dst) {
-            Some(&index) => index,
-            None => return Err(format!("No destination index {}", dst).to_string()),
-        };
-        let src_block = blocks.get(src_index).unwrap().clone();
-        let dst_block = blocks.get(dst_index).unwrap().clone();
-        src_block.borrow_mut().add_succ(dst_block.clone());
-        dst_block.borrow_mut().add_prev(src_block.clone());
+    // Targets are leaders, so the block of the instruction at a target starts there
+    let edges = edges.into_iter()
+        .map(|(src, dst)| index.position(dst).map(|position| (src, block_of[position])).ok_or(CfgError::MissingDestination(dst)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let blocks: Vec<BlockPtr> = (0..block_of.last().map_or(0, |&block| block + 1)).map(|_| BasicBlock::new()).collect();
+    for (inst, block) in instructions.into_iter().zip(block_of) {
+        blocks[block].borrow_mut().push(inst);
+    }
+    for (src, dst) in edges {
+        blocks[src].borrow_mut().add_succ(blocks[dst].clone());
+        blocks[dst].borrow_mut().add_prev(blocks[src].clone());
     }
     Ok(blocks)
 }
 
-
 #[cfg(test)]
 mod test {
-    use std::{cell::RefCell, rc::Rc};
-    use super::get_blocks;
-    use super::{opcode::Opcode, block::BasicBlock};
+    use std::{cell::RefCell, rc::Rc, sync::Arc};
+    use dex::DexReader;
+    use crate::testing::{sample_dex, DexBuilder, ClassDef, MethodDef, CodeDef, TryDef, ACC_ABSTRACT, ACC_CONSTRUCTOR, ACC_PRIVATE, ACC_PUBLIC, ACC_STATIC, ACC_SYNTHETIC, SAMPLE_METHODS};
+    use crate::options::{AnalysisOptions, DecodeMode, DedupKey, Normalization, Strictness};
+    use crate::error::{CfgError, Error};
+    use super::{get_blocks, decode_opcodes, decode_method_by_index, decode_method_lenient, decode_method_recursive, scan_opcodes, unreachable_instructions, MethodDecode, parse_dexes, process_dex_with, NamedDex, BlockPtr, Coverage, MethodDeduplicator, OpStats, TryRegion, CatchHandler};
+    use super::{opcode::{Opcode, OpcodeCategory}, block::BasicBlock, Instruction, MethodCfg};
 
     fn assert_block_starts(opcodes: &[Opcode], blocks: &[Rc<RefCell<BasicBlock>>]) {
         for (opcode, block) in opcodes.iter().zip(blocks.iter()) {
@@ -234,4 +1056,566 @@ mod test {
             &blocks
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_get_blocks_goto32_past_64k() {
+        // goto/32 +70000; 69997 nops; goto/32 -69997, back to the first nop; return-void
+        let mut raw_bytecode = vec![0x002A, (70000 & 0xFFFF) as u16, (70000 >> 16) as u16];
+        raw_bytecode.resize(70000, 0x0000);
+        let backward = (-69997i32) as u32;
+        raw_bytecode.extend([0x002A, backward as u16, (backward >> 16) as u16, 0x000E]);
+        let blocks = get_blocks(&raw_bytecode).unwrap();
+        let starts = blocks.iter().map(|block| *block.borrow().instructions()[0].offset()).collect::<Vec<_>>();
+        assert_eq!(starts, [0, 3, 70000]);
+        let succ_start = |block: &BlockPtr| *block.borrow().succ()[0].borrow().instructions()[0].offset();
+        assert_eq!((succ_start(&blocks[0]), succ_start(&blocks[2])), (70000, 3));
+        let backward = blocks[2].borrow();
+        assert_eq!((backward.instructions()[0].relative_offset(), backward.instructions()[0].absolute_target(raw_bytecode.len())), (Some(-69997), Some(3)));
+
+        // goto +10 in a method of two code units
+        assert!(matches!(get_blocks(&[0x0A28, 0x000E]), Err(Error::CfgConstruction { reason: CfgError::JumpTargetOutOfBounds(10) })));
+    }
+
+    #[test]
+    fn test_method_deduplicator() {
+        let method0: &[u16] = &[4207, 743, 2, 96, 57, 275, 33, 4148, 15, 26, 21033, 8305, 855, 2, 266, 312, 7, 8532, 22998, 8302, 714, 1, 14];
+        let method1: &[u16] = &[8276, 11170, 4206, 1757, 0, 10, 218, 7936, 8532, 11172, 313, 4, 274, 1320, 4206, 1757, 1, 266, 4272, 218, 7936, 8533, 11171, 312, 3, 4370, 4272, 15];
+        let mut deduplicator = MethodDeduplicator::default();
+        let ids = [method0, method1, method0, method0, method1]
+            .into_iter()
+            .map(|method| deduplicator.add(method).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![0, 1, 0, 0, 1]);
+
+        let (unique_sequences, methods) = deduplicator.into_parts();
+        assert_eq!(unique_sequences.len(), 2);
+        assert_eq!(methods, ids);
+        assert_eq!(unique_sequences[0].first(), Some(&(Opcode::InvokeSuper as u8)));
+        assert_eq!(unique_sequences[1].first(), Some(&(Opcode::IgetObject as u8)));
+
+        let mut full_seq = vec![];
+        for id in methods.iter() {
+            full_seq.extend_from_slice(&unique_sequences[*id]);
+        }
+        let unique_len = unique_sequences.iter().map(Vec::len).sum::<usize>();
+        assert!(unique_len < full_seq.len());
+    }
+
+    #[test]
+    fn test_deduplicator_truncate_to() {
+        let mut deduplicator = MethodDeduplicator::new(Strictness::Strict);
+        deduplicator.add(&[0x0012, 0x0012, 0x000E]).unwrap();
+        deduplicator.truncate_to(5);
+        deduplicator.add(&[0x0012, 0x0012, 0x0012, 0x000E]).unwrap();
+        deduplicator.truncate_to(5);
+        assert_eq!(deduplicator.unique_len, 5);
+        let (unique_sequences, methods) = deduplicator.into_parts();
+        assert_eq!(unique_sequences.iter().map(Vec::len).collect::<Vec<_>>(), [3, 2]);
+        assert_eq!(methods, [0, 1]);
+    }
+
+    #[test]
+    fn test_deduplicator_keys() {
+        // const/4 v0, 0; return v0 and const/4 v1, 1; return v1 have the same opcodes
+        let bodies: [&[u16]; 3] = [&[0x0012, 0x000F], &[0x1112, 0x010F], &[0x0012, 0x000F]];
+        let ids = |key: DedupKey| {
+            let mut deduplicator = MethodDeduplicator::new(Strictness::Strict).key(key);
+            bodies.iter().map(|body| deduplicator.add(body).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(DedupKey::Bytecode), [0, 1, 0]);
+        assert_eq!(ids(DedupKey::Opcodes), [0, 0, 0]);
+
+        let mut deduplicator = MethodDeduplicator::new(Strictness::Strict).key(DedupKey::Opcodes);
+        deduplicator.add(bodies[0]).unwrap();
+        deduplicator.forget();
+        deduplicator.add(bodies[1]).unwrap();
+        assert_eq!(deduplicator.coverage().decoded_methods, 2);
+        let (unique_sequences, methods) = deduplicator.into_parts();
+        assert_eq!(unique_sequences, [[Opcode::Const4 as u8, Opcode::Return as u8]; 2]);
+        assert_eq!(methods, [0, 1]);
+    }
+
+    #[test]
+    fn test_deduplicator_next_input() {
+        let bodies: [&[u16]; 3] = [&[0x0012, 0x000F], &[0x1112, 0x010F], &[0x000E]];
+        let mut deduplicator = MethodDeduplicator::new(Strictness::Strict);
+        deduplicator.add(bodies[0]).unwrap();
+        deduplicator.add(bodies[1]).unwrap();
+        assert_eq!(deduplicator.next_input(), (0, vec![vec![0x12, 0x0F]; 2], vec![0, 1]));
+        // The second input only gets its new body, and refers to the first one by its id
+        deduplicator.add(bodies[2]).unwrap();
+        deduplicator.add(bodies[0]).unwrap();
+        assert_eq!(deduplicator.coverage().decoded_methods, 2);
+        assert_eq!(deduplicator.next_input(), (2, vec![vec![0x0E]], vec![2, 0]));
+        assert_eq!(deduplicator.next_input(), (3, vec![], vec![]));
+    }
+
+    #[test]
+    fn test_deduplicator_truncated_across_inputs() {
+        let bodies: [&[u16]; 2] = [&[0x0012, 0x000E], &[0x0012, 0x0012, 0x0012, 0x000E]];
+        let mut deduplicator = MethodDeduplicator::new(Strictness::Strict);
+        for body in bodies {
+            deduplicator.add(body).unwrap();
+            deduplicator.truncate_to(4);
+        }
+        // The last method of the first input only fits in part
+        assert_eq!(deduplicator.next_input(), (0, vec![vec![0x12, 0x0E], vec![0x12, 0x12]], vec![0, 1]));
+        // The second input has room for the whole body, which it decodes again rather than sharing the truncated sequence
+        deduplicator.add(bodies[1]).unwrap();
+        deduplicator.truncate_to(4);
+        assert_eq!(deduplicator.next_input(), (2, vec![vec![0x12, 0x12, 0x12, 0x0E]], vec![2]));
+        // The whole sequence is shared from then on
+        deduplicator.add(bodies[1]).unwrap();
+        assert_eq!(deduplicator.next_input(), (3, vec![], vec![2]));
+    }
+
+    #[test]
+    fn test_normalized_sequences() {
+        // Lorg/fdroid/fdroid/views/main/MainActivity;onStart
+        let raw_bytecode = [4207, 743, 2, 96, 57, 275, 33, 4148, 15, 26, 21033, 8305, 855, 2, 266, 312, 7, 8532, 22998, 8302, 714, 1, 14];
+        let normalized = |normalization: Normalization| {
+            let mut deduplicator = MethodDeduplicator::new(Strictness::Strict).normalization(normalization);
+            deduplicator.add(&raw_bytecode).unwrap();
+            deduplicator.into_parts().0.remove(0)
+        };
+        let opcodes = [
+            Opcode::InvokeSuper, Opcode::Sget, Opcode::Const16, Opcode::IfLt, Opcode::ConstString, Opcode::InvokeStatic,
+            Opcode::MoveResult, Opcode::IfEqz, Opcode::IgetObject, Opcode::InvokeVirtual, Opcode::ReturnVoid,
+        ];
+        assert_eq!(normalized(Normalization::None), opcodes.map(|opcode| opcode as u8));
+        let mut merged = opcodes.map(|opcode| opcode as u8);
+        merged[0] = Opcode::InvokeVirtual as u8;
+        merged[5] = Opcode::InvokeVirtual as u8;
+        assert_eq!(normalized(Normalization::InvokeMerged), merged);
+        let categories = [
+            OpcodeCategory::Invoke, OpcodeCategory::StaticField, OpcodeCategory::Const, OpcodeCategory::If, OpcodeCategory::Const, OpcodeCategory::Invoke,
+            OpcodeCategory::Move, OpcodeCategory::If, OpcodeCategory::InstanceField, OpcodeCategory::Invoke, OpcodeCategory::Return,
+        ];
+        assert_eq!(normalized(Normalization::Category), categories.map(|category| category as u8));
+    }
+
+    #[test]
+    fn test_decode_method_lenient() {
+        let raw_bytecode = [
+            // packed-switch v0, +6; return-void; nop
+            0x002B, 6, 0, 0x000E, 0x0000,
+            // unused opcode 0x3e
+            0x003E,
+            // packed-switch-payload with a single target
+            0x0100, 1, 0, 0, 3, 0,
+            // return-void
+            0x000E,
+        ];
+        assert!(decode_opcodes(&raw_bytecode, &mut vec![], &Normalization::None).is_err());
+
+        let decoded = decode_method_lenient(&raw_bytecode);
+        assert_eq!(decoded.undecoded, vec![5]);
+        let instructions = decoded.instructions.iter().map(|inst| (*inst.opcode(), *inst.offset())).collect::<Vec<_>>();
+        assert_eq!(instructions, vec![
+            (Opcode::PackedSwitch, 0), (Opcode::ReturnVoid, 3), (Opcode::Nop, 4), (Opcode::ReturnVoid, 12)
+        ]);
+    }
+
+    #[test]
+    fn test_scan_opcodes_matches_full_decode() {
+        let decode = |insns: &[u16]| {
+            let mut decoded = vec![];
+            let mut offset = 0;
+            while offset < insns.len() {
+                let Some((inst, length)) = Instruction::try_from_raw_bytecode(insns, offset).unwrap() else { break };
+                decoded.push((*inst.opcode() as u8, length));
+                offset += length;
+            }
+            decoded
+        };
+        let dex = DexReader::from_vec(include_bytes!("../../tests/fixtures/sample.dex").to_vec()).unwrap();
+        let mut methods = SAMPLE_METHODS.iter().map(|(_, insns)| insns.to_vec()).collect::<Vec<_>>();
+        for class in dex.classes() {
+            methods.extend(class.unwrap().methods().filter_map(|method| method.code().map(|code| code.insns().to_vec())));
+        }
+        assert!(methods.len() > SAMPLE_METHODS.len());
+        for insns in methods {
+            let mut opcodes = vec![];
+            decode_opcodes(&insns, &mut opcodes, &Normalization::None).unwrap();
+            let scanned = scan_opcodes(&insns).unwrap();
+            assert_eq!(scanned.iter().map(|&(opcode, _)| opcode).collect::<Vec<_>>(), opcodes);
+            assert_eq!(scanned, decode(&insns));
+        }
+    }
+
+    #[test]
+    fn test_method_report_registers() {
+        let (_, on_start) = SAMPLE_METHODS[0];
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lorg/example/Sample;")
+            .method(MethodDef::new("onStart", "V", &[]).code(CodeDef::new(3, 1, 2, on_start)))
+            .method(MethodDef::new("onCreate", "V", &["Landroid/os/Bundle;"]).code(CodeDef::new(5, 2, 0, &[0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let (op_seq, methods) = parse_dexes(NamedDex::multidex([dex]), &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        assert_eq!(methods.len(), 2);
+        assert_eq!((methods[0].registers_size(), methods[0].ins_size(), methods[0].locals_size(), methods[0].outs_size()), (3, 1, 2, 2));
+        assert_eq!((methods[1].registers_size(), methods[1].ins_size(), methods[1].locals_size(), methods[1].outs_size()), (5, 2, 3, 0));
+        assert_eq!(methods[1].end(), op_seq.len() - 1);
+    }
+
+    #[test]
+    fn test_method_report_access_flags() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("<init>", "V", &[]).access_flags(ACC_PUBLIC | ACC_CONSTRUCTOR).code(CodeDef::new(1, 1, 0, &[0x000E])))
+            .method(MethodDef::new("parse", "V", &["Ljava/lang/String;"]).access_flags(ACC_PUBLIC | ACC_STATIC).code(CodeDef::new(1, 1, 0, &[0x000E])))
+            .method(MethodDef::new("lambda$run$0", "V", &[]).access_flags(ACC_PRIVATE | ACC_STATIC | ACC_SYNTHETIC).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let (_, methods) = parse_dexes(NamedDex::multidex([dex]), &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        let method = |name: &str| methods.iter().find(|method| method.name() == name).unwrap();
+        let predicates = |name: &str| {
+            let method = method(name);
+            (method.is_static(), method.is_constructor(), method.is_synthetic(), method.is_abstract(), method.is_native())
+        };
+        assert_eq!(predicates("<init>"), (false, true, false, false, false));
+        assert_eq!(predicates("parse"), (true, false, false, false, false));
+        assert_eq!(predicates("lambda$run$0"), (true, false, true, false, false));
+        assert_eq!(method("parse").access_flags(), ACC_PUBLIC | ACC_STATIC);
+        assert_eq!(serde_json::to_value(method("parse")).unwrap()["access_flags"], ACC_PUBLIC | ACC_STATIC);
+    }
+
+    #[test]
+    fn test_method_report_tries() {
+        let (_, get_request_time) = SAMPLE_METHODS[1];
+        let mut builder = DexBuilder::new();
+        // The move-exception handler at code unit 43 catches what the first instructions throw
+        builder.class(ClassDef::new("Lorg/bouncycastle/dvcs/DVCSRequestInfo;")
+            .method(MethodDef::new("getRequestTime", "V", &[]).code(CodeDef::new(8, 1, 4, get_request_time)
+                .try_block(TryDef::new(0, 43).catch("Ljava/text/ParseException;", 43).catch_all(43))))
+            .method(MethodDef::new("noTries", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let (_, methods) = parse_dexes(NamedDex::multidex([dex]), &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        assert_eq!(methods[0].tries(), [TryRegion {
+            start_addr: 0,
+            insn_count: 43,
+            handlers: vec![
+                CatchHandler { exception_type: Some("Ljava/text/ParseException;".to_string()), addr: 43 },
+                CatchHandler { exception_type: None, addr: 43 },
+            ],
+        }]);
+        let (move_exception, _) = Instruction::try_from_raw_bytecode(get_request_time, 43).unwrap().unwrap();
+        assert_eq!(*move_exception.opcode(), Opcode::MoveException);
+        assert!(methods[1].tries().is_empty());
+    }
+
+    #[test]
+    fn test_method_report_caught_types() {
+        let mut builder = DexBuilder::new();
+        let get_device_id = builder.method("Landroid/telephony/TelephonyManager;", "getDeviceId", "Ljava/lang/String;", &[]) as u16;
+        // invoke-virtual {v1}, getDeviceId; return-void; move-exception v0; return-void
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("readId", "V", &[]).code(CodeDef::new(2, 1, 1, &[0x106E, get_device_id, 0x0001, 0x000E, 0x000D, 0x000E])
+                .try_block(TryDef::new(0, 3).catch("Ljava/lang/SecurityException;", 4).catch_all(4))
+                .try_block(TryDef::new(3, 1).catch("Ljava/lang/SecurityException;", 4))))
+            .method(MethodDef::new("noTries", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let (_, methods) = parse_dexes(NamedDex::multidex([dex]), &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        let read_id = methods.iter().find(|method| method.name() == "readId").unwrap();
+        assert_eq!(read_id.caught_types(), ["Ljava/lang/SecurityException;"]);
+        assert!(read_id.catches_all());
+        let no_tries = methods.iter().find(|method| method.name() == "noTries").unwrap();
+        assert!(no_tries.caught_types().is_empty() && !no_tries.catches_all());
+    }
+
+    #[test]
+    fn test_shallow_sequences() {
+        let bytes = sample_dex(3);
+        let parse = |options: AnalysisOptions| parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes.clone()).unwrap()]), &options, &mut Coverage::default(), &mut vec![]);
+        let (rich, rich_methods) = parse(AnalysisOptions::default());
+        let (shallow, shallow_methods) = parse(AnalysisOptions::default().shallow(true));
+        assert!(!rich.is_empty());
+        assert_eq!(rich, shallow);
+        assert_eq!(rich_methods.len(), shallow_methods.len());
+    }
+
+    #[test]
+    fn test_decode_method_recursive() {
+        // goto +4; a data blob decoding as const-wide/16 v0, 0 and a const/16 swallowing the return-void; return-void
+        let blob = [0x0428, 0x0016, 0x0000, 0x0013, 0x000E];
+        let opcodes = |decoded: &MethodDecode| decoded.instructions.iter().map(|inst| (*inst.opcode(), *inst.offset())).collect::<Vec<_>>();
+        assert_eq!(opcodes(&decode_method_lenient(&blob)), [(Opcode::Goto, 0), (Opcode::ConstWide16, 1), (Opcode::Const16, 3)]);
+        let decoded = decode_method_recursive(&blob, []);
+        assert_eq!(opcodes(&decoded), [(Opcode::Goto, 0), (Opcode::ReturnVoid, 4)]);
+        assert!(decoded.undecoded.is_empty());
+
+        // return-void; unused opcode 0x3e; move-exception v0; throw v0, the last two only reachable as a catch handler
+        let handled = [0x000E, 0x003E, 0x000D, 0x0027];
+        assert_eq!(opcodes(&decode_method_recursive(&handled, [])), [(Opcode::ReturnVoid, 0)]);
+        assert_eq!(opcodes(&decode_method_recursive(&handled, [2])), [(Opcode::ReturnVoid, 0), (Opcode::MoveException, 2), (Opcode::Throw, 3)]);
+
+        // if-eqz v0, +3 over a goto +1 into the unused opcode 0x3e; return-void
+        let decoded = decode_method_recursive(&[0x0038, 3, 0x0128, 0x000E, 0x003E], []);
+        assert_eq!(opcodes(&decoded), [(Opcode::IfEqz, 0), (Opcode::Goto, 2), (Opcode::ReturnVoid, 3)]);
+        assert!(decoded.undecoded.is_empty());
+        assert_eq!(decode_method_recursive(&[0x0128, 0x003E], []).undecoded, [1]);
+    }
+
+    #[test]
+    fn test_unreachable_instructions() {
+        // const/4 v0, 0; if-eqz v0, +3; nop; return-void
+        assert_eq!(unreachable_instructions(&[0x0012, 0x0038, 0x0003, 0x0000, 0x000E], [], 0), Some((0, 4)));
+        // return-void; const/4 v0, 1; return v0; nop, the trailing code after the return is dead
+        assert_eq!(unreachable_instructions(&[0x000E, 0x1012, 0x000F, 0x0000], [], 0), Some((3, 4)));
+        // return-void; move-exception v0; throw v0, a catch handler is only reachable through its exception edge
+        let handled = [0x000E, 0x000D, 0x0027];
+        assert_eq!(unreachable_instructions(&handled, [], 0), Some((2, 3)));
+        assert_eq!(unreachable_instructions(&handled, [1], 0), Some((0, 3)));
+        // if-eqz v0, +3; return-void; goto -1; return-void; const/4 v0, 0; return-void; move-exception v0; throw v0
+        let raw = [0x0038, 0x0003, 0x000E, 0xFF28, 0x000E, 0x0012, 0x000E, 0x000D, 0x0027];
+        assert_eq!(unreachable_instructions(&raw, [], 0), Some((5, 8)));
+        assert_eq!(unreachable_instructions(&raw, [7], 0), Some((3, 8)));
+        // Every instruction of straight-line code is one deeper than the previous one
+        assert_eq!(unreachable_instructions(&[0x0012, 0x0012, 0x000E], [], 3), Some((0, 3)));
+        assert_eq!(unreachable_instructions(&[0x0012, 0x0012, 0x000E], [], 2), None);
+    }
+
+    #[test]
+    fn test_decode_method_by_index() {
+        let mut builder = DexBuilder::new();
+        let run = builder.method("Lcom/example/Main;", "run", "V", &[]);
+        let broken = builder.method("Lcom/example/Main;", "broken", "V", &[]);
+        let listen = builder.method("Lcom/example/Listener;", "listen", "V", &[]);
+        let length = builder.method("Ljava/lang/String;", "length", "I", &[]);
+        // const/4 v0, 0; return-void
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000E])))
+            // const/4 v0, 0; unused opcode 0x3e
+            .method(MethodDef::new("broken", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x003E]))));
+        builder.class(ClassDef::new("Lcom/example/Listener;").method(MethodDef::new("listen", "V", &[]).access_flags(ACC_PUBLIC | ACC_ABSTRACT)));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+
+        let instructions = decode_method_by_index(&dex, run).unwrap();
+        assert_eq!(instructions.iter().map(|inst| *inst.opcode()).collect::<Vec<_>>(), [Opcode::Const4, Opcode::ReturnVoid]);
+        let err = decode_method_by_index(&dex, broken).unwrap_err();
+        assert!(matches!(err, Error::InstructionDecode { offset: 1, opcode_byte: 0x3E, method: Some(ref method), .. } if method == "broken"));
+        let err = decode_method_by_index(&dex, listen).unwrap_err();
+        assert_eq!(err.to_string(), "Method Lcom/example/Listener;->listen has no code");
+        // Referenced but defined by the framework
+        assert!(matches!(decode_method_by_index(&dex, length), Err(Error::MethodNotFound { .. })));
+        assert!(matches!(decode_method_by_index(&dex, 1000), Err(Error::MethodNotFound { method_idx: 1000 })));
+    }
+
+    #[test]
+    fn test_recursive_sequences() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("blob", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0428, 0x0016, 0x0000, 0x0013, 0x000E])))
+            .method(MethodDef::new("handled", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E, 0x003E, 0x000D, 0x0027])
+                .try_block(TryDef::new(0, 1).catch_all(2)))));
+        let bytes = builder.build();
+        let parse = |options: AnalysisOptions| parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes.clone()).unwrap()]), &options, &mut Coverage::default(), &mut vec![]).0;
+        // The linear sweep decodes the blob as code and drops the method with the unused opcode
+        let linear = [Opcode::Goto, Opcode::ConstWide16, Opcode::Const16].map(|opcode| opcode as u8);
+        assert_eq!(parse(AnalysisOptions::default()), linear);
+        let recursive = [Opcode::Goto, Opcode::ReturnVoid, Opcode::ReturnVoid, Opcode::MoveException, Opcode::Throw].map(|opcode| opcode as u8);
+        assert_eq!(parse(AnalysisOptions::default().decode_mode(DecodeMode::Recursive)), recursive);
+    }
+
+    #[test]
+    fn test_process_dex_with_names() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("first", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000E])))
+            .method(MethodDef::new("second", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let bytes = builder.build();
+        let dex = NamedDex::new("classes.dex", DexReader::from_vec(bytes.clone()).unwrap());
+
+        let mut names = vec![];
+        let mut streamed = vec![];
+        let coverage = process_dex_with(&dex, &AnalysisOptions::default(), &mut vec![], |method| {
+            names.push(format!("{}->{}", method.info.class().jtype().type_descriptor().as_str(), method.info.method().name().as_str()));
+            streamed.extend_from_slice(method.opcodes);
+        });
+        assert_eq!(names, ["Lcom/example/Main;->first", "Lcom/example/Main;->second"]);
+        assert_eq!(coverage.decoded_methods, 2);
+        let (op_seq, _) = parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes).unwrap()]), &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        assert_eq!(streamed, op_seq);
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_process_dexes_parallel() {
+        let mut first = DexBuilder::new();
+        first.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("first", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000E])))
+            .method(MethodDef::new("second", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let mut second = DexBuilder::new();
+        second.class(ClassDef::new("Lcom/example/Main;").method(MethodDef::new("first", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        second.class(ClassDef::new("Lcom/example/Helper;")
+            .method(MethodDef::new("help", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x0012, 0x000E]))));
+        let bytes = [first.build(), second.build()];
+        let dexes: Vec<(String, Arc<[u8]>)> = vec![("classes.dex".into(), bytes[0].clone().into()), ("classes2.dex".into(), bytes[1].clone().into())];
+
+        let mut per_dex = [vec![], vec![]];
+        let (coverage, warnings) = super::process_dexes_parallel(&dexes, &AnalysisOptions::default(), 1, |method| per_dex[method.dex].push(method)).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(coverage.decoded_methods, 3);
+        let names: Vec<Vec<String>> = per_dex.iter().map(|methods| methods.iter().map(|method| format!("{}->{}", method.report.class(), method.report.name())).collect()).collect();
+        assert_eq!(names, [vec!["Lcom/example/Main;->first", "Lcom/example/Main;->second"], vec!["Lcom/example/Helper;->help"]]);
+        assert_eq!(per_dex[1][0].report.start(), 0);
+
+        let streamed: Vec<u8> = per_dex.iter().flatten().flat_map(|method| method.opcodes.iter().copied()).collect();
+        let dexes = NamedDex::multidex(bytes.map(|bytes| DexReader::from_vec(bytes).unwrap()));
+        let (op_seq, _) = parse_dexes(dexes, &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        assert_eq!(streamed, op_seq);
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_process_dexes_parallel_small_buffer() {
+        // Two dexes of many methods each, far more than the single method a dex may keep waiting for the caller
+        let dex = |prefix: &str| {
+            let mut builder = DexBuilder::new();
+            for i in 0..50 {
+                let mut class = ClassDef::new(&format!("Lorg/example/{}{};", prefix, i));
+                for (name, insns) in SAMPLE_METHODS {
+                    class = class.method(MethodDef::new(name, "V", &[]).code(CodeDef::new(8, 1, 4, insns)));
+                }
+                builder.class(class);
+            }
+            builder.build()
+        };
+        let bytes = [dex("First"), dex("Second")];
+        let dexes: Vec<(String, Arc<[u8]>)> = vec![("classes.dex".into(), bytes[0].clone().into()), ("classes2.dex".into(), bytes[1].clone().into())];
+
+        let mut methods = vec![];
+        let (coverage, warnings) = super::process_dexes_parallel(&dexes, &AnalysisOptions::default(), 1, |method| methods.push(method)).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(coverage.decoded_methods, 100 * SAMPLE_METHODS.len());
+        // Every method, in dex order
+        assert!(methods.windows(2).all(|pair| pair[0].dex <= pair[1].dex));
+        let streamed: Vec<u8> = methods.iter().flat_map(|method| method.opcodes.iter().copied()).collect();
+        let dexes = NamedDex::multidex(bytes.map(|bytes| DexReader::from_vec(bytes).unwrap()));
+        let (op_seq, reports) = parse_dexes(dexes, &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        assert_eq!(methods.len(), reports.len());
+        assert_eq!(streamed, op_seq);
+    }
+
+    /// Walks kept in their serialized form, counting the lookups that found one
+    #[cfg(feature = "parallel")]
+    #[derive(Debug, Default)]
+    struct MemoryCache {
+        walks: std::sync::Mutex<std::collections::HashMap<String, String>>,
+        hits: std::sync::atomic::AtomicUsize,
+    }
+
+    #[cfg(feature = "parallel")]
+    impl super::DexCache for MemoryCache {
+        fn get(&self, key: &str) -> Option<super::DexWalk> {
+            let walk = serde_json::from_str(self.walks.lock().unwrap().get(key)?).unwrap();
+            self.hits.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Some(walk)
+        }
+
+        fn put(&self, key: &str, walk: &super::DexWalk) {
+            self.walks.lock().unwrap().insert(key.to_string(), serde_json::to_string(walk).unwrap());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn test_dex_cache() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000E])))
+            // const/4 v0, 0; unused opcode 0x3e
+            .method(MethodDef::new("broken", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x003E]))));
+        let bytes: Arc<[u8]> = builder.build().into();
+        let dexes = vec![("classes.dex".to_string(), bytes.clone()), ("classes2.dex".to_string(), bytes.clone())];
+        let cache = Arc::new(MemoryCache::default());
+        let options = AnalysisOptions::default().strictness(Strictness::Lenient).with_offsets(true).op_stats(true);
+        let cached = options.clone().dex_cache(cache.clone());
+        let walk = |options: &AnalysisOptions| {
+            let mut methods = vec![];
+            let (coverage, warnings) = super::process_dexes_parallel(&dexes, options, super::DEX_BUFFER, |method| methods.push(method)).unwrap();
+            (methods, coverage, warnings)
+        };
+        let decoded = walk(&options);
+        assert_eq!(decoded.2.len(), 1);
+        assert_eq!(walk(&cached), decoded);
+        // The second dex skips the class of the first, a walk of its own
+        assert_eq!((cache.walks.lock().unwrap().len(), cache.hits.load(std::sync::atomic::Ordering::Relaxed)), (2, 0));
+        let hit = walk(&cached);
+        assert_eq!(cache.hits.load(std::sync::atomic::Ordering::Relaxed), 2);
+        assert_eq!(hit, decoded);
+        assert_eq!(hit.0[1].report.flags(), decoded.0[1].report.flags());
+    }
+
+    #[test]
+    fn test_method_offsets() {
+        let (_, on_start) = SAMPLE_METHODS[0];
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lorg/example/Sample;").method(MethodDef::new("onStart", "V", &[]).code(CodeDef::new(3, 1, 2, on_start))));
+        let bytes = builder.build();
+        let mut expected = vec![];
+        let mut offset = 0;
+        while offset < on_start.len() {
+            let (_, length) = Instruction::try_from_raw_bytecode(on_start, offset).unwrap().unwrap();
+            expected.push(offset as u32);
+            offset += length;
+        }
+
+        for shallow in [false, true] {
+            let options = AnalysisOptions::default().with_offsets(true).shallow(shallow);
+            let (op_seq, methods) = parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes.clone()).unwrap()]), &options, &mut Coverage::default(), &mut vec![]);
+            assert_eq!(methods[0].offsets(), Some(expected.as_slice()));
+            assert_eq!(op_seq.len(), expected.len());
+        }
+        let (_, methods) = parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes.clone()).unwrap()]), &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        assert_eq!(methods[0].offsets(), None);
+
+        let options = AnalysisOptions::default().instructions_lite(true).shallow(true);
+        let (_, methods) = parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes).unwrap()]), &options, &mut Coverage::default(), &mut vec![]);
+        let instructions = methods[0].instructions().unwrap();
+        assert_eq!(instructions.iter().map(|inst| *inst.offset() as u32).collect::<Vec<_>>(), expected);
+        assert_eq!(instructions, decode_method_lenient(on_start).instructions);
+    }
+
+    #[test]
+    fn test_method_op_stats() {
+        let bytes = sample_dex(1);
+        for shallow in [false, true] {
+            let options = AnalysisOptions::default().op_stats(true).shallow(shallow);
+            let (_, methods) = parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes.clone()).unwrap()]), &options, &mut Coverage::default(), &mut vec![]);
+            for (method, (_, insns)) in methods.iter().zip(SAMPLE_METHODS) {
+                assert_eq!(method.op_stats(), Some(&OpStats::from_instructions(&decode_method_lenient(insns).instructions)));
+            }
+        }
+        let (_, methods) = parse_dexes(NamedDex::multidex([DexReader::from_vec(bytes).unwrap()]), &AnalysisOptions::default(), &mut Coverage::default(), &mut vec![]);
+        assert_eq!(methods[0].op_stats(), None);
+    }
+
+    #[test]
+    fn test_short_buffers_never_panic() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20_000 {
+            let len = rng.gen_range(0..6);
+            let raw_bytecode: Vec<u16> = (0..len).map(|_| rng.gen()).collect();
+            for offset in 0..=len + 1 {
+                let _ = Instruction::try_from_raw_bytecode(&raw_bytecode, offset);
+                let _ = Instruction::try_opcode_from_raw_bytecode(&raw_bytecode, offset);
+                let _ = Instruction::payload_length(&raw_bytecode, offset);
+            }
+            let _ = scan_opcodes(&raw_bytecode);
+            let _ = decode_opcodes(&raw_bytecode, &mut vec![], &Normalization::None);
+            let decoded = decode_method_lenient(&raw_bytecode);
+            for inst in &decoded.instructions {
+                let _ = inst.switch_targets(&raw_bytecode);
+                let _ = inst.invocation_registers(&raw_bytecode);
+            }
+            let _ = decode_method_recursive(&raw_bytecode, [rng.gen_range(0..8)]);
+            let _ = unreachable_instructions(&raw_bytecode, [], 0);
+            let _ = get_blocks(&raw_bytecode);
+            if let Ok(cfg) = MethodCfg::build(&raw_bytecode) {
+                cfg.depth_first(0);
+            }
+        }
+    }
+}
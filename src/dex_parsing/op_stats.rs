@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::{instruction::Instruction, opcode::Opcode};
+
+
+/// Counts of the invokes, allocations and other telling opcodes of a method, computed while decoding it without
+/// building its CFG
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpStats {
+    /// `invoke-virtual` and `invoke-virtual/range`
+    pub invoke_virtual: u32,
+    pub invoke_static: u32,
+    pub invoke_direct: u32,
+    pub invoke_interface: u32,
+    pub invoke_super: u32,
+    /// `const-string` and `const-string/jumbo`
+    pub const_string: u32,
+    pub new_instance: u32,
+    pub throw: u32,
+    pub monitor_enter: u32,
+    /// Number of distinct method indices invoked by the kinds above
+    pub distinct_methods: u32,
+    /// Number of instructions of the method
+    pub instructions: u32,
+    /// Register to register moves, `move`, `move-wide` and `move-object` in all their widths. `move-result` and
+    /// `move-exception` follow from invokes and handlers rather than from register allocation, and aren't counted
+    pub moves: u32,
+    /// Length of the longest run of consecutive moves
+    pub longest_move_chain: u32,
+}
+
+
+impl OpStats {
+    /// Stats of a method from its decoded instructions
+    pub fn from_instructions<'a>(instructions: impl IntoIterator<Item = &'a Instruction>) -> Self {
+        let mut counter = OpStatsCounter::default();
+        for inst in instructions {
+            counter.add(inst);
+        }
+        counter.finish()
+    }
+
+    /// Share of the instructions that are moves, `None` for a method without instructions
+    pub fn move_ratio(&self) -> Option<f64> {
+        (self.instructions > 0).then(|| self.moves as f64 / self.instructions as f64)
+    }
+}
+
+
+/// Accumulates the `OpStats` of a method instruction by instruction
+#[derive(Debug, Default)]
+pub(crate) struct OpStatsCounter {
+    stats: OpStats,
+    invoked: HashSet<u32>,
+    /// Length of the run of moves ending at the last instruction added
+    move_chain: u32,
+}
+
+
+impl OpStatsCounter {
+    pub(crate) fn add(&mut self, inst: &Instruction) {
+        let stats = &mut self.stats;
+        stats.instructions += 1;
+        if is_move(inst.opcode()) {
+            stats.moves += 1;
+            self.move_chain += 1;
+            stats.longest_move_chain = stats.longest_move_chain.max(self.move_chain);
+        } else {
+            self.move_chain = 0;
+        }
+        let count = match inst.opcode() {
+            Opcode::InvokeVirtual | Opcode::InvokeVirtualRange => &mut stats.invoke_virtual,
+            Opcode::InvokeStatic | Opcode::InvokeStaticRange => &mut stats.invoke_static,
+            Opcode::InvokeDirect | Opcode::InvokeDirectRange => &mut stats.invoke_direct,
+            Opcode::InvokeInterface | Opcode::InvokeInterfaceRange => &mut stats.invoke_interface,
+            Opcode::InvokeSuper | Opcode::InvokeSuperRange => &mut stats.invoke_super,
+            Opcode::ConstString | Opcode::ConstStringJumbo => &mut stats.const_string,
+            Opcode::NewInstance => &mut stats.new_instance,
+            Opcode::Throw => &mut stats.throw,
+            Opcode::MonitorEnter => &mut stats.monitor_enter,
+            _ => return,
+        };
+        *count += 1;
+        if let (Some(method_idx), Some("method")) = (inst.reference(), inst.reference_kind()) {
+            self.invoked.insert(*method_idx);
+        }
+    }
+
+    /// Stats of the instructions added since the last call, which starts a new method
+    pub(crate) fn finish(&mut self) -> OpStats {
+        let stats = OpStats { distinct_methods: self.invoked.len() as u32, ..self.stats };
+        self.stats = OpStats::default();
+        self.invoked.clear();
+        self.move_chain = 0;
+        stats
+    }
+}
+
+
+/// Whether `opcode` copies a register into another
+fn is_move(opcode: &Opcode) -> bool {
+    matches!(opcode,
+        Opcode::Move | Opcode::MoveFrom16 | Opcode::Move16 | Opcode::MoveWide | Opcode::MoveWideFrom16 | Opcode::MoveWide16
+        | Opcode::MoveObject | Opcode::MoveObjectFrom16 | Opcode::MoveObject16)
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::{dex_parsing::decode_method_lenient, testing::SAMPLE_METHODS};
+    use super::*;
+
+    #[test]
+    fn test_op_stats() {
+        // new-instance v0, type@3; invoke-direct {v0}, method@7; const-string v1, string@2; invoke-virtual {v0, v1}, method@8;
+        // invoke-static/range {v0}, method@7; monitor-enter v0; invoke-super {v0}, method@9; throw v0
+        let raw_bytecode = [
+            0x0022, 0x0003, 0x1070, 0x0007, 0x0000, 0x011A, 0x0002, 0x206E, 0x0008, 0x0010,
+            0x0177, 0x0007, 0x0000, 0x001D, 0x106F, 0x0009, 0x0000, 0x0027,
+        ];
+        let decoded = decode_method_lenient(&raw_bytecode);
+        assert!(decoded.undecoded.is_empty());
+        assert_eq!(OpStats::from_instructions(&decoded.instructions), OpStats {
+            invoke_virtual: 1,
+            invoke_static: 1,
+            invoke_direct: 1,
+            invoke_interface: 0,
+            invoke_super: 1,
+            const_string: 1,
+            new_instance: 1,
+            throw: 1,
+            monitor_enter: 1,
+            distinct_methods: 3,
+            instructions: 8,
+            moves: 0,
+            longest_move_chain: 0,
+        });
+        // return-void
+        assert_eq!(OpStats::from_instructions(&decode_method_lenient(&[0x000E]).instructions), OpStats { instructions: 1, ..OpStats::default() });
+
+        let (_, get_request_time) = SAMPLE_METHODS[1];
+        let stats = OpStats::from_instructions(&decode_method_lenient(get_request_time).instructions);
+        assert_eq!((stats.invoke_virtual, stats.invoke_direct, stats.new_instance, stats.const_string, stats.throw), (11, 3, 3, 1, 1));
+        assert_eq!(stats.distinct_methods, 12);
+    }
+
+    #[test]
+    fn test_move_stats() {
+        // The samples are D8 output: their only moves are move-result and move-exception, which aren't counted
+        for ((_, insns), instructions) in SAMPLE_METHODS.iter().zip([11, 36, 17]) {
+            let stats = OpStats::from_instructions(&decode_method_lenient(insns).instructions);
+            assert_eq!((stats.instructions, stats.moves, stats.longest_move_chain), (instructions, 0, 0));
+            assert_eq!(stats.move_ratio(), Some(0.0));
+        }
+
+        // move v0, v1; move-object/from16 v2, v300; move-wide v4, v6; const/4 v0, 0; move-object v3, v2;
+        // invoke-static {}, method@1; move-result v0; move/16 v300, v0; return-void
+        let raw_bytecode = [0x1001, 0x0208, 0x012C, 0x6404, 0x0012, 0x2307, 0x0071, 0x0001, 0x0000, 0x000A, 0x0003, 0x012C, 0x0000, 0x000E];
+        let decoded = decode_method_lenient(&raw_bytecode);
+        assert!(decoded.undecoded.is_empty());
+        let stats = OpStats::from_instructions(&decoded.instructions);
+        assert_eq!((stats.instructions, stats.moves, stats.longest_move_chain), (9, 5, 3));
+        assert_eq!(stats.move_ratio(), Some(5.0 / 9.0));
+        assert_eq!(OpStats::default().move_ratio(), None);
+    }
+}
@@ -1,4 +1,7 @@
+use std::fmt;
+
 use num_derive::FromPrimitive;
+use serde::{Serialize, Serializer};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, Hash)]
 pub enum Opcode {
@@ -226,4 +229,432 @@ pub enum Opcode {
     InvokeCustomRange,
     ConstMethodHandle,
     ConstMethodType,
-}
\ No newline at end of file
+}
+
+
+/// Coarse family of an opcode, for models working on a smaller alphabet than the full instruction set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpcodeCategory {
+    Nop,
+    /// Register moves, including `move-result` and `move-exception`
+    Move,
+    Return,
+    /// Constants of every kind: literals, strings, classes, method handles and method types
+    Const,
+    Monitor,
+    /// `check-cast`, `instance-of` and `new-instance`
+    Type,
+    /// Array creation, length and element access
+    Array,
+    Throw,
+    Goto,
+    Switch,
+    Compare,
+    If,
+    InstanceField,
+    StaticField,
+    Invoke,
+    /// Negations and primitive conversions
+    Unary,
+    /// Arithmetic and bitwise operations, including the `/2addr` and literal forms
+    Binary,
+}
+
+
+/// Layout of the code units of an instruction, named as in the Dalvik bytecode formats: the number of code units,
+/// the number of registers and the kind of extra data, e.g. `35c` for 3 units, up to 5 registers and a constant pool index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstructionFormat {
+    F10x,
+    F12x,
+    F11n,
+    F11x,
+    F10t,
+    F20t,
+    F22x,
+    F21t,
+    F21s,
+    F21h,
+    F21c,
+    F23x,
+    F22b,
+    F22t,
+    F22s,
+    F22c,
+    F30t,
+    F32x,
+    F31i,
+    F31t,
+    F31c,
+    F35c,
+    F3rc,
+    F45cc,
+    F4rcc,
+    F51l,
+}
+
+
+impl InstructionFormat {
+    /// Format ID, e.g. `35c`
+    pub fn id(&self) -> &'static str {
+        match self {
+            InstructionFormat::F10x => "10x",
+            InstructionFormat::F12x => "12x",
+            InstructionFormat::F11n => "11n",
+            InstructionFormat::F11x => "11x",
+            InstructionFormat::F10t => "10t",
+            InstructionFormat::F20t => "20t",
+            InstructionFormat::F22x => "22x",
+            InstructionFormat::F21t => "21t",
+            InstructionFormat::F21s => "21s",
+            InstructionFormat::F21h => "21h",
+            InstructionFormat::F21c => "21c",
+            InstructionFormat::F23x => "23x",
+            InstructionFormat::F22b => "22b",
+            InstructionFormat::F22t => "22t",
+            InstructionFormat::F22s => "22s",
+            InstructionFormat::F22c => "22c",
+            InstructionFormat::F30t => "30t",
+            InstructionFormat::F32x => "32x",
+            InstructionFormat::F31i => "31i",
+            InstructionFormat::F31t => "31t",
+            InstructionFormat::F31c => "31c",
+            InstructionFormat::F35c => "35c",
+            InstructionFormat::F3rc => "3rc",
+            InstructionFormat::F45cc => "45cc",
+            InstructionFormat::F4rcc => "4rcc",
+            InstructionFormat::F51l => "51l",
+        }
+    }
+
+    /// Length of the instruction in code units
+    pub fn units(&self) -> usize {
+        (self.id().as_bytes()[0] - b'0') as usize
+    }
+
+    /// Number of registers the instruction names at most, `None` for the register ranges of `3rc` and `4rcc`
+    pub fn max_registers(&self) -> Option<u8> {
+        match self.id().as_bytes()[1] {
+            b'r' => None,
+            count => Some(count - b'0'),
+        }
+    }
+
+    /// Whether the operands packed in the first code unit of an instruction fit the format:
+    /// the unused byte of `10x` must be zero and the argument count of `35c` and `45cc` at most 5
+    pub fn accepts(&self, first_unit: u16) -> bool {
+        let operands = (first_unit >> 8) as u8;
+        match self {
+            InstructionFormat::F10x => operands == 0,
+            InstructionFormat::F35c | InstructionFormat::F45cc => self.max_registers().is_some_and(|max| operands >> 4 <= max),
+            _ => true,
+        }
+    }
+}
+
+
+impl Opcode {
+    /// Name of the opcode in smali, e.g. `invoke-virtual/range`
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Nop => "nop",
+            Opcode::Move => "move",
+            Opcode::MoveFrom16 => "move/from16",
+            Opcode::Move16 => "move/16",
+            Opcode::MoveWide => "move-wide",
+            Opcode::MoveWideFrom16 => "move-wide/from16",
+            Opcode::MoveWide16 => "move-wide/16",
+            Opcode::MoveObject => "move-object",
+            Opcode::MoveObjectFrom16 => "move-object/from16",
+            Opcode::MoveObject16 => "move-object/16",
+            Opcode::MoveResult => "move-result",
+            Opcode::MoveResultWide => "move-result-wide",
+            Opcode::MoveResultObject => "move-result-object",
+            Opcode::MoveException => "move-exception",
+            Opcode::ReturnVoid => "return-void",
+            Opcode::Return => "return",
+            Opcode::ReturnWide => "return-wide",
+            Opcode::ReturnObject => "return-object",
+            Opcode::Const4 => "const/4",
+            Opcode::Const16 => "const/16",
+            Opcode::Const => "const",
+            Opcode::ConstHigh16 => "const/high16",
+            Opcode::ConstWide16 => "const-wide/16",
+            Opcode::ConstWide32 => "const-wide/32",
+            Opcode::ConstWide => "const-wide",
+            Opcode::ConstWideHigh16 => "const-wide/high16",
+            Opcode::ConstString => "const-string",
+            Opcode::ConstStringJumbo => "const-string/jumbo",
+            Opcode::ConstClass => "const-class",
+            Opcode::MonitorEnter => "monitor-enter",
+            Opcode::MonitorExit => "monitor-exit",
+            Opcode::CheckCast => "check-cast",
+            Opcode::InstanceOf => "instance-of",
+            Opcode::ArrayLength => "array-length",
+            Opcode::NewInstance => "new-instance",
+            Opcode::NewArray => "new-array",
+            Opcode::FilledNewArray => "filled-new-array",
+            Opcode::FilledNewArrayRange => "filled-new-array/range",
+            Opcode::FillArrayData => "fill-array-data",
+            Opcode::Throw => "throw",
+            Opcode::Goto => "goto",
+            Opcode::Goto16 => "goto/16",
+            Opcode::Goto32 => "goto/32",
+            Opcode::PackedSwitch => "packed-switch",
+            Opcode::SparseSwitch => "sparse-switch",
+            Opcode::CmplFloat => "cmpl-float",
+            Opcode::CmpgFloat => "cmpg-float",
+            Opcode::CmplDouble => "cmpl-double",
+            Opcode::CmpgDouble => "cmpg-double",
+            Opcode::CmpLong => "cmp-long",
+            Opcode::IfEq => "if-eq",
+            Opcode::IfNe => "if-ne",
+            Opcode::IfLt => "if-lt",
+            Opcode::IfGe => "if-ge",
+            Opcode::IfGt => "if-gt",
+            Opcode::IfLe => "if-le",
+            Opcode::IfEqz => "if-eqz",
+            Opcode::IfNez => "if-nez",
+            Opcode::IfLtz => "if-ltz",
+            Opcode::IfGez => "if-gez",
+            Opcode::IfGtz => "if-gtz",
+            Opcode::IfLez => "if-lez",
+            Opcode::Aget => "aget",
+            Opcode::AgetWide => "aget-wide",
+            Opcode::AgetObject => "aget-object",
+            Opcode::AgetBoolean => "aget-boolean",
+            Opcode::AgetByte => "aget-byte",
+            Opcode::AgetChar => "aget-char",
+            Opcode::AgetShort => "aget-short",
+            Opcode::Aput => "aput",
+            Opcode::AputWide => "aput-wide",
+            Opcode::AputObject => "aput-object",
+            Opcode::AputBoolean => "aput-boolean",
+            Opcode::AputByte => "aput-byte",
+            Opcode::AputChar => "aput-char",
+            Opcode::AputShort => "aput-short",
+            Opcode::Iget => "iget",
+            Opcode::IgetWide => "iget-wide",
+            Opcode::IgetObject => "iget-object",
+            Opcode::IgetBoolean => "iget-boolean",
+            Opcode::IgetByte => "iget-byte",
+            Opcode::IgetChar => "iget-char",
+            Opcode::IgetShort => "iget-short",
+            Opcode::Iput => "iput",
+            Opcode::IputWide => "iput-wide",
+            Opcode::IputObject => "iput-object",
+            Opcode::IputBoolean => "iput-boolean",
+            Opcode::IputByte => "iput-byte",
+            Opcode::IputChar => "iput-char",
+            Opcode::IputShort => "iput-short",
+            Opcode::Sget => "sget",
+            Opcode::SgetWide => "sget-wide",
+            Opcode::SgetObject => "sget-object",
+            Opcode::SgetBoolean => "sget-boolean",
+            Opcode::SgetByte => "sget-byte",
+            Opcode::SgetChar => "sget-char",
+            Opcode::SgetShort => "sget-short",
+            Opcode::Sput => "sput",
+            Opcode::SputWide => "sput-wide",
+            Opcode::SputObject => "sput-object",
+            Opcode::SputBoolean => "sput-boolean",
+            Opcode::SputByte => "sput-byte",
+            Opcode::SputChar => "sput-char",
+            Opcode::SputShort => "sput-short",
+            Opcode::InvokeVirtual => "invoke-virtual",
+            Opcode::InvokeSuper => "invoke-super",
+            Opcode::InvokeDirect => "invoke-direct",
+            Opcode::InvokeStatic => "invoke-static",
+            Opcode::InvokeInterface => "invoke-interface",
+            Opcode::InvokeVirtualRange => "invoke-virtual/range",
+            Opcode::InvokeSuperRange => "invoke-super/range",
+            Opcode::InvokeDirectRange => "invoke-direct/range",
+            Opcode::InvokeStaticRange => "invoke-static/range",
+            Opcode::InvokeInterfaceRange => "invoke-interface/range",
+            Opcode::NegInt => "neg-int",
+            Opcode::NotInt => "not-int",
+            Opcode::NegLong => "neg-long",
+            Opcode::NotLong => "not-long",
+            Opcode::NegFloat => "neg-float",
+            Opcode::NegDouble => "neg-double",
+            Opcode::IntToLong => "int-to-long",
+            Opcode::IntToFloat => "int-to-float",
+            Opcode::IntToDouble => "int-to-double",
+            Opcode::LongToInt => "long-to-int",
+            Opcode::LongToFloat => "long-to-float",
+            Opcode::LongToDouble => "long-to-double",
+            Opcode::FloatToInt => "float-to-int",
+            Opcode::FloatToLong => "float-to-long",
+            Opcode::FloatToDouble => "float-to-double",
+            Opcode::DoubleToInt => "double-to-int",
+            Opcode::DoubleToLong => "double-to-long",
+            Opcode::DoubleToFloat => "double-to-float",
+            Opcode::IntToByte => "int-to-byte",
+            Opcode::IntToChar => "int-to-char",
+            Opcode::IntToShort => "int-to-short",
+            Opcode::AddInt => "add-int",
+            Opcode::SubInt => "sub-int",
+            Opcode::MulInt => "mul-int",
+            Opcode::DivInt => "div-int",
+            Opcode::RemInt => "rem-int",
+            Opcode::AndInt => "and-int",
+            Opcode::OrInt => "or-int",
+            Opcode::XorInt => "xor-int",
+            Opcode::ShlInt => "shl-int",
+            Opcode::ShrInt => "shr-int",
+            Opcode::UshrInt => "ushr-int",
+            Opcode::AddLong => "add-long",
+            Opcode::SubLong => "sub-long",
+            Opcode::MulLong => "mul-long",
+            Opcode::DivLong => "div-long",
+            Opcode::RemLong => "rem-long",
+            Opcode::AndLong => "and-long",
+            Opcode::OrLong => "or-long",
+            Opcode::XorLong => "xor-long",
+            Opcode::ShlLong => "shl-long",
+            Opcode::ShrLong => "shr-long",
+            Opcode::UshrLong => "ushr-long",
+            Opcode::AddFloat => "add-float",
+            Opcode::SubFloat => "sub-float",
+            Opcode::MulFloat => "mul-float",
+            Opcode::DivFloat => "div-float",
+            Opcode::RemFloat => "rem-float",
+            Opcode::AddDouble => "add-double",
+            Opcode::SubDouble => "sub-double",
+            Opcode::MulDouble => "mul-double",
+            Opcode::DivDouble => "div-double",
+            Opcode::RemDouble => "rem-double",
+            Opcode::AddInt2Addr => "add-int/2addr",
+            Opcode::SubInt2Addr => "sub-int/2addr",
+            Opcode::MulInt2Addr => "mul-int/2addr",
+            Opcode::DivInt2Addr => "div-int/2addr",
+            Opcode::RemInt2Addr => "rem-int/2addr",
+            Opcode::AndInt2Addr => "and-int/2addr",
+            Opcode::OrInt2Addr => "or-int/2addr",
+            Opcode::XorInt2Addr => "xor-int/2addr",
+            Opcode::ShlInt2Addr => "shl-int/2addr",
+            Opcode::ShrInt2Addr => "shr-int/2addr",
+            Opcode::UshrInt2Addr => "ushr-int/2addr",
+            Opcode::AddLong2Addr => "add-long/2addr",
+            Opcode::SubLong2Addr => "sub-long/2addr",
+            Opcode::MulLong2Addr => "mul-long/2addr",
+            Opcode::DivLong2Addr => "div-long/2addr",
+            Opcode::RemLong2Addr => "rem-long/2addr",
+            Opcode::AndLong2Addr => "and-long/2addr",
+            Opcode::OrLong2Addr => "or-long/2addr",
+            Opcode::XorLong2Addr => "xor-long/2addr",
+            Opcode::ShlLong2Addr => "shl-long/2addr",
+            Opcode::ShrLong2Addr => "shr-long/2addr",
+            Opcode::UshrLong2Addr => "ushr-long/2addr",
+            Opcode::AddFloat2Addr => "add-float/2addr",
+            Opcode::SubFloat2Addr => "sub-float/2addr",
+            Opcode::MulFloat2Addr => "mul-float/2addr",
+            Opcode::DivFloat2Addr => "div-float/2addr",
+            Opcode::RemFloat2Addr => "rem-float/2addr",
+            Opcode::AddDouble2Addr => "add-double/2addr",
+            Opcode::SubDouble2Addr => "sub-double/2addr",
+            Opcode::MulDouble2Addr => "mul-double/2addr",
+            Opcode::DivDouble2Addr => "div-double/2addr",
+            Opcode::RemDouble2Addr => "rem-double/2addr",
+            Opcode::AddIntLit16 => "add-int/lit16",
+            Opcode::SubIntLit16 => "sub-int/lit16",
+            Opcode::MulIntLit16 => "mul-int/lit16",
+            Opcode::DivIntLit16 => "div-int/lit16",
+            Opcode::RemIntLit16 => "rem-int/lit16",
+            Opcode::AndIntLit16 => "and-int/lit16",
+            Opcode::OrIntLit16 => "or-int/lit16",
+            Opcode::XorIntLit16 => "xor-int/lit16",
+            Opcode::AddIntLit8 => "add-int/lit8",
+            Opcode::SubIntLit8 => "sub-int/lit8",
+            Opcode::MulIntLit8 => "mul-int/lit8",
+            Opcode::DivIntLit8 => "div-int/lit8",
+            Opcode::RemIntLit8 => "rem-int/lit8",
+            Opcode::AndIntLit8 => "and-int/lit8",
+            Opcode::OrIntLit8 => "or-int/lit8",
+            Opcode::XorIntLit8 => "xor-int/lit8",
+            Opcode::ShlIntLit8 => "shl-int/lit8",
+            Opcode::ShrIntLit8 => "shr-int/lit8",
+            Opcode::UshrIntLit8 => "ushr-int/lit8",
+            Opcode::InvokePolymorphic => "invoke-polymorphic",
+            Opcode::InvokePolymorphicRange => "invoke-polymorphic/range",
+            Opcode::InvokeCustom => "invoke-custom",
+            Opcode::InvokeCustomRange => "invoke-custom/range",
+            Opcode::ConstMethodHandle => "const-method-handle",
+            Opcode::ConstMethodType => "const-method-type",
+        }
+    }
+
+    /// Whether the opcode is an `invoke-kind` or `invoke-kind/range`, calling the method its reference indexes.
+    /// `invoke-polymorphic` and `invoke-custom` are left out
+    pub fn is_invoke(&self) -> bool {
+        matches!(*self as u8, 0x6E..=0x72 | 0x74..=0x78)
+    }
+
+    pub fn category(&self) -> OpcodeCategory {
+        match *self as u8 {
+            0x00 => OpcodeCategory::Nop,
+            0x01..=0x0D => OpcodeCategory::Move,
+            0x0E..=0x11 => OpcodeCategory::Return,
+            0x12..=0x1C | 0xFE | 0xFF => OpcodeCategory::Const,
+            0x1D | 0x1E => OpcodeCategory::Monitor,
+            0x1F | 0x20 | 0x22 => OpcodeCategory::Type,
+            0x21 | 0x23..=0x26 | 0x44..=0x51 => OpcodeCategory::Array,
+            0x27 => OpcodeCategory::Throw,
+            0x28..=0x2A => OpcodeCategory::Goto,
+            0x2B | 0x2C => OpcodeCategory::Switch,
+            0x2D..=0x31 => OpcodeCategory::Compare,
+            0x32..=0x3D => OpcodeCategory::If,
+            0x52..=0x5F => OpcodeCategory::InstanceField,
+            0x60..=0x6D => OpcodeCategory::StaticField,
+            0x6E..=0x72 | 0x74..=0x78 | 0xFA..=0xFD => OpcodeCategory::Invoke,
+            0x7B..=0x8F => OpcodeCategory::Unary,
+            _ => OpcodeCategory::Binary,
+        }
+    }
+
+    pub fn format(&self) -> InstructionFormat {
+        match *self as u8 {
+            0x00 | 0x0E => InstructionFormat::F10x,
+            0x01 | 0x04 | 0x07 | 0x21 | 0x7B..=0x8F | 0xB0..=0xCF => InstructionFormat::F12x,
+            0x02 | 0x05 | 0x08 => InstructionFormat::F22x,
+            0x03 | 0x06 | 0x09 => InstructionFormat::F32x,
+            0x0A..=0x0D | 0x0F..=0x11 | 0x1D | 0x1E | 0x27 => InstructionFormat::F11x,
+            0x12 => InstructionFormat::F11n,
+            0x13 | 0x16 => InstructionFormat::F21s,
+            0x14 | 0x17 => InstructionFormat::F31i,
+            0x15 | 0x19 => InstructionFormat::F21h,
+            0x18 => InstructionFormat::F51l,
+            0x1A | 0x1C | 0x1F | 0x22 | 0x60..=0x6D | 0xFE | 0xFF => InstructionFormat::F21c,
+            0x1B => InstructionFormat::F31c,
+            0x20 | 0x23 | 0x52..=0x5F => InstructionFormat::F22c,
+            0x24 | 0x6E..=0x72 | 0xFC => InstructionFormat::F35c,
+            0x25 | 0x74..=0x78 | 0xFD => InstructionFormat::F3rc,
+            0x26 | 0x2B | 0x2C => InstructionFormat::F31t,
+            0x28 => InstructionFormat::F10t,
+            0x29 => InstructionFormat::F20t,
+            0x2A => InstructionFormat::F30t,
+            0x2D..=0x31 | 0x44..=0x51 | 0x90..=0xAF => InstructionFormat::F23x,
+            0x32..=0x37 => InstructionFormat::F22t,
+            0x38..=0x3D => InstructionFormat::F21t,
+            0xD0..=0xD7 => InstructionFormat::F22s,
+            0xD8..=0xE2 => InstructionFormat::F22b,
+            0xFA => InstructionFormat::F45cc,
+            0xFB => InstructionFormat::F4rcc,
+            byte => unreachable!("{:#04x} is an unused opcode", byte),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+
+/// Serializes as the mnemonic, e.g. `"invoke-virtual"`
+impl Serialize for Opcode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.mnemonic())
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use super::{instruction::{word, Instruction, InstructionParsingError}, opcode::InstructionFormat};
+
+
+/// Register operands of an instruction, in the order of its format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Registers {
+    /// Registers named one by one, the first `len` of `registers`
+    List { registers: [u16; 5], len: u8 },
+    /// `count` consecutive registers from `first`, for the `3rc` and `4rcc` formats
+    Range { first: u16, count: u8 },
+}
+
+
+impl Registers {
+    /// Reads the register operands of the instruction at `offset`, an error when the method ends before its last code unit
+    pub(super) fn decode(format: InstructionFormat, raw_bytecode: &[u16], offset: usize) -> Result<Self, InstructionParsingError> {
+        let unit = |index: usize| word(raw_bytecode, offset + index);
+        let first = unit(0)?;
+        let (a, b, aa) = ((first >> 8) & 0xF, first >> 12, first >> 8);
+        Ok(match format {
+            InstructionFormat::F10x | InstructionFormat::F10t | InstructionFormat::F20t | InstructionFormat::F30t => Self::list(&[]),
+            InstructionFormat::F12x | InstructionFormat::F22t | InstructionFormat::F22s | InstructionFormat::F22c => Self::list(&[a, b]),
+            InstructionFormat::F11n => Self::list(&[a]),
+            InstructionFormat::F11x | InstructionFormat::F21t | InstructionFormat::F21s | InstructionFormat::F21h | InstructionFormat::F21c
+                | InstructionFormat::F31i | InstructionFormat::F31t | InstructionFormat::F31c | InstructionFormat::F51l => Self::list(&[aa]),
+            InstructionFormat::F22x => Self::list(&[aa, unit(1)?]),
+            InstructionFormat::F23x => Self::list(&[aa, unit(1)? & 0xFF, unit(1)? >> 8]),
+            InstructionFormat::F22b => Self::list(&[aa, unit(1)? & 0xFF]),
+            InstructionFormat::F32x => Self::list(&[unit(1)?, unit(2)?]),
+            // A|G|op BBBB F|E|D|C, the count A is at most 5 once accepted by the format
+            InstructionFormat::F35c | InstructionFormat::F45cc => {
+                let count = b as usize;
+                let packed = unit(2)?;
+                let nibbles = [packed & 0xF, (packed >> 4) & 0xF, (packed >> 8) & 0xF, packed >> 12, a];
+                Self::list(&nibbles[..count.min(nibbles.len())])
+            },
+            // AA|op BBBB CCCC, AA registers from CCCC
+            InstructionFormat::F3rc | InstructionFormat::F4rcc => Self::Range { first: unit(2)?, count: aa as u8 },
+        })
+    }
+
+    fn list(named: &[u16]) -> Self {
+        let mut registers = [0; 5];
+        registers[..named.len()].copy_from_slice(named);
+        Self::List { registers, len: named.len() as u8 }
+    }
+
+    /// Every register operand, those of a range expanded
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        let (named, range) = match self {
+            Self::List { registers, len } => (&registers[..*len as usize], 0..0),
+            Self::Range { first, count } => (&[][..], *first as u32..*first as u32 + *count as u32),
+        };
+        named.iter().copied().chain(range.map(|register| register as u16))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::List { len, .. } => *len as usize,
+            Self::Range { count, .. } => *count as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+/// How an instruction accesses its first register operand, the other ones are only read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Written,
+    /// Both read and written, as by the `/2addr` operations
+    ReadWritten,
+}
+
+
+fn first_access(opcode_byte: u8) -> Access {
+    match opcode_byte {
+        // moves, move-result, move-exception, consts, instance-of, array-length, new-instance, new-array, cmp, aget, iget, sget,
+        // unary operations, binary operations on three registers or literals, const-method-handle, const-method-type
+        0x01..=0x0D | 0x12..=0x1C | 0x20..=0x23 | 0x2D..=0x31 | 0x44..=0x4A | 0x52..=0x58 | 0x60..=0x66
+            | 0x7B..=0xAF | 0xD0..=0xE2 | 0xFE | 0xFF => Access::Written,
+        0xB0..=0xCF => Access::ReadWritten,
+        _ => Access::Read,
+    }
+}
+
+
+/// Number of registers held by each of the first three operands: 2 for the low register of a wide pair, 1 otherwise
+fn operand_widths(opcode_byte: u8) -> [u16; 3] {
+    const N: u16 = 1;
+    const W: u16 = 2;
+    match opcode_byte {
+        // move-wide, neg-long, not-long, neg-double, long-to-double, double-to-long, long shifts
+        0x04..=0x06 | 0x7D | 0x7E | 0x80 | 0x86 | 0x8B | 0xA3..=0xA5 => [W, W, N],
+        // move-result-wide, return-wide, const-wide, aget, aput, iget, iput, sget and sput-wide, int and float to long or double
+        0x0B | 0x10 | 0x16..=0x19 | 0x45 | 0x4C | 0x53 | 0x5A | 0x61 | 0x68 | 0x81 | 0x83 | 0x88 | 0x89 => [W, N, N],
+        // cmpl-double, cmpg-double, cmp-long
+        0x2F..=0x31 => [N, W, W],
+        // long and double to int or float
+        0x84 | 0x85 | 0x8A | 0x8C => [N, W, N],
+        // long and double binary operations
+        0x9B..=0xA2 | 0xAB..=0xAF => [W, W, W],
+        // long and double /2addr operations, but the shifts
+        0xBB..=0xC2 | 0xCB..=0xCF => [W, W, N],
+        // long /2addr shifts
+        0xC3..=0xC5 => [W, N, N],
+        _ => [N, N, N],
+    }
+}
+
+
+impl Instruction {
+    /// Operands as their first register and the number of consecutive registers they span: 2 for wide pairs, the count for ranges
+    pub(crate) fn operands(&self) -> Vec<(u16, u16)> {
+        let widths = operand_widths(*self.opcode() as u8);
+        match *self.registers() {
+            Registers::List { registers, len } => registers[..len as usize].iter()
+                .enumerate()
+                .map(|(index, &register)| (register, widths.get(index).copied().unwrap_or(1)))
+                .collect(),
+            Registers::Range { first, count } => vec![(first, count as u16)],
+        }
+    }
+
+    /// Registers written by the instruction, both halves of a wide pair included
+    pub fn defs(&self) -> Vec<u16> {
+        match first_access(*self.opcode() as u8) {
+            Access::Read => vec![],
+            Access::Written | Access::ReadWritten => self.operands().first().map_or(vec![], |&(register, width)| span(register, width)),
+        }
+    }
+
+    /// Registers read by the instruction, both halves of a wide pair included
+    pub fn uses(&self) -> Vec<u16> {
+        let skip = usize::from(first_access(*self.opcode() as u8) == Access::Written);
+        self.operands().into_iter().skip(skip).flat_map(|(register, width)| span(register, width)).collect()
+    }
+}
+
+
+fn span(register: u16, width: u16) -> Vec<u16> {
+    (0..width).map(|offset| register.wrapping_add(offset)).collect()
+}
+
+
+/// Renumbers the registers of the instructions of a method in order of first use, so that methods differing only
+/// in register allocation become equal. A wide pair or range first seen as a whole takes as many consecutive numbers,
+/// keeping the registers it spans adjacent
+pub fn normalize_registers(instructions: &mut [Instruction]) {
+    let mut renamed: HashMap<u16, u16> = HashMap::new();
+    let mut next: u16 = 0;
+    let mut rename = |register: u16, width: u16| {
+        if !renamed.contains_key(&register) {
+            let width = width.max(1);
+            for offset in 0..width {
+                renamed.entry(register.wrapping_add(offset)).or_insert(next.saturating_add(offset));
+            }
+            next = next.saturating_add(width);
+        }
+        renamed[&register]
+    };
+    for inst in instructions {
+        let operands = inst.operands();
+        match inst.registers_mut() {
+            Registers::List { registers, .. } => {
+                for (register, (original, width)) in registers.iter_mut().zip(operands) {
+                    *register = rename(original, width);
+                }
+            },
+            Registers::Range { first, count } => *first = rename(*first, *count as u16),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::dex_parsing::decode_method_lenient;
+    use super::*;
+
+    #[test]
+    fn test_defs_and_uses() {
+        // long-to-int v0, v2; add-int/2addr v0, v1; aput-wide v4, v6, v7; invoke-static/range {v8 .. v10}
+        let decoded = decode_method_lenient(&[0x2084, 0x10B0, 0x044C, 0x0706, 0x0377, 0x0000, 0x0008]).instructions;
+        assert_eq!((decoded[0].defs(), decoded[0].uses()), (vec![0], vec![2, 3]));
+        assert_eq!((decoded[1].defs(), decoded[1].uses()), (vec![0], vec![0, 1]));
+        assert_eq!((decoded[2].defs(), decoded[2].uses()), (vec![], vec![4, 5, 6, 7]));
+        assert_eq!(*decoded[3].registers(), Registers::Range { first: 8, count: 3 });
+        assert_eq!((decoded[3].defs(), decoded[3].uses()), (vec![], vec![8, 9, 10]));
+    }
+
+    #[test]
+    fn test_normalize_registers() {
+        // const/4 v1, 0; const-wide/16 v2, 5; long-to-int v0, v2; add-int/2addr v0, v1; return v0
+        let original = [0x0112, 0x0216, 0x0005, 0x2084, 0x10B0, 0x000F];
+        // The same with v1 as v4, the pair v2, v3 as v0, v1 and v0 as v2
+        let permuted = [0x0412, 0x0016, 0x0005, 0x0284, 0x42B0, 0x020F];
+        let mut original = decode_method_lenient(&original).instructions;
+        let mut permuted = decode_method_lenient(&permuted).instructions;
+        assert_ne!(original, permuted);
+        normalize_registers(&mut original);
+        normalize_registers(&mut permuted);
+        assert_eq!(original, permuted);
+        // v1 comes first, the pair takes 1 and 2, v0 is renamed last
+        let registers = original.iter().map(|inst| inst.registers().iter().collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(registers, [vec![0], vec![1], vec![3, 1], vec![3, 0], vec![3]]);
+        assert_eq!(original[2].uses(), [1, 2]);
+    }
+}
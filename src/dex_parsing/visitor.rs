@@ -0,0 +1,376 @@
+use std::ops::ControlFlow;
+
+use dex::{Dex, class::Class, code::CodeItem, method::Method};
+use num_traits::FromPrimitive;
+
+use crate::options::{DecodeMode, Strictness};
+use super::{decode_method_recursive, scan_opcodes, instruction::{Instruction, InstructionParsingError}, opcode::Opcode};
+
+
+/// Class about to be walked
+pub struct ClassInfo<'a> {
+    class: &'a Class,
+}
+
+
+/// Method about to be walked, along with its class
+pub struct MethodInfo<'a> {
+    class: &'a Class,
+    method: &'a Method,
+}
+
+
+/// Instruction decoded during a walk and the number of code units it spans
+#[derive(Debug)]
+pub struct DecodedInstruction {
+    pub instruction: Instruction,
+    pub length: usize,
+}
+
+
+/// Callbacks driven by `walk_dex`, instructions are handed over one at a time instead of collected per method.
+/// Returning `ControlFlow::Break` from `visit_class` or `visit_method` skips the class or method,
+/// from `leave_method` it ends the walk
+pub trait InstructionVisitor {
+    fn visit_class(&mut self, _class: &ClassInfo) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    fn visit_method(&mut self, _method: &MethodInfo) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    fn visit_instruction(&mut self, inst: &DecodedInstruction);
+
+    /// Called instead of `visit_instruction` in shallow walks, with only the opcode and its offset decoded
+    fn visit_opcode(&mut self, _opcode: Opcode, _offset: usize) {}
+
+    /// Called for every class definition that can't be parsed, the class is skipped
+    fn visit_class_error(&mut self, _err: &dex::Error) {}
+
+    /// Called for every code unit that can't be decoded, in strict mode the rest of the method is skipped
+    fn visit_error(&mut self, _err: &InstructionParsingError) {}
+
+    /// Called once all instructions of a visited method are walked
+    fn leave_method(&mut self, _method: &MethodInfo) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    /// Strict walks stop a method at its first payload or undecodable instruction, code left after the payloads is
+    /// reported to `visit_error`. Lenient walks skip over them as `decode_method_lenient` does
+    fn strictness(&self) -> Strictness {
+        Strictness::Strict
+    }
+
+    /// Shallow walks skip operand decoding, see `Instruction::try_opcode_from_raw_bytecode`
+    fn shallow(&self) -> bool {
+        false
+    }
+
+    /// Recursive walks only visit the reachable instructions, see `decode_method_recursive`, and are never shallow
+    fn decode_mode(&self) -> DecodeMode {
+        DecodeMode::Linear
+    }
+}
+
+
+impl<'a> ClassInfo<'a> {
+    pub fn class(&self) -> &'a Class {
+        self.class
+    }
+
+    /// Descriptor of the class, e.g. `Lcom/example/Main;`
+    pub fn descriptor(&self) -> &'a str {
+        self.class.jtype().type_descriptor()
+    }
+}
+
+
+impl<'a> MethodInfo<'a> {
+    pub fn class(&self) -> &'a Class {
+        self.class
+    }
+
+    pub fn method(&self) -> &'a Method {
+        self.method
+    }
+
+    /// `None` for abstract and native methods
+    pub fn code(&self) -> Option<&'a CodeItem> {
+        self.method.code()
+    }
+}
+
+
+/// Walks the classes, methods and instructions of a dex in order
+pub fn walk_dex(dex: &Dex<impl AsRef<[u8]>>, visitor: &mut impl InstructionVisitor) {
+    for class in dex.classes() {
+        let class = match class {
+            Ok(class) => class,
+            Err(err) => {
+                visitor.visit_class_error(&err);
+                continue;
+            }
+        };
+        if visitor.visit_class(&ClassInfo { class: &class }).is_break() {
+            continue;
+        }
+        for method in class.methods() {
+            let info = MethodInfo { class: &class, method };
+            if visitor.visit_method(&info).is_break() {
+                continue;
+            }
+            match method.code() {
+                Some(code) if visitor.decode_mode() == DecodeMode::Recursive => {
+                    let handlers = code.tries().iter().flat_map(|try_block| try_block.catch_handlers().iter().map(|handler| handler.addr() as usize));
+                    walk_code_recursive(code.insns(), handlers, visitor);
+                },
+                Some(code) => walk_code(code.insns(), visitor),
+                None => (),
+            }
+            if visitor.leave_method(&info).is_break() {
+                return;
+            }
+        }
+    }
+}
+
+
+/// Walks the instructions of a method body
+pub(crate) fn walk_code(raw_bytecode: &[u16], visitor: &mut impl InstructionVisitor) {
+    let lenient = visitor.strictness() == Strictness::Lenient;
+    let shallow = visitor.shallow();
+    // Strict shallow walks scan the whole method at once. On an error, the walk below visits the opcodes before it
+    // and reports it
+    if shallow && !lenient {
+        if let Ok(scanned) = scan_opcodes(raw_bytecode) {
+            let mut offset = 0;
+            for (opcode, length) in scanned {
+                visitor.visit_opcode(Opcode::from_u8(opcode).expect("scanned opcode"), offset);
+                offset += length;
+            }
+            if let Some(offset) = code_after_payloads(raw_bytecode, offset) {
+                visitor.visit_error(&InstructionParsingError::at(raw_bytecode, offset));
+            }
+            return;
+        }
+    }
+    let mut offset = 0;
+    while offset < raw_bytecode.len() {
+        let decoded = if shallow {
+            Instruction::try_opcode_from_raw_bytecode(raw_bytecode, offset).map(|decoded| decoded.map(|(opcode, length)| {
+                visitor.visit_opcode(opcode, offset);
+                length
+            }))
+        } else {
+            Instruction::try_from_raw_bytecode(raw_bytecode, offset).map(|decoded| decoded.map(|(instruction, length)| {
+                visitor.visit_instruction(&DecodedInstruction { instruction, length });
+                length
+            }))
+        };
+        match decoded {
+            Ok(Some(length)) => offset += length,
+            // Payloads end the code of a method, code the strict walk would skip after them is reported
+            Ok(None) if !lenient => {
+                if let Some(offset) = code_after_payloads(raw_bytecode, offset) {
+                    visitor.visit_error(&InstructionParsingError::at(raw_bytecode, offset));
+                }
+                return;
+            },
+            Ok(None) => match Instruction::payload_length(raw_bytecode, offset) {
+                Some(length) => offset += length,
+                None => {
+                    visitor.visit_error(&InstructionParsingError::at(raw_bytecode, offset));
+                    offset += 1;
+                }
+            },
+            Err(err) => {
+                visitor.visit_error(&err);
+                if !lenient {
+                    return;
+                }
+                offset += 1;
+            }
+        }
+    }
+}
+
+
+/// Offset of the first code unit of `raw_bytecode` from `offset` on that is neither a payload nor the nop padding
+/// aligning one, `None` when payloads end the method
+fn code_after_payloads(raw_bytecode: &[u16], mut offset: usize) -> Option<usize> {
+    while offset < raw_bytecode.len() {
+        match Instruction::payload_length(raw_bytecode, offset) {
+            Some(length) => offset += length,
+            None if raw_bytecode[offset] == 0 => offset += 1,
+            None => return Some(offset),
+        }
+    }
+    None
+}
+
+
+/// Walks the instructions of a method body reachable from its entry and `handlers`, in code order
+fn walk_code_recursive(raw_bytecode: &[u16], handlers: impl IntoIterator<Item = usize>, visitor: &mut impl InstructionVisitor) {
+    let lenient = visitor.strictness() == Strictness::Lenient;
+    let decoded = decode_method_recursive(raw_bytecode, handlers);
+    let mut undecoded = decoded.undecoded.into_iter().peekable();
+    // Strict walks stop at the first reachable code unit that can't be decoded
+    let error = |offset: usize| InstructionParsingError::at(raw_bytecode, offset);
+    for instruction in decoded.instructions {
+        while let Some(offset) = undecoded.next_if(|&offset| offset < *instruction.offset()) {
+            visitor.visit_error(&error(offset));
+            if !lenient {
+                return;
+            }
+        }
+        let length = instruction.opcode().format().units();
+        visitor.visit_instruction(&DecodedInstruction { instruction, length });
+    }
+    for offset in undecoded {
+        visitor.visit_error(&error(offset));
+        if !lenient {
+            return;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{sample_dex, SAMPLE_METHODS};
+    use crate::dex_parsing::decode_method_lenient;
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingVisitor {
+        strictness: Strictness,
+        shallow: bool,
+        opcodes: Vec<Opcode>,
+        classes: usize,
+        methods: usize,
+        instructions: usize,
+        code_units: usize,
+        errors: usize,
+        error_offset: Option<usize>,
+    }
+
+    impl InstructionVisitor for CountingVisitor {
+        fn visit_class(&mut self, _class: &ClassInfo) -> ControlFlow<()> {
+            self.classes += 1;
+            ControlFlow::Continue(())
+        }
+
+        fn visit_method(&mut self, _method: &MethodInfo) -> ControlFlow<()> {
+            self.methods += 1;
+            ControlFlow::Continue(())
+        }
+
+        fn visit_instruction(&mut self, inst: &DecodedInstruction) {
+            self.instructions += 1;
+            self.code_units += inst.length;
+            self.opcodes.push(*inst.instruction.opcode());
+        }
+
+        fn visit_opcode(&mut self, opcode: Opcode, _offset: usize) {
+            self.opcodes.push(opcode);
+        }
+
+        fn visit_error(&mut self, err: &InstructionParsingError) {
+            self.errors += 1;
+            self.error_offset = Some(err.offset());
+        }
+
+        fn strictness(&self) -> Strictness {
+            self.strictness
+        }
+
+        fn shallow(&self) -> bool {
+            self.shallow
+        }
+    }
+
+    #[test]
+    fn test_walk_code_counts() {
+        let raw_bytecode = [
+            // packed-switch v0, +6; return-void; nop
+            0x002B, 6, 0, 0x000E, 0x0000,
+            // unused opcode 0x3e
+            0x003E,
+            // packed-switch-payload with a single target
+            0x0100, 1, 0, 0, 3, 0,
+            // return-void
+            0x000E,
+        ];
+        let mut strict = CountingVisitor::default();
+        walk_code(&raw_bytecode, &mut strict);
+        assert_eq!((strict.instructions, strict.errors), (3, 1));
+
+        let mut lenient = CountingVisitor { strictness: Strictness::Lenient, ..Default::default() };
+        walk_code(&raw_bytecode, &mut lenient);
+        assert_eq!((lenient.instructions, lenient.errors), (4, 1));
+        assert_eq!(lenient.instructions, decode_method_lenient(&raw_bytecode).instructions.len());
+    }
+
+    #[test]
+    fn test_strict_walk_stops_at_payloads() {
+        // packed-switch v0, +4; return-void; packed-switch-payload with a single target; nop padding
+        let raw_bytecode = [0x002B, 4, 0, 0x000E, 0x0100, 1, 0, 0, 3, 0, 0x0000];
+        let mut strict = CountingVisitor::default();
+        walk_code(&raw_bytecode, &mut strict);
+        assert_eq!((strict.instructions, strict.errors), (2, 0));
+
+        // The same followed by a return-void, which the strict walk can't reach
+        let raw_bytecode = [0x002B, 4, 0, 0x000E, 0x0100, 1, 0, 0, 3, 0, 0x0000, 0x000E];
+        let mut strict = CountingVisitor::default();
+        walk_code(&raw_bytecode, &mut strict);
+        assert_eq!((strict.instructions, strict.errors, strict.error_offset), (2, 1, Some(11)));
+    }
+
+    #[test]
+    fn test_walk_code_recursive_counts() {
+        // packed-switch v0, +6 with its only case at the return-void; return-void; nop; unused opcode 0x3e; payload; return-void
+        let raw_bytecode = [0x002B, 6, 0, 0x000E, 0x0000, 0x003E, 0x0100, 1, 0, 0, 3, 0, 0x000E];
+        let mut strict = CountingVisitor::default();
+        walk_code_recursive(&raw_bytecode, [], &mut strict);
+        assert_eq!(strict.opcodes, [Opcode::PackedSwitch, Opcode::ReturnVoid]);
+        assert_eq!((strict.code_units, strict.errors), (4, 0));
+
+        // if-eqz v0, +3 falling through into the unused opcode 0x3e; return-void
+        let raw_bytecode = [0x0038, 3, 0x003E, 0x000E];
+        let mut strict = CountingVisitor::default();
+        walk_code_recursive(&raw_bytecode, [], &mut strict);
+        assert_eq!((strict.instructions, strict.errors), (1, 1));
+        let mut lenient = CountingVisitor { strictness: Strictness::Lenient, ..Default::default() };
+        walk_code_recursive(&raw_bytecode, [], &mut lenient);
+        assert_eq!((lenient.instructions, lenient.errors), (2, 1));
+    }
+
+    #[test]
+    fn test_shallow_walk_opcodes() {
+        for strictness in [Strictness::Strict, Strictness::Lenient] {
+            for (name, raw_bytecode) in SAMPLE_METHODS {
+                let mut rich = CountingVisitor { strictness, ..Default::default() };
+                walk_code(raw_bytecode, &mut rich);
+                let mut shallow = CountingVisitor { strictness, shallow: true, ..Default::default() };
+                walk_code(raw_bytecode, &mut shallow);
+                assert!(!rich.opcodes.is_empty(), "{}", name);
+                assert_eq!(rich.opcodes, shallow.opcodes, "{}", name);
+                assert_eq!((shallow.instructions, shallow.errors), (0, rich.errors), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_walk_dex_counts() {
+        let dex = DexReader::from_vec(sample_dex(3)).unwrap();
+        let mut visitor = CountingVisitor { strictness: Strictness::Lenient, ..Default::default() };
+        walk_dex(&dex, &mut visitor);
+        let instructions: usize = SAMPLE_METHODS.iter().map(|(_, insns)| decode_method_lenient(insns).instructions.len()).sum();
+        assert_eq!((visitor.classes, visitor.methods), (3, 3 * SAMPLE_METHODS.len()));
+        assert_eq!(visitor.instructions, 3 * instructions);
+        assert_eq!(visitor.errors, 0);
+    }
+}
@@ -0,0 +1,110 @@
+use std::{collections::HashSet, fmt, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::warning::{Warning, WarningKind};
+use super::{coverage::Coverage, method::{MethodReport, StoredReport}};
+
+
+/// Store of the walks of single dexes, shared by the inputs of a run and across runs, e.g. in a directory.
+/// `process_dexes_parallel` looks every dex up under its `DexWalk::key` before decoding it, and puts the walks it had to
+/// decode. The walk depends on the options, which a store kept across runs has to tell apart with
+/// `AnalysisOptions::walk_fingerprint`
+pub trait DexCache: fmt::Debug + Send + Sync {
+    fn get(&self, key: &str) -> Option<DexWalk>;
+
+    /// Failing to store a walk only costs a later decode, so errors are for the store to report
+    fn put(&self, key: &str, walk: &DexWalk);
+}
+
+
+/// A cache shared with the caller, e.g. to read its counters after a run
+impl<T: DexCache + ?Sized> DexCache for Arc<T> {
+    fn get(&self, key: &str) -> Option<DexWalk> {
+        (**self).get(key)
+    }
+
+    fn put(&self, key: &str, walk: &DexWalk) {
+        (**self).put(key, walk)
+    }
+}
+
+
+/// Opcodes and reports of the methods of a dex as `process_dexes_parallel` walks it, with its coverage and warnings.
+/// Positions start at 0 in the dex. Instructions aren't stored, walks with `instructions_lite` aren't cached
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "StoredWalk", from = "StoredWalk")]
+pub struct DexWalk {
+    pub(crate) methods: Vec<(Vec<u8>, MethodReport)>,
+    pub(crate) coverage: Coverage,
+    pub(crate) warnings: Vec<Warning>,
+}
+
+
+impl DexWalk {
+    /// SHA-256 of the dex `name`, its `bytes` and the classes an earlier dex of the APK defines, which the walk skips
+    pub fn key(name: &str, bytes: &[u8], skipped: &HashSet<String>) -> String {
+        let mut skipped: Vec<&String> = skipped.iter().collect();
+        skipped.sort();
+        let mut key = Sha256::new();
+        key.update(name);
+        key.update([0]);
+        key.update(Sha256::digest(bytes));
+        for descriptor in skipped {
+            key.update(descriptor);
+            key.update([0]);
+        }
+        format!("{:x}", key.finalize())
+    }
+}
+
+
+/// Fields of a `DexWalk`, serialized the same way whatever their values
+#[derive(Serialize, Deserialize)]
+struct StoredWalk {
+    methods: Vec<(Vec<u8>, StoredReport)>,
+    /// Counts of the `Coverage`, in declaration order
+    coverage: [usize; 6],
+    warnings: Vec<StoredWarning>,
+}
+
+
+/// Fields of a `Warning`, serialized whether they are set or not
+#[derive(Serialize, Deserialize)]
+struct StoredWarning {
+    kind: WarningKind,
+    class: Option<String>,
+    class_index: Option<usize>,
+    method: Option<String>,
+    offset: Option<usize>,
+    message: String,
+}
+
+
+impl From<DexWalk> for StoredWalk {
+    fn from(walk: DexWalk) -> Self {
+        let Coverage { methods, decoded_methods, partial_methods, skipped_methods, code_units, decoded_code_units } = walk.coverage;
+        Self {
+            methods: walk.methods.into_iter().map(|(opcodes, report)| (opcodes, StoredReport::from(report))).collect(),
+            coverage: [methods, decoded_methods, partial_methods, skipped_methods, code_units, decoded_code_units],
+            warnings: walk.warnings.into_iter()
+                .map(|Warning { kind, class, class_index, method, offset, message }| StoredWarning { kind, class, class_index, method, offset, message })
+                .collect(),
+        }
+    }
+}
+
+
+impl From<StoredWalk> for DexWalk {
+    fn from(stored: StoredWalk) -> Self {
+        let [methods, decoded_methods, partial_methods, skipped_methods, code_units, decoded_code_units] = stored.coverage;
+        Self {
+            methods: stored.methods.into_iter().map(|(opcodes, report)| (opcodes, MethodReport::from(report))).collect(),
+            coverage: Coverage { methods, decoded_methods, partial_methods, skipped_methods, code_units, decoded_code_units },
+            warnings: stored.warnings.into_iter()
+                .map(|StoredWarning { kind, class, class_index, method, offset, message }| Warning { kind, class, class_index, method, offset, message })
+                .collect(),
+        }
+    }
+}
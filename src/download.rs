@@ -0,0 +1,117 @@
+#[cfg(feature = "http")]
+use std::io::Read;
+
+use thiserror::Error;
+
+use crate::budget::ByteBudget;
+
+
+/// Why an input URL could not be fetched, reported in place of its analysis
+#[derive(Debug, Error)]
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub enum DownloadError {
+    #[error("HTTP status {0}")]
+    Status(u16),
+    #[error("larger than the max download size of {limit} bytes")]
+    TooLarge { limit: u64 },
+    #[error("received {received} of the {expected} bytes of the content length")]
+    LengthMismatch { expected: u64, received: u64 },
+    #[cfg(feature = "http")]
+    #[error("{0}")]
+    Request(#[from] reqwest::Error),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("URL inputs need a build with the http feature")]
+    Unsupported,
+}
+
+
+/// Fetches URL inputs into memory, a bounded number at a time whatever the number of worker threads
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub struct Downloader {
+    max_size: u64,
+    /// Counts downloads in flight rather than bytes, one unit each
+    slots: ByteBudget,
+    #[cfg(feature = "http")]
+    client: reqwest::blocking::Client,
+}
+
+
+impl Downloader {
+    /// Downloads larger than `max_size` bytes are abandoned, at most `concurrency` run at the same time
+    pub fn new(max_size: u64, concurrency: usize) -> Self {
+        Self {
+            max_size,
+            slots: ByteBudget::new(concurrency.max(1) as u64),
+            #[cfg(feature = "http")]
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+
+    /// Body of a successful response to a GET of `url`, which must be as long as its content length when announced
+    #[cfg(feature = "http")]
+    pub fn fetch(&self, url: &str) -> Result<Vec<u8>, DownloadError> {
+        let _slot = self.slots.acquire(1);
+        let response = self.client.get(url).send()?;
+        if !response.status().is_success() {
+            return Err(DownloadError::Status(response.status().as_u16()));
+        }
+        let expected = response.content_length();
+        if expected.is_some_and(|expected| expected > self.max_size) {
+            return Err(DownloadError::TooLarge { limit: self.max_size });
+        }
+        let mut data = vec![];
+        // One byte past the limit tells a body of exactly the max size from a larger one
+        response.take(self.max_size + 1).read_to_end(&mut data)?;
+        let received = data.len() as u64;
+        if received > self.max_size {
+            return Err(DownloadError::TooLarge { limit: self.max_size });
+        }
+        match expected {
+            Some(expected) if expected != received => Err(DownloadError::LengthMismatch { expected, received }),
+            _ => Ok(data),
+        }
+    }
+
+    #[cfg(not(feature = "http"))]
+    pub fn fetch(&self, _url: &str) -> Result<Vec<u8>, DownloadError> {
+        Err(DownloadError::Unsupported)
+    }
+}
+
+
+#[cfg(all(test, feature = "http"))]
+mod test {
+    use std::{io::{Read, Write}, net::TcpListener, thread};
+
+    use super::{DownloadError, Downloader};
+
+    /// Answers one request per response on a local port, returns the base URL
+    fn serve(responses: Vec<(u16, Vec<u8>)>) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                // The client hangs up on bodies it won't read
+                let _ = write!(stream, "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+                let _ = stream.write_all(&body);
+            }
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_fetch() {
+        let apk = include_bytes!("../tests/fixtures/sample.dex").to_vec();
+        let (url, server) = serve(vec![(200, apk.clone()), (404, b"not found".to_vec()), (200, apk.clone())]);
+        let downloader = Downloader::new(apk.len() as u64, 2);
+        assert_eq!(downloader.fetch(&format!("{}/sample.apk", url)).unwrap(), apk);
+        assert!(matches!(downloader.fetch(&format!("{}/missing.apk", url)), Err(DownloadError::Status(404))));
+        let downloader = Downloader::new(apk.len() as u64 - 1, 2);
+        assert!(matches!(downloader.fetch(&format!("{}/sample.apk", url)), Err(DownloadError::TooLarge { .. })));
+        server.join().unwrap();
+    }
+}
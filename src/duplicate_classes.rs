@@ -0,0 +1,113 @@
+use std::collections::{HashMap, HashSet};
+
+use dex::class::Class;
+use serde::Serialize;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::dex_parsing::NamedDex;
+
+
+/// Class defined by more than one dex of an APK. The runtime loads the copy of the first dex in the order of the
+/// archive, and so do the opcode sequences: methods of the later copies are left out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicateClass {
+    /// Descriptor of the class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    /// Names of the dexes defining the class, in dex order. The first one is the copy in the sequences
+    pub dexes: Vec<String>,
+    /// Whether the methods of a later copy differ from those of the first, by name or bytecode
+    pub bytecode_differs: bool,
+}
+
+
+/// Classes defined by several of `dexes`, in the order of their first definition.
+/// Classes that fail to parse are ignored, as the sequences report them
+pub fn duplicate_classes<T: AsRef<[u8]>>(dexes: &[NamedDex<T>]) -> Vec<DuplicateClass> {
+    let mut defined: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut order = vec![];
+    for (index, dex) in dexes.iter().enumerate() {
+        for class in dex.dex.classes().filter_map(Result::ok) {
+            let descriptor = class.jtype().type_descriptor().to_string();
+            let indices = defined.entry(descriptor.clone()).or_default();
+            if indices.is_empty() {
+                order.push(descriptor);
+            }
+            indices.push(index);
+        }
+    }
+    // Only the copies of duplicated classes are hashed, in a second pass
+    let duplicated: HashSet<&str> = defined.iter().filter(|(_, indices)| indices.len() > 1).map(|(class, _)| class.as_str()).collect();
+    if duplicated.is_empty() {
+        return vec![];
+    }
+    let mut hashes: HashMap<&str, Vec<u64>> = HashMap::new();
+    for dex in dexes {
+        for class in dex.dex.classes().filter_map(Result::ok) {
+            let descriptor = class.jtype().type_descriptor().to_string();
+            if let Some(&class_name) = duplicated.get(descriptor.as_str()) {
+                hashes.entry(class_name).or_default().push(methods_hash(&class));
+            }
+        }
+    }
+    order.into_iter()
+        .filter(|class| duplicated.contains(class.as_str()))
+        .map(|class| {
+            let copies = &hashes[class.as_str()];
+            let dexes = defined[&class].iter().map(|&index| dexes[index].name.clone()).collect();
+            DuplicateClass { bytecode_differs: copies.iter().any(|&hash| hash != copies[0]), dexes, class }
+        })
+        .collect()
+}
+
+
+/// Hash of the names and code units of the methods of a class, in declaration order
+fn methods_hash(class: &Class) -> u64 {
+    let mut hasher = Xxh3::new();
+    for method in class.methods() {
+        hasher.update(method.name().as_bytes());
+        hasher.update(&[0]);
+        for unit in method.code().map(|code| code.insns()).unwrap_or_default() {
+            hasher.update(&unit.to_le_bytes());
+        }
+        hasher.update(&[0xFF, 0xFF]);
+    }
+    hasher.digest()
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::{analysis::analyze_dexes, options::AnalysisOptions, testing::{ClassDef, CodeDef, DexBuilder, MethodDef}, Sequences};
+    use dex::DexReader;
+    use super::*;
+
+    fn dex(classes: &[(&str, &[u16])]) -> dex::Dex<Vec<u8>> {
+        let mut builder = DexBuilder::new();
+        for &(class, insns) in classes {
+            builder.class(ClassDef::new(class).method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, insns))));
+        }
+        DexReader::from_vec(builder.build()).unwrap()
+    }
+
+    #[test]
+    fn test_duplicate_classes() {
+        // const/4 v0, 0; return-void in the first dex, a bare return-void in the second
+        let dexes = NamedDex::multidex([
+            dex(&[("Lcom/example/Main;", &[0x0012, 0x000E]), ("Lcom/example/Same;", &[0x000E])]),
+            dex(&[("Lcom/example/Same;", &[0x000E]), ("Lcom/example/Main;", &[0x000E])]),
+        ]);
+        let duplicates = duplicate_classes(&dexes);
+        assert_eq!(duplicates, [
+            DuplicateClass { class: "Lcom/example/Main;".to_string(), dexes: vec!["classes.dex".to_string(), "classes2.dex".to_string()], bytecode_differs: true },
+            DuplicateClass { class: "Lcom/example/Same;".to_string(), dexes: vec!["classes.dex".to_string(), "classes2.dex".to_string()], bytecode_differs: false },
+        ]);
+
+        // The sequences hold the copies of the first dex only
+        let report = analyze_dexes(dexes, None, &AnalysisOptions::default());
+        assert_eq!(report.duplicate_classes, duplicates);
+        let Sequences::Flat { methods, op_seq, .. } = &report.sequences else { unreachable!() };
+        let attributed = methods.iter().map(|method| (method.class(), method.dex_name())).collect::<Vec<_>>();
+        assert_eq!(attributed, [("Lcom/example/Main;", "classes.dex"), ("Lcom/example/Same;", "classes.dex")]);
+        assert_eq!(op_seq.len(), 3);
+    }
+}
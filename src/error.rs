@@ -0,0 +1,144 @@
+use std::io;
+
+use thiserror::Error;
+use zip::result::ZipError;
+
+use crate::dex_parsing::InstructionParsingError;
+
+
+/// Errors of the analysis, the underlying error of the external crates is kept as the source
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Error reading input: {0}")]
+    Io(#[from] io::Error),
+    #[error("Error reading archive: {0}")]
+    Zip(#[from] ZipError),
+    #[error("Error parsing dex: {0}")]
+    Dex(#[from] dex::Error),
+    #[error("Error parsing manifest: {0}")]
+    Manifest(#[from] axmldecoder::ParseError),
+    /// A class definition that could not be parsed, fatal with `AnalysisOptions::strict_classes`
+    #[error("Invalid class definition {index} of {dex}: {source}")]
+    InvalidClass {
+        /// Name of the dex, e.g. `classes2.dex`
+        dex: String,
+        /// Index of the class definition in the dex
+        index: usize,
+        #[source]
+        source: dex::Error,
+    },
+    /// A method index outside the method ids, or of a method no class of the dex defines, e.g. a framework method
+    #[error("Method {method_idx} is not defined in the dex")]
+    MethodNotFound { method_idx: u32 },
+    /// An abstract or native method, which has no code to decode
+    #[error("Method {class}->{method} has no code")]
+    NoCode {
+        /// Descriptor of the class of the method
+        class: String,
+        method: String,
+    },
+    /// An opcode byte that isn't a valid instruction, or an instruction cut short by the end of the method
+    #[error("Invalid instruction at offset {offset}: {opcode_byte}")]
+    InstructionDecode {
+        /// Descriptor of the class of the method, when known
+        class: Option<String>,
+        method: Option<String>,
+        /// Offset in code units of the instruction in the method
+        offset: usize,
+        opcode_byte: u8,
+        #[source]
+        source: InstructionParsingError,
+    },
+    /// A task decoding a dex in parallel panicked
+    #[error("Decoding {dex} panicked: {message}")]
+    DecodePanicked {
+        /// Name of the dex, e.g. `classes2.dex`
+        dex: String,
+        message: String,
+    },
+    /// The branches of a method could not be linked into basic blocks
+    #[error("Invalid control flow: {reason}")]
+    CfgConstruction {
+        #[source]
+        reason: CfgError,
+    },
+}
+
+
+impl Error {
+    /// Attaches the method an `InstructionDecode` error occurred in, other errors are returned unchanged
+    pub fn in_method(self, class: impl ToString, method: impl ToString) -> Self {
+        match self {
+            Error::InstructionDecode { offset, opcode_byte, source, .. } => Error::InstructionDecode {
+                class: Some(class.to_string()),
+                method: Some(method.to_string()),
+                offset,
+                opcode_byte,
+                source,
+            },
+            err => err,
+        }
+    }
+}
+
+
+impl From<InstructionParsingError> for Error {
+    fn from(err: InstructionParsingError) -> Self {
+        Error::InstructionDecode { class: None, method: None, offset: err.offset(), opcode_byte: err.byte(), source: err }
+    }
+}
+
+impl From<CfgError> for Error {
+    fn from(reason: CfgError) -> Self {
+        Error::CfgConstruction { reason }
+    }
+}
+
+
+/// Why the basic blocks of a method could not be built
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CfgError {
+    #[error("Jump target out of bounds: {0}")]
+    JumpTargetOutOfBounds(usize),
+    #[error("No source index {0}")]
+    MissingSource(usize),
+    #[error("No destination index {0}")]
+    MissingDestination(usize),
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error as _, io::Cursor};
+
+    use crate::{analysis::parse_apk_from, dex_parsing::MethodCfg};
+    use super::*;
+
+    #[test]
+    fn test_corrupt_method_error() {
+        // nop; unused opcode 0x3e
+        let err = MethodCfg::build(&[0x0000, 0x003E]).unwrap_err().in_method("Lcom/example/Corrupt;", "broken");
+        let Error::InstructionDecode { class, method, offset, opcode_byte, .. } = &err else { panic!("expected an instruction error, got {:?}", err) };
+        assert_eq!((class.as_deref(), method.as_deref(), *offset, *opcode_byte), (Some("Lcom/example/Corrupt;"), Some("broken"), 1, 0x3E));
+        assert_eq!(err.to_string(), "Invalid instruction at offset 1: 62");
+        assert_eq!(err.source().unwrap().downcast_ref::<InstructionParsingError>().unwrap().offset(), 1);
+    }
+
+    #[test]
+    fn test_cfg_error_source() {
+        // packed-switch v0, +3 pointing past the end of the method
+        let err = MethodCfg::build(&[0x002B, 3, 0]).unwrap_err();
+        assert!(matches!(err, Error::CfgConstruction { reason: CfgError::JumpTargetOutOfBounds(3) }));
+        assert_eq!(err.to_string(), "Invalid control flow: Jump target out of bounds: 3");
+        assert!(err.source().unwrap().downcast_ref::<CfgError>().is_some());
+    }
+
+    #[test]
+    fn test_corrupt_archive_error() {
+        let err = parse_apk_from(Cursor::new(b"PK\x03\x04 not an archive".to_vec())).err().unwrap();
+        assert!(matches!(err, Error::Zip(_)));
+        assert!(err.to_string().starts_with("Error reading archive: "));
+        assert!(err.source().unwrap().downcast_ref::<ZipError>().is_some());
+        assert_eq!(crate::warning::Warning::from(err).kind, crate::warning::WarningKind::InvalidArchive);
+    }
+}
@@ -0,0 +1,248 @@
+use std::{cell::{OnceCell, RefCell}, collections::{BTreeMap, HashMap}, fmt, sync::Arc};
+
+use dex::{class::Class, method::Method, Dex};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    dex_parsing::{decode_method_lenient, is_selected, Instruction, MethodCfg},
+    options::AnalysisOptions,
+    reference::{resolve_method, resolve_string, resolve_type, MethodRef},
+};
+
+
+/// Outputs of the registered method analyses by analysis name
+pub type Extensions = BTreeMap<String, Vec<MethodOutput>>;
+
+
+/// Per-method analysis registered on `AnalysisOptions::method_analysis`, whose outputs are reported under `extensions.<name>`
+pub trait MethodAnalysis: Send + Sync {
+    /// Key of the outputs in the reports, e.g. `api_calls`
+    fn name(&self) -> &str;
+
+    /// Output for a method with code of the selected classes, `Value::Null` leaves the method out
+    fn run(&self, ctx: &MethodContext) -> Value;
+}
+
+
+/// Lookups into the dex of a method, whatever holds its bytes
+pub trait DexHandle {
+    fn resolve_method(&self, method_idx: u32) -> Option<MethodRef>;
+
+    fn resolve_string(&self, string_idx: u32) -> Option<String>;
+
+    fn resolve_type(&self, type_idx: u32) -> Option<String>;
+}
+
+
+impl<T: AsRef<[u8]>> DexHandle for Dex<T> {
+    fn resolve_method(&self, method_idx: u32) -> Option<MethodRef> {
+        resolve_method(self, method_idx)
+    }
+
+    fn resolve_string(&self, string_idx: u32) -> Option<String> {
+        resolve_string(self, string_idx)
+    }
+
+    fn resolve_type(&self, type_idx: u32) -> Option<String> {
+        resolve_type(self, type_idx)
+    }
+}
+
+
+/// Lookups into a dex memoized over the methods of a walk, the analyses resolving the same indices over and over
+struct CachedDex<'a, T> {
+    dex: &'a Dex<T>,
+    methods: RefCell<HashMap<u32, Option<MethodRef>>>,
+    strings: RefCell<HashMap<u32, Option<String>>>,
+    types: RefCell<HashMap<u32, Option<String>>>,
+}
+
+
+impl<'a, T: AsRef<[u8]>> CachedDex<'a, T> {
+    fn new(dex: &'a Dex<T>) -> Self {
+        Self { dex, methods: RefCell::default(), strings: RefCell::default(), types: RefCell::default() }
+    }
+}
+
+
+impl<T: AsRef<[u8]>> DexHandle for CachedDex<'_, T> {
+    fn resolve_method(&self, method_idx: u32) -> Option<MethodRef> {
+        self.methods.borrow_mut().entry(method_idx).or_insert_with(|| resolve_method(self.dex, method_idx)).clone()
+    }
+
+    fn resolve_string(&self, string_idx: u32) -> Option<String> {
+        self.strings.borrow_mut().entry(string_idx).or_insert_with(|| resolve_string(self.dex, string_idx)).clone()
+    }
+
+    fn resolve_type(&self, type_idx: u32) -> Option<String> {
+        self.types.borrow_mut().entry(type_idx).or_insert_with(|| resolve_type(self.dex, type_idx)).clone()
+    }
+}
+
+
+/// Method handed to a `MethodAnalysis`, with its instructions decoded once for all analyses
+pub struct MethodContext<'a> {
+    dex_index: usize,
+    dex: &'a dyn DexHandle,
+    class: &'a Class,
+    method: &'a Method,
+    raw_bytecode: &'a [u16],
+    instructions: Vec<Instruction>,
+    cfg: OnceCell<Option<MethodCfg>>,
+}
+
+
+impl<'a> MethodContext<'a> {
+    /// Index of the dex in the APK
+    pub fn dex_index(&self) -> usize {
+        self.dex_index
+    }
+
+    pub fn dex(&self) -> &'a dyn DexHandle {
+        self.dex
+    }
+
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub fn class(&self) -> &'a str {
+        self.class.jtype().type_descriptor()
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.method.name()
+    }
+
+    pub fn method(&self) -> &'a Method {
+        self.method
+    }
+
+    pub fn raw_bytecode(&self) -> &'a [u16] {
+        self.raw_bytecode
+    }
+
+    /// Instructions of the method in code order, decoded as `decode_method_lenient` does
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Control flow graph of the method, built on the first call. `None` when the code can't be split into blocks
+    pub fn cfg(&self) -> Option<&MethodCfg> {
+        self.cfg.get_or_init(|| MethodCfg::build(self.raw_bytecode).ok()).as_ref()
+    }
+}
+
+
+/// Output of a method analysis for one method
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MethodOutput {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub name: String,
+    pub output: Value,
+}
+
+
+/// Method analyses registered on `AnalysisOptions`, in registration order
+#[derive(Clone, Default)]
+pub struct MethodAnalyses(pub(crate) Vec<Arc<dyn MethodAnalysis>>);
+
+
+impl MethodAnalyses {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+
+impl fmt::Debug for MethodAnalyses {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter().map(|analysis| analysis.name())).finish()
+    }
+}
+
+
+/// Runs `f` on every method with code of the selected classes of `dex`, whose lookups are memoized across the methods.
+/// Classes that fail to parse are left out
+pub(crate) fn for_each_method<T: AsRef<[u8]>>(dex_index: usize, dex: &Dex<T>, options: &AnalysisOptions, mut f: impl FnMut(&MethodContext)) {
+    let cached = CachedDex::new(dex);
+    for class in dex.classes().flatten().filter(|class| is_selected(class, options)) {
+        for method in class.methods() {
+            let Some(code) = method.code() else { continue };
+            let instructions = decode_method_lenient(code.insns()).instructions;
+            f(&MethodContext { dex_index, dex: &cached, class: &class, method, raw_bytecode: code.insns(), instructions, cfg: OnceCell::new() });
+        }
+    }
+}
+
+
+/// Runs the registered method analyses on every method with code of the selected classes of the dexes
+pub fn extensions<T: AsRef<[u8]>>(dexes: &[Dex<T>], options: &AnalysisOptions) -> Extensions {
+    let analyses = &options.method_analyses.0;
+    let mut outputs: Vec<Vec<MethodOutput>> = vec![vec![]; analyses.len()];
+    for (index, dex) in dexes.iter().enumerate() {
+        for_each_method(index, dex, options, |ctx| {
+            for (analysis, outputs) in analyses.iter().zip(&mut outputs) {
+                let output = analysis.run(ctx);
+                if !output.is_null() {
+                    outputs.push(MethodOutput { dex: ctx.dex_index(), class: ctx.class().to_string(), name: ctx.name().to_string(), output });
+                }
+            }
+        });
+    }
+    analyses.iter().map(|analysis| analysis.name().to_string()).zip(outputs).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::{
+        analysis::analyze_dexes,
+        api_sequence::ApiCalls,
+        dex_parsing::{NamedDex, Opcode},
+        testing::{ClassDef, CodeDef, DexBuilder, MethodDef},
+    };
+    use super::*;
+
+    struct NopCount;
+
+    impl MethodAnalysis for NopCount {
+        fn name(&self) -> &str {
+            "nop_count"
+        }
+
+        fn run(&self, ctx: &MethodContext) -> Value {
+            let nops = ctx.instructions().iter().filter(|inst| *inst.opcode() == Opcode::Nop).count();
+            assert!(ctx.cfg().is_some());
+            if nops > 0 { Value::from(nops) } else { Value::Null }
+        }
+    }
+
+    #[test]
+    fn test_registered_analyses() {
+        let mut builder = DexBuilder::new();
+        let log = builder.method("Landroid/util/Log;", "d", "I", &["Ljava/lang/String;", "Ljava/lang/String;"]) as u16;
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("run", "V", &[]).code(CodeDef::new(2, 0, 2, &[0x0000, 0x2071, log, 0x0010, 0x0000, 0x000E])))
+            .method(MethodDef::new("stop", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let options = AnalysisOptions::default().method_analysis(NopCount).method_analysis(ApiCalls).build();
+        assert_eq!(format!("{:?}", options.method_analyses), r#"["nop_count", "api_calls"]"#);
+
+        let report = serde_json::to_value(analyze_dexes(NamedDex::multidex([dex]), None, &options)).unwrap();
+        // stop has no nop and no framework call, so neither analysis reports it
+        assert_eq!(report["extensions"]["nop_count"], serde_json::json!([{ "dex": 0, "class": "Lcom/example/Main;", "name": "run", "output": 2 }]));
+        assert_eq!(report["extensions"]["api_calls"][0]["output"], serde_json::json!(["Landroid/util/Log;->d"]));
+        let report = serde_json::to_value(analyze_dexes(NamedDex::multidex([DexReader::from_vec(DexBuilder::new().build()).unwrap()]), None, &AnalysisOptions::default())).unwrap();
+        assert!(report.get("extensions").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "a method analysis named api_calls is already registered")]
+    fn test_duplicate_names_rejected() {
+        let _ = AnalysisOptions::default().method_analysis(ApiCalls).method_analysis(ApiCalls);
+    }
+}
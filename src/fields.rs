@@ -0,0 +1,140 @@
+use dex::{class::Class, encoded_value::EncodedValue, field::{AccessFlags, Field}, Dex};
+use serde::Serialize;
+
+use crate::{dex_parsing::is_selected, options::AnalysisOptions};
+
+
+/// Field declared by a class of a dex
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldRecord {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub name: String,
+    /// Type descriptor, e.g. `Ljava/lang/String;`
+    #[serde(rename = "type")]
+    pub field_type: String,
+    pub access_flags: u32,
+    /// Initial value of a static final field, from the static values of its class
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<ConstantValue>,
+}
+
+
+/// Initial value of a static field. Bytes, shorts and chars are widened to ints
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+pub enum ConstantValue {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Boolean(bool),
+    Null,
+    /// Enum constant as `Lcom/example/Mode;->FAST`
+    Enum(String),
+}
+
+
+/// Every field of the classes of `dex` selected by the options, static fields first in each class.
+/// Classes that fail to parse are left out
+pub fn fields<T: AsRef<[u8]>>(dex_index: usize, dex: &Dex<T>, options: &AnalysisOptions) -> Vec<FieldRecord> {
+    dex.classes()
+        .filter_map(Result::ok)
+        .filter(|class| is_selected(class, options))
+        .flat_map(|class| {
+            let descriptor = class.jtype().type_descriptor().to_string();
+            class_fields(&class)
+                .map(|field| FieldRecord {
+                    dex: dex_index,
+                    class: descriptor.clone(),
+                    name: field.name().to_string(),
+                    field_type: field.jtype().type_descriptor().to_string(),
+                    access_flags: field.access_flags().bits() as u32,
+                    value: initial_value(dex, field),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+
+fn class_fields(class: &Class) -> impl Iterator<Item = &Field> {
+    class.static_fields().chain(class.instance_fields())
+}
+
+
+fn initial_value<T: AsRef<[u8]>>(dex: &Dex<T>, field: &Field) -> Option<ConstantValue> {
+    if !field.access_flags().contains(AccessFlags::STATIC) || !field.access_flags().contains(AccessFlags::FINAL) {
+        return None;
+    }
+    Some(match field.initial_value()? {
+        EncodedValue::Byte(value) => ConstantValue::Int(*value as i32),
+        EncodedValue::Short(value) => ConstantValue::Int(*value as i32),
+        EncodedValue::Char(value) => ConstantValue::Int(*value as i32),
+        EncodedValue::Int(value) => ConstantValue::Int(*value),
+        EncodedValue::Long(value) => ConstantValue::Long(*value),
+        EncodedValue::Float(value) => ConstantValue::Float(*value),
+        EncodedValue::Double(value) => ConstantValue::Double(*value),
+        EncodedValue::String(value) => ConstantValue::String(value.to_string()),
+        EncodedValue::Boolean(value) => ConstantValue::Boolean(*value),
+        EncodedValue::Null => ConstantValue::Null,
+        EncodedValue::Enum(item) => {
+            let class = dex.get_type(item.class_idx() as u32).ok()?;
+            let name = dex.get_string(item.name_idx()).ok()?;
+            ConstantValue::Enum(format!("{}->{}", class.type_descriptor(), name))
+        },
+        // Types, method handles, arrays and annotations are not constants worth reporting
+        _ => return None,
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{DexBuilder, ClassDef, FieldDef, ValueDef, ACC_FINAL, ACC_PRIVATE, ACC_PUBLIC, ACC_STATIC};
+    use super::*;
+
+    #[test]
+    fn test_static_final_values() {
+        let mut builder = DexBuilder::new();
+        let constant = ACC_PUBLIC | ACC_STATIC | ACC_FINAL;
+        builder.class(ClassDef::new("Lcom/example/Config;")
+            .field(FieldDef::new("API_KEY", "Ljava/lang/String;").access_flags(constant).value(ValueDef::String("sk-123".to_string())))
+            .field(FieldDef::new("RETRIES", "I").access_flags(constant).value(ValueDef::Int(3)))
+            .field(FieldDef::new("DEBUG", "Z").access_flags(ACC_STATIC).value(ValueDef::Boolean(true)))
+            .field(FieldDef::new("MODE", "Lcom/example/Mode;").access_flags(constant)
+                .value(ValueDef::Enum("Lcom/example/Mode;".to_string(), "FAST".to_string(), "Lcom/example/Mode;".to_string())))
+            .field(FieldDef::new("TIMEOUT", "J").access_flags(constant).value(ValueDef::Long(-30_000_000_000)))
+            .field(FieldDef::new("RATIO", "F").access_flags(constant).value(ValueDef::Float(0.75)))
+            .field(FieldDef::new("SCALE", "D").access_flags(constant).value(ValueDef::Double(-1.5e300)))
+            .field(FieldDef::new("ENABLED", "Z").access_flags(constant).value(ValueDef::Boolean(true)))
+            .field(FieldDef::new("FALLBACK", "Ljava/lang/String;").access_flags(constant).value(ValueDef::Null))
+            .field(FieldDef::new("count", "J").access_flags(ACC_PRIVATE)));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let fields = fields(0, &dex, &AnalysisOptions::default());
+        let values = fields.iter().map(|field| (field.name.as_str(), field.value.clone())).collect::<Vec<_>>();
+        assert_eq!(values, [
+            ("API_KEY", Some(ConstantValue::String("sk-123".to_string()))),
+            ("RETRIES", Some(ConstantValue::Int(3))),
+            // Not final, the value may change at runtime
+            ("DEBUG", None),
+            ("MODE", Some(ConstantValue::Enum("Lcom/example/Mode;->FAST".to_string()))),
+            ("TIMEOUT", Some(ConstantValue::Long(-30_000_000_000))),
+            ("RATIO", Some(ConstantValue::Float(0.75))),
+            ("SCALE", Some(ConstantValue::Double(-1.5e300))),
+            ("ENABLED", Some(ConstantValue::Boolean(true))),
+            ("FALLBACK", Some(ConstantValue::Null)),
+            ("count", None),
+        ]);
+        assert_eq!((fields[0].class.as_str(), fields[0].field_type.as_str(), fields[0].access_flags), ("Lcom/example/Config;", "Ljava/lang/String;", constant));
+        let json = serde_json::to_value(&fields[0]).unwrap();
+        assert_eq!(json["value"], serde_json::json!({ "kind": "string", "value": "sk-123" }));
+        let json = serde_json::to_value(&fields[8]).unwrap();
+        assert_eq!(json["value"], serde_json::json!({ "kind": "null" }));
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{self, Write};
+
+use dex::Dex;
+use dexompiler::{
+    analysis::parse_apk,
+    class_graph::ClassGraph,
+    reference::{resolve_instruction_type, resolve_method, resolve_string_encoded},
+    dex_parsing::InstructionFormat,
+    decode_method, Error, Instruction, InvalidStrings, MethodCfg, Opcode, Registers,
+};
+
+use crate::cli::{GraphFormat, InspectArgs};
+
+
+/// Prints the listing of the methods matching the filters of `args`, or an index of the classes and their methods without filters,
+/// or the class reference graph
+pub fn inspect(args: &InspectArgs, out: &mut impl Write) -> Result<(), Error> {
+    let contents = parse_apk(&args.input)?;
+    if let Some(format) = args.class_graph {
+        let graph = ClassGraph::from_dexes(&contents.dexes);
+        match format {
+            GraphFormat::Dot => graph.write_dot(out)?,
+            GraphFormat::Graphml => graph.write_graphml(out)?,
+            GraphFormat::Ndjson => graph.write_ndjson(out)?,
+        }
+        return Ok(());
+    }
+    let listing = args.class.is_some() || args.method.is_some();
+    for (dex, bytes) in contents.dexes.iter().map(|named| &named.dex).zip(&contents.dex_bytes) {
+        for class in dex.classes() {
+            let class = match class {
+                Ok(class) => class,
+                Err(err) => {
+                    eprintln!("Warning: {}: {}", args.input, err);
+                    continue;
+                }
+            };
+            let descriptor = class.jtype().type_descriptor().to_string();
+            if args.class.as_ref().is_some_and(|class| *class != descriptor) {
+                continue;
+            }
+            if !listing {
+                writeln!(out, "{}", descriptor)?;
+            }
+            for method in class.methods() {
+                let name = method.name().to_string();
+                if args.method.as_ref().is_some_and(|method| *method != name) {
+                    continue;
+                }
+                let code = method.code().map(|code| code.insns());
+                if listing {
+                    write_method(out, &descriptor, &name, code, |inst| resolve_reference(dex, bytes, inst))?;
+                } else {
+                    match code {
+                        Some(code) => writeln!(out, "    {} ({} code units)", name, code.len())?,
+                        None => writeln!(out, "    {} (no code)", name)?,
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+
+/// Writes the summary line and the listing of a method, `resolve` gives the comment of the instructions with a reference
+fn write_method(
+    out: &mut impl Write,
+    class: &str,
+    name: &str,
+    code: Option<&[u16]>,
+    resolve: impl Fn(&Instruction) -> Option<String>,
+) -> io::Result<()> {
+    writeln!(out, "{}->{}", class, name)?;
+    let Some(raw_bytecode) = code else {
+        return writeln!(out, "    no code");
+    };
+    let decoded = decode_method(raw_bytecode);
+    match MethodCfg::build(raw_bytecode) {
+        Ok(cfg) => writeln!(out, "    blocks: {}, complexity: {}, instructions: {}", cfg.len(), complexity(&cfg), decoded.instructions.len())?,
+        Err(err) => writeln!(out, "    {}, instructions: {}", err, decoded.instructions.len())?,
+    }
+
+    let mut undecoded = decoded.undecoded.iter().peekable();
+    for inst in &decoded.instructions {
+        while let Some(offset) = undecoded.next_if(|&&offset| offset < *inst.offset()) {
+            write_undecoded(out, raw_bytecode, *offset)?;
+        }
+        match resolve(inst) {
+            Some(resolved) => writeln!(out, "    {:04x}: {}  # {}", inst.offset(), smali(inst, raw_bytecode), resolved)?,
+            None => writeln!(out, "    {:04x}: {}", inst.offset(), smali(inst, raw_bytecode))?,
+        }
+    }
+    for offset in undecoded {
+        write_undecoded(out, raw_bytecode, *offset)?;
+    }
+    Ok(())
+}
+
+/// The instruction as smali writes it, the mnemonic followed by the registers, the literal, the absolute branch target
+/// and the constant pool reference, e.g. `invoke-virtual {v1, v0}, method@714` or `const/16 v1, 0x21`
+fn smali(inst: &Instruction, raw_bytecode: &[u16]) -> String {
+    let mut operands = vec![];
+    let registers = inst.registers();
+    match (inst.opcode().format(), *registers) {
+        (_, Registers::Range { count: 0, .. }) => operands.push("{}".to_string()),
+        (_, Registers::Range { first, count }) => operands.push(format!("{{v{} .. v{}}}", first, first as u32 + count as u32 - 1)),
+        (InstructionFormat::F35c | InstructionFormat::F45cc, _) => {
+            operands.push(format!("{{{}}}", registers.iter().map(|register| format!("v{}", register)).collect::<Vec<_>>().join(", ")))
+        },
+        _ => operands.extend(registers.iter().map(|register| format!("v{}", register))),
+    }
+    if let Some(literal) = literal(inst, raw_bytecode) {
+        operands.push(if literal < 0 { format!("-{:#x}", literal.unsigned_abs()) } else { format!("{:#x}", literal) });
+    }
+    if let Some(target) = inst.branch_target() {
+        operands.push(format!("{:#06x}", target));
+    }
+    if let (Some(reference), Some(kind)) = (inst.reference(), inst.reference_kind()) {
+        operands.push(format!("{}@{}", kind, reference));
+    }
+    match operands.is_empty() {
+        true => inst.opcode().mnemonic().to_string(),
+        false => format!("{} {}", inst.opcode().mnemonic(), operands.join(", ")),
+    }
+}
+
+
+/// Literal operand of the `const` and `/lit` instructions, sign-extended and shifted into place for the `high16` ones
+fn literal(inst: &Instruction, raw_bytecode: &[u16]) -> Option<i64> {
+    let unit = |index: usize| raw_bytecode.get(inst.offset() + index).copied();
+    Some(match inst.opcode().format() {
+        InstructionFormat::F11n => (unit(0)? as i16 >> 12) as i64,
+        InstructionFormat::F21s | InstructionFormat::F22s => unit(1)? as i16 as i64,
+        InstructionFormat::F21h if *inst.opcode() == Opcode::ConstWideHigh16 => (unit(1)? as i64) << 48,
+        InstructionFormat::F21h => ((unit(1)? as i32) << 16) as i64,
+        InstructionFormat::F22b => (unit(1)? >> 8) as i8 as i64,
+        InstructionFormat::F31i => (unit(1)? as u32 | (unit(2)? as u32) << 16) as i32 as i64,
+        InstructionFormat::F51l => (0..4).try_fold(0u64, |literal, index| Some(literal | (unit(1 + index)? as u64) << (index * 16)))? as i64,
+        _ => return None,
+    })
+}
+
+fn write_undecoded(out: &mut impl Write, raw_bytecode: &[u16], offset: usize) -> io::Result<()> {
+    writeln!(out, "    {:04x}: <undecodable {:#06x}>", offset, raw_bytecode[offset])
+}
+
+
+/// Cyclomatic complexity of a method: one plus the extra paths opened by every branching block
+fn complexity(cfg: &MethodCfg) -> usize {
+    1 + cfg.blocks().iter().map(|block| block.borrow().succ().len().saturating_sub(1)).sum::<usize>()
+}
+
+
+/// Comment of an instruction: the string, method or type its reference resolves to. Strings that aren't valid MUTF-8
+/// are shown with their bytes escaped, `bytes` being the dex
+fn resolve_reference<T: AsRef<[u8]>>(dex: &Dex<T>, bytes: &[u8], inst: &Instruction) -> Option<String> {
+    let reference = (*inst.reference())?;
+    match inst.reference_kind()? {
+        "string" => resolve_string_encoded(dex, bytes, reference, InvalidStrings::Escaped).map(|string| format!("{:?}", string)),
+        "method" => resolve_method(dex, reference).map(|method| format!("{}->{}", method.class, method.name)),
+        "type" => resolve_instruction_type(dex, inst),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+    use dexompiler::testing::{ClassDef, CodeDef, DexBuilder, MethodDef, SAMPLE_METHODS};
+
+    use super::*;
+
+    const ON_START_LISTING: &str = "\
+Lorg/example/Sample0;->onStart
+    blocks: 4, complexity: 3, instructions: 11
+    0000: invoke-super {v2}, method@743
+    0003: sget v0, field@57
+    0005: const/16 v1, 0x21
+    0007: if-lt v0, v1, 0x0016
+    0009: const-string v0, string@21033
+    000b: invoke-static {v2, v0}, method@855
+    000e: move-result v1
+    000f: if-eqz v1, 0x0016
+    0011: iget-object v1, v2, field@22998
+    0013: invoke-virtual {v1, v0}, method@714
+    0016: return-void
+";
+
+    #[test]
+    fn test_listing_snapshot() {
+        let bytes = include_bytes!("../tests/fixtures/sample.dex");
+        let dex = DexReader::from_vec(bytes.to_vec()).unwrap();
+        let class = dex.classes().next().unwrap().unwrap();
+        let method = class.methods().find(|method| method.name().as_str() == "onStart").unwrap();
+        let mut out = vec![];
+        let descriptor = class.jtype().type_descriptor().to_string();
+        write_method(&mut out, &descriptor, "onStart", method.code().map(|code| code.insns()), |inst| resolve_reference(&dex, bytes, inst)).unwrap();
+        // The fixture methods were lifted from other apps, so their references are out of range and left unresolved
+        assert_eq!(String::from_utf8(out).unwrap(), ON_START_LISTING);
+    }
+
+    #[test]
+    fn test_listing_resolves_references() {
+        let mut builder = DexBuilder::new();
+        let string = builder.string("hello") as u16;
+        let string_builder = builder.type_idx("Ljava/lang/StringBuilder;") as u16;
+        let append = builder.method("Ljava/lang/StringBuilder;", "append", "Ljava/lang/StringBuilder;", &["Ljava/lang/String;"]) as u16;
+        // const-string v0, "hello"; new-instance v1, StringBuilder; invoke-virtual {v1, v0}, append; return-void
+        let body = [0x001A, string, 0x0122, string_builder, 0x206E, append, 0x0001, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Main;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(2, 0, 2, &body))));
+        let bytes = builder.build();
+        let dex = DexReader::from_vec(bytes.clone()).unwrap();
+        let mut out = vec![];
+        write_method(&mut out, "Lcom/example/Main;", "run", Some(&body), |inst| resolve_reference(&dex, &bytes, inst)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("\
+Lcom/example/Main;->run
+    blocks: 1, complexity: 1, instructions: 4
+    0000: const-string v0, string@{}  # \"hello\"
+    0002: new-instance v1, type@{}  # java.lang.StringBuilder
+    0004: invoke-virtual {{v1, v0}}, method@{}  # Ljava/lang/StringBuilder;->append
+    0007: return-void
+", string, string_builder, append));
+    }
+
+    #[test]
+    fn test_smali_operands() {
+        let smali_at = |raw_bytecode: &[u16]| {
+            let (inst, _) = Instruction::try_from_raw_bytecode(raw_bytecode, 0).unwrap().unwrap();
+            smali(&inst, raw_bytecode)
+        };
+        assert_eq!(smali_at(&[0xF012]), "const/4 v0, -0x1");
+        assert_eq!(smali_at(&[0x0215, 0x4120]), "const/high16 v2, 0x41200000");
+        assert_eq!(smali_at(&[0x0219, 0x4024]), "const-wide/high16 v2, 0x4024000000000000");
+        assert_eq!(smali_at(&[0x0014, 0x5678, 0x1234]), "const v0, 0x12345678");
+        assert_eq!(smali_at(&[0x0018, 1, 0, 0, 0x8000]), "const-wide v0, -0x7fffffffffffffff");
+        assert_eq!(smali_at(&[0x00D8, 0xFE01]), "add-int/lit8 v0, v1, -0x2");
+        assert_eq!(smali_at(&[0x10D2, 300]), "mul-int/lit16 v0, v1, 0x12c");
+        assert_eq!(smali_at(&[0x0374, 7, 3]), "invoke-virtual/range {v3 .. v5}, method@7");
+        assert_eq!(smali_at(&[0x0071, 7, 0]), "invoke-static {}, method@7");
+        assert_eq!(smali_at(&[0x1032, 2]), "if-eq v0, v1, 0x0002");
+    }
+
+    #[test]
+    fn test_listing_operands() {
+        let (_, on_start) = SAMPLE_METHODS[0];
+        let mut out = vec![];
+        write_method(&mut out, "Lorg/example/Sample0;", "onStart", Some(on_start), |_| None).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), ON_START_LISTING);
+    }
+}
@@ -0,0 +1,367 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, ops::ControlFlow};
+
+use dex::Dex;
+use serde::Serialize;
+
+use crate::{
+    dex_parsing::{block_leaders, is_selected, walk_dex, ClassInfo, DecodedInstruction, Instruction, InstructionVisitor, MethodInfo, Opcode},
+    manifest_parsing::{ComponentKind, Manifest},
+    options::{AnalysisOptions, Strictness},
+    reference::{resolve_method, resolve_referenced_class, resolve_string, resolve_string_encoded},
+};
+
+
+const INTENT: &str = "Landroid/content/Intent;";
+const COMPONENT_NAME: &str = "Landroid/content/ComponentName;";
+
+/// Names of the methods handing an intent over to the system, whatever their class
+pub const LAUNCH_METHODS: &[&str] = &[
+    "startActivity", "startActivityForResult", "startActivities", "startService", "startForegroundService", "bindService",
+    "sendBroadcast", "sendOrderedBroadcast", "sendStickyBroadcast", "setResult",
+];
+
+
+/// Intent created by a method, with what the instructions of its basic block tell about it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IntentSite {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub method: String,
+    /// Code unit offset of the `new-instance` of the intent
+    pub offset: usize,
+    /// Action of an implicit intent, e.g. `android.intent.action.VIEW`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// Descriptor of the component targeted by an explicit intent, e.g. `Lcom/example/SettingsActivity;`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Call handing the intent over in the same block
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub launch: Option<IntentLaunch>,
+}
+
+
+/// Call of one of the `LAUNCH_METHODS` with an intent as argument
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IntentLaunch {
+    /// Invoked method, e.g. `Landroid/app/Activity;->startActivity`
+    pub call: String,
+    /// Code unit offset of the invoke
+    pub offset: usize,
+}
+
+
+/// Intents created by every method of the selected classes of `dex`. Actions and targets are only followed through
+/// the registers of a basic block, an intent built across branches is reported without them. With the `bytes` of the
+/// dex, actions that aren't valid MUTF-8 are written as set by the options rather than left out
+pub fn intents<T: AsRef<[u8]>>(dex_index: usize, dex: &Dex<T>, bytes: Option<&[u8]>, options: &AnalysisOptions) -> Vec<IntentSite> {
+    let mut visitor = IntentVisitor { dex, bytes, dex_index, options, instructions: vec![], sites: vec![] };
+    walk_dex(dex, &mut visitor);
+    visitor.sites
+}
+
+
+/// Statically known contents of a register
+#[derive(Debug, Clone)]
+enum Value {
+    String(String),
+    /// Descriptor loaded by `const-class`
+    Class(String),
+    /// Index of the intent into the sites of the method
+    Intent(usize),
+    /// `ComponentName` and the descriptor of its class, once constructed
+    Component(Option<String>),
+}
+
+
+struct IntentVisitor<'a, T> {
+    dex: &'a Dex<T>,
+    bytes: Option<&'a [u8]>,
+    dex_index: usize,
+    options: &'a AnalysisOptions,
+    /// Decoded instructions of the current method, scanned once the block boundaries are known
+    instructions: Vec<Instruction>,
+    sites: Vec<IntentSite>,
+}
+
+impl<T: AsRef<[u8]>> IntentVisitor<'_, T> {
+    /// Follows the registers of every block of a method, appending its intents to the sites
+    fn scan(&mut self, class: String, method: String, instructions: &[Instruction], leaders: &BTreeSet<usize>) {
+        let mut values: HashMap<u16, Value> = HashMap::new();
+        for inst in instructions {
+            if leaders.contains(inst.offset()) {
+                values.clear();
+            }
+            let registers: Vec<u16> = inst.registers().iter().collect();
+            match inst.opcode() {
+                Opcode::ConstString | Opcode::ConstStringJumbo => {
+                    let string = (*inst.reference()).and_then(|string_idx| match self.bytes {
+                        Some(bytes) => resolve_string_encoded(self.dex, bytes, string_idx, self.options.invalid_strings),
+                        None => resolve_string(self.dex, string_idx),
+                    });
+                    match string {
+                        Some(string) => values.insert(registers[0], Value::String(string)),
+                        None => values.remove(&registers[0]),
+                    };
+                },
+                Opcode::ConstClass => {
+                    match resolve_referenced_class(self.dex, inst) {
+                        Some(class) => values.insert(registers[0], Value::Class(class)),
+                        None => values.remove(&registers[0]),
+                    };
+                },
+                Opcode::NewInstance => {
+                    match resolve_referenced_class(self.dex, inst).as_deref() {
+                        Some(INTENT) => {
+                            values.insert(registers[0], Value::Intent(self.sites.len()));
+                            self.sites.push(IntentSite {
+                                dex: self.dex_index,
+                                class: class.clone(),
+                                method: method.clone(),
+                                offset: *inst.offset(),
+                                action: None,
+                                target: None,
+                                launch: None,
+                            });
+                        },
+                        Some(COMPONENT_NAME) => {
+                            values.insert(registers[0], Value::Component(None));
+                        },
+                        _ => {
+                            values.remove(&registers[0]);
+                        },
+                    }
+                },
+                Opcode::MoveObject | Opcode::MoveObjectFrom16 | Opcode::MoveObject16 => {
+                    match values.get(&registers[1]).cloned() {
+                        Some(value) => values.insert(registers[0], value),
+                        None => values.remove(&registers[0]),
+                    };
+                },
+                opcode if opcode.is_invoke() => self.invoke(inst, &registers, &mut values),
+                // Every other instruction writing its first register
+                opcode if writes_first_register(*opcode) => {
+                    values.remove(&registers[0]);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Applies a constructor, setter or launch call to the tracked intents
+    fn invoke(&mut self, inst: &Instruction, registers: &[u16], values: &mut HashMap<u16, Value>) {
+        let Some(method) = (*inst.reference()).and_then(|method_idx| resolve_method(self.dex, method_idx)) else { return };
+        let arg = |index: usize| registers.get(index).and_then(|register| values.get(register));
+        match (method.class.as_str(), method.name.as_str()) {
+            (INTENT, "<init>" | "setAction" | "setClass" | "setClassName" | "setComponent") => {
+                let Some(&Value::Intent(site)) = arg(0) else { return };
+                // The parameters follow `this`, none of those read here is wide
+                for (index, param) in method.params.iter().enumerate() {
+                    let site = &mut self.sites[site];
+                    match (param.as_str(), arg(index + 1)) {
+                        ("Ljava/lang/String;", Some(Value::String(string))) => match method.name.as_str() {
+                            "setClassName" if index + 1 == method.params.len() => site.target = Some(class_descriptor(string)),
+                            "<init>" | "setAction" => site.action = Some(string.clone()),
+                            _ => {},
+                        },
+                        ("Ljava/lang/Class;", Some(Value::Class(class))) => site.target = Some(class.clone()),
+                        (COMPONENT_NAME, Some(Value::Component(Some(class)))) => site.target = Some(class.clone()),
+                        _ => {},
+                    }
+                }
+            },
+            (COMPONENT_NAME, "<init>") => {
+                let Some(Value::Component(_)) = arg(0) else { return };
+                // The class is the last parameter of every constructor: a class name or a `Class`
+                let class = match arg(method.params.len()) {
+                    Some(Value::String(string)) => Some(class_descriptor(string)),
+                    Some(Value::Class(class)) => Some(class.clone()),
+                    _ => None,
+                };
+                values.insert(registers[0], Value::Component(class));
+            },
+            (_, name) if LAUNCH_METHODS.contains(&name) => {
+                let launched = registers.iter().filter_map(|register| match values.get(register) {
+                    Some(&Value::Intent(site)) => Some(site),
+                    _ => None,
+                });
+                for site in launched.collect::<Vec<_>>() {
+                    self.sites[site].launch.get_or_insert_with(|| IntentLaunch { call: format!("{}->{}", method.class, method.name), offset: *inst.offset() });
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> InstructionVisitor for IntentVisitor<'_, T> {
+    fn visit_class(&mut self, class: &ClassInfo) -> ControlFlow<()> {
+        if is_selected(class.class(), self.options) { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+    }
+
+    fn visit_instruction(&mut self, inst: &DecodedInstruction) {
+        self.instructions.push(inst.instruction.clone());
+    }
+
+    fn leave_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        let mut instructions = std::mem::take(&mut self.instructions);
+        if let Some(code) = method.code().filter(|_| instructions.iter().any(|inst| *inst.opcode() == Opcode::NewInstance)) {
+            // The catch handlers start blocks too, entered with the registers of any instruction of their try block
+            let mut leaders = block_leaders(code.insns(), &instructions);
+            leaders.extend(code.tries().iter().flat_map(|try_block| try_block.catch_handlers().iter().map(|handler| handler.addr() as usize)));
+            let class = method.class().jtype().type_descriptor().to_string();
+            self.scan(class, method.method().name().to_string(), &instructions, &leaders);
+        }
+        // The buffer is reused by the next method
+        instructions.clear();
+        self.instructions = instructions;
+        ControlFlow::Continue(())
+    }
+
+    fn strictness(&self) -> Strictness {
+        Strictness::Lenient
+    }
+}
+
+
+/// Intents targeting a component declared by the manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentInteractions {
+    pub kind: ComponentKind,
+    /// Intents whose target is the component, in dex and code order
+    pub intents: Vec<IntentSite>,
+}
+
+
+/// Which code starts which component of the manifest: the explicit intents of `sites` by the class name of the
+/// component they target, e.g. `com.example.SettingsActivity`. Components no intent targets are left out
+pub fn component_interactions(sites: &[IntentSite], manifest: &Manifest) -> BTreeMap<String, ComponentInteractions> {
+    let mut interactions = BTreeMap::new();
+    for component in &manifest.components {
+        let descriptor = class_descriptor(&component.name);
+        let intents: Vec<IntentSite> = sites.iter().filter(|site| site.target.as_ref() == Some(&descriptor)).cloned().collect();
+        if !intents.is_empty() {
+            interactions.insert(component.name.clone(), ComponentInteractions { kind: component.kind, intents });
+        }
+    }
+    interactions
+}
+
+
+/// Whether an instruction other than an object move, `const-string`, `const-class` and `new-instance` overwrites its first register
+fn writes_first_register(opcode: Opcode) -> bool {
+    // Moves and results, constants, instance-of to new-array, array, instance and static field reads, unary and binary operations
+    matches!(opcode as u8, 0x01..=0x0D | 0x12..=0x1C | 0x20..=0x23 | 0x44..=0x4A | 0x52..=0x58 | 0x60..=0x66 | 0x7B..=0xE2)
+}
+
+
+/// Descriptor of a Java class name, e.g. `Lcom/example/Main;` for `com.example.Main`
+fn class_descriptor(name: &str) -> String {
+    format!("L{};", name.replace('.', "/"))
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::{manifest_parsing::Component, testing::{DexBuilder, ClassDef, MethodDef, CodeDef}};
+    use super::*;
+
+    #[test]
+    fn test_explicit_intent() {
+        let mut builder = DexBuilder::new();
+        let intent = builder.type_idx(INTENT) as u16;
+        let target = builder.type_idx("Lcom/example/SettingsActivity;") as u16;
+        let view = builder.string("android.intent.action.VIEW") as u16;
+        let init = builder.method(INTENT, "<init>", "V", &["Landroid/content/Context;", "Ljava/lang/Class;"]) as u16;
+        let init_action = builder.method(INTENT, "<init>", "V", &["Ljava/lang/String;"]) as u16;
+        let start = builder.method("Landroid/app/Activity;", "startActivity", "V", &[INTENT]) as u16;
+        // new-instance v0, Intent; const-class v1, SettingsActivity; invoke-direct {v0, v2, v1}, Intent.<init>(Context, Class);
+        // invoke-virtual {v2, v0}, Activity.startActivity; return-void
+        let open = [0x0022, intent, 0x011C, target, 0x3070, init, 0x0120, 0x206E, start, 0x0002, 0x000E];
+        // new-instance v0, Intent; const-string v1, VIEW; if-eqz v1, +5; invoke-direct {v0, v1}, Intent.<init>(String); return-void
+        let branching = [0x0022, intent, 0x011A, view, 0x0138, 0x0005, 0x2070, init_action, 0x0010, 0x000E];
+        // new-instance v0, Intent; const-string v1, VIEW; invoke-direct {v0, v1}, Intent.<init>(String); return-void
+        let view_body = [0x0022, intent, 0x011A, view, 0x2070, init_action, 0x0010, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("open", "V", &[]).code(CodeDef::new(3, 1, 3, &open)))
+            .method(MethodDef::new("branching", "V", &[]).code(CodeDef::new(2, 0, 2, &branching)))
+            .method(MethodDef::new("view", "V", &[]).code(CodeDef::new(2, 0, 2, &view_body))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+
+        let sites = intents(0, &dex, None, &AnalysisOptions::default());
+        assert_eq!(sites, [
+            IntentSite {
+                dex: 0,
+                class: "Lcom/example/Main;".to_string(),
+                method: "open".to_string(),
+                offset: 0,
+                action: None,
+                target: Some("Lcom/example/SettingsActivity;".to_string()),
+                launch: Some(IntentLaunch { call: "Landroid/app/Activity;->startActivity".to_string(), offset: 7 }),
+            },
+            // The constructor is past the branch, in another block than the string
+            IntentSite {
+                dex: 0,
+                class: "Lcom/example/Main;".to_string(),
+                method: "branching".to_string(),
+                offset: 0,
+                action: None,
+                target: None,
+                launch: None,
+            },
+            IntentSite {
+                dex: 0,
+                class: "Lcom/example/Main;".to_string(),
+                method: "view".to_string(),
+                offset: 0,
+                action: Some("android.intent.action.VIEW".to_string()),
+                target: None,
+                launch: None,
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_component_interactions() {
+        let site = |method: &str, target: Option<&str>| IntentSite {
+            dex: 0,
+            class: "Lcom/example/Main;".to_string(),
+            method: method.to_string(),
+            offset: 0,
+            action: None,
+            target: target.map(str::to_string),
+            launch: None,
+        };
+        let sites = [
+            site("open", Some("Lcom/example/SettingsActivity;")),
+            site("view", None),
+            site("sync", Some("Lcom/example/SyncService;")),
+            site("reopen", Some("Lcom/example/SettingsActivity;")),
+            site("other", Some("Lorg/other/Activity;")),
+        ];
+        let component = |kind, name: &str| Component { kind, name: name.to_string() };
+        let manifest = Manifest {
+            components: vec![
+                component(ComponentKind::Activity, "com.example.SettingsActivity"),
+                component(ComponentKind::Service, "com.example.SyncService"),
+                component(ComponentKind::Receiver, "com.example.BootReceiver"),
+            ],
+            ..Manifest::default()
+        };
+        let interactions = component_interactions(&sites, &manifest);
+        assert_eq!(interactions.keys().collect::<Vec<_>>(), ["com.example.SettingsActivity", "com.example.SyncService"]);
+        let settings = &interactions["com.example.SettingsActivity"];
+        assert_eq!(settings.kind, ComponentKind::Activity);
+        assert_eq!(settings.intents.iter().map(|site| site.method.as_str()).collect::<Vec<_>>(), ["open", "reopen"]);
+        assert_eq!(interactions["com.example.SyncService"].kind, ComponentKind::Service);
+    }
+
+    #[test]
+    fn test_class_descriptor() {
+        assert_eq!(class_descriptor("com.example.Main"), "Lcom/example/Main;");
+    }
+}
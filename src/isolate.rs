@@ -0,0 +1,112 @@
+use std::{env, fs::{File, OpenOptions}, io::{self, Write}, process::{Command, ExitStatus, Stdio}, sync::Mutex, thread};
+
+use dexompiler::{ApkReport, Coverage};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{dedup_counts, dex_bytes, stats::{move_density, MoveDensity}};
+
+
+/// Hidden flag running the analysis of a single input in a child process for `--isolate`
+pub const WORKER_FLAG: &str = "--worker-single";
+
+
+/// Records of the input of a worker, as they appear under its key in json mode, and its totals.
+/// A worker prints it on stdout in an `Ok`, or its analysis error in an `Err`
+#[derive(Serialize, Deserialize)]
+pub struct Isolated<R> {
+    pub coverage: Coverage,
+    /// Methods and unique method bodies, both 0 without deduplication
+    pub methods: usize,
+    pub unique_methods: usize,
+    /// Size of the dexes and number of emitted opcodes of the input
+    pub dex_bytes: u64,
+    pub instructions: u64,
+    /// Methods by share of moves, all 0 without op stats
+    #[serde(default)]
+    pub move_density: MoveDensity,
+    /// Dexes found in and missing from `--cache-dir`
+    #[serde(default)]
+    pub cache_hits: u64,
+    #[serde(default)]
+    pub cache_misses: u64,
+    pub warnings: Vec<String>,
+    pub records: Vec<R>,
+}
+
+
+impl<R> Isolated<R> {
+    pub fn new(report: &ApkReport, records: Vec<R>) -> Self {
+        let (methods, unique_methods) = dedup_counts(&report.sequences);
+        let warnings = report.warnings.iter().map(ToString::to_string).collect();
+        Self {
+            coverage: report.coverage,
+            methods,
+            unique_methods,
+            dex_bytes: dex_bytes(report),
+            instructions: report.sequences.opcode_count() as u64,
+            move_density: move_density(&report.sequences),
+            cache_hits: 0,
+            cache_misses: 0,
+            warnings,
+            records,
+        }
+    }
+}
+
+
+/// Why a worker failed to return the records of its input
+#[derive(Debug, Error)]
+pub enum WorkerError {
+    /// The input couldn't be analyzed, it would have failed the same way without `--isolate`
+    #[error("{0}")]
+    Analysis(String),
+    #[error("the worker {0}")]
+    Crashed(ExitStatus),
+    #[error("unreadable worker output: {0}")]
+    Output(#[from] serde_json::Error),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+
+/// Runs `path` through a child process of the same binary, with the arguments of this one.
+/// `data` is piped to the child in place of the file, for the stdin APK and downloads
+pub fn analyze_isolated(path: &str, data: Option<&[u8]>) -> Result<Isolated<serde_json::Value>, WorkerError> {
+    let mut child = Command::new(env::current_exe()?)
+        .args(env::args_os().skip(1))
+        .args([WORKER_FLAG, if data.is_some() { "-" } else { path }])
+        .stdin(if data.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take();
+    let output = thread::scope(|scope| {
+        if let (Some(mut stdin), Some(data)) = (stdin, data) {
+            // The worker reads all of stdin before writing, a failed write shows as its crash
+            scope.spawn(move || stdin.write_all(data));
+        }
+        child.wait_with_output()
+    })?;
+    if !output.status.success() {
+        return Err(WorkerError::Crashed(output.status));
+    }
+    serde_json::from_slice::<Result<_, String>>(&output.stdout)?.map_err(WorkerError::Analysis)
+}
+
+
+/// List of the inputs that crashed their worker, one per line so it can be given back with `--input-list`
+pub struct Quarantine(Mutex<File>);
+
+
+impl Quarantine {
+    /// Opens the list for appending, keeping the inputs quarantined by earlier runs
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(Self(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)))
+    }
+
+    pub fn add(&self, input: &str) -> io::Result<()> {
+        let mut file = self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        writeln!(file, "{}", input)?;
+        file.flush()
+    }
+}
@@ -0,0 +1,112 @@
+use dex::Dex;
+use serde::Serialize;
+
+
+const ACC_SYNTHETIC: u32 = 0x1000;
+
+/// Annotation type the Kotlin compiler puts on every class it emits
+pub const KOTLIN_METADATA: &str = "Lkotlin/Metadata;";
+
+
+/// Why a class is considered generated by the compiler rather than written by hand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyntheticKind {
+    /// Desugared Java lambda or Kotlin lambda, e.g. `-$$Lambda$Main$1` or `$$ExternalSyntheticLambda0`
+    Lambda,
+    /// Numbered anonymous class, e.g. `Main$1`, which coroutine continuations are too
+    Anonymous,
+    /// Helper classes of the Kotlin compiler, e.g. `$WhenMappings` and `$DefaultImpls`
+    KotlinGenerated,
+    /// Class with the synthetic access flag
+    Synthetic,
+}
+
+
+/// Kotlin usage of an APK and share of its classes generated by the compiler
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct KotlinReport {
+    /// Whether a dex references the `kotlin.Metadata` annotation
+    pub detected: bool,
+    /// Number of class definitions of all dexes
+    pub classes: usize,
+    pub synthetic_classes: usize,
+    /// `synthetic_classes` over `classes`, 0 without classes
+    pub synthetic_class_ratio: f64,
+}
+
+
+/// Kind of a generated class from its descriptor and access flags, `None` for a class written by hand
+pub fn synthetic_kind(descriptor: &str, access_flags: u32) -> Option<SyntheticKind> {
+    let name = descriptor.trim_end_matches(';');
+    let nested = name.rsplit_once('$').map(|(_, nested)| nested);
+    if name.contains("$$Lambda$") || name.contains("$$ExternalSyntheticLambda") || name.contains("$lambda$") || name.contains("$lambda-") {
+        Some(SyntheticKind::Lambda)
+    } else if name.ends_with("$WhenMappings") || name.ends_with("$DefaultImpls") || name.contains("$sam$") {
+        Some(SyntheticKind::KotlinGenerated)
+    } else if nested.is_some_and(|nested| !nested.is_empty() && nested.bytes().all(|byte| byte.is_ascii_digit())) {
+        Some(SyntheticKind::Anonymous)
+    } else if access_flags & ACC_SYNTHETIC != 0 {
+        Some(SyntheticKind::Synthetic)
+    } else {
+        None
+    }
+}
+
+
+/// Looks for the Kotlin metadata annotation among the types of the dexes and counts the generated classes.
+/// Classes that fail to parse are left out
+pub fn kotlin_report<T: AsRef<[u8]>>(dexes: &[Dex<T>]) -> KotlinReport {
+    let mut report = KotlinReport { detected: dexes.iter().any(|dex| references_type(dex, KOTLIN_METADATA)), ..Default::default() };
+    for class in dexes.iter().flat_map(|dex| dex.classes().flatten()) {
+        report.classes += 1;
+        if synthetic_kind(class.jtype().type_descriptor(), class.access_flags().bits() as u32).is_some() {
+            report.synthetic_classes += 1;
+        }
+    }
+    if report.classes > 0 {
+        report.synthetic_class_ratio = report.synthetic_classes as f64 / report.classes as f64;
+    }
+    report
+}
+
+
+/// Whether `descriptor` is among the type ids of `dex`
+fn references_type<T: AsRef<[u8]>>(dex: &Dex<T>, descriptor: &str) -> bool {
+    (0..dex.header().type_ids_size()).any(|type_idx| dex.get_type(type_idx).is_ok_and(|jtype| jtype.type_descriptor().as_str() == descriptor))
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{ClassDef, DexBuilder};
+    use super::*;
+
+    #[test]
+    fn test_synthetic_kind() {
+        assert_eq!(synthetic_kind("Lcom/example/-$$Lambda$Main$xyz;", 0), Some(SyntheticKind::Lambda));
+        assert_eq!(synthetic_kind("Lcom/example/Main$$ExternalSyntheticLambda0;", 0), Some(SyntheticKind::Lambda));
+        assert_eq!(synthetic_kind("Lcom/example/Main$onCreate$lambda$1;", 0), Some(SyntheticKind::Lambda));
+        assert_eq!(synthetic_kind("Lcom/example/Main$WhenMappings;", 0), Some(SyntheticKind::KotlinGenerated));
+        assert_eq!(synthetic_kind("Lcom/example/Repo$load$1;", 0), Some(SyntheticKind::Anonymous));
+        assert_eq!(synthetic_kind("Lcom/example/Main$Access;", ACC_SYNTHETIC), Some(SyntheticKind::Synthetic));
+        assert_eq!(synthetic_kind("Lcom/example/Main$Companion;", 0), None);
+        assert_eq!(synthetic_kind("Lcom/example/Main2;", 0), None);
+    }
+
+    #[test]
+    fn test_kotlin_report() {
+        let mut builder = DexBuilder::new();
+        builder.type_idx(KOTLIN_METADATA);
+        builder.class(ClassDef::new("Lcom/example/Main;"));
+        builder.class(ClassDef::new("Lcom/example/-$$Lambda$Main$1;"));
+        let report = kotlin_report(&[DexReader::from_vec(builder.build()).unwrap()]);
+        assert_eq!(report, KotlinReport { detected: true, classes: 2, synthetic_classes: 1, synthetic_class_ratio: 0.5 });
+
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;"));
+        assert!(!kotlin_report(&[DexReader::from_vec(builder.build()).unwrap()]).detected);
+    }
+}
@@ -0,0 +1,97 @@
+//! Decoding and analysis of Android APKs and dex files.
+//!
+//! The pipeline of the `dexompiler` binary is available through [`analyze_apk`], [`analyze_apk_bytes`] and [`analyze_dex`],
+//! while [`decode_method`] and [`MethodCfg`] work on the code of a single method.
+//!
+//! Functions taking file paths need the `fs` feature, enabled by the default `cli` feature.
+//! Without default features the crate builds for `wasm32-unknown-unknown`, and the `wasm` feature
+//! exports `decodeDex` through wasm-bindgen.
+//!
+//! ```
+//! use dexompiler::{decode_method, Opcode};
+//!
+//! // const/4 v0, 0; return v0
+//! let decoded = dexompiler::decode_method(&[0x0012, 0x000F]);
+//! assert_eq!(decoded.instructions.len(), 2);
+//! assert_eq!(*decoded.instructions[1].opcode(), Opcode::Return);
+//! ```
+//!
+//! ```no_run
+//! use dexompiler::{analyze_apk, AnalysisOptions, Strictness};
+//!
+//! let report = analyze_apk("app.apk", &AnalysisOptions::default().strictness(Strictness::Lenient).build()).unwrap();
+//! println!("{}", serde_json::to_string(&report).unwrap());
+//! ```
+
+pub mod access_flags;
+pub mod analysis;
+pub mod api_sequence;
+pub mod call_graph;
+pub mod class_graph;
+pub mod debug_info;
+pub mod dex_parsing;
+pub mod duplicate_classes;
+pub mod error;
+pub mod extension;
+pub mod fields;
+pub mod intents;
+pub mod kotlin;
+pub mod manifest_parsing;
+pub mod network;
+pub mod obfuscation;
+pub mod opcode_map;
+pub mod options;
+pub mod packer;
+pub mod permissions;
+#[cfg(feature = "python")]
+mod python;
+pub mod reference;
+pub mod sections;
+pub mod signature;
+pub mod string_pool;
+pub mod verify;
+pub mod warning;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watchlist;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+#[cfg(feature = "fs")]
+pub use analysis::{analyze_apk, read_manifest, read_permissions};
+pub use analysis::{analyze_apk_bytes, analyze_dex, analyze_dexes, ApkContents, ApkReport, BigramCounts, DexClasses, DexReport, FormattedSequences, HeaderCounts, Sequences};
+pub use options::{AnalysisOptions, CapStrategy, ClassFilter, DecodeMode, DedupKey, DedupScope, InvalidStrings, Normalization, ReportField, Sampling, Strictness};
+pub use dex_parsing::{decode_method_by_index, normalize_registers, process_dex_with, scan_opcodes, unreachable_instructions, CodelessKind, CodelessMethod, Coverage, Instruction, InstructionIndex, MethodCfg, MethodDecode, MethodSequence, NamedDex, Opcode, OpcodeCategory, OpStats, Registers};
+#[cfg(feature = "parallel")]
+pub use dex_parsing::{process_dexes_parallel, DecodedMethod, DexCache, DexWalk, DEX_BUFFER};
+pub use error::{CfgError, Error};
+pub use extension::{MethodAnalysis, MethodContext};
+pub use manifest_parsing::Manifest;
+pub use signature::{Signatures, SigningScheme};
+pub use warning::{Warning, WarningKind};
+
+
+/// Decodes the code units of a method into instructions.
+/// Payloads are skipped and undecodable code units are reported in `MethodDecode::undecoded`,
+/// so decoding always reaches the end of the method
+pub fn decode_method(raw_bytecode: &[u16]) -> MethodDecode {
+    dex_parsing::decode_method_lenient(raw_bytecode)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_method_and_cfg() {
+        // if-eqz v0, +4; const/4 v0, 1; return v0; const/4 v0, 0; return v0
+        let raw = [0x0038, 0x0004, 0x1012, 0x000F, 0x0012, 0x000F];
+        let decoded = decode_method(&raw);
+        assert!(decoded.undecoded.is_empty());
+        assert_eq!(decoded.instructions.len(), 5);
+        let cfg = MethodCfg::build(&raw).unwrap();
+        assert_eq!(cfg.len(), 3);
+        assert_eq!(*cfg.entry().unwrap().borrow().instructions()[0].opcode(), Opcode::IfEqz);
+    }
+}
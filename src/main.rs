@@ -1,117 +1,630 @@
-mod dex_parsing;
-mod manifest_parsing;
 mod cli;
+mod budget;
+mod cache;
+mod download;
+mod inspect;
+mod isolate;
+mod merge;
+mod output;
+mod progress;
+mod stats;
 
 use clap::Parser;
-use manifest_parsing::parse_permissions;
-use dex_parsing::parse_dexes;
-use cli::Args;
+use dexompiler::{analysis::{analyze_contents, parse_apk, parse_apk_from, read_manifest_from, read_permissions_from}, analyze_apk, analyze_apk_bytes, read_manifest, read_permissions, verify::{decode_failures, DecodeFailure}, AnalysisOptions, ApkReport, Coverage, Error, Sequences};
+use cli::{is_url, Args, Command, Emit, Format, Scope};
+use budget::ByteBudget;
+use cache::Cache;
+use download::{DownloadError, Downloader};
+use isolate::{analyze_isolated, Isolated, Quarantine, WorkerError};
+use output::{records, write_csv, write_isolated_json, write_json, write_keyed_json, write_ndjson_summary, Meta, NdjsonWriter, Record, BATCH_BYTES};
+use progress::{eprintln_above, Mode, Progress};
+use stats::{move_density, CountingWriter, RunStats, Stage, Summary};
 
-use std::{fs::{OpenOptions, self}, sync::{Mutex, Arc}, collections::HashMap, io::Read, fmt, error::Error};
+use std::{borrow::Cow, fmt::Display, fs::{File, OpenOptions, self}, panic::{self, AssertUnwindSafe}, sync::{Mutex, MutexGuard, PoisonError, Arc, atomic::{AtomicUsize, Ordering}}, collections::HashMap, thread, time::{Duration, Instant}};
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
-use dex::{DexReader, Dex};
 use serde::{Serialize, Serializer};
-use indicatif::ParallelProgressIterator;
-use std::io::BufWriter;
-use std::path::Path;
-use zip::ZipArchive;
+use indicatif::{ParallelProgressIterator, ProgressBar, HumanBytes};
+use std::io::{self, BufReader, BufWriter, Cursor, Read};
+use xxhash_rust::xxh3::xxh3_128;
 
 
 pub struct MutexWrapper<T: ?Sized>(pub Mutex<T>);
 
+impl<T: ?Sized> MutexWrapper<T> {
+    /// Locks the data, even if a worker panicked while holding the lock: the results already in are still worth writing
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
 impl<T: ?Sized + Serialize> Serialize for MutexWrapper<T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        self.0
-            .lock()
-            .expect("mutex is poisoned")
-            .serialize(serializer)
+        self.lock().serialize(serializer)
     }
 }
 
 
-#[derive(Debug)]
-pub struct ParseApkError {
-    path: String
+/// Runs the analysis of one input, reporting a panic like an analysis error so the other inputs still make it to the output
+fn guarded<T>(path: &str, analyze: impl FnOnce() -> Result<T, Error>) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(analyze)) {
+        Ok(Ok(report)) => Some(report),
+        Ok(Err(err)) => {
+            eprintln_above!("Error parsing {}: {}", path, err);
+            None
+        },
+        Err(_) => {
+            eprintln_above!("Error parsing {}: the analysis panicked", path);
+            None
+        },
+    }
 }
 
-impl Error for ParseApkError {}
 
-impl fmt::Display for ParseApkError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Failed to parse apk at {}", self.path)
+/// Input path standing for an APK read from stdin
+const STDIN: &str = "-";
+
+
+/// APK read from stdin with `-i -`
+struct StdinApk {
+    /// Key of its record, the name given with `--stdin-name` or the hash of its bytes
+    key: String,
+    data: Vec<u8>,
+}
+
+
+impl StdinApk {
+    fn read(name: Option<&str>) -> io::Result<Self> {
+        let mut data = vec![];
+        io::stdin().lock().read_to_end(&mut data)?;
+        let key = name.map_or_else(|| format!("{:032x}", xxh3_128(&data)), str::to_string);
+        Ok(Self { key, data })
     }
 }
 
 
-fn parse_apk(path: &str) -> Result<(Vec<Dex<impl AsRef<[u8]>>>, Option<Vec<String>>), ParseApkError> {
-    let file = match fs::File::open(Path::new(path)) {
-        Ok(file) => file,
-        _ => return Err(ParseApkError { path: path.to_string() })
-    };
-    let mut zip_handler = match ZipArchive::new(file) {
-        Ok(zip_handler) => zip_handler,
-        _ => return Err(ParseApkError { path: path.to_string() })
+/// Key of the record of an input: the key of the stdin APK for `-`, the path otherwise
+fn record_key<'a>(path: &'a str, stdin: Option<&'a StdinApk>) -> &'a str {
+    match stdin {
+        Some(stdin) if path == STDIN => &stdin.key,
+        _ => path,
+    }
+}
+
+
+/// Bytes of an input that isn't a file: the APK read from stdin for `-`, the download of a URL. `None` for files
+fn input_bytes<'a>(path: &str, stdin: Option<&'a StdinApk>, downloader: &Downloader) -> Option<Result<Cow<'a, [u8]>, DownloadError>> {
+    match stdin {
+        Some(stdin) if path == STDIN => Some(Ok(Cow::Borrowed(&stdin.data))),
+        _ if is_url(path) => Some(downloader.fetch(path).map(Cow::Owned)),
+        _ => None,
+    }
+}
+
+
+/// Summary of the run once the serialization started at `started` reaches the footer
+fn serialized_summary(stats: &RunStats, started: Instant) -> Summary {
+    stats.elapsed(Stage::Serialization, started);
+    stats.summary()
+}
+
+
+/// Number of methods and of unique method bodies of deduplicated sequences, both 0 otherwise
+fn dedup_counts(sequences: &Sequences) -> (usize, usize) {
+    match sequences {
+        Sequences::Deduplicated { unique_sequences, methods, .. } => (methods.len(), unique_sequences.len()),
+        _ => (0, 0),
+    }
+}
+
+
+fn echo_warnings(key: &str, warnings: impl IntoIterator<Item = impl Display>) {
+    for warning in warnings {
+        eprintln_above!("Warning: {}: {}", key, warning);
+    }
+}
+
+
+/// Total size of the dexes of a report, as announced by their headers
+fn dex_bytes(report: &ApkReport) -> u64 {
+    report.header_counts.iter().map(|counts| counts.file_size as u64).sum()
+}
+
+
+/// Reports a fatal error and exits
+fn exit_with(context: &str, err: impl Display) -> ! {
+    eprintln_above!("Error {}: {}", context, err);
+    std::process::exit(1);
+}
+
+
+/// Options of the analysis, with the dexes cached in `--cache-dir` if given. The lookups are counted in `stats`
+fn analysis_options(args: &Args, stats: &Arc<RunStats>) -> AnalysisOptions {
+    let options = args.analysis_options().unwrap_or_else(|err| exit_with("reading the options files", err));
+    match &args.cache_dir {
+        Some(dir) => {
+            let cache = Cache::open(dir, &options, stats.clone()).unwrap_or_else(|err| exit_with(&format!("opening {}", dir), err));
+            options.dex_cache(cache)
+        },
+        None => options,
+    }
+}
+
+
+/// Analyzes the single input of a child process of `--isolate`, from stdin for `-`, and prints the outcome as JSON on stdout.
+/// Panics aren't caught, the crash is for the parent to see
+fn worker_single(args: &Args, path: &str) {
+    // Lets the tests crash a worker on purpose
+    #[cfg(debug_assertions)]
+    if std::env::var("DEXOMPILER_TEST_WORKER_PANIC").is_ok_and(|trigger| !trigger.is_empty() && path.contains(&trigger)) {
+        panic!("deliberate worker panic on {}", path);
+    }
+    let stats = Arc::new(RunStats::new());
+    let options = analysis_options(args, &stats);
+    let report = if path == STDIN {
+        let stdin = StdinApk::read(None).unwrap_or_else(|err| exit_with("reading stdin", err));
+        analyze_apk_bytes(&stdin.data, &options)
+    } else {
+        analyze_apk(path, &options)
     };
+    let meta = Meta::new(args.granularity, args.include_codeless).opcode_map(options.opcode_map_hash()).fields(&args.fields)
+        .row_records(args.format == Format::Ndjson).mnemonics(options.writes_mnemonics());
+    let summary = stats.summary();
+    let outcome = report.as_ref().map_err(ToString::to_string).and_then(|report| {
+        let records = records(None, report, &meta).map_err(|err| err.to_string())?;
+        Ok(Isolated { cache_hits: summary.cache_hits.unwrap_or(0), cache_misses: summary.cache_misses.unwrap_or(0), ..Isolated::new(report, records) })
+    });
+    if let Err(err) = serde_json::to_writer(io::stdout().lock(), &outcome) {
+        exit_with("writing the worker output", err);
+    }
+}
 
-    let mut dexes = vec![];
-    let mut permissions = None;
 
-    for i in 0..zip_handler.len() {
-        let (file_name, contents) = {
-            let mut current_file = match zip_handler.by_index(i) {
-                Ok(file) => file,
-                _ => continue
-            };
-            let mut contents = Vec::new();
-            if let Ok(_) = current_file.read_to_end(&mut contents) {
-                let is_xml = current_file.name().to_string();
-                (is_xml, contents)
-            } else {
-                continue;
-            }
+/// Reads an input with `from_bytes` or `from_file`, which only look at its manifest. Inputs that aren't APKs are skipped
+/// with a warning naming `flag`, like inputs that couldn't be read
+fn read_without_dexes<T>(
+    path: &str, stdin: Option<&StdinApk>, downloader: &Downloader, flag: &str,
+    from_bytes: impl FnOnce(Cursor<&[u8]>) -> Result<Option<T>, Error>,
+    from_file: impl FnOnce(&str) -> Result<Option<T>, Error>,
+) -> Option<Option<T>> {
+    let key = record_key(path, stdin);
+    let data = match input_bytes(path, stdin, downloader).transpose() {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln_above!("Error downloading {}: {}", key, err);
+            return None;
+        },
+    };
+    let read = guarded(key, || {
+        let read = match &data {
+            Some(data) => from_bytes(Cursor::new(data)),
+            None => from_file(path),
         };
+        match read {
+            Err(Error::Zip(_)) => {
+                eprintln_above!("Warning: {} is not an APK, skipped with {}", key, flag);
+                Ok(None)
+            },
+            read => read.map(Some),
+        }
+    });
+    read.flatten()
+}
 
-        if file_name == "AndroidManifest.xml" {
-            permissions = parse_permissions(contents);
-        } else if contents.starts_with(&[100, 101, 120, 10]) {
-            if let Ok(dex) = DexReader::from_vec(contents) {
-                dexes.push(dex);
-            }
+
+/// Writes what `read` gets out of every input, without analyzing any. `read` gives the record key along with the value,
+/// inputs it fails on are left out. In ndjson mode, the `meta` line is followed by the `records` of every input.
+/// In json mode, `write_json` gets the values keyed by record key
+fn emit_per_input<'a, T: Send>(
+    args: &Args, inputs: impl ParallelIterator<Item = &'a String>, meta: Option<&Meta>, writer: CountingWriter<BufWriter<File>>,
+    read: impl Fn(&'a String) -> Option<(&'a str, T)> + Sync,
+    records: impl for<'r> Fn(&'r str, &'r T) -> Vec<Record<'r>> + Sync,
+    write_json: impl FnOnce(CountingWriter<BufWriter<File>>, HashMap<&'a str, T>) -> serde_json::Result<()>,
+) -> io::Result<()> {
+    if args.format == Format::Ndjson {
+        let writer = NdjsonWriter::new(writer, args.threads * 2);
+        if let Some(meta) = meta {
+            writer.batcher(1, BATCH_BYTES).push(&HashMap::from([("meta", meta)]))?;
         }
+        inputs.for_each_init(
+            || writer.batcher(args.batch_records, BATCH_BYTES),
+            |batcher, path| if let Some((key, value)) = read(path) {
+                for record in records(key, &value) {
+                    if let Err(err) = batcher.push(&record) {
+                        eprintln_above!("Error serializing {}: {}", key, err);
+                    }
+                }
+            }
+        );
+        writer.finish()?;
+    } else {
+        write_json(writer, inputs.filter_map(&read).collect())?;
     }
+    Ok(())
+}
+
+
+/// Writes the manifest of every input for `--emit manifest`, without reading any dex
+fn emit_manifests(args: &Args, inputs: &[String], stdin: Option<&StdinApk>, downloader: &Downloader, writer: CountingWriter<BufWriter<File>>, progress: ProgressBar) -> io::Result<()> {
+    let meta = Meta::new(args.granularity, args.include_codeless).manifest_only(true);
+    emit_per_input(args, inputs.par_iter().progress_with(progress), Some(&meta), writer,
+        |path| Some((record_key(path, stdin), read_without_dexes(path, stdin, downloader, "--emit manifest", |data| read_manifest_from(data), |path| read_manifest(path))?)),
+        |key, manifest| vec![Record::Manifest { path: Some(key), manifest: manifest.as_ref() }],
+        |writer, manifests| write_keyed_json(writer, Some(&meta), manifests.iter().map(|(path, manifest)| (path, Record::Manifest { path: None, manifest: manifest.as_ref() }))),
+    )
+}
+
 
-    Ok((dexes, permissions))
+/// Writes the requested permissions of every input for `--only-permissions`, without reading any dex.
+/// The json output is a bare map of paths to permissions, an APK without a manifest requests none
+fn emit_permissions(args: &Args, inputs: &[String], stdin: Option<&StdinApk>, downloader: &Downloader, writer: CountingWriter<BufWriter<File>>, progress: ProgressBar) -> io::Result<()> {
+    emit_per_input(args, inputs.par_iter().progress_with(progress), None, writer,
+        |path| Some((record_key(path, stdin), read_without_dexes(path, stdin, downloader, "--only-permissions", |data| read_permissions_from(data), |path| read_permissions(path))?.unwrap_or_default())),
+        |key, permissions| vec![Record::Permissions { path: Some(key), permissions }],
+        |writer, permissions| write_keyed_json(writer, None, permissions),
+    )
+}
+
+
+/// Outcome of `--verify-only`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct VerifyCounts {
+    /// Code units that failed to decode
+    failures: usize,
+    /// Inputs that couldn't be read or parsed, whose methods weren't checked
+    unreadable: usize,
+}
+
+
+/// Decodes every method of every input for `--verify-only` and writes the code units that can't be decoded, without
+/// building any sequence. The json output is the meta header and a map of paths to failures
+fn emit_decode_failures<'a>(args: &Args, inputs: &'a [String], stdin: Option<&'a StdinApk>, downloader: &Downloader, options: &AnalysisOptions, writer: CountingWriter<BufWriter<File>>, progress: ProgressBar) -> io::Result<VerifyCounts> {
+    let meta = Meta::new(args.granularity, args.include_codeless);
+    let (failures, unreadable) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let check = |path: &'a String| -> Option<(&'a str, Vec<DecodeFailure>)> {
+        let key = record_key(path, stdin);
+        let data = match input_bytes(path, stdin, downloader).transpose() {
+            Ok(data) => data,
+            Err(err) => {
+                eprintln_above!("Error downloading {}: {}", key, err);
+                unreadable.fetch_add(1, Ordering::Relaxed);
+                return None;
+            },
+        };
+        let Some(input_failures) = guarded(key, || {
+            let contents = match &data {
+                Some(data) => parse_apk_from(Cursor::new(&**data))?,
+                None => parse_apk(path)?,
+            };
+            if args.echo_warnings {
+                echo_warnings(key, &contents.warnings);
+            }
+            Ok(contents.dexes.iter().enumerate().flat_map(|(index, dex)| decode_failures(index, &dex.dex, options)).collect::<Vec<_>>())
+        }) else {
+            unreadable.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        failures.fetch_add(input_failures.len(), Ordering::Relaxed);
+        Some((key, input_failures))
+    };
+    emit_per_input(args, inputs.par_iter().progress_with(progress), Some(&meta), writer, check,
+        |key, failures| failures.iter().map(|failure| Record::DecodeFailure { path: Some(key), failure }).collect(),
+        |writer, failures| write_keyed_json(writer, Some(&meta), failures),
+    )?;
+    Ok(VerifyCounts { failures: failures.into_inner(), unreadable: unreadable.into_inner() })
 }
 
 
 fn main() {
     let args: Args = Args::parse();
+    if let Some(Command::Inspect(inspect_args)) = &args.command {
+        if let Err(err) = inspect::inspect(inspect_args, &mut io::stdout().lock()) {
+            eprintln_above!("Error inspecting {}: {}", inspect_args.input, err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Merge(merge_args)) = &args.command {
+        let file = File::create(&merge_args.output).unwrap_or_else(|err| exit_with(&format!("opening {}", merge_args.output), err));
+        let open = |path: &str| File::open(path).map(BufReader::new);
+        match merge::merge(&merge_args.inputs, open, merge_args.format, merge_args.on_conflict, BufWriter::new(file)) {
+            Ok(merged) => println!("Merged {} inputs from {} outputs, {} duplicates left out", merged.inputs, merge_args.inputs.len(), merged.duplicates),
+            Err(err) => exit_with("merging", err),
+        }
+        return;
+    }
+    if let Some(path) = &args.worker_single {
+        worker_single(&args, path);
+        return;
+    }
+    let output = args.output.as_deref().expect("the output is required without a subcommand");
+    let inputs = args.resolve_inputs().unwrap_or_else(|err| exit_with("reading the input list", err));
+    if inputs.is_empty() {
+        eprintln_above!("Error: no input files matched");
+        std::process::exit(1);
+    }
+    if let Some(conflict) = args.csv_conflict() {
+        eprintln_above!("Error: --format csv can't be used with {}", conflict);
+        std::process::exit(1);
+    }
+    if args.isolate && args.dedup_methods && args.dedup_scope == Scope::Run {
+        eprintln_above!("Error: --dedup-scope run can't be used with --isolate, whose child processes don't share a table");
+        std::process::exit(1);
+    }
+    if inputs.iter().filter(|path| *path == STDIN).count() > 1 {
+        eprintln_above!("Error: stdin can only be given once as an input");
+        std::process::exit(1);
+    }
+    let stdin = inputs.iter().any(|path| path == STDIN)
+        .then(|| StdinApk::read(args.stdin_name.as_deref()).unwrap_or_else(|err| exit_with("reading stdin", err)));
+    let stdin = stdin.as_ref();
+
+    println!("Parsing {} files up to {} opcodes, using {} threads", inputs.len(), args.sequence_cap, args.threads);
 
-    println!("Parsing {} files up to {} opcodes, using {} threads", args.input.len(), args.sequence_cap, args.threads);
+    let stats = Arc::new(RunStats::new());
+    let options = analysis_options(&args, &stats);
 
-    rayon::ThreadPoolBuilder::new().num_threads(args.threads).build_global().unwrap();
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .unwrap_or_else(|err| exit_with("starting the worker threads", err));
     let accumulator = Arc::new(MutexWrapper(Mutex::new(HashMap::new())));
-    args.input.par_iter().progress_count(args.input.len() as u64).for_each(|path| {
-        if let Ok((dexes, permissions)) = parse_apk(path) {
-            let (op_seq, method_bounds) = parse_dexes(dexes, args.sequence_cap);
-            let mut accumulator = accumulator.0.lock().unwrap();
-            accumulator.insert(path, (op_seq, method_bounds, permissions));
-        } else {
-            eprintln!("Error parsing: {}", path);
+    let coverage = Mutex::new(Coverage::default());
+    let budget = ByteBudget::new(args.memory_budget);
+    let downloader = Downloader::new(args.max_download_size, args.download_concurrency);
+    let progress = Progress::new(inputs.len() as u64, stats.clone(), Mode::detect(args.no_progress), io::stderr());
+    if let Some(interval) = args.progress_stats {
+        let stats = stats.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(interval.max(1)));
+            eprintln_above!("{}", stats.summary());
+        });
+    }
+    let tally = |input_coverage: Coverage, methods: usize, unique: usize| {
+        *coverage.lock().unwrap_or_else(PoisonError::into_inner) += input_coverage;
+        stats.deduplicated(methods as u64, unique as u64);
+    };
+    // Bytes of an input that isn't a file and a share of the memory budget for the whole input
+    let load = |path: &String| {
+        let key = record_key(path, stdin);
+        progress.start(key);
+        let data = match input_bytes(path, stdin, &downloader).transpose() {
+            Ok(data) => data,
+            Err(err) => {
+                eprintln_above!("Error downloading {}: {}", key, err);
+                progress.failed();
+                return None;
+            },
+        };
+        let size = match &data {
+            Some(data) => data.len() as u64,
+            None => fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0),
+        };
+        let permit = budget.acquire(size);
+        progress.set_message(format!("{} in flight", HumanBytes(budget.in_flight())));
+        Some((data, permit))
+    };
+    let process = |path: &String| -> Option<ApkReport> {
+        let key = record_key(path, stdin);
+        let (data, _permit) = load(path)?;
+        let Some(report) = guarded(key, || {
+            let contents = stats.time(Stage::ZipRead, || match &data {
+                Some(data) => parse_apk_from(Cursor::new(&**data)),
+                None => parse_apk(path),
+            })?;
+            stats.time(Stage::Decode, || analyze_contents(contents, &options))
+        }) else {
+            progress.failed();
+            return None;
+        };
+        if args.echo_warnings {
+            echo_warnings(key, &report.warnings);
         }
+        let (methods, unique) = dedup_counts(&report.sequences);
+        tally(report.coverage, methods, unique);
+        stats.moves(&move_density(&report.sequences));
+        stats.processed(dex_bytes(&report), report.sequences.opcode_count() as u64);
+        Some(report)
+    };
+    let quarantine = args.isolate.then(|| {
+        let path = args.quarantine.clone().unwrap_or_else(|| format!("{}.quarantine", output));
+        Quarantine::open(&path).unwrap_or_else(|err| exit_with(&format!("opening {}", path), err))
     });
-
-    println!("Writing to file");
+    // Runs an input in child processes until one doesn't crash, quarantining it after the last retry
+    let run_isolated = |path: &String, data: Option<&[u8]>| -> Option<Isolated<serde_json::Value>> {
+        let key = record_key(path, stdin);
+        let mut attempts = 0;
+        loop {
+            match analyze_isolated(path, data) {
+                Ok(isolated) => return Some(isolated),
+                Err(WorkerError::Analysis(err)) => {
+                    eprintln_above!("Error parsing {}: {}", key, err);
+                    progress.failed();
+                    return None;
+                },
+                Err(err) if attempts < args.isolate_retries => {
+                    eprintln_above!("Error parsing {}: {}, retrying", key, err);
+                    attempts += 1;
+                },
+                Err(err) => {
+                    eprintln_above!("Error parsing {}: {}, quarantined", key, err);
+                    if let Some(Err(err)) = quarantine.as_ref().map(|quarantine| quarantine.add(key)) {
+                        eprintln_above!("Error quarantining {}: {}", key, err);
+                    }
+                    stats.skipped();
+                    return None;
+                },
+            }
+        }
+    };
 
     let file = OpenOptions::new()
         .write(true)
         .create(true)
-        .open(args.output)
-        .unwrap();
-    let buffered_file = BufWriter::new(file);
+        .truncate(true)
+        .open(output)
+        .unwrap_or_else(|err| exit_with(&format!("opening {}", output), err));
+    let buffered_file = stats.counting(BufWriter::new(file));
+
+    if args.only_permissions {
+        let written = emit_permissions(&args, &inputs, stdin, &downloader, buffered_file, progress.bar());
+        progress.finish();
+        if let Err(err) = written {
+            exit_with(&format!("writing {}", output), err);
+        }
+        return;
+    }
+    if args.emit.contains(&Emit::Manifest) {
+        let written = emit_manifests(&args, &inputs, stdin, &downloader, buffered_file, progress.bar());
+        progress.finish();
+        if let Err(err) = written {
+            exit_with(&format!("writing {}", output), err);
+        }
+        return;
+    }
+    if args.verify_only {
+        let failures = emit_decode_failures(&args, &inputs, stdin, &downloader, &options, buffered_file, progress.bar());
+        progress.finish();
+        match failures {
+            Ok(VerifyCounts { failures: 0, unreadable: 0 }) => println!("Every method decoded"),
+            Ok(VerifyCounts { failures, unreadable }) => {
+                if failures > 0 {
+                    eprintln_above!("{} code units failed to decode", failures);
+                }
+                if unreadable > 0 {
+                    eprintln_above!("{} inputs couldn't be read, their methods weren't checked", unreadable);
+                }
+                std::process::exit(1);
+            },
+            Err(err) => exit_with(&format!("writing {}", output), err),
+        }
+        return;
+    }
+
+    let meta = Meta::new(args.granularity, args.include_codeless).opcode_map(options.opcode_map_hash()).fields(&args.fields)
+        .row_records(args.format == Format::Ndjson).mnemonics(options.writes_mnemonics());
+    // Records of an input as plain JSON, from a child process
+    let process_isolated = |path: &String| -> Option<Vec<serde_json::Value>> {
+        let key = record_key(path, stdin);
+        let (data, _permit) = load(path)?;
+        let isolated = run_isolated(path, data.as_deref())?;
+        if args.echo_warnings {
+            echo_warnings(key, &isolated.warnings);
+        }
+        tally(isolated.coverage, isolated.methods, isolated.unique_methods);
+        stats.cache_lookups(isolated.cache_hits, isolated.cache_misses);
+        stats.moves(&isolated.move_density);
+        stats.processed(isolated.dex_bytes, isolated.instructions);
+        Some(isolated.records)
+    };
+    if args.isolate && args.format == Format::Ndjson {
+        let writer = NdjsonWriter::new(buffered_file, args.threads * 2);
+        writer.batcher(1, BATCH_BYTES).push(&HashMap::from([("meta", &meta)]))
+            .unwrap_or_else(|err| exit_with("serializing the meta header", err));
+        inputs.par_iter().progress_with(progress.bar()).for_each_init(
+            || writer.batcher(args.batch_records, BATCH_BYTES),
+            |batcher, path| if let Some(records) = process_isolated(path) {
+                let key = record_key(path, stdin);
+                stats.time(Stage::Serialization, || for record in &records {
+                    if let Err(err) = batcher.push(&Record::Isolated { path: Some(key), record }) {
+                        eprintln_above!("Error serializing {}: {}", key, err);
+                    }
+                });
+            }
+        );
+        progress.finish();
+        if let Err(err) = writer.finish().and_then(|writer| write_ndjson_summary(writer, &stats.summary())) {
+            exit_with(&format!("writing {}", output), err);
+        }
+    } else if args.isolate {
+        let records: HashMap<&str, _> = inputs.par_iter().progress_with(progress.bar())
+            .filter_map(|path| Some((record_key(path, stdin), process_isolated(path)?)))
+            .collect();
+        progress.finish();
+        println!("Writing to file");
+        // The summary footer is written last, with the serialization time of the records
+        let started = Instant::now();
+        if let Err(err) = write_isolated_json(buffered_file, &meta, &records, || serialized_summary(&stats, started)) {
+            exit_with(&format!("writing {}", output), err);
+        }
+    } else if args.format == Format::Ndjson {
+        let writer = NdjsonWriter::new(buffered_file, args.threads * 2);
+        writer.batcher(1, BATCH_BYTES).push(&HashMap::from([("meta", &meta)]))
+            .unwrap_or_else(|err| exit_with("serializing the meta header", err));
+        inputs.par_iter().progress_with(progress.bar()).for_each_init(
+            || writer.batcher(args.batch_records, BATCH_BYTES),
+            |batcher, path| if let Some(report) = process(path) {
+                let key = record_key(path, stdin);
+                stats.time(Stage::Serialization, || match records(Some(key), &report, &meta) {
+                    Ok(records) => for record in records {
+                        if let Err(err) = batcher.push(&record) {
+                            eprintln_above!("Error serializing {}: {}", key, err);
+                        }
+                    },
+                    Err(err) => eprintln_above!("Error serializing {}: {}", key, err),
+                });
+            }
+        );
+        progress.finish();
+        if let Err(err) = writer.finish().and_then(|writer| write_ndjson_summary(writer, &stats.summary())) {
+            exit_with(&format!("writing {}", output), err);
+        }
+    } else {
+        inputs.par_iter().progress_with(progress.bar()).for_each(|path| {
+            if let Some(report) = process(path) {
+                accumulator.lock().insert(record_key(path, stdin), report);
+            }
+        });
+        progress.finish();
+        println!("Writing to file");
+        let started = Instant::now();
+        let written = match args.format {
+            Format::Csv => stats.time(Stage::Serialization, || write_csv(buffered_file, &meta, &accumulator.lock())),
+            _ => write_json(buffered_file, &meta, &accumulator.lock(), || serialized_summary(&stats, started)).map_err(io::Error::from),
+        };
+        if let Err(err) = written {
+            exit_with(&format!("writing {}", output), err);
+        }
+    }
+
+    let coverage = coverage.into_inner().unwrap_or_else(PoisonError::into_inner);
+    println!("Decoded {:.2}% of {} methods and {:.2}% of {} code units, {} methods partially decoded and {} skipped",
+        coverage.methods_pct(), coverage.methods, coverage.code_units_pct(), coverage.code_units, coverage.partial_methods, coverage.skipped_methods);
+    println!("{}", stats.summary());
+}
+
 
-    serde_json::to_writer(buffered_file, &accumulator).unwrap();
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_panicking_worker_keeps_other_results() {
+        let accumulator = MutexWrapper(Mutex::new(HashMap::new()));
+        let paths = (0..8).map(|i| i.to_string()).collect::<Vec<_>>();
+        paths.par_iter().for_each(|path| {
+            let report = guarded(path, || if path == "3" {
+                panic!("analysis panicked");
+            } else {
+                Ok::<_, Error>(path.len())
+            });
+            // A panic while holding the lock poisons it for every other worker
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut accumulator = accumulator.lock();
+                if path == "5" {
+                    panic!("worker panicked while holding the lock");
+                }
+                if let Some(report) = report {
+                    accumulator.insert(path.clone(), report);
+                }
+            }));
+        });
+        assert!(accumulator.0.is_poisoned());
+        let written = serde_json::to_value(&accumulator).unwrap();
+        let mut written = written.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        written.sort();
+        assert_eq!(written, ["0", "1", "2", "4", "6", "7"]);
+    }
 }
@@ -1,13 +1,124 @@
-use axmldecoder::{Node, XmlDocument};
-
-pub(crate) fn parse_permissions(contents: Vec<u8>) -> Option<Vec<String>> {
-    let xml = match axmldecoder::parse(&contents) {
-        Ok(xml) => xml,
-        _ => return None
-    };
-    let XmlDocument { root } = xml;
+use axmldecoder::{Element, Node, XmlDocument};
+use serde::Serialize;
+
+use crate::error::Error;
+
+
+/// Information extracted from a binary `AndroidManifest.xml`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+    /// Requested permissions, without the `android.permission.` prefix
+    pub permissions: Vec<String>,
+    /// `android:name` of the `<application>` element, the app's `Application` subclass when it has one, e.g. `com.example.App`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application: Option<String>,
+    /// Activity started from the launcher, the first with a `MAIN` and `LAUNCHER` intent filter, e.g. `com.example.MainActivity`.
+    /// For an `<activity-alias>`, its target activity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub launcher_activity: Option<String>,
+    /// Activities, services and broadcast receivers of the application, in document order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<Component>,
+}
+
+
+/// Component of the application that intents can start
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Component {
+    pub kind: ComponentKind,
+    /// Fully qualified class name, e.g. `com.example.SettingsActivity`. For an `<activity-alias>`, its own name
+    pub name: String,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentKind {
+    /// An `<activity>` or `<activity-alias>`
+    Activity,
+    Service,
+    Receiver,
+}
+
+
+impl Manifest {
+    /// Decodes a binary manifest
+    pub fn parse(contents: Vec<u8>) -> Result<Self, Error> {
+        let XmlDocument { root } = axmldecoder::parse(&contents)?;
+        let Some(Node::Element(root)) = root else { return Ok(Self::default()) };
+        Ok(Self::from_root(root))
+    }
+
+    fn from_root(root: Element) -> Self {
+        let package = root.attributes.get("package").cloned().unwrap_or_default();
+        let application_element = children(&root, &["application"]).next();
+        let application = application_element
+            .and_then(|element| element.attributes.get("android:name"))
+            .map(|name| class_name(&package, name));
+        let launcher_activity = application_element.and_then(|element| launcher_activity(element, &package));
+        let components = application_element.map(|element| components(element, &package)).unwrap_or_default();
+        Self { permissions: permissions(root), application, launcher_activity, components }
+    }
+}
+
+
+/// Child elements of `element` with one of the `tags`, in document order
+fn children<'a>(element: &'a Element, tags: &'a [&str]) -> impl Iterator<Item = &'a Element> {
+    element.children.iter().filter_map(move |node| match node {
+        Node::Element(child) if tags.contains(&child.get_tag()) => Some(child),
+        _ => None,
+    })
+}
+
+
+fn launcher_activity(application: &Element, package: &str) -> Option<String> {
+    let declares = |filter: &Element, tag: &str, name: &str| children(filter, &[tag]).any(|child| child.attributes.get("android:name").is_some_and(|value| value == name));
+    let activity = children(application, &["activity", "activity-alias"]).find(|activity| children(activity, &["intent-filter"])
+        .any(|filter| declares(filter, "action", "android.intent.action.MAIN") && declares(filter, "category", "android.intent.category.LAUNCHER")))?;
+    let attribute = if activity.get_tag() == "activity-alias" { "android:targetActivity" } else { "android:name" };
+    activity.attributes.get(attribute).map(|name| class_name(package, name))
+}
+
+
+fn components(application: &Element, package: &str) -> Vec<Component> {
+    children(application, &["activity", "activity-alias", "service", "receiver"])
+        .filter_map(|element| {
+            let kind = match element.get_tag() {
+                "service" => ComponentKind::Service,
+                "receiver" => ComponentKind::Receiver,
+                _ => ComponentKind::Activity,
+            };
+            element.attributes.get("android:name").map(|name| Component { kind, name: class_name(package, name) })
+        })
+        .collect()
+}
+
+
+/// Fully qualified name of a component of the app `package`, whose manifest may name it relative to the package:
+/// `.Main` or `Main` for `com.example.Main`
+fn class_name(package: &str, name: &str) -> String {
+    if name.starts_with('.') {
+        format!("{}{}", package, name)
+    } else if !name.contains('.') && !package.is_empty() {
+        format!("{}.{}", package, name)
+    } else {
+        name.to_string()
+    }
+}
+
+
+/// Requested permissions of a binary manifest, without the `android.permission.` prefix, none for a document without a root element
+pub fn parse_permissions(contents: Vec<u8>) -> Result<Vec<String>, Error> {
+    let XmlDocument { root } = axmldecoder::parse(&contents)?;
     if let Some(Node::Element(root)) = root {
-        return Some(root.children.into_iter()
+        return Ok(permissions(root))
+    }
+    Ok(vec![])
+}
+
+
+fn permissions(root: Element) -> Vec<String> {
+    root.children.into_iter()
         .filter_map(|node| match node {
             Node::Element(mut element) if element.get_tag() == "uses-permission" => {
                 element.attributes.remove("android:name")
@@ -18,7 +129,89 @@ pub(crate) fn parse_permissions(contents: Vec<u8>) -> Option<Vec<String>> {
             Some(s) => Some(s.to_string()),
             None => None
         })
-        .collect())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn element(tag: &str, attributes: &[(&str, &str)], children: Vec<Node>) -> Node {
+        Node::Element(Element {
+            attributes: attributes.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            tag: tag.to_string(),
+            children,
+        })
+    }
+
+    fn intent_filter(action: &str, category: &str) -> Node {
+        element("intent-filter", &[], vec![
+            element("action", &[("android:name", action)], vec![]),
+            element("category", &[("android:name", category)], vec![]),
+        ])
+    }
+
+    fn manifest(application: Node) -> Manifest {
+        let root = element("manifest", &[("package", "com.example")], vec![
+            element("uses-permission", &[("android:name", "android.permission.INTERNET")], vec![]),
+            application,
+        ]);
+        let Node::Element(root) = root else { unreachable!() };
+        Manifest::from_root(root)
+    }
+
+    #[test]
+    fn test_entry_points() {
+        let parsed = manifest(element("application", &[("android:name", ".App")], vec![
+            element("activity", &[("android:name", "com.example.SettingsActivity")], vec![
+                intent_filter("android.intent.action.VIEW", "android.intent.category.DEFAULT"),
+            ]),
+            element("activity", &[("android:name", ".ui.MainActivity")], vec![
+                intent_filter("android.intent.action.MAIN", "android.intent.category.LAUNCHER"),
+            ]),
+        ]));
+        assert_eq!(parsed, Manifest {
+            permissions: vec!["INTERNET".to_string()],
+            application: Some("com.example.App".to_string()),
+            launcher_activity: Some("com.example.ui.MainActivity".to_string()),
+            components: vec![
+                Component { kind: ComponentKind::Activity, name: "com.example.SettingsActivity".to_string() },
+                Component { kind: ComponentKind::Activity, name: "com.example.ui.MainActivity".to_string() },
+            ],
+        });
+
+        // An alias launches its target, and an app without its own Application subclass has none
+        let parsed = manifest(element("application", &[], vec![
+            element("activity", &[("android:name", "Main")], vec![]),
+            element("activity-alias", &[("android:name", ".Launcher"), ("android:targetActivity", "Main")], vec![
+                intent_filter("android.intent.action.MAIN", "android.intent.category.LAUNCHER"),
+            ]),
+        ]));
+        assert_eq!((parsed.application, parsed.launcher_activity.as_deref()), (None, Some("com.example.Main")));
+        assert_eq!(parsed.components.iter().map(|component| component.name.as_str()).collect::<Vec<_>>(), ["com.example.Main", "com.example.Launcher"]);
+
+        let parsed = manifest(element("application", &[], vec![
+            element("service", &[("android:name", ".SyncService")], vec![]),
+            element("receiver", &[("android:name", "com.example.BootReceiver")], vec![]),
+            element("provider", &[("android:name", ".Provider")], vec![]),
+        ]));
+        assert_eq!(parsed.components, [
+            Component { kind: ComponentKind::Service, name: "com.example.SyncService".to_string() },
+            Component { kind: ComponentKind::Receiver, name: "com.example.BootReceiver".to_string() },
+        ]);
+
+        let parsed = manifest(element("application", &[("android:name", "org.other.StubApp")], vec![]));
+        assert_eq!((parsed.application.as_deref(), parsed.launcher_activity), (Some("org.other.StubApp"), None));
+    }
+
+    #[test]
+    fn test_application_resolved_against_package() {
+        // The fully qualified name is the one the packer rules match, e.g. `com.stub.StubApp` for Jiagu
+        for name in [".StubApp", "StubApp", "com.stub.StubApp"] {
+            let root = element("manifest", &[("package", "com.stub")], vec![element("application", &[("android:name", name)], vec![])]);
+            let Node::Element(root) = root else { unreachable!() };
+            assert_eq!(Manifest::from_root(root).application.as_deref(), Some("com.stub.StubApp"), "{}", name);
+        }
     }
-    None
 }
@@ -0,0 +1,570 @@
+use std::{collections::{HashMap, HashSet}, fmt, io::{self, BufRead, Write}};
+
+use serde::{de::{DeserializeSeed, Error as _, MapAccess, Visitor}, Deserializer};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::{cli::{Conflict, Format}, output::{write_ndjson_summary, Record}, stats::{dedup_ratio, RunStats, Summary}};
+
+
+/// Why outputs can't be merged
+#[derive(Debug, Error)]
+pub enum MergeError {
+    #[error("{0} has no meta header, only the outputs of analyses can be merged")]
+    MissingMeta(String),
+    #[error("{path} has {field} {found} in its meta header where {first} has {expected}")]
+    /// Values of the meta headers as JSON
+    MetaMismatch { path: String, field: String, found: String, first: String, expected: String },
+    #[error("{key} is in both {first} and {second}")]
+    Conflict { key: String, first: String, second: String },
+    #[error("{0}")]
+    Format(String),
+    #[error("{path}: {source}")]
+    Json { path: String, source: serde_json::Error },
+    #[error("{0}")]
+    Io(#[from] io::Error),
+}
+
+
+/// Inputs and records kept by a merge
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Merged {
+    pub inputs: usize,
+    /// Inputs left out for being in an output merged before or after, as set by the conflict policy
+    pub duplicates: usize,
+}
+
+
+/// Format of an output, told by its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Json,
+    Ndjson,
+    Csv,
+}
+
+
+/// Part of an output, walked in order by `read_output`
+enum Item {
+    /// Meta header, the header row for CSV
+    Meta(Value),
+    Summary(Summary),
+    /// Records of an input, without their path: the entry of a json output or one line of an ndjson output
+    Records { key: String, records: Vec<Value> },
+    /// Row of a CSV output, without its line break, and the SHA-256 of its input when the output has the column
+    Row { key: String, version: String, sha256: Option<String>, line: String },
+}
+
+
+impl Item {
+    /// What tells the inputs of the outputs apart: the SHA-256 of their contents, recorded by every record and CSV row,
+    /// or the key for older outputs without it. The same contents under two paths are one input. `None` for the header
+    /// and the footer
+    fn identity(&self) -> Option<String> {
+        let (key, sha256) = match self {
+            Item::Records { key, records } => (key, records.first().and_then(|record| record.get("sha256")).and_then(Value::as_str)),
+            Item::Row { key, sha256, .. } => (key, sha256.as_deref()),
+            Item::Meta(_) | Item::Summary(_) => return None,
+        };
+        Some(match sha256 {
+            Some(sha256) => format!("sha256:{}", sha256),
+            None => format!("key:{}", key),
+        })
+    }
+}
+
+
+/// Merges `inputs`, read twice through `open`, into one output in `format`. The first pass checks the meta headers and
+/// picks the output and the key every input is kept from, inputs being told apart by `identity`, the second writes their
+/// records. Records are streamed one at a time, only the keys of the inputs are held. The summary footer counts the kept inputs, their dexes and instructions, and sums
+/// the failed and skipped inputs and the wall time of the merged outputs
+pub fn merge<R: BufRead>(inputs: &[String], open: impl Fn(&str) -> io::Result<R>, format: Format, conflict: Conflict, writer: impl Write) -> Result<Merged, MergeError> {
+    let mut reference: Option<(usize, Value)> = None;
+    let mut csv_version: Option<(usize, String)> = None;
+    // Output and key every input is kept from, by identity
+    let mut owners: HashMap<String, (usize, String)> = HashMap::new();
+    let mut summaries = vec![];
+    let mut formats = vec![];
+    let mut listed = 0;
+    for (index, path) in inputs.iter().enumerate() {
+        let mut seen = HashSet::new();
+        let mut claim = |identity: String, key: String| -> Result<(), MergeError> {
+            if !seen.insert((identity.clone(), key.clone())) {
+                return Ok(());
+            }
+            listed += 1;
+            match (owners.get(&identity), conflict) {
+                (Some((owner, _)), Conflict::Error) => Err(MergeError::Conflict { key, first: inputs[*owner].clone(), second: path.clone() }),
+                (Some(_), Conflict::First) => Ok(()),
+                _ => {
+                    owners.insert(identity, (index, key));
+                    Ok(())
+                },
+            }
+        };
+        let read = read_output(path, open(path)?, &mut |item| match (item.identity(), item) {
+            (_, Item::Meta(meta)) => match &reference {
+                None => {
+                    reference = Some((index, meta));
+                    Ok(())
+                },
+                Some((first, expected)) => match meta_mismatch(expected, &meta) {
+                    Some(field) => Err(MergeError::MetaMismatch {
+                        path: path.clone(),
+                        found: meta.get(&field).unwrap_or(&Value::Null).to_string(),
+                        first: inputs[*first].clone(),
+                        expected: expected.get(&field).unwrap_or(&Value::Null).to_string(),
+                        field,
+                    }),
+                    None => Ok(()),
+                },
+            },
+            (_, Item::Summary(summary)) => {
+                summaries.push(summary);
+                Ok(())
+            },
+            (Some(identity), Item::Records { key, .. }) => claim(identity, key),
+            (Some(identity), Item::Row { key, version, .. }) => match csv_version.get_or_insert_with(|| (index, version.clone())) {
+                (first, expected) if *expected != version => Err(MergeError::MetaMismatch {
+                    path: path.clone(),
+                    field: "version".to_string(),
+                    found: version,
+                    first: inputs[*first].clone(),
+                    expected: expected.clone(),
+                }),
+                _ => claim(identity, key),
+            },
+            (None, _) => Ok(()),
+        })?;
+        formats.push(read);
+    }
+    let Some((_, meta)) = reference else { return Ok(Merged::default()) };
+    if formats.iter().any(|&read| (read == OutputFormat::Csv) != (format == Format::Csv)) {
+        return Err(MergeError::Format("CSV outputs can only be merged with each other and into CSV".to_string()));
+    }
+    let grouped = meta.get("granularity").and_then(Value::as_str).is_some_and(|granularity| granularity != "apk");
+    if format == Format::Json && grouped && formats.contains(&OutputFormat::Ndjson) {
+        return Err(MergeError::Format("the class and method records of ndjson outputs can only be merged into ndjson".to_string()));
+    }
+
+    let stats = RunStats::new();
+    let mut writer = stats.counting(writer);
+    let (mut dex_bytes, mut instructions) = (0, 0);
+    let mut count = |records: &[Value]| for record in records {
+        let (record_dex_bytes, record_instructions) = record_counts(record);
+        dex_bytes += record_dex_bytes;
+        instructions += record_instructions;
+    };
+    // Whether `item` of the output at `index` holds the records an input is kept from
+    let owned = |index: usize, item: &Item| match (item.identity(), item) {
+        (Some(identity), Item::Records { key, .. } | Item::Row { key, .. }) => owners.get(&identity) == Some(&(index, key.clone())),
+        _ => false,
+    };
+    match format {
+        Format::Csv => {
+            writeln!(writer, "{}", meta.as_str().unwrap_or_default())?;
+            for (index, path) in inputs.iter().enumerate() {
+                read_output(path, open(path)?, &mut |item| match item {
+                    Item::Row { ref line, .. } if owned(index, &item) => Ok(writeln!(writer, "{}", line)?),
+                    _ => Ok(()),
+                })?;
+            }
+            writer.flush()?;
+        },
+        Format::Ndjson => {
+            let output_error = |source| MergeError::Json { path: "the merged output".to_string(), source };
+            serde_json::to_writer(&mut writer, &HashMap::from([("meta", &meta)])).map_err(output_error)?;
+            writeln!(writer)?;
+            for (index, path) in inputs.iter().enumerate() {
+                read_output(path, open(path)?, &mut |item| match item {
+                    Item::Records { ref key, ref records } if owned(index, &item) => {
+                        count(records);
+                        for record in records {
+                            serde_json::to_writer(&mut writer, &Record::Isolated { path: Some(key), record }).map_err(output_error)?;
+                            writeln!(writer)?;
+                        }
+                        Ok(())
+                    },
+                    _ => Ok(()),
+                })?;
+            }
+            let summary = merged_summary(&summaries, owners.len(), dex_bytes, instructions, &stats);
+            write_ndjson_summary(writer, &summary)?;
+        },
+        Format::Json => {
+            let output_error = |source| MergeError::Json { path: "the merged output".to_string(), source };
+            // The records are written as they are read, so the object around them is laid out by hand
+            write!(writer, "{{\"meta\":")?;
+            serde_json::to_writer(&mut writer, &meta).map_err(output_error)?;
+            write!(writer, ",\"inputs\":{{")?;
+            let mut written = HashSet::new();
+            for (index, path) in inputs.iter().enumerate() {
+                read_output(path, open(path)?, &mut |item| match item {
+                    Item::Records { ref key, ref records } if owned(index, &item) => {
+                        if !written.insert(key.clone()) {
+                            return Err(MergeError::Format(format!("{} names different inputs, which can only be merged into ndjson", key)));
+                        }
+                        count(records);
+                        if written.len() > 1 {
+                            write!(writer, ",")?;
+                        }
+                        serde_json::to_writer(&mut writer, key).map_err(output_error)?;
+                        write!(writer, ":")?;
+                        match records.as_slice() {
+                            [record] if !grouped => serde_json::to_writer(&mut writer, record),
+                            records => serde_json::to_writer(&mut writer, records),
+                        }.map_err(output_error)
+                    },
+                    _ => Ok(()),
+                })?;
+            }
+            write!(writer, "}},\"summary\":")?;
+            let summary = merged_summary(&summaries, owners.len(), dex_bytes, instructions, &stats);
+            serde_json::to_writer(&mut writer, &summary).map_err(output_error)?;
+            write!(writer, "}}")?;
+            writer.flush()?;
+        },
+    }
+    Ok(Merged { inputs: owners.len(), duplicates: listed - owners.len() })
+}
+
+
+/// Summary footer of the merged output, `stats` counting the bytes written so far
+fn merged_summary(summaries: &[Summary], inputs: usize, dex_bytes: u64, instructions: u64, stats: &RunStats) -> Summary {
+    let wall_time_secs = summaries.iter().map(|summary| summary.wall_time_secs).sum();
+    let deduplicated_methods = summaries.iter().filter_map(|summary| summary.deduplicated_methods).reduce(|total, methods| total + methods);
+    let unique_methods = summaries.iter().filter_map(|summary| summary.unique_methods).reduce(|total, methods| total + methods);
+    Summary {
+        wall_time_secs,
+        inputs_processed: inputs as u64,
+        inputs_failed: summaries.iter().map(|summary| summary.inputs_failed).sum(),
+        inputs_skipped: summaries.iter().map(|summary| summary.inputs_skipped).sum(),
+        dex_bytes,
+        instructions,
+        instructions_per_sec: if wall_time_secs > 0.0 { instructions as f64 / wall_time_secs } else { 0.0 },
+        peak_rss_bytes: summaries.iter().filter_map(|summary| summary.peak_rss_bytes).max(),
+        bytes_written: stats.summary().bytes_written,
+        cache_hits: summaries.iter().filter_map(|summary| summary.cache_hits).reduce(|total, hits| total + hits),
+        cache_misses: summaries.iter().filter_map(|summary| summary.cache_misses).reduce(|total, misses| total + misses),
+        deduplicated_methods,
+        unique_methods,
+        dedup_ratio: deduplicated_methods.zip(unique_methods).and_then(|(methods, unique)| dedup_ratio(methods, unique)),
+        stage_secs: summaries.iter().filter_map(|summary| summary.stage_secs).reduce(|total, secs| total + secs),
+        move_density: summaries.iter().filter_map(|summary| summary.move_density)
+            .reduce(|total, density| std::array::from_fn(|bucket| total[bucket] + density[bucket])),
+    }
+}
+
+
+/// Size of the dexes of an apk record, from its header counts, and number of opcodes of its sequences
+fn record_counts(record: &Value) -> (u64, u64) {
+    let len = |value: &Value| value.as_array().map_or(0, Vec::len) as u64;
+    let dex_bytes = record.get("header_counts").and_then(Value::as_array)
+        .map_or(0, |dexes| dexes.iter().filter_map(|dex| dex["file_size"].as_u64()).sum());
+    let instructions = match (record.get("op_seq"), record.get("unique_sequences"), record.get("counts")) {
+        (Some(op_seq), _, _) => len(op_seq),
+        (None, Some(Value::Array(sequences)), Some(Value::Array(counts))) => sequences.iter().zip(counts)
+            .map(|(sequence, count)| len(sequence) * count.as_u64().unwrap_or(0))
+            .sum(),
+        _ => 0,
+    };
+    (dex_bytes, instructions)
+}
+
+
+/// First key of the meta headers whose values differ
+fn meta_mismatch(expected: &Value, found: &Value) -> Option<String> {
+    match (expected, found) {
+        (Value::Object(expected), Value::Object(found)) => expected.keys().chain(found.keys())
+            .find(|key| expected.get(*key) != found.get(*key))
+            .cloned(),
+        (expected, found) if expected != found => Some("header".to_string()),
+        _ => None,
+    }
+}
+
+
+/// Walks the output at `path` in order, handing its parts to `f`. The meta header must come first
+fn read_output(path: &str, mut reader: impl BufRead, f: &mut dyn FnMut(Item) -> Result<(), MergeError>) -> Result<OutputFormat, MergeError> {
+    let json_error = |source| MergeError::Json { path: path.to_string(), source };
+    if reader.fill_buf()?.starts_with(b"path,") {
+        let mut lines = reader.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let hashed = header.starts_with("path,version,sha256,");
+        f(Item::Meta(Value::String(header)))?;
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let unreadable = || MergeError::Format(format!("{}: unreadable row {}", path, line));
+            let (key, rest) = csv_key(&line).ok_or_else(unreadable)?;
+            let mut columns = rest.split(',');
+            let version = columns.next().ok_or_else(unreadable)?.to_string();
+            let sha256 = if hashed { columns.next().ok_or_else(unreadable)? } else { "" };
+            let sha256 = (!sha256.is_empty()).then(|| sha256.to_string());
+            f(Item::Row { key, version, sha256, line: line.clone() })?;
+        }
+        return Ok(OutputFormat::Csv);
+    }
+
+    // The first line of an ndjson output is an object holding only the meta header
+    let mut failed = None;
+    let entries = serde_json::Deserializer::from_reader(&mut reader).deserialize_map(Entries { path, f, failed: &mut failed });
+    if let Some(err) = failed {
+        return Err(err);
+    }
+    if entries.map_err(json_error)? > 0 {
+        return Ok(OutputFormat::Json);
+    }
+    for line in serde_json::Deserializer::from_reader(reader).into_iter::<Map<String, Value>>() {
+        let mut record = line.map_err(json_error)?;
+        if let Some(summary) = record.remove("summary") {
+            f(Item::Summary(serde_json::from_value(summary).map_err(json_error)?))?;
+            continue;
+        }
+        let Some(Value::String(key)) = record.remove("path") else {
+            return Err(MergeError::Format(format!("{}: a record has no path", path)));
+        };
+        f(Item::Records { key, records: vec![Value::Object(record)] })?;
+    }
+    Ok(OutputFormat::Ndjson)
+}
+
+
+/// Path column of a CSV row, unquoted, and the columns after it
+fn csv_key(line: &str) -> Option<(String, &str)> {
+    match line.strip_prefix('"') {
+        Some(quoted) => {
+            let mut path = String::new();
+            let mut chars = quoted.char_indices();
+            loop {
+                match chars.next()? {
+                    (index, '"') if quoted[index + 1..].starts_with('"') => {
+                        path.push('"');
+                        chars.next();
+                    },
+                    (index, '"') => break Some((path, quoted[index + 1..].strip_prefix(',')?)),
+                    (_, char) => path.push(char),
+                }
+            }
+        },
+        None => line.split_once(',').map(|(path, rest)| (path.to_string(), rest)),
+    }
+}
+
+
+/// Visits the top-level object of an output entry by entry, and the inputs under its `inputs` key input by input, so
+/// that a json output is never read whole. Counts the inputs
+struct Entries<'a> {
+    path: &'a str,
+    f: &'a mut dyn FnMut(Item) -> Result<(), MergeError>,
+    /// Error of `f`, which serde can only report as a message
+    failed: &'a mut Option<MergeError>,
+}
+
+
+impl<'de> Visitor<'de> for Entries<'_> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object starting with the meta header")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<usize, A::Error> {
+        let mut entries = 0;
+        let mut first = true;
+        while let Some(key) = map.next_key::<String>()? {
+            let item = match key.as_str() {
+                "meta" => Item::Meta(map.next_value()?),
+                _ if first => {
+                    *self.failed = Some(MergeError::MissingMeta(self.path.to_string()));
+                    return Err(A::Error::custom("no meta header"));
+                },
+                "inputs" => {
+                    entries += map.next_value_seed(Inputs { entries: &mut self })?;
+                    continue;
+                },
+                "summary" => Item::Summary(map.next_value()?),
+                _ => {
+                    *self.failed = Some(MergeError::Format(format!("{}: unexpected key {} besides meta, inputs and summary", self.path, key)));
+                    return Err(A::Error::custom("unexpected key"));
+                },
+            };
+            first = false;
+            self.visit(item)?;
+        }
+        Ok(entries)
+    }
+}
+
+
+impl Entries<'_> {
+    /// Hands `item` to `f`, keeping its error for `read_output`
+    fn visit<E: serde::de::Error>(&mut self, item: Item) -> Result<(), E> {
+        (self.f)(item).map_err(|err| {
+            *self.failed = Some(err);
+            E::custom("merge failed")
+        })
+    }
+}
+
+
+/// The `inputs` key of a json output, visited input by input. Counts the inputs
+struct Inputs<'a, 'b> {
+    entries: &'a mut Entries<'b>,
+}
+
+
+impl<'de> DeserializeSeed<'de> for Inputs<'_, '_> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+
+impl<'de> Visitor<'de> for Inputs<'_, '_> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object of the records of every input, keyed by path")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<usize, A::Error> {
+        let mut inputs = 0;
+        while let Some(key) = map.next_key::<String>()? {
+            let records = match map.next_value()? {
+                Value::Array(records) => records,
+                record => vec![record],
+            };
+            inputs += 1;
+            self.entries.visit(Item::Records { key, records })?;
+        }
+        Ok(inputs)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use serde_json::json;
+
+    use super::*;
+
+    const META: &str = r#"{"meta":{"version":"0.1.0","schema":3,"granularity":"apk","include_codeless":false,"manifest_only":false}}"#;
+
+    fn record(path: &str, opcodes: usize) -> String {
+        json!({"path": path, "op_seq": vec![14; opcodes], "header_counts": [{"file_size": 100}]}).to_string()
+    }
+
+    fn summary(failed: u64, wall_time_secs: f64) -> String {
+        json!({"summary": {"wall_time_secs": wall_time_secs, "inputs_processed": 2, "inputs_failed": failed, "inputs_skipped": 0,
+            "dex_bytes": 200, "instructions": 5, "instructions_per_sec": 1.0, "peak_rss_bytes": 1024, "bytes_written": 10}}).to_string()
+    }
+
+    /// Two ndjson shards sharing `b.apk`, with 2 opcodes in the first and 3 in the second
+    fn shards() -> HashMap<String, String> {
+        HashMap::from([
+            ("first.ndjson".to_string(), [META.to_string(), record("a.apk", 1), record("b.apk", 2), summary(1, 2.0)].join("\n") + "\n"),
+            ("second.ndjson".to_string(), [META.to_string(), record("b.apk", 3), record("c.apk", 4), summary(0, 3.0)].join("\n") + "\n"),
+        ])
+    }
+
+    fn run(files: &HashMap<String, String>, format: Format, conflict: Conflict) -> Result<(Merged, String), MergeError> {
+        let mut inputs: Vec<String> = files.keys().cloned().collect();
+        inputs.sort();
+        let mut output = vec![];
+        let merged = merge(&inputs, |path| Ok(Cursor::new(files[path].as_bytes())), format, conflict, &mut output)?;
+        Ok((merged, String::from_utf8(output).unwrap()))
+    }
+
+    #[test]
+    fn test_merge_overlapping_ndjson() {
+        let (merged, output) = run(&shards(), Format::Ndjson, Conflict::First).unwrap();
+        assert_eq!(merged, Merged { inputs: 3, duplicates: 1 });
+        let lines: Vec<Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines[0], serde_json::from_str::<Value>(META).unwrap());
+        let opcodes: Vec<_> = lines[1..4].iter().map(|line| (line["path"].as_str().unwrap(), line["op_seq"].as_array().unwrap().len())).collect();
+        assert_eq!(opcodes, [("a.apk", 1), ("b.apk", 2), ("c.apk", 4)]);
+        let summary = &lines[4]["summary"];
+        assert_eq!((summary["inputs_processed"].as_u64(), summary["inputs_failed"].as_u64()), (Some(3), Some(1)));
+        assert_eq!((summary["instructions"].as_u64(), summary["dex_bytes"].as_u64()), (Some(7), Some(300)));
+        assert_eq!(summary["wall_time_secs"], 5.0);
+        assert_eq!(summary["bytes_written"], output.rfind("{\"summary\"").unwrap());
+
+        let (_, output) = run(&shards(), Format::Ndjson, Conflict::Last).unwrap();
+        let b = output.lines().map(|line| serde_json::from_str::<Value>(line).unwrap()).find(|line| line["path"] == "b.apk").unwrap();
+        assert_eq!(b["op_seq"].as_array().unwrap().len(), 3);
+        assert!(matches!(run(&shards(), Format::Ndjson, Conflict::Error), Err(MergeError::Conflict { key, .. }) if key == "b.apk"));
+    }
+
+    #[test]
+    fn test_merge_ndjson_into_json_and_back() {
+        let (_, json) = run(&shards(), Format::Json, Conflict::First).unwrap();
+        let output: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(output["inputs"]["b.apk"]["op_seq"].as_array().unwrap().len(), 2);
+        assert!(output["inputs"]["b.apk"].get("path").is_none());
+        assert_eq!(output["summary"]["inputs_processed"], 3);
+
+        let files = HashMap::from([("merged.json".to_string(), json), ("third.ndjson".to_string(), [META.to_string(), record("d.apk", 5)].join("\n"))]);
+        let (merged, output) = run(&files, Format::Ndjson, Conflict::Error).unwrap();
+        assert_eq!(merged, Merged { inputs: 4, duplicates: 0 });
+        assert_eq!(output.lines().count(), 6);
+    }
+
+    #[test]
+    fn test_merge_meta_mismatch() {
+        let mut files = shards();
+        let other = META.replace("\"apk\"", "\"class\"");
+        files.insert("third.ndjson".to_string(), [other, record("d.apk", 1)].join("\n"));
+        let err = run(&files, Format::Ndjson, Conflict::First).unwrap_err();
+        assert!(matches!(&err, MergeError::MetaMismatch { field, path, .. } if field == "granularity" && path == "third.ndjson"), "{}", err);
+        // Outputs of another layout, or from before it was versioned
+        for other in [META.replace("\"schema\":3", "\"schema\":2"), META.replace("\"schema\":3,", "")] {
+            files.insert("third.ndjson".to_string(), [other, record("d.apk", 1)].join("\n"));
+            let err = run(&files, Format::Ndjson, Conflict::First).unwrap_err();
+            assert!(matches!(&err, MergeError::MetaMismatch { field, .. } if field == "schema"), "{}", err);
+        }
+        files.insert("third.ndjson".to_string(), record("d.apk", 1));
+        assert!(matches!(run(&files, Format::Ndjson, Conflict::First), Err(MergeError::MissingMeta(_))));
+    }
+
+    #[test]
+    fn test_merge_by_contents() {
+        let hashed = |path: &str, sha256: &str, opcodes: usize| json!({"path": path, "sha256": sha256, "op_seq": vec![14; opcodes]}).to_string();
+        let files = HashMap::from([
+            ("first.ndjson".to_string(), [META.to_string(), hashed("a.apk", "aa", 1), hashed("copy-of-a.apk", "aa", 1)].join("\n") + "\n"),
+            ("second.ndjson".to_string(), [META.to_string(), hashed("renamed-a.apk", "aa", 1), hashed("a.apk", "bb", 2)].join("\n") + "\n"),
+        ]);
+        // The copies of `aa` are one input whatever their paths, the new contents of a.apk another
+        let (merged, output) = run(&files, Format::Ndjson, Conflict::First).unwrap();
+        assert_eq!(merged, Merged { inputs: 2, duplicates: 2 });
+        let kept: Vec<(String, String)> = output.lines().skip(1).filter_map(|line| {
+            let record: Value = serde_json::from_str(line).unwrap();
+            Some((record.get("path")?.as_str()?.to_string(), record["sha256"].as_str()?.to_string()))
+        }).collect();
+        assert_eq!(kept, [("a.apk".to_string(), "aa".to_string()), ("a.apk".to_string(), "bb".to_string())]);
+        let (_, output) = run(&files, Format::Ndjson, Conflict::Last).unwrap();
+        assert!(output.contains("renamed-a.apk") && !output.contains("copy-of-a.apk"));
+        assert!(matches!(run(&files, Format::Ndjson, Conflict::Error), Err(MergeError::Conflict { key, .. }) if key == "copy-of-a.apk"));
+        // Both are a.apk, which a json output can't key twice
+        assert!(matches!(run(&files, Format::Json, Conflict::First), Err(MergeError::Format(_))));
+    }
+
+    #[test]
+    fn test_merge_csv() {
+        let files = HashMap::from([
+            ("first.csv".to_string(), "path,version,methods,nop\na.apk,0.1.0,2,0\n\"b,1.apk\",0.1.0,1,0\n".to_string()),
+            ("second.csv".to_string(), "path,version,methods,nop\n\"b,1.apk\",0.1.0,3,1\nc.apk,0.1.0,1,0\n".to_string()),
+        ]);
+        let (merged, output) = run(&files, Format::Csv, Conflict::Last).unwrap();
+        assert_eq!(merged, Merged { inputs: 3, duplicates: 1 });
+        assert_eq!(output, "path,version,methods,nop\na.apk,0.1.0,2,0\n\"b,1.apk\",0.1.0,3,1\nc.apk,0.1.0,1,0\n");
+        assert!(matches!(run(&files, Format::Json, Conflict::First), Err(MergeError::Format(_))));
+        assert_eq!(csv_key("\"a \"\"b\"\", c.apk\",0.2.0,1"), Some(("a \"b\", c.apk".to_string(), "0.2.0,1")));
+    }
+}
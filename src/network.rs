@@ -0,0 +1,297 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, net::{Ipv4Addr, Ipv6Addr, SocketAddrV4}, ops::ControlFlow};
+
+use dex::Dex;
+use serde::Serialize;
+
+use crate::{
+    dex_parsing::{is_selected, walk_dex, ClassInfo, DecodedInstruction, InstructionVisitor, MethodInfo, Opcode},
+    fields::{fields, ConstantValue},
+    options::{AnalysisOptions, Strictness},
+    reference::{resolve_method, resolve_string},
+};
+
+
+/// Schemes of the extracted URLs
+pub const URL_SCHEMES: &[&str] = &["http", "https", "ws", "wss", "ftp"];
+
+/// Top-level domains common in abuse reports, domains outside URLs are only extracted under them
+pub const SUSPICIOUS_TLDS: &[&str] = &["tk", "ml", "ga", "cf", "gq", "xyz", "top", "pw", "cc", "su", "icu", "buzz", "onion"];
+
+
+/// Kind of a network indicator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndicatorKind {
+    Url,
+    Ipv4,
+    Ipv6,
+    /// Domain under one of the `SUSPICIOUS_TLDS`
+    Domain,
+}
+
+
+/// URL, address or domain found in the strings of an APK, with every place it appears
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NetworkIndicator {
+    pub kind: IndicatorKind,
+    /// The indicator as written, format placeholders such as `%s` included
+    pub value: String,
+    pub locations: Vec<IndicatorLocation>,
+}
+
+
+/// `const-string` of a method or initial value of a static final field holding an indicator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IndicatorLocation {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// Code unit offset of the `const-string` in the method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Networking APIs invoked by the method, e.g. `webview`. Empty for fields
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub context: BTreeSet<&'static str>,
+}
+
+
+/// Indicators in the strings of the methods and the static final fields of the selected classes of the dexes,
+/// one per distinct value in value order
+pub fn network_indicators<T: AsRef<[u8]>>(dexes: &[Dex<T>], options: &AnalysisOptions) -> Vec<NetworkIndicator> {
+    let mut indicators: BTreeMap<String, NetworkIndicator> = BTreeMap::new();
+    let mut add = |kind, value: &str, location: IndicatorLocation| {
+        indicators.entry(value.to_string())
+            .or_insert_with(|| NetworkIndicator { kind, value: value.to_string(), locations: vec![] })
+            .locations.push(location);
+    };
+    for (dex_index, dex) in dexes.iter().enumerate() {
+        let mut visitor = IndicatorVisitor { dex, options, contexts: HashMap::new(), strings: vec![], context: BTreeSet::new(), found: vec![] };
+        walk_dex(dex, &mut visitor);
+        for (class, method, offset, string, context) in visitor.found {
+            for (kind, value) in extract_indicators(&string) {
+                add(kind, value, IndicatorLocation {
+                    dex: dex_index,
+                    class: class.clone(),
+                    method: Some(method.clone()),
+                    offset: Some(offset),
+                    field: None,
+                    context: context.clone(),
+                });
+            }
+        }
+        for field in fields(dex_index, dex, options) {
+            let Some(ConstantValue::String(string)) = &field.value else { continue };
+            for (kind, value) in extract_indicators(string) {
+                add(kind, value, IndicatorLocation {
+                    dex: dex_index,
+                    class: field.class.clone(),
+                    method: None,
+                    offset: None,
+                    field: Some(field.name.clone()),
+                    context: BTreeSet::new(),
+                });
+            }
+        }
+    }
+    indicators.into_values().collect()
+}
+
+
+/// Context tag of an invoked method, `None` outside the networking APIs
+fn network_context(class: &str, name: &str) -> Option<&'static str> {
+    match (class, name) {
+        ("Ljava/net/HttpURLConnection;" | "Ljavax/net/ssl/HttpsURLConnection;", _) | ("Ljava/net/URL;", "openConnection" | "openStream") => Some("http_url_connection"),
+        (class, _) if class.starts_with("Lokhttp3/") || class.starts_with("Lcom/squareup/okhttp/") => Some("okhttp"),
+        ("Landroid/webkit/WebView;", "loadUrl" | "postUrl" | "loadDataWithBaseURL") => Some("webview"),
+        _ => None,
+    }
+}
+
+
+struct IndicatorVisitor<'a, T> {
+    dex: &'a Dex<T>,
+    options: &'a AnalysisOptions,
+    /// Context tag of every method index invoked so far
+    contexts: HashMap<u32, Option<&'static str>>,
+    /// Offset and contents of the `const-string`s of the current method
+    strings: Vec<(usize, String)>,
+    /// Networking APIs invoked by the current method
+    context: BTreeSet<&'static str>,
+    /// Class, method, offset, string and context of every `const-string` of the dex
+    found: Vec<(String, String, usize, String, BTreeSet<&'static str>)>,
+}
+
+impl<T: AsRef<[u8]>> InstructionVisitor for IndicatorVisitor<'_, T> {
+    fn visit_class(&mut self, class: &ClassInfo) -> ControlFlow<()> {
+        if is_selected(class.class(), self.options) { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+    }
+
+    fn visit_instruction(&mut self, inst: &DecodedInstruction) {
+        let inst = &inst.instruction;
+        let Some(reference) = *inst.reference() else { return };
+        if matches!(inst.opcode(), Opcode::ConstString | Opcode::ConstStringJumbo) {
+            if let Some(string) = resolve_string(self.dex, reference) {
+                self.strings.push((*inst.offset(), string));
+            }
+        } else if inst.opcode().is_invoke() {
+            let dex = self.dex;
+            let context = *self.contexts.entry(reference).or_insert_with(|| {
+                resolve_method(dex, reference).and_then(|method| network_context(&method.class, &method.name))
+            });
+            self.context.extend(context);
+        }
+    }
+
+    fn leave_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        let context = std::mem::take(&mut self.context);
+        let class = method.class().jtype().type_descriptor();
+        for (offset, string) in self.strings.drain(..) {
+            self.found.push((class.to_string(), method.method().name().to_string(), offset, string, context.clone()));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn strictness(&self) -> Strictness {
+        Strictness::Lenient
+    }
+}
+
+
+/// URLs, IP addresses and domains under the `SUSPICIOUS_TLDS` in a string, in order of appearance. Addresses and
+/// domains inside a URL aren't reported on their own, and format placeholders such as `%s` or `%1$d` split words
+pub fn extract_indicators(text: &str) -> Vec<(IndicatorKind, &str)> {
+    let mut indicators = vec![];
+    let mut urls = vec![];
+    for (separator, _) in text.match_indices("://") {
+        let start = text[..separator].char_indices().rev()
+            .find(|(_, c)| !c.is_ascii_alphanumeric())
+            .map_or(0, |(index, c)| index + c.len_utf8());
+        if !URL_SCHEMES.iter().any(|scheme| text[start..separator].eq_ignore_ascii_case(scheme)) {
+            continue;
+        }
+        let end = text[separator..].find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>' | '`'))
+            .map_or(text.len(), |index| separator + index);
+        let url = text[start..end].trim_end_matches(['.', ',', ';', ')']);
+        if url.len() > separator - start + 3 {
+            indicators.push((start, IndicatorKind::Url, url));
+            urls.push(start..start + url.len());
+        }
+    }
+    for (start, word) in words(text) {
+        if urls.iter().any(|url| url.contains(&start)) {
+            continue;
+        }
+        let word = word.trim_end_matches(['.', ':', '-']);
+        if word.parse::<Ipv4Addr>().is_ok() {
+            indicators.push((start, IndicatorKind::Ipv4, word));
+        } else if let Ok(address) = word.parse::<SocketAddrV4>() {
+            // The port is left out, the address ends before the colon
+            let address_len = address.ip().to_string().len();
+            indicators.push((start, IndicatorKind::Ipv4, &word[..address_len]));
+        } else if word.matches(':').count() >= 2 && word.chars().any(|c| c.is_ascii_hexdigit()) && word.parse::<Ipv6Addr>().is_ok() {
+            indicators.push((start, IndicatorKind::Ipv6, word));
+        } else if is_suspicious_domain(word) {
+            indicators.push((start, IndicatorKind::Domain, word));
+        }
+    }
+    indicators.sort_by_key(|(start, _, _)| *start);
+    indicators.into_iter().map(|(_, kind, value)| (kind, value)).collect()
+}
+
+
+/// Runs of letters, digits, dots, colons and hyphens of a string and their byte offsets, format placeholders excluded
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut words = vec![];
+    let mut start = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '-') {
+            start.get_or_insert(index);
+            continue;
+        }
+        if let Some(start) = start.take() {
+            words.push((start, &text[start..index]));
+        }
+        if c == '%' {
+            // Skips the argument index, flags, width and precision, then the conversion letter
+            while chars.next_if(|(_, c)| c.is_ascii_digit() || matches!(c, '$' | '.' | '-' | '+' | '#' | ' ' | ',')).is_some() {}
+            chars.next_if(|(_, c)| c.is_ascii_alphabetic() || *c == '%');
+        }
+    }
+    if let Some(start) = start {
+        words.push((start, &text[start..]));
+    }
+    words
+}
+
+
+fn is_suspicious_domain(word: &str) -> bool {
+    let labels: Vec<&str> = word.split('.').collect();
+    labels.len() >= 2
+        && labels.iter().all(|label| !label.is_empty() && !label.starts_with('-') && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        && SUSPICIOUS_TLDS.iter().any(|tld| labels[labels.len() - 1].eq_ignore_ascii_case(tld))
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{DexBuilder, ClassDef, FieldDef, MethodDef, CodeDef, ValueDef, ACC_FINAL, ACC_STATIC};
+    use super::*;
+
+    #[test]
+    fn test_extract_indicators() {
+        assert_eq!(extract_indicators("see https://api.example.com/v1/users?id=%d, or mirror at 10.0.0.7:8080"), [
+            (IndicatorKind::Url, "https://api.example.com/v1/users?id=%d"),
+            (IndicatorKind::Ipv4, "10.0.0.7"),
+        ]);
+        assert_eq!(extract_indicators("http://%s/gate.php"), [(IndicatorKind::Url, "http://%s/gate.php")]);
+        // The placeholder doesn't stick to the domain
+        assert_eq!(extract_indicators("%sc2.evil.tk:%d"), [(IndicatorKind::Domain, "c2.evil.tk")]);
+        assert_eq!(extract_indicators("fe80::1ff:fe23:4567:890a and ::1"), [
+            (IndicatorKind::Ipv6, "fe80::1ff:fe23:4567:890a"),
+            (IndicatorKind::Ipv6, "::1"),
+        ]);
+        // Versions, times, package names and schemes outside the list aren't indicators
+        assert!(extract_indicators("version 1.2.3.4.5 at 12:30:45 in com.example.app, content://media/1").is_empty());
+        assert!(extract_indicators("https://").is_empty());
+    }
+
+    #[test]
+    fn test_network_indicators() {
+        let mut builder = DexBuilder::new();
+        let url = builder.string("https://login.example.com/auth?user=%s") as u16;
+        let load_url = builder.method("Landroid/webkit/WebView;", "loadUrl", "V", &["Ljava/lang/String;"]) as u16;
+        // const-string v0, url; invoke-virtual {v1, v0}, WebView.loadUrl; return-void
+        let open = [0x001A, url, 0x206E, load_url, 0x0001, 0x000E];
+        // const-string v0, url; return-void
+        let keep = [0x001A, url, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Browser;")
+            .field(FieldDef::new("BACKUP", "Ljava/lang/String;").access_flags(ACC_STATIC | ACC_FINAL)
+                .value(ValueDef::String("backup at 203.0.113.9".to_string())))
+            .method(MethodDef::new("open", "V", &[]).code(CodeDef::new(2, 1, 2, &open)))
+            .method(MethodDef::new("keep", "V", &[]).code(CodeDef::new(1, 0, 0, &keep))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+
+        let indicators = network_indicators(&[dex], &AnalysisOptions::default());
+        assert_eq!(indicators.len(), 2);
+        assert_eq!((indicators[0].kind, indicators[0].value.as_str()), (IndicatorKind::Ipv4, "203.0.113.9"));
+        assert_eq!(indicators[0].locations[0].field.as_deref(), Some("BACKUP"));
+        // Both methods load the URL, deduplicated into one indicator
+        let url = &indicators[1];
+        assert_eq!((url.kind, url.value.as_str()), (IndicatorKind::Url, "https://login.example.com/auth?user=%s"));
+        assert_eq!(url.locations.len(), 2);
+        assert_eq!((url.locations[0].method.as_deref(), url.locations[0].offset), (Some("open"), Some(0)));
+        assert_eq!(url.locations[0].context, BTreeSet::from(["webview"]));
+        assert!(url.locations[1].context.is_empty());
+        let json = serde_json::to_value(url).unwrap();
+        assert_eq!(json["kind"], "url");
+        assert_eq!(json["locations"][0]["context"], serde_json::json!(["webview"]));
+    }
+}
@@ -0,0 +1,163 @@
+use dex::{method::AccessFlags, Dex};
+use serde::Serialize;
+
+use crate::{
+    call_graph::CallGraph,
+    dex_parsing::{decode_method_lenient, Opcode, OpcodeCategory},
+    reference::{resolve_method, MethodRef},
+};
+
+
+/// Signs of obfuscation found in the dexes of an input
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Obfuscation {
+    /// Likely string decryption helpers, in decreasing order of score
+    pub string_decryptors: Vec<StringDecryptor>,
+}
+
+
+/// Thresholds of the string decryptor heuristic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecryptorThresholds {
+    /// Minimum share of the opcodes of the body that are array accesses, int xors and adds, and char or byte conversions
+    pub min_score: f64,
+    /// Minimum number of distinct classes calling the method
+    pub min_caller_classes: usize,
+}
+
+
+impl Default for DecryptorThresholds {
+    fn default() -> Self {
+        Self { min_score: 0.35, min_caller_classes: 3 }
+    }
+}
+
+
+/// Static method returning a string whose body looks like a decryption loop and which is called from many classes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StringDecryptor {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    #[serde(flatten)]
+    pub method: MethodRef,
+    /// Share of the opcodes of the body typical of a decryption loop, in `[0, 1]`
+    pub score: f64,
+    /// Number of invoke instructions calling the method
+    pub call_count: usize,
+    pub caller_classes: usize,
+}
+
+
+/// Static methods of `dex` returning a string that pass the thresholds, `graph` being the call graph of the same dex
+pub fn string_decryptors<T: AsRef<[u8]>>(dex_index: usize, dex: &Dex<T>, graph: &CallGraph, thresholds: &DecryptorThresholds) -> Vec<StringDecryptor> {
+    let mut decryptors = vec![];
+    for class in dex.classes().flatten() {
+        for method in class.methods() {
+            let idx = method.id() as u32;
+            // The call graph lookups are cheaper than decoding, so the body is only scored for well-called candidates
+            let caller_classes = graph.caller_classes(idx);
+            if caller_classes < thresholds.min_caller_classes
+                || !method.access_flags().contains(AccessFlags::STATIC)
+                || method.return_type().type_descriptor().as_str() != "Ljava/lang/String;" {
+                continue;
+            }
+            let Some(code) = method.code() else { continue };
+            let score = body_score(code.insns());
+            if score < thresholds.min_score {
+                continue;
+            }
+            if let Some(method) = resolve_method(dex, idx) {
+                decryptors.push(StringDecryptor { dex: dex_index, method, score, call_count: graph.call_sites(idx), caller_classes });
+            }
+        }
+    }
+    decryptors.sort_by(|a, b| b.score.total_cmp(&a.score).then(b.call_count.cmp(&a.call_count)));
+    decryptors
+}
+
+
+/// Share of the decoded opcodes of a method that are typical of a decryption loop
+fn body_score(raw_bytecode: &[u16]) -> f64 {
+    let instructions = decode_method_lenient(raw_bytecode).instructions;
+    if instructions.is_empty() {
+        return 0.0;
+    }
+    let matching = instructions.iter().filter(|inst| is_decryption_op(*inst.opcode())).count();
+    matching as f64 / instructions.len() as f64
+}
+
+
+fn is_decryption_op(opcode: Opcode) -> bool {
+    opcode.category() == OpcodeCategory::Array || matches!(opcode,
+        Opcode::XorInt | Opcode::XorInt2Addr | Opcode::XorIntLit16 | Opcode::XorIntLit8
+        | Opcode::AddInt | Opcode::AddInt2Addr | Opcode::AddIntLit16 | Opcode::AddIntLit8
+        | Opcode::IntToChar | Opcode::IntToByte)
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::dex_parsing::NamedDex;
+
+    use crate::testing::{DexBuilder, ClassDef, MethodDef, CodeDef, ACC_PUBLIC, ACC_STATIC};
+    use super::*;
+
+    #[test]
+    fn test_body_score() {
+        // aget-char v2, v0, v1; xor-int/lit8 v2, v2, 0x2a; int-to-char v2, v2; return-object v2
+        assert_eq!(body_score(&[0x0249, 0x0100, 0x02DF, 0x2A02, 0x228E, 0x0211]), 0.75);
+        assert_eq!(body_score(&[0x0011]), 0.0);
+        assert_eq!(body_score(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_string_decryptors() {
+        let string = "Ljava/lang/String;";
+        let mut builder = DexBuilder::new();
+        let to_char_array = builder.method(string, "toCharArray", "[C", &[]) as u16;
+        let init = builder.method(string, "<init>", "V", &["[C"]) as u16;
+        let decrypt = builder.method("Lcom/a/b;", "a", string, &[string]) as u16;
+        let control = builder.method("Lcom/a/b;", "c", string, &[string]) as u16;
+        let string_type = builder.type_idx(string) as u16;
+        let encrypted = builder.string("KBX") as u16;
+        let static_method = ACC_PUBLIC | ACC_STATIC;
+        // Xors every char of the string with 0x2a
+        let decrypt_body = [
+            0x106E, to_char_array, 0x0003, 0x000C, 0x0112,  // invoke-virtual {p0}, toCharArray; move-result-object v0; const/4 v1, 0
+            0x0221, 0x2135, 12,                              // :loop array-length v2, v0; if-ge v1, v2, :end
+            0x0249, 0x0100, 0x02DF, 0x2A02, 0x228E,          // aget-char v2, v0, v1; xor-int/lit8 v2, v2, 0x2a; int-to-char v2, v2
+            0x0250, 0x0100, 0x01D8, 0x0101, 0xF428,          // aput-char v2, v0, v1; add-int/lit8 v1, v1, 1; goto :loop
+            0x0122, string_type, 0x2070, init, 0x0001,       // :end new-instance v1, String; invoke-direct {v1, v0}, <init>
+            0x0111,                                          // return-object v1
+        ];
+        builder.class(ClassDef::new("Lcom/a/b;")
+            .method(MethodDef::new("a", string, &[string]).access_flags(static_method).code(CodeDef::new(4, 1, 2, &decrypt_body)))
+            .method(MethodDef::new("c", string, &[string]).access_flags(static_method).code(CodeDef::new(1, 1, 0, &[0x0011]))));
+        // const-string v0, encrypted; then twice invoke-static {v0}, a; move-result-object v0; and once c; return-void
+        let caller_body = [
+            0x001A, encrypted,
+            0x1071, decrypt, 0x0000, 0x000C,
+            0x1071, decrypt, 0x0000, 0x000C,
+            0x1071, control, 0x0000, 0x000C,
+            0x000E,
+        ];
+        for class in ["Lcom/x/A;", "Lcom/x/B;", "Lcom/x/C;"] {
+            builder.class(ClassDef::new(class).method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 1, &caller_body))));
+        }
+        let dex = NamedDex::new("classes.dex", DexReader::from_vec(builder.build()).unwrap());
+        let graph = CallGraph::from_dex(&dex);
+        let dex = dex.dex;
+
+        let decryptors = string_decryptors(0, &dex, &graph, &DecryptorThresholds::default());
+        assert_eq!(decryptors.len(), 1);
+        assert_eq!((decryptors[0].method.class.as_str(), decryptors[0].method.name.as_str()), ("Lcom/a/b;", "a"));
+        assert_eq!((decryptors[0].call_count, decryptors[0].caller_classes), (6, 3));
+        assert_eq!(decryptors[0].score, 6.0 / 14.0);
+        // The control method has the same callers but no decryption loop
+        assert_eq!(graph.caller_classes(control as u32), 3);
+        let strict = DecryptorThresholds { min_caller_classes: 4, ..Default::default() };
+        assert!(string_decryptors(0, &dex, &graph, &strict).is_empty());
+    }
+}
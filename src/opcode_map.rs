@@ -0,0 +1,146 @@
+use std::{collections::BTreeMap, io};
+#[cfg(feature = "fs")]
+use std::{fs, path::Path};
+
+use num_traits::FromPrimitive;
+use serde::Deserialize;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::dex_parsing::Opcode;
+
+
+/// Output token of every opcode, a custom vocabulary for the emitted sequences.
+/// Bytes that aren't opcodes are never emitted and keep their own value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeMap {
+    tokens: [u8; 256],
+}
+
+
+/// Mapping file, e.g. in TOML:
+///
+/// ```toml
+/// default = 255
+///
+/// [map]
+/// if-eq = 40
+/// 0x33 = 40
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MapFile {
+    /// Token of the opcodes missing from `map`, which are passed through without it
+    default: Option<u8>,
+    /// Opcode mnemonic, decimal byte or `0x` hex byte to token
+    #[serde(default)]
+    map: BTreeMap<String, u8>,
+}
+
+
+impl OpcodeMap {
+    /// Every opcode emitted as its own byte, the `full` preset
+    pub fn full() -> Self {
+        Self::from_fn(|opcode| opcode as u8)
+    }
+
+    /// Every opcode emitted as the index of its `OpcodeCategory`, the `kinds` preset
+    pub fn kinds() -> Self {
+        Self::from_fn(|opcode| opcode.category() as u8)
+    }
+
+    pub fn from_fn(token: impl Fn(Opcode) -> u8) -> Self {
+        Self { tokens: std::array::from_fn(|byte| Opcode::from_u8(byte as u8).map_or(byte as u8, &token)) }
+    }
+
+    /// Reads a mapping file, JSON for a `.json` extension and TOML otherwise
+    #[cfg(feature = "fs")]
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let contents = fs::read_to_string(&path)?;
+        if path.as_ref().extension().is_some_and(|extension| extension == "json") {
+            Self::from_json(&contents)
+        } else {
+            Self::from_toml(&contents)
+        }
+    }
+
+    pub fn from_toml(toml: &str) -> io::Result<Self> {
+        Self::from_map_file(toml::from_str(toml).map_err(|err| invalid(format!("Invalid opcode map: {}", err)))?)
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        Self::from_map_file(serde_json::from_str(json).map_err(|err| invalid(format!("Invalid opcode map: {}", err)))?)
+    }
+
+    fn from_map_file(file: MapFile) -> io::Result<Self> {
+        let mut map = match file.default {
+            Some(default) => Self::from_fn(|_| default),
+            None => Self::full(),
+        };
+        for (key, token) in file.map {
+            let byte = opcode_byte(&key).ok_or_else(|| invalid(format!("Unknown opcode in the opcode map: {}", key)))?;
+            map.tokens[byte as usize] = token;
+        }
+        Ok(map)
+    }
+
+    /// Token emitted for `opcode`
+    pub fn apply(&self, opcode: Opcode) -> u8 {
+        self.tokens[opcode as usize]
+    }
+
+    /// Hash of the tokens, equal for equal vocabularies
+    pub fn hash(&self) -> u64 {
+        xxh3_64(&self.tokens)
+    }
+}
+
+
+/// Byte of the opcode named by a key of a mapping file, `None` if it names no opcode
+fn opcode_byte(key: &str) -> Option<u8> {
+    let byte = match key.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => key.parse().ok(),
+    };
+    byte.or_else(|| (0..=u8::MAX).find(|&byte| Opcode::from_u8(byte).is_some_and(|opcode| opcode.mnemonic() == key)))
+        .filter(|&byte| Opcode::from_u8(byte).is_some())
+}
+
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::dex_parsing::OpcodeCategory;
+    use super::*;
+
+    #[test]
+    fn test_map_collapses_if_variants() {
+        let toml = "default = 255\n[map]\nif-eq = 40\n0x33 = 40\n\"14\" = 1\n";
+        let map = OpcodeMap::from_toml(toml).unwrap();
+        assert_eq!(map.apply(Opcode::IfEq), 40);
+        assert_eq!(map.apply(Opcode::IfNe), 40);
+        assert_eq!(map.apply(Opcode::ReturnVoid), 1);
+        assert_eq!(map.apply(Opcode::Nop), 255);
+
+        let json = r#"{"map": {"if-eq": 40, "if-ne": 40}}"#;
+        let map = OpcodeMap::from_json(json).unwrap();
+        assert_eq!((map.apply(Opcode::IfEq), map.apply(Opcode::IfNe)), (40, 40));
+        // Unmapped opcodes are passed through without a default
+        assert_eq!(map.apply(Opcode::IfLt), Opcode::IfLt as u8);
+        assert_ne!(map.hash(), OpcodeMap::full().hash());
+
+        assert!(OpcodeMap::from_toml("[map]\nif-maybe = 1\n").is_err());
+        // 0x3e is unused
+        assert!(OpcodeMap::from_toml("[map]\n0x3e = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(OpcodeMap::full().apply(Opcode::InvokeVirtual), Opcode::InvokeVirtual as u8);
+        assert_eq!(OpcodeMap::kinds().apply(Opcode::IfLez), OpcodeCategory::If as u8);
+        assert_eq!(OpcodeMap::kinds().apply(Opcode::IfEq), OpcodeMap::kinds().apply(Opcode::IfNe));
+    }
+}
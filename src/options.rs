@@ -0,0 +1,724 @@
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "parallel")]
+use crate::dex_parsing::DexCache;
+use crate::{dex_parsing::{DedupTable, Opcode, OpcodeCategory}, extension::{MethodAnalyses, MethodAnalysis}, obfuscation::DecryptorThresholds, opcode_map::OpcodeMap, packer::PackerRules, watchlist::Watchlist};
+
+
+/// How decoding reacts to an instruction it can't decode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Drop the whole method
+    #[default]
+    Strict,
+    /// Skip payloads and undecodable code units and keep decoding
+    Lenient,
+}
+
+
+/// Which code units of a method are decoded as instructions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Every code unit from the start to the end of the method, in order
+    #[default]
+    Linear,
+    /// Only the code reachable from the entry and the catch handlers, following branches, switches and fall-through,
+    /// so that data embedded between instructions isn't mistaken for code
+    Recursive,
+}
+
+
+/// Alphabet of the emitted opcode sequences
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Opcode bytes as they are
+    #[default]
+    None,
+    /// Every `invoke-*` becomes `invoke-virtual`, other opcodes are kept
+    InvokeMerged,
+    /// The index of the opcode's `OpcodeCategory`
+    Category,
+    /// Token of the opcode in a custom vocabulary
+    Map(Box<OpcodeMap>),
+}
+
+
+impl Normalization {
+    /// Symbol emitted for `opcode`
+    pub fn apply(&self, opcode: Opcode) -> u8 {
+        match self {
+            Normalization::None => opcode as u8,
+            Normalization::InvokeMerged if opcode.category() == OpcodeCategory::Invoke => Opcode::InvokeVirtual as u8,
+            Normalization::InvokeMerged => opcode as u8,
+            Normalization::Category => opcode.category() as u8,
+            Normalization::Map(map) => map.apply(opcode),
+        }
+    }
+
+    /// Symbol of every opcode, whose hash tells the vocabularies of outputs apart
+    pub fn opcode_map(&self) -> OpcodeMap {
+        match self {
+            Normalization::Map(map) => **map,
+            _ => OpcodeMap::from_fn(|opcode| self.apply(opcode)),
+        }
+    }
+
+    /// Whether every emitted symbol is the byte of an opcode, which mnemonics can stand for
+    pub fn keeps_opcodes(&self) -> bool {
+        match self {
+            Normalization::None | Normalization::InvokeMerged => true,
+            Normalization::Category => false,
+            Normalization::Map(map) => **map == OpcodeMap::full(),
+        }
+    }
+}
+
+
+/// Which methods share a table of unique sequences with `dedup_methods`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupScope {
+    /// All methods of an input
+    #[default]
+    Input,
+    /// The methods of each dex, a body found in two dexes is emitted twice
+    Dex,
+    /// Every method of the inputs analyzed with the same options or their clones: a body is emitted by the first input
+    /// having it, the methods of later inputs refer to it by its run-wide id
+    Run,
+}
+
+
+/// What makes two method bodies duplicates with `dedup_methods`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupKey {
+    /// Identical code units
+    #[default]
+    Bytecode,
+    /// Identical opcode sequences after normalization, whatever their registers, literals and references
+    Opcodes,
+}
+
+
+/// How the opcode sequence of an input is cut at `sequence_cap`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CapStrategy {
+    /// The sequence of all dexes is cut exactly at the cap, truncating the method reaching it
+    #[default]
+    TruncateExact,
+    /// The sequence of all dexes ends with the last method fitting whole under the cap
+    TruncateMethods,
+    /// The sequence of every dex is cut exactly at the cap, an input emits up to one cap per dex
+    PerDex,
+}
+
+
+/// How the string pool writes the strings that aren't valid MUTF-8, which can't be decoded to text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InvalidStrings {
+    /// Hex of the bytes, e.g. `6180`
+    #[default]
+    Hex,
+    /// The bytes with those outside printable ASCII escaped, e.g. `a\x80`
+    Escaped,
+    /// Standard base64 of the bytes with padding, e.g. `YYA=`
+    Base64,
+}
+
+
+/// Selects classes by descriptor prefix, e.g. `Landroidx/`.
+/// With no includes every class not excluded is selected
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+
+impl ClassFilter {
+    pub fn include(mut self, prefix: impl Into<String>) -> Self {
+        self.include.push(prefix.into());
+        self
+    }
+
+    pub fn exclude(mut self, prefix: impl Into<String>) -> Self {
+        self.exclude.push(prefix.into());
+        self
+    }
+
+    pub fn matches(&self, descriptor: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|prefix| descriptor.starts_with(prefix.as_str())))
+            && !self.exclude.iter().any(|prefix| descriptor.starts_with(prefix.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+
+/// Deterministic random selection of a fraction of the methods of every input, different for every input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sampling {
+    /// Probability of a method being kept, in `[0, 1]`
+    pub rate: f64,
+    pub seed: u64,
+}
+
+
+impl Sampling {
+    /// Which of `count` methods of an input are kept, `input` being a hash telling the input apart, see
+    /// `Sequences::identity`. The same seed always selects the same methods of the same input
+    pub fn select(&self, count: usize, input: u64) -> Vec<bool> {
+        let mut rng = StdRng::seed_from_u64(self.seed ^ input);
+        (0..count).map(|_| rng.gen_bool(self.rate)).collect()
+    }
+}
+
+
+/// Top-level section of a report, see `AnalysisOptions::report_fields`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportField {
+    /// The opcode sequences and the reports of their methods
+    Opcodes,
+    Permissions,
+    Watchlist,
+    CodelessMethods,
+    Coverage,
+    HeaderCounts,
+    /// Name, classes and, with `sections`, layout of every dex
+    Dexes,
+    DuplicateClasses,
+    Signatures,
+    /// The string pool
+    Strings,
+    Sections,
+    Fields,
+    ApiSequences,
+    Intents,
+    NetworkIndicators,
+    Kotlin,
+    DebugInfo,
+    VerifyErrors,
+    Metrics,
+    Obfuscation,
+    Packer,
+    Warnings,
+    /// Outputs of the registered method analyses
+    Extensions,
+    DeadApiCalls,
+}
+
+
+impl ReportField {
+    pub const ALL: [ReportField; 24] = [
+        ReportField::Opcodes, ReportField::Permissions, ReportField::Watchlist, ReportField::CodelessMethods, ReportField::Coverage,
+        ReportField::HeaderCounts, ReportField::Dexes, ReportField::DuplicateClasses, ReportField::Signatures, ReportField::Strings,
+        ReportField::Sections, ReportField::Fields, ReportField::ApiSequences, ReportField::Intents, ReportField::NetworkIndicators,
+        ReportField::Kotlin, ReportField::DebugInfo, ReportField::VerifyErrors, ReportField::Metrics, ReportField::Obfuscation,
+        ReportField::Packer, ReportField::Warnings, ReportField::Extensions, ReportField::DeadApiCalls,
+    ];
+
+    /// Name of the field on the command line and in the `meta` header, e.g. `header_counts`
+    pub fn name(self) -> &'static str {
+        match self {
+            ReportField::Opcodes => "opcodes",
+            ReportField::Permissions => "permissions",
+            ReportField::Watchlist => "watchlist",
+            ReportField::CodelessMethods => "codeless_methods",
+            ReportField::Coverage => "coverage",
+            ReportField::HeaderCounts => "header_counts",
+            ReportField::Dexes => "dexes",
+            ReportField::DuplicateClasses => "duplicate_classes",
+            ReportField::Signatures => "signatures",
+            ReportField::Strings => "strings",
+            ReportField::Sections => "sections",
+            ReportField::Fields => "fields",
+            ReportField::ApiSequences => "api_sequences",
+            ReportField::Intents => "intents",
+            ReportField::NetworkIndicators => "network_indicators",
+            ReportField::Kotlin => "kotlin",
+            ReportField::DebugInfo => "debug_info",
+            ReportField::VerifyErrors => "verify_errors",
+            ReportField::Metrics => "metrics",
+            ReportField::Obfuscation => "obfuscation",
+            ReportField::Packer => "packer",
+            ReportField::Warnings => "warnings",
+            ReportField::Extensions => "extensions",
+            ReportField::DeadApiCalls => "dead_api_calls",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.name() == name)
+    }
+
+    /// Whether the field is serialized under `key` in APK and dex reports
+    pub fn has_key(self, key: &str) -> bool {
+        match self {
+            ReportField::Opcodes => matches!(key, "op_seq" | "methods" | "unique_sequences" | "counts"),
+            ReportField::Strings => key == "string_pool",
+            ReportField::Sections => matches!(key, "sections" | "anomalies"),
+            ReportField::Intents => matches!(key, "intents" | "components"),
+            field => key == field.name(),
+        }
+    }
+}
+
+
+/// What to extract when analyzing an APK or a dex, built with chained setters:
+///
+/// ```
+/// use dexompiler::{AnalysisOptions, Strictness};
+///
+/// let options = AnalysisOptions::default().sequence_cap(5000).strictness(Strictness::Lenient).build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisOptions {
+    pub(crate) sequence_cap: usize,
+    pub(crate) cap_strategy: CapStrategy,
+    pub(crate) method_cap: usize,
+    pub(crate) class_filter: ClassFilter,
+    pub(crate) strictness: Strictness,
+    pub(crate) dedup_methods: bool,
+    pub(crate) dedup_scope: DedupScope,
+    /// Shared by the clones of the options, for `DedupScope::Run`
+    pub(crate) dedup_table: Arc<DedupTable>,
+    pub(crate) dedup_key: DedupKey,
+    pub(crate) call_graph_metrics: bool,
+    pub(crate) watchlist: Watchlist,
+    pub(crate) sampling: Option<Sampling>,
+    pub(crate) normalization: Normalization,
+    pub(crate) shallow: bool,
+    pub(crate) decode_mode: DecodeMode,
+    pub(crate) with_offsets: bool,
+    pub(crate) op_stats: bool,
+    pub(crate) instructions_lite: bool,
+    pub(crate) string_pool: bool,
+    pub(crate) invalid_strings: InvalidStrings,
+    pub(crate) sections: bool,
+    pub(crate) fields: bool,
+    pub(crate) api_sequences: bool,
+    pub(crate) dead_api_calls: bool,
+    pub(crate) intents: bool,
+    pub(crate) network_indicators: bool,
+    pub(crate) kotlin: bool,
+    pub(crate) exclude_synthetic: bool,
+    pub(crate) debug_info: bool,
+    pub(crate) mnemonics: bool,
+    pub(crate) string_decryptors: Option<DecryptorThresholds>,
+    pub(crate) packer: Option<PackerRules>,
+    pub(crate) max_cfg_depth: usize,
+    pub(crate) verify: bool,
+    pub(crate) strict_classes: bool,
+    pub(crate) report_fields: Option<Vec<ReportField>>,
+    pub(crate) method_analyses: MethodAnalyses,
+    #[cfg(feature = "parallel")]
+    pub(crate) dex_cache: Option<Arc<dyn DexCache>>,
+}
+
+
+/// Version of `WalkSettings`, to bump whenever the walk of a dex changes under the same settings
+const WALK_SETTINGS_VERSION: u32 = 1;
+
+
+/// Options the walk of a single dex depends on, serialized for `AnalysisOptions::walk_fingerprint`. Enums are written
+/// by name, so that reordering their variants doesn't change the fingerprint
+#[derive(Serialize)]
+struct WalkSettings<'a> {
+    version: u32,
+    sequence_cap: usize,
+    cap_strategy: &'static str,
+    method_cap: usize,
+    include: &'a [String],
+    exclude: &'a [String],
+    strictness: &'static str,
+    opcode_map: u64,
+    shallow: bool,
+    decode_mode: &'static str,
+    with_offsets: bool,
+    op_stats: bool,
+    instructions_lite: bool,
+    exclude_synthetic: bool,
+}
+
+
+impl AnalysisOptions {
+    /// Max opcode sequence length of an input, 0 for no limit. The methods after the cap are skipped, and the method
+    /// reaching it is kept as the `cap_strategy` says. Method bounds never point past the emitted sequence.
+    /// With `dedup_methods` the cap bounds the unique sequences of all dexes and the strategy is ignored
+    pub fn sequence_cap(mut self, sequence_cap: usize) -> Self {
+        self.sequence_cap = sequence_cap;
+        self
+    }
+
+    pub fn cap_strategy(mut self, cap_strategy: CapStrategy) -> Self {
+        self.cap_strategy = cap_strategy;
+        self
+    }
+
+    /// Max number of methods emitted for an input, 0 for no limit
+    pub fn method_cap(mut self, method_cap: usize) -> Self {
+        self.method_cap = method_cap;
+        self
+    }
+
+    /// Classes whose methods are emitted
+    pub fn class_filter(mut self, class_filter: ClassFilter) -> Self {
+        self.class_filter = class_filter;
+        self
+    }
+
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Decode byte-identical method bodies once and report a table of unique sequences
+    pub fn dedup_methods(mut self, dedup_methods: bool) -> Self {
+        self.dedup_methods = dedup_methods;
+        self
+    }
+
+    pub fn dedup_scope(mut self, dedup_scope: DedupScope) -> Self {
+        self.dedup_scope = dedup_scope;
+        self
+    }
+
+    pub fn dedup_key(mut self, dedup_key: DedupKey) -> Self {
+        self.dedup_key = dedup_key;
+        self
+    }
+
+    /// Compute the call graph metrics of every dex, along with the methods with unreachable instructions
+    pub fn call_graph_metrics(mut self, call_graph_metrics: bool) -> Self {
+        self.call_graph_metrics = call_graph_metrics;
+        self
+    }
+
+    /// APIs whose calls are counted, the default watchlist otherwise
+    pub fn watchlist(mut self, watchlist: Watchlist) -> Self {
+        self.watchlist = watchlist;
+        self
+    }
+
+    /// Emit only a random subset of the decoded methods
+    pub fn sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+
+    /// Remaps the opcodes of the emitted sequences to a smaller alphabet
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Decode only opcodes and instruction lengths for the sequences, skipping operands.
+    /// Methods with a branch before their start are then kept in strict mode, and deduplication still decodes fully
+    pub fn shallow(mut self, shallow: bool) -> Self {
+        self.shallow = shallow;
+        self
+    }
+
+    /// Which code units of every method are decoded for the sequences. Recursive decoding needs the operands and ignores `shallow`,
+    /// and deduplication always decodes linearly
+    pub fn decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+        self
+    }
+
+    /// Report the code unit offset of every emitted opcode in the methods' reports. Ignored with `dedup_methods`
+    pub fn with_offsets(mut self, with_offsets: bool) -> Self {
+        self.with_offsets = with_offsets;
+        self
+    }
+
+    /// Report the invoke, const-string, new-instance, throw and monitor-enter counts and the number of distinct invoked
+    /// methods in the methods' reports. Methods are then decoded fully even when `shallow`. Ignored with `dedup_methods`
+    pub fn op_stats(mut self, op_stats: bool) -> Self {
+        self.op_stats = op_stats;
+        self
+    }
+
+    /// Report the lightweight form of every emitted instruction in the methods' reports: its opcode, offset and branch target.
+    /// Methods are then decoded fully even when `shallow`. Ignored with `dedup_methods`
+    pub fn instructions_lite(mut self, instructions_lite: bool) -> Self {
+        self.instructions_lite = instructions_lite;
+        self
+    }
+
+    /// Report every string of every dex with the number of instructions referencing it
+    pub fn string_pool(mut self, string_pool: bool) -> Self {
+        self.string_pool = string_pool;
+        self
+    }
+
+    /// How the string pool writes the strings that aren't valid MUTF-8, hex by default
+    pub fn invalid_strings(mut self, invalid_strings: InvalidStrings) -> Self {
+        self.invalid_strings = invalid_strings;
+        self
+    }
+
+    /// Report the sections declared by the map_list of every dex and the anomalies of their layout, such as data
+    /// appended past the end of the dex
+    pub fn sections(mut self, sections: bool) -> Self {
+        self.sections = sections;
+        self
+    }
+
+    /// Report every field of the selected classes with the initial value of the static final ones
+    pub fn fields(mut self, fields: bool) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    /// Report the framework APIs invoked by every method of the selected classes, in bytecode order
+    pub fn api_sequences(mut self, api_sequences: bool) -> Self {
+        self.api_sequences = api_sequences;
+        self
+    }
+
+    /// Report the calls of permission-guarded framework APIs none of whose permissions the manifest requests, see
+    /// `permissions::PERMISSION_APIS`. Only APKs with a manifest have the section
+    pub fn dead_api_calls(mut self, dead_api_calls: bool) -> Self {
+        self.dead_api_calls = dead_api_calls;
+        self
+    }
+
+    /// Report the intents created by every method of the selected classes, with their action or target component and the call starting them
+    pub fn intents(mut self, intents: bool) -> Self {
+        self.intents = intents;
+        self
+    }
+
+    /// Report the URLs, IP addresses and suspicious domains in the strings and static final fields of the selected classes,
+    /// tagged with the networking APIs invoked by the methods loading them
+    pub fn network_indicators(mut self, network_indicators: bool) -> Self {
+        self.network_indicators = network_indicators;
+        self
+    }
+
+    /// Report whether the APK is written in Kotlin and the share of its classes generated by the compiler
+    pub fn kotlin(mut self, kotlin: bool) -> Self {
+        self.kotlin = kotlin;
+        self
+    }
+
+    /// Report the source file of every class and the share of classes and methods that kept their debug info
+    pub fn debug_info(mut self, debug_info: bool) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
+
+    /// Leave out the lambdas, anonymous classes and other classes generated by the compiler, see `kotlin::synthetic_kind`,
+    /// like the classes excluded by the class filter
+    pub fn exclude_synthetic(mut self, exclude_synthetic: bool) -> Self {
+        self.exclude_synthetic = exclude_synthetic;
+        self
+    }
+
+    /// Serialize the opcodes as mnemonics, e.g. `invoke-virtual`, instead of bytes. Ignored with `Normalization::Category` and vocabularies other than `OpcodeMap::full`
+    pub fn mnemonics(mut self, mnemonics: bool) -> Self {
+        self.mnemonics = mnemonics;
+        self
+    }
+
+    /// Whether the opcodes are serialized as mnemonics: categories are not opcodes and keep their numbers, as do the
+    /// symbols of other vocabularies
+    pub fn writes_mnemonics(&self) -> bool {
+        self.mnemonics && self.normalization.keeps_opcodes()
+    }
+
+    /// Report the likely string decryption helpers of every dex
+    pub fn string_decryptors(mut self, thresholds: DecryptorThresholds) -> Self {
+        self.string_decryptors = Some(thresholds);
+        self
+    }
+
+    /// Match the packer and obfuscator fingerprints of `rules` on every APK
+    pub fn packer(mut self, rules: PackerRules) -> Self {
+        self.packer = Some(rules);
+        self
+    }
+
+    /// Max depth of the traversals of the control flow of a method, 0 for no limit. They keep their own stack
+    /// whatever the depth, the limit bounds the work spent on adversarial methods, which are then reported in the warnings
+    pub fn max_cfg_depth(mut self, max_cfg_depth: usize) -> Self {
+        self.max_cfg_depth = max_cfg_depth;
+        self
+    }
+
+    /// Check the register operands of every instruction of the selected classes against the register count of its method,
+    /// and warn about branches into the middle of an instruction
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Fail the analysis of an input on the first class definition that can't be parsed, instead of skipping it with an
+    /// `InvalidClass` warning. `analyze_dexes` can't fail and always skips
+    pub fn strict_classes(mut self, strict_classes: bool) -> Self {
+        self.strict_classes = strict_classes;
+        self
+    }
+
+    /// Only compute these sections of the reports, the others are left empty but for the header counts, read for free.
+    /// Sections enabled by their own option, such as `string_pool`, still need it. Every section is computed by default
+    pub fn report_fields(mut self, fields: impl IntoIterator<Item = ReportField>) -> Self {
+        self.report_fields = Some(fields.into_iter().collect());
+        self
+    }
+
+    /// Register a method analysis, run on every method with code of the selected classes. Its outputs are reported
+    /// under `extensions.<name>`. Panics when an analysis of the same name is already registered
+    pub fn method_analysis(mut self, analysis: impl MethodAnalysis + 'static) -> Self {
+        assert!(
+            self.method_analyses.0.iter().all(|registered| registered.name() != analysis.name()),
+            "a method analysis named {} is already registered", analysis.name(),
+        );
+        self.method_analyses.0.push(Arc::new(analysis));
+        self
+    }
+
+    /// Store of the walks of single dexes, looked up before decoding a dex and filled with the decoded ones. Only the
+    /// parallel decoding of the dexes of an APK goes through it, that is without `dedup_methods`, without a cap shared by
+    /// the dexes and without `instructions_lite`
+    #[cfg(feature = "parallel")]
+    pub fn dex_cache(mut self, cache: impl DexCache + 'static) -> Self {
+        self.dex_cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Finishes the options, a sampling rate of 1 or more keeps every method and is dropped
+    pub fn build(mut self) -> Self {
+        if self.sampling.is_some_and(|sampling| sampling.rate >= 1.0) {
+            self.sampling = None;
+        }
+        self
+    }
+
+    /// Hash of the vocabulary of the emitted sequences, see `Normalization::opcode_map`
+    pub fn opcode_map_hash(&self) -> u64 {
+        self.normalization.opcode_map().hash()
+    }
+
+    /// Hex SHA-256 of a versioned serialization of the options the walk of a single dex depends on: the caps, the
+    /// class selection, the strictness, the vocabulary and what is decoded and reported of every method. Walks stored
+    /// by a `DexCache` are only valid under the same fingerprint
+    pub fn walk_fingerprint(&self) -> String {
+        let settings = WalkSettings {
+            version: WALK_SETTINGS_VERSION,
+            sequence_cap: self.sequence_cap,
+            cap_strategy: match self.cap_strategy {
+                CapStrategy::TruncateExact => "truncate_exact",
+                CapStrategy::TruncateMethods => "truncate_methods",
+                CapStrategy::PerDex => "per_dex",
+            },
+            method_cap: self.method_cap,
+            include: &self.class_filter.include,
+            exclude: &self.class_filter.exclude,
+            strictness: match self.strictness {
+                Strictness::Strict => "strict",
+                Strictness::Lenient => "lenient",
+            },
+            opcode_map: self.opcode_map_hash(),
+            shallow: self.shallow,
+            decode_mode: match self.decode_mode {
+                DecodeMode::Linear => "linear",
+                DecodeMode::Recursive => "recursive",
+            },
+            with_offsets: self.with_offsets,
+            op_stats: self.op_stats,
+            instructions_lite: self.instructions_lite,
+            exclude_synthetic: self.exclude_synthetic,
+        };
+        let serialized = serde_json::to_vec(&settings).expect("the settings serialize to JSON");
+        format!("{:x}", Sha256::digest(serialized))
+    }
+
+    /// Whether the dexes of an APK share a cap, which then has to be applied to them one after the other
+    #[cfg(feature = "parallel")]
+    pub(crate) fn shares_caps(&self) -> bool {
+        self.method_cap > 0 || (self.sequence_cap > 0 && self.cap_strategy != CapStrategy::PerDex)
+    }
+
+    pub(crate) fn lenient(&self) -> bool {
+        self.strictness == Strictness::Lenient
+    }
+
+    /// Whether `field` is among the `report_fields`
+    pub(crate) fn selects(&self, field: ReportField) -> bool {
+        self.report_fields.as_ref().is_none_or(|fields| fields.contains(&field))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_options() {
+        let options = AnalysisOptions::default().build();
+        assert_eq!(options.sequence_cap, 0);
+        assert_eq!(options.cap_strategy, CapStrategy::TruncateExact);
+        assert_eq!(options.method_cap, 0);
+        assert!(options.class_filter.is_empty());
+        assert_eq!(options.strictness, Strictness::Strict);
+        assert!(!options.dedup_methods && !options.call_graph_metrics);
+        assert!(options.sampling.is_none());
+        assert_eq!(options.normalization, Normalization::None);
+        assert!(!options.shallow && !options.with_offsets && !options.op_stats && !options.instructions_lite);
+        assert!(AnalysisOptions::default().sampling(Sampling { rate: 1.0, seed: 0 }).build().sampling.is_none());
+    }
+
+    #[test]
+    fn test_walk_fingerprint() {
+        let fingerprint = AnalysisOptions::default().walk_fingerprint();
+        assert_eq!(fingerprint.len(), 64);
+        assert_eq!(fingerprint, AnalysisOptions::default().dedup_methods(true).intents(true).walk_fingerprint());
+        assert_ne!(fingerprint, AnalysisOptions::default().sequence_cap(10).walk_fingerprint());
+        assert_ne!(fingerprint, AnalysisOptions::default().class_filter(ClassFilter::default().exclude("Landroidx/")).walk_fingerprint());
+        assert_ne!(fingerprint, AnalysisOptions::default().normalization(Normalization::Category).walk_fingerprint());
+    }
+
+    #[test]
+    fn test_class_filter() {
+        let filter = ClassFilter::default().exclude("Landroidx/");
+        assert!(filter.matches("Lcom/example/Main;"));
+        assert!(!filter.matches("Landroidx/core/app/ActivityCompat;"));
+        let filter = ClassFilter::default().include("Lcom/example/").exclude("Lcom/example/R$");
+        assert!(filter.matches("Lcom/example/Main;"));
+        assert!(!filter.matches("Lcom/example/R$id;"));
+        assert!(!filter.matches("Lorg/other/Main;"));
+    }
+
+    #[test]
+    fn test_sampling_same_seed_same_subset() {
+        let sampling = Sampling { rate: 0.1, seed: 42 };
+        let selected = sampling.select(1000, 0);
+        assert_eq!(selected, sampling.select(1000, 0));
+        assert!((50..150).contains(&selected.iter().filter(|&&keep| keep).count()));
+        assert_ne!(selected, Sampling { seed: 43, ..sampling }.select(1000, 0));
+        assert!(Sampling { rate: 1.0, seed: 42 }.select(100, 0).into_iter().all(|keep| keep));
+    }
+
+    #[test]
+    fn test_normalization() {
+        let opcodes = [Opcode::InvokeSuper, Opcode::ConstString, Opcode::IgetObject, Opcode::InvokeStaticRange, Opcode::IfEqz, Opcode::ReturnVoid];
+        let normalized = |normalization: Normalization| opcodes.iter().map(|&opcode| normalization.apply(opcode)).collect::<Vec<_>>();
+        assert_eq!(normalized(Normalization::None), vec![0x6F, 0x1A, 0x54, 0x77, 0x38, 0x0E]);
+        assert_eq!(normalized(Normalization::InvokeMerged), vec![0x6E, 0x1A, 0x54, 0x6E, 0x38, 0x0E]);
+        let categories = [OpcodeCategory::Invoke, OpcodeCategory::Const, OpcodeCategory::InstanceField, OpcodeCategory::Invoke, OpcodeCategory::If, OpcodeCategory::Return];
+        assert_eq!(normalized(Normalization::Category), categories.map(|category| category as u8).to_vec());
+        assert_eq!(normalized(Normalization::Map(Box::new(OpcodeMap::kinds()))), normalized(Normalization::Category));
+        assert_eq!(Normalization::Category.opcode_map(), OpcodeMap::kinds());
+        assert_eq!(Normalization::None.opcode_map().hash(), OpcodeMap::full().hash());
+        assert!(Normalization::Map(Box::new(OpcodeMap::full())).keeps_opcodes() && !Normalization::Map(Box::new(OpcodeMap::kinds())).keeps_opcodes());
+    }
+}
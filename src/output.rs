@@ -0,0 +1,682 @@
+use std::{borrow::Cow, collections::HashMap, io::{self, Write}, mem, sync::mpsc::{sync_channel, SyncSender}, thread::{self, JoinHandle}};
+
+use dexompiler::{access_flags::MethodFlags, fields::FieldRecord, string_pool::PoolString, verify::DecodeFailure, ApkReport, FormattedSequences, Manifest, Opcode, ReportField, Sequences};
+use num_traits::FromPrimitive;
+use serde::{ser::{Error as _, SerializeMap}, Serialize, Serializer};
+
+use crate::{cli::Granularity, stats::Summary};
+
+
+/// Size in bytes after which a worker's batch is handed to the writer, whatever its record count
+pub const BATCH_BYTES: usize = 1 << 20;
+
+
+/// Version of the layout of the records and of the json object around them, bumped whenever keys are added, moved or
+/// dropped so that consumers and merges can tell outputs apart whatever `version` wrote them:
+/// 1. before the layout was versioned
+/// 2. register and argument counts of the methods, json inputs under `inputs`
+/// 3. watchlist hits of the APK records, with their tags
+pub const SCHEMA_VERSION: u32 = 3;
+
+
+/// Header of the output, the first line in ndjson mode and the `meta` key in json mode, where the inputs follow under
+/// the `inputs` key. The output ends with a `stats::Summary` footer in the same way
+#[derive(Serialize)]
+pub struct Meta {
+    pub version: &'static str,
+    /// `SCHEMA_VERSION` of the output
+    pub schema: u32,
+    pub granularity: Granularity,
+    /// Whether class and method records cover the methods without code
+    pub include_codeless: bool,
+    /// Whether records only hold the manifest of every input
+    pub manifest_only: bool,
+    /// Hash of the vocabulary of the opcode sequences, outputs with different hashes can't be concatenated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opcode_map: Option<String>,
+    /// Sections of the APK records, all of them when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<ReportField>>,
+    /// Whether the string pool and the fields of an input follow its APK record as records of their own, one per
+    /// string and field, rather than sections of it. They always do at class and method granularity
+    #[serde(skip)]
+    pub row_records: bool,
+    /// Whether the opcodes of the sequences are written as their mnemonics, see `AnalysisOptions::writes_mnemonics`
+    #[serde(skip)]
+    pub mnemonics: bool,
+}
+
+
+impl Meta {
+    pub fn new(granularity: Granularity, include_codeless: bool) -> Self {
+        Self { version: env!("CARGO_PKG_VERSION"), schema: SCHEMA_VERSION, granularity, include_codeless, manifest_only: false, opcode_map: None, fields: None, row_records: false, mnemonics: false }
+    }
+
+    pub fn manifest_only(self, manifest_only: bool) -> Self {
+        Self { manifest_only, ..self }
+    }
+
+    pub fn opcode_map(self, hash: u64) -> Self {
+        Self { opcode_map: Some(format!("{:016x}", hash)), ..self }
+    }
+
+    /// Keeps only the keys of `fields` in the APK records, all of them when empty
+    pub fn fields(self, fields: &[ReportField]) -> Self {
+        Self { fields: (!fields.is_empty()).then(|| fields.to_vec()), ..self }
+    }
+
+    pub fn row_records(self, row_records: bool) -> Self {
+        Self { row_records, ..self }
+    }
+
+    pub fn mnemonics(self, mnemonics: bool) -> Self {
+        Self { mnemonics, ..self }
+    }
+}
+
+
+/// Record of the output. The path is left out in json mode, where records are keyed by it
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Record<'a> {
+    Apk {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        #[serde(flatten)]
+        report: SelectedReport<'a>,
+    },
+    Class {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        /// SHA-256 of the input, see `ApkReport::sha256`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<&'a str>,
+        class: &'a str,
+        method_count: usize,
+        #[serde(flatten)]
+        sequences: FormattedSequences<Sequences>,
+    },
+    Method {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<&'a str>,
+        class: &'a str,
+        method: &'a str,
+        /// Parameter and return types, e.g. `(Ljava/lang/String;I)V`, telling overloads apart
+        descriptor: &'a str,
+        flags: MethodFlags,
+        #[serde(flatten)]
+        sequences: FormattedSequences<Sequences>,
+    },
+    /// String of the string pool of an input, with `--emit string-pool`
+    PoolString {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<&'a str>,
+        #[serde(flatten)]
+        string: &'a PoolString,
+    },
+    /// Field of a selected class of an input, with `--emit fields`
+    Field {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<&'a str>,
+        #[serde(flatten)]
+        field: &'a FieldRecord,
+    },
+    /// Manifest of an input read with `--emit manifest`, no fields besides the path for an APK without one
+    Manifest {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        #[serde(flatten)]
+        manifest: Option<&'a Manifest>,
+    },
+    /// Requested permissions of an input read with `--only-permissions`
+    Permissions {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        permissions: &'a [String],
+    },
+    /// Record of an input analyzed by a child process with `--isolate`, as the child wrote it
+    Isolated {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        #[serde(flatten)]
+        record: &'a serde_json::Value,
+    },
+    /// Code unit of an input that failed to decode with `--verify-only`
+    DecodeFailure {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a str>,
+        #[serde(flatten)]
+        failure: &'a DecodeFailure,
+    },
+}
+
+
+/// Report of an APK record as set by `meta`: with only the keys of the selected fields or whole without a selection,
+/// and without the codeless methods unless included
+pub struct SelectedReport<'a> {
+    pub report: &'a ApkReport,
+    pub meta: &'a Meta,
+}
+
+
+impl Serialize for SelectedReport<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let meta = self.meta;
+        // The string pool and the fields are left out for the records of their own following the APK record
+        let rows = meta.row_records && (self.report.string_pool.is_some() || self.report.fields.is_some());
+        let codeless = !meta.include_codeless && !self.report.codeless_methods.is_empty();
+        if meta.fields.is_none() && !rows && !codeless && !meta.mnemonics {
+            return self.report.serialize(serializer);
+        }
+        let mut report = match self.report.to_value(meta.mnemonics).map_err(S::Error::custom)? {
+            serde_json::Value::Object(report) => report,
+            _ => return Err(S::Error::custom("a report serializes as an object")),
+        };
+        // The hash identifies the input, whatever the selection
+        report.retain(|key, _| {
+            let selected = key == "sha256" || meta.fields.as_ref().is_none_or(|fields| fields.iter().any(|field| field.has_key(key)));
+            selected && !(rows && (key == "string_pool" || key == "fields")) && !(codeless && key == "codeless_methods")
+        });
+        report.serialize(serializer)
+    }
+}
+
+
+/// Records of the report of one input at the granularity of `meta`, class and method records need flat sequences and
+/// are an error for deduplicated ones.
+/// Methods without code get empty sequences when `meta` includes them, and are only listed by APK records then.
+/// They are followed by a record per string of the string pool and per field, unless these stay in the APK record
+pub fn records<'a>(path: Option<&'a str>, report: &'a ApkReport, meta: &'a Meta) -> serde_json::Result<Vec<Record<'a>>> {
+    let sha256 = report.sha256.as_deref();
+    let row_records = meta.row_records || meta.granularity != Granularity::Apk;
+    let mut records = sequence_records(path, report, meta)?;
+    if row_records {
+        let strings = report.string_pool.iter().flatten().map(|string| Record::PoolString { path, sha256, string });
+        let fields = report.fields.iter().flatten().map(|field| Record::Field { path, sha256, field });
+        records.extend(strings.chain(fields));
+    }
+    Ok(records)
+}
+
+
+fn sequence_records<'a>(path: Option<&'a str>, report: &'a ApkReport, meta: &'a Meta) -> serde_json::Result<Vec<Record<'a>>> {
+    let codeless = report.codeless_methods.iter().filter(|_| meta.include_codeless);
+    let sha256 = report.sha256.as_deref();
+    let formatted = |sequences| FormattedSequences { sequences, mnemonics: meta.mnemonics };
+    let deduplicated = |granularity| move || serde_json::Error::custom(format!("{} records need the sequences of every method, which deduplication drops", granularity));
+    Ok(match meta.granularity {
+        Granularity::Apk => vec![Record::Apk { path, report: SelectedReport { report, meta } }],
+        Granularity::Class => {
+            let mut records: Vec<Record> = report.sequences.by_class()
+                .ok_or_else(deduplicated("class"))?
+                .into_iter()
+                .map(|(class, method_count, sequences)| Record::Class { path, sha256, class, method_count, sequences: formatted(sequences) })
+                .collect();
+            for method in codeless {
+                let record = records.iter_mut().find(|record| matches!(record, Record::Class { class, .. } if *class == method.class));
+                match record {
+                    Some(Record::Class { method_count, .. }) => *method_count += 1,
+                    _ => records.push(Record::Class { path, sha256, class: &method.class, method_count: 1, sequences: formatted(Sequences::flat(vec![], vec![])) }),
+                }
+            }
+            records
+        },
+        Granularity::Method => report.sequences.by_method()
+            .ok_or_else(deduplicated("method"))?
+            .into_iter()
+            .map(|(method, sequences)| Record::Method {
+                path,
+                sha256,
+                class: method.class(),
+                method: method.name(),
+                descriptor: method.descriptor(),
+                flags: method.flags(),
+                sequences: formatted(sequences),
+            })
+            .chain(codeless.map(|method| Record::Method {
+                path,
+                sha256,
+                class: &method.class,
+                method: &method.name,
+                descriptor: &method.descriptor,
+                flags: method.flags,
+                sequences: formatted(Sequences::flat(vec![], vec![])),
+            }))
+            .collect(),
+    })
+}
+
+
+/// Entries of the `inputs` key of json outputs, keyed by path
+struct Inputs<K, V>(Vec<(K, V)>);
+
+
+impl<K: AsRef<str>, V: Serialize> Serialize for Inputs<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key.as_ref(), value)?;
+        }
+        map.end()
+    }
+}
+
+
+/// Records of an input in json mode: its only record when `single`, the list of its records otherwise
+struct InputRecords<'a, R> {
+    records: &'a [R],
+    single: bool,
+}
+
+
+impl<R: Serialize> Serialize for InputRecords<'_, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.records {
+            [record] if self.single => record.serialize(serializer),
+            records => records.serialize(serializer),
+        }
+    }
+}
+
+
+/// Writes the meta header, the records of every input keyed by path under `inputs` and the summary footer as a single
+/// JSON object. At apk granularity every path maps to its report, otherwise to the list of its records.
+/// The string pool and the fields stay in the report unless `meta` asks for records of their own.
+/// The summary is taken once the records are written
+pub fn write_json<K: AsRef<str>>(writer: impl Write, meta: &Meta, reports: &HashMap<K, ApkReport>, summary: impl FnOnce() -> Summary) -> serde_json::Result<()> {
+    let records = reports.iter()
+        .map(|(path, report)| Ok((path, records(None, report, meta)?)))
+        .collect::<serde_json::Result<Vec<_>>>()?;
+    let single = meta.granularity == Granularity::Apk && !meta.row_records;
+    let inputs = Inputs(records.iter().map(|(path, records)| (path, InputRecords { records, single })).collect());
+    let mut serializer = serde_json::Serializer::new(writer);
+    let mut map = serializer.serialize_map(Some(3))?;
+    map.serialize_entry("meta", meta)?;
+    map.serialize_entry("inputs", &inputs)?;
+    map.serialize_entry("summary", &summary())?;
+    map.end()
+}
+
+
+/// Writes the opcode histogram of every input as CSV, one row per input in path order. The `path`, `version`, `sha256`
+/// and `methods` columns are followed by a count column per opcode, named by its mnemonic in opcode byte order.
+/// `sha256` is empty for reports not read from an archive
+pub fn write_csv<K: AsRef<str>>(mut writer: impl Write, meta: &Meta, reports: &HashMap<K, ApkReport>) -> io::Result<()> {
+    let opcodes: Vec<Opcode> = (0..=u8::MAX).filter_map(Opcode::from_u8).collect();
+    write!(writer, "path,version,sha256,methods")?;
+    for opcode in &opcodes {
+        write!(writer, ",{}", opcode.mnemonic())?;
+    }
+    writeln!(writer)?;
+    let mut reports: Vec<(&str, &ApkReport)> = reports.iter().map(|(path, report)| (path.as_ref(), report)).collect();
+    reports.sort_by_key(|(path, _)| *path);
+    for (path, report) in reports {
+        let sequences = &report.sequences;
+        write!(writer, "{},{},{},{}", csv_field(path), meta.version, report.sha256.as_deref().unwrap_or_default(), sequences.method_count())?;
+        let histogram = sequences.opcode_histogram();
+        for opcode in &opcodes {
+            write!(writer, ",{}", histogram[*opcode as usize])?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+
+/// Quotes a CSV field holding a separator, a quote or a line break
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+
+/// Writes a value of every input keyed by path, under `inputs` after the meta header when there is one, as a bare
+/// JSON object otherwise
+pub fn write_keyed_json<K: AsRef<str>, V: Serialize>(writer: impl Write, meta: Option<&Meta>, values: impl IntoIterator<Item = (K, V)>) -> serde_json::Result<()> {
+    let inputs = Inputs(values.into_iter().collect());
+    let mut serializer = serde_json::Serializer::new(writer);
+    let Some(meta) = meta else { return inputs.serialize(&mut serializer) };
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry("meta", meta)?;
+    map.serialize_entry("inputs", &inputs)?;
+    map.end()
+}
+
+
+/// Writes the meta header, the records of every input analyzed with `--isolate` and the summary footer as a single
+/// JSON object, laid out like `write_json`
+pub fn write_isolated_json<K: AsRef<str>>(writer: impl Write, meta: &Meta, records: &HashMap<K, Vec<serde_json::Value>>, summary: impl FnOnce() -> Summary) -> serde_json::Result<()> {
+    let single = meta.granularity == Granularity::Apk;
+    let inputs = Inputs(records.iter().map(|(path, records)| (path, InputRecords { records, single })).collect());
+    let mut serializer = serde_json::Serializer::new(writer);
+    let mut map = serializer.serialize_map(Some(3))?;
+    map.serialize_entry("meta", meta)?;
+    map.serialize_entry("inputs", &inputs)?;
+    map.serialize_entry("summary", &summary())?;
+    map.end()
+}
+
+
+/// Ends ndjson output with the summary footer, a `summary` record
+pub fn write_ndjson_summary(mut writer: impl Write, summary: &Summary) -> io::Result<()> {
+    serde_json::to_writer(&mut writer, &HashMap::from([("summary", summary)]))?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+
+/// Writes newline-delimited JSON records on a dedicated thread.
+/// Workers serialize into their own `RecordBatcher` and send whole batches, so records never interleave
+pub struct NdjsonWriter<W> {
+    sender: SyncSender<Vec<u8>>,
+    handle: JoinHandle<io::Result<W>>,
+}
+
+
+impl<W: Write + Send + 'static> NdjsonWriter<W> {
+    /// Spawns the writer thread, at most `capacity` batches wait to be written before workers block
+    pub fn new(mut writer: W, capacity: usize) -> Self {
+        let (sender, receiver) = sync_channel::<Vec<u8>>(capacity);
+        let handle = thread::spawn(move || {
+            for batch in receiver {
+                writer.write_all(&batch)?;
+            }
+            writer.flush()?;
+            Ok(writer)
+        });
+        Self { sender, handle }
+    }
+
+    pub fn batcher(&self, max_records: usize, max_bytes: usize) -> RecordBatcher {
+        RecordBatcher { sender: self.sender.clone(), buffer: vec![], records: 0, max_records, max_bytes }
+    }
+
+    /// Waits for every batch sent so far to be written, batchers must have been dropped before
+    pub fn finish(self) -> io::Result<W> {
+        drop(self.sender);
+        self.handle.join()
+            .unwrap_or_else(|_| Err(io::Error::other("writer thread panicked")))
+    }
+}
+
+
+/// Buffers serialized records of one worker, flushing them as one batch once either limit is reached and on drop
+pub struct RecordBatcher {
+    sender: SyncSender<Vec<u8>>,
+    buffer: Vec<u8>,
+    records: usize,
+    max_records: usize,
+    max_bytes: usize,
+}
+
+
+impl RecordBatcher {
+    pub fn push(&mut self, record: &impl Serialize) -> serde_json::Result<()> {
+        let len = self.buffer.len();
+        if let Err(err) = serde_json::to_writer(&mut self.buffer, record) {
+            // Drop the partially serialized record
+            self.buffer.truncate(len);
+            return Err(err);
+        }
+        self.buffer.push(b'\n');
+        self.records += 1;
+        if self.records >= self.max_records || self.buffer.len() >= self.max_bytes {
+            self.flush();
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            // The receiver only goes away once the writer failed, which `finish` reports
+            let _ = self.sender.send(mem::take(&mut self.buffer));
+            self.records = 0;
+        }
+    }
+}
+
+
+impl Drop for RecordBatcher {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, thread};
+
+    use dex::DexReader;
+    use dexompiler::{
+        analyze_dexes,
+        testing::{sample_dex, ClassDef, CodeDef, DexBuilder, FieldDef, MethodDef, ValueDef, ACC_ABSTRACT, ACC_FINAL, ACC_INTERFACE, ACC_PUBLIC, ACC_STATIC, SAMPLE_METHODS},
+        AnalysisOptions, Manifest, NamedDex, ReportField, Strictness,
+    };
+    use serde::Serialize;
+
+    use super::{csv_field, records, write_csv, write_json, write_keyed_json, Granularity, Meta, NdjsonWriter, Record as OutputRecord, Summary, BATCH_BYTES};
+
+    #[derive(Serialize)]
+    struct Record {
+        worker: usize,
+        index: usize,
+        payload: String,
+    }
+
+    #[test]
+    fn test_batches_keep_records_whole() {
+        let writer = NdjsonWriter::new(vec![], 4);
+        thread::scope(|scope| {
+            for worker in 0..8 {
+                let mut batcher = writer.batcher(16, BATCH_BYTES);
+                scope.spawn(move || {
+                    for index in 0..1000 {
+                        batcher.push(&Record { worker, index, payload: "x".repeat(index % 50) }).unwrap();
+                    }
+                });
+            }
+        });
+        let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let mut counts = [0; 8];
+        for line in output.lines() {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            counts[record["worker"].as_u64().unwrap() as usize] += 1;
+        }
+        assert_eq!(counts, [1000; 8]);
+    }
+
+    #[test]
+    fn test_flush_on_size_and_drop() {
+        let writer = NdjsonWriter::new(vec![], 16);
+        {
+            let mut batcher = writer.batcher(usize::MAX, 1);
+            batcher.push(&Record { worker: 0, index: 0, payload: String::new() }).unwrap();
+            assert!(batcher.buffer.is_empty());
+            let mut batcher = writer.batcher(usize::MAX, BATCH_BYTES);
+            batcher.push(&Record { worker: 1, index: 0, payload: String::new() }).unwrap();
+            assert!(!batcher.buffer.is_empty());
+        }
+        let output = writer.finish().unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_records_per_granularity() {
+        let options = AnalysisOptions::default().strictness(Strictness::Lenient);
+        let report = analyze_dexes(NamedDex::multidex([DexReader::from_vec(sample_dex(3)).unwrap()]), None, &options);
+        let count = |granularity| records(Some("app.apk"), &report, &Meta::new(granularity, false)).unwrap().len();
+        assert_eq!(count(Granularity::Apk), 1);
+        assert_eq!(count(Granularity::Class), 3);
+        assert_eq!(count(Granularity::Method), 3 * SAMPLE_METHODS.len());
+
+        let record = serde_json::to_value(&records(Some("app.apk"), &report, &Meta::new(Granularity::Class, false)).unwrap()[1]).unwrap();
+        assert_eq!(record["path"], "app.apk");
+        assert_eq!(record["class"], "Lorg/example/Sample1;");
+        assert_eq!(record["method_count"], SAMPLE_METHODS.len());
+        let record = serde_json::to_value(&records(Some("app.apk"), &report, &Meta::new(Granularity::Method, false)).unwrap()[0]).unwrap();
+        assert_eq!(record["method"], SAMPLE_METHODS[0].0);
+        assert_eq!(record["methods"][0]["start"], 0);
+        let meta = Meta::new(Granularity::Method, false).mnemonics(true);
+        assert!(serde_json::to_value(&records(Some("app.apk"), &report, &meta).unwrap()[0]).unwrap()["op_seq"][0].is_string());
+        let meta = Meta::new(Granularity::Apk, false).mnemonics(true);
+        assert!(serde_json::to_value(&records(Some("app.apk"), &report, &meta).unwrap()[0]).unwrap()["op_seq"][0].is_string());
+
+        let mut output = vec![];
+        write_json(&mut output, &Meta::new(Granularity::Method, false), &HashMap::from([("app.apk", report)]), Summary::default).unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(output["meta"]["granularity"], "method");
+        assert_eq!(output["meta"]["schema"], super::SCHEMA_VERSION);
+        assert_eq!(output["inputs"]["app.apk"].as_array().unwrap().len(), 3 * SAMPLE_METHODS.len());
+        assert!(output["inputs"]["app.apk"][0].get("path").is_none());
+        assert_eq!(output["summary"]["inputs_processed"], 0);
+    }
+
+    #[test]
+    fn test_json_inputs_apart_from_meta() {
+        let options = AnalysisOptions::default().strictness(Strictness::Lenient);
+        let report = analyze_dexes(NamedDex::multidex([DexReader::from_vec(sample_dex(1)).unwrap()]), None, &options);
+        let mut output = vec![];
+        write_json(&mut output, &Meta::new(Granularity::Apk, false), &HashMap::from([("meta", report)]), Summary::default).unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(output["meta"]["granularity"], "apk");
+        assert!(output["inputs"]["meta"]["op_seq"].is_array());
+
+        // Class and method records can't be split out of deduplicated sequences
+        let report = analyze_dexes(NamedDex::multidex([DexReader::from_vec(sample_dex(2)).unwrap()]), None, &options.dedup_methods(true));
+        assert!(records(None, &report, &Meta::new(Granularity::Method, false)).is_err());
+        assert!(write_json(vec![], &Meta::new(Granularity::Class, false), &HashMap::from([("app.apk", report)]), Summary::default).is_err());
+    }
+
+    #[test]
+    fn test_records_include_codeless() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Listener;").access_flags(ACC_PUBLIC | ACC_INTERFACE | ACC_ABSTRACT)
+            .method(MethodDef::new("onEvent", "V", &[]).access_flags(ACC_PUBLIC | ACC_ABSTRACT))
+            .method(MethodDef::new("onError", "V", &[]).access_flags(ACC_PUBLIC | ACC_ABSTRACT)));
+        builder.class(ClassDef::new("Lcom/example/Main;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let report = analyze_dexes(NamedDex::multidex([DexReader::from_vec(builder.build()).unwrap()]), None, &AnalysisOptions::default());
+        let methods = |include_codeless| records(Some("app.apk"), &report, &Meta::new(Granularity::Method, include_codeless)).unwrap().into_iter()
+            .map(|record| serde_json::to_value(&record).unwrap())
+            .map(|record| (record["method"].as_str().unwrap().to_string(), record["op_seq"].as_array().unwrap().len()))
+            .collect::<Vec<_>>();
+        assert_eq!(methods(false), [("run".to_string(), 1)]);
+        assert_eq!(methods(true), [("run".to_string(), 1), ("onEvent".to_string(), 0), ("onError".to_string(), 0)]);
+        let record = serde_json::to_value(&records(None, &report, &Meta::new(Granularity::Method, true)).unwrap()[1]).unwrap();
+        assert_eq!(record["flags"]["abstract"], true);
+        assert_eq!((&record["class"], &record["descriptor"]), (&"Lcom/example/Listener;".into(), &"()V".into()));
+        let record = serde_json::to_value(&records(None, &report, &Meta::new(Granularity::Method, true)).unwrap()[0]).unwrap();
+        assert_eq!(record["descriptor"], "()V");
+        let codeless = |include_codeless| serde_json::to_value(&records(None, &report, &Meta::new(Granularity::Apk, include_codeless)).unwrap()[0]).unwrap()
+            .get("codeless_methods")
+            .map(|methods| methods.as_array().unwrap().len());
+        assert_eq!((codeless(false), codeless(true)), (None, Some(2)));
+        assert_eq!(records(None, &report, &Meta::new(Granularity::Class, false)).unwrap().len(), 1);
+        assert_eq!(records(None, &report, &Meta::new(Granularity::Class, true)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_records_with_fields() {
+        let report = analyze_dexes(NamedDex::multidex([DexReader::from_vec(sample_dex(1)).unwrap()]), None, &AnalysisOptions::default());
+        let keys = |meta: &Meta| match serde_json::to_value(&records(Some("app.apk"), &report, meta).unwrap()[0]).unwrap() {
+            serde_json::Value::Object(record) => record.keys().cloned().collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        let all = keys(&Meta::new(Granularity::Apk, false));
+        assert!(all.contains(&"watchlist".to_string()) && all.contains(&"header_counts".to_string()));
+        let selected = keys(&Meta::new(Granularity::Apk, false).fields(&[ReportField::Opcodes, ReportField::Coverage]));
+        assert!(selected.contains(&"path".to_string()) && selected.contains(&"op_seq".to_string()) && selected.contains(&"coverage".to_string()));
+        assert!(!selected.contains(&"watchlist".to_string()) && !selected.contains(&"header_counts".to_string()) && !selected.contains(&"dexes".to_string()));
+        assert_eq!(keys(&Meta::new(Granularity::Apk, false).fields(&[])), all);
+    }
+
+    #[test]
+    fn test_row_records() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Config;")
+            .field(FieldDef::new("URL", "Ljava/lang/String;").access_flags(ACC_PUBLIC | ACC_STATIC | ACC_FINAL).value(ValueDef::String("https://example.com".to_string())))
+            .method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let options = AnalysisOptions::default().string_pool(true).fields(true);
+        let report = analyze_dexes(NamedDex::multidex([DexReader::from_vec(builder.build()).unwrap()]), None, &options);
+        let strings = report.string_pool.as_ref().unwrap().len();
+        let values = |meta: &Meta| records(Some("app.apk"), &report, meta).unwrap().iter().map(|record| serde_json::to_value(record).unwrap()).collect::<Vec<_>>();
+
+        // The sections stay in the APK record unless asked otherwise
+        let nested = values(&Meta::new(Granularity::Apk, false));
+        assert_eq!(nested.len(), 1);
+        assert_eq!(nested[0]["fields"][0]["name"], "URL");
+
+        let rows = values(&Meta::new(Granularity::Apk, false).row_records(true));
+        assert_eq!(rows.len(), 1 + strings + 1);
+        assert!(rows[0].get("string_pool").is_none() && rows[0].get("fields").is_none() && rows[0].get("op_seq").is_some());
+        let string = rows[1..=strings].iter().find(|record| record["value"] == "https://example.com").unwrap();
+        assert_eq!((&string["path"], &string["dex"], &string["uses"], &string["valid_utf8"]), (&"app.apk".into(), &0.into(), &0.into(), &true.into()));
+        let field = rows.last().unwrap();
+        assert_eq!(field["class"], "Lcom/example/Config;");
+        assert_eq!(field["value"], serde_json::json!({"kind": "string", "value": "https://example.com"}));
+
+        // Class and method records always leave them to their own records
+        assert_eq!(values(&Meta::new(Granularity::Method, false)).len(), 1 + strings + 1);
+        let mut output = vec![];
+        write_json(&mut output, &Meta::new(Granularity::Apk, false), &HashMap::from([("app.apk", report)]), Summary::default).unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(output["inputs"]["app.apk"]["string_pool"].as_array().unwrap().len(), strings);
+    }
+
+    #[test]
+    fn test_manifest_records() {
+        let manifest = Manifest { permissions: vec!["INTERNET".to_string()], application: Some("com.example.App".to_string()), launcher_activity: None, components: vec![] };
+        let record = serde_json::to_value(OutputRecord::Manifest { path: Some("app.apk"), manifest: Some(&manifest) }).unwrap();
+        assert_eq!(record, serde_json::json!({"path": "app.apk", "permissions": ["INTERNET"], "application": "com.example.App"}));
+        let record = serde_json::to_value(OutputRecord::Manifest { path: Some("empty.apk"), manifest: None }).unwrap();
+        assert_eq!(record, serde_json::json!({"path": "empty.apk"}));
+
+        let mut output = vec![];
+        let meta = Meta::new(Granularity::Apk, false).manifest_only(true);
+        let manifests = [("app.apk", Some(manifest)), ("empty.apk", None)];
+        write_keyed_json(&mut output, Some(&meta), manifests.iter().map(|(path, manifest)| (path, OutputRecord::Manifest { path: None, manifest: manifest.as_ref() }))).unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(output["meta"]["manifest_only"], true);
+        assert!(output["meta"].get("opcode_map").is_none());
+        assert_eq!(output["inputs"]["app.apk"], serde_json::json!({"permissions": ["INTERNET"], "application": "com.example.App"}));
+        assert_eq!(output["inputs"]["empty.apk"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut builder = DexBuilder::new();
+        // const/4 v0, 0; const/4 v0, 0; return-void
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("first", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x0012, 0x000E])))
+            // return-void
+            .method(MethodDef::new("second", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        let report = analyze_dexes(NamedDex::multidex([DexReader::from_vec(builder.build()).unwrap()]), None, &AnalysisOptions::default());
+        let mut output = vec![];
+        write_csv(&mut output, &Meta::new(Granularity::Apk, false), &HashMap::from([("app, v2.apk", report)])).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("path,version,sha256,methods,nop,move,move/from16,"));
+        assert!(lines[0].ends_with(",const-method-type"));
+
+        let header: Vec<&str> = lines[0].split(',').collect();
+        let row: Vec<&str> = lines[1].strip_prefix("\"app, v2.apk\",").unwrap().split(',').collect();
+        assert_eq!(row.len(), header.len() - 1);
+        let column = |name| row[header.iter().position(|column| *column == name).unwrap() - 1];
+        assert_eq!(column("version"), env!("CARGO_PKG_VERSION"));
+        // Unknown without an archive
+        assert_eq!(column("sha256"), "");
+        assert_eq!(column("methods"), "2");
+        assert_eq!((column("const/4"), column("return-void"), column("nop")), ("2", "2", "0"));
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,353 @@
+use std::{collections::{HashMap, HashSet}, io};
+#[cfg(feature = "fs")]
+use std::{fs, path::Path};
+
+use dex::Dex;
+use serde::{Deserialize, Serialize};
+
+use crate::{dex_parsing::unreachable_instructions, manifest_parsing::Manifest, warning::{Warning, WarningKind}};
+
+
+/// Rules shipped with the crate, see the comments of the file for their syntax
+pub const DEFAULT_RULES: &str = include_str!("packer_rules.toml");
+
+/// Minimum share of the classes in packages with single character names, e.g. `Lo/a;`
+const SHORT_PACKAGE_SHARE: f64 = 0.5;
+/// Minimum share of the classes with non-ASCII characters in their descriptor
+const UNICODE_NAME_SHARE: f64 = 0.1;
+/// Minimum share of the decoded instructions that can't be reached from the entry of their method or a catch handler
+const DEAD_CODE_SHARE: f64 = 0.05;
+/// Maximum number of classes of all dexes of an APK whose code is unpacked from an asset
+const TINY_DEX_CLASSES: u32 = 64;
+/// Minimum size in bytes of an encrypted asset
+const ENCRYPTED_ASSET_SIZE: u64 = 64 * 1024;
+/// Minimum entropy in bits per byte of an encrypted asset
+const ENCRYPTED_ASSET_ENTROPY: f64 = 7.5;
+/// Class loaders able to load code unpacked at runtime
+const CLASS_LOADERS: &[&str] = &["Ldalvik/system/DexClassLoader;", "Ldalvik/system/InMemoryDexClassLoader;"];
+
+
+/// Entry of the `assets/` directory of an APK
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    /// Path in the archive, e.g. `assets/data.bin`
+    pub name: String,
+    pub size: u64,
+    /// Shannon entropy of the contents, in bits per byte
+    pub entropy: f64,
+}
+
+
+impl Asset {
+    pub fn new(name: impl Into<String>, contents: &[u8]) -> Self {
+        Self { name: name.into(), size: contents.len() as u64, entropy: entropy(contents) }
+    }
+}
+
+
+/// Property of an APK that rules can require, computed only when a rule uses it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Signal {
+    ShortPackageNames,
+    UnicodeClassNames,
+    DeadCode,
+    /// Few classes in the dexes next to a large asset with high entropy
+    EncryptedAssets,
+    /// A reference to a class loader of dex files
+    DexClassLoader,
+}
+
+
+/// What made a rule match
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Evidence {
+    Class { descriptor: String },
+    Application { name: String },
+    Asset { name: String },
+    /// Share of the classes in packages with single character names
+    ShortPackageNames { share: f64 },
+    /// Share of the classes with non-ASCII names
+    UnicodeClassNames { share: f64 },
+    /// Share of the decoded instructions no control flow reaches
+    DeadCode { share: f64 },
+    EncryptedAssets { dex_classes: u32, asset: String, size: u64, entropy: f64 },
+    DexClassLoader { descriptor: String },
+}
+
+
+/// Packer or obfuscator recognized by a rule
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackerMatch {
+    pub label: String,
+    /// One item per condition of the matching rule
+    pub evidence: Vec<Evidence>,
+}
+
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    label: String,
+    #[serde(default)]
+    classes: Vec<String>,
+    #[serde(default)]
+    application: Vec<String>,
+    #[serde(default)]
+    assets: Vec<String>,
+    #[serde(default)]
+    signals: Vec<Signal>,
+}
+
+
+#[derive(Deserialize)]
+struct RuleFile {
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+
+
+/// Ordered fingerprints of packers and obfuscators, the embedded defaults plus any user-supplied rules
+#[derive(Debug, Clone)]
+pub struct PackerRules {
+    rules: Vec<Rule>,
+}
+
+
+impl Default for PackerRules {
+    fn default() -> Self {
+        let mut rules = Self { rules: vec![] };
+        rules.extend_from_toml(DEFAULT_RULES).expect("the embedded packer rules are valid");
+        rules
+    }
+}
+
+
+impl PackerRules {
+    /// Appends the rules of a TOML file in the format of `DEFAULT_RULES`
+    #[cfg(feature = "fs")]
+    pub fn extend_from_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.extend_from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn extend_from_toml(&mut self, toml: &str) -> io::Result<()> {
+        let file: RuleFile = toml::from_str(toml)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid packer rules: {}", err)))?;
+        if let Some(rule) = file.rule.iter().find(|rule| rule.classes.is_empty() && rule.application.is_empty() && rule.assets.is_empty() && rule.signals.is_empty()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Packer rule without conditions: {}", rule.label)));
+        }
+        self.rules.extend(file.rule);
+        Ok(())
+    }
+
+    /// Matches of the rules on an APK, in rule order and with each label at most once.
+    /// Methods whose control flow goes deeper than `max_cfg_depth` are left out of the dead code signal and pushed to `warnings`
+    pub fn detect<T: AsRef<[u8]>>(&self, dexes: &[Dex<T>], manifest: Option<&Manifest>, assets: &[Asset], max_cfg_depth: usize, warnings: &mut Vec<Warning>) -> Vec<PackerMatch> {
+        let used = self.rules.iter().flat_map(|rule| &rule.signals).copied().collect();
+        let facts = Facts::collect(dexes, manifest, assets, &used, max_cfg_depth, warnings);
+        let mut matches: Vec<PackerMatch> = vec![];
+        for rule in &self.rules {
+            if matches.iter().any(|packer| packer.label == rule.label) {
+                continue;
+            }
+            if let Some(evidence) = facts.evidence(rule) {
+                matches.push(PackerMatch { label: rule.label.clone(), evidence });
+            }
+        }
+        matches
+    }
+}
+
+
+/// What the rules are matched against
+struct Facts<'a> {
+    classes: Vec<String>,
+    application: Option<&'a str>,
+    assets: &'a [Asset],
+    signals: HashMap<Signal, Evidence>,
+}
+
+
+impl<'a> Facts<'a> {
+    fn collect<T: AsRef<[u8]>>(dexes: &[Dex<T>], manifest: Option<&'a Manifest>, assets: &'a [Asset], used: &HashSet<Signal>, max_cfg_depth: usize, warnings: &mut Vec<Warning>) -> Self {
+        let classes: Vec<String> = dexes.iter()
+            .flat_map(|dex| dex.classes().filter_map(Result::ok))
+            .map(|class| class.jtype().type_descriptor().to_string())
+            .collect();
+        let signals = used.iter()
+            .filter_map(|&signal| Some((signal, match signal {
+                Signal::ShortPackageNames => short_package_names(&classes)?,
+                Signal::UnicodeClassNames => unicode_class_names(&classes)?,
+                Signal::DeadCode => dead_code(dexes, max_cfg_depth, warnings)?,
+                Signal::EncryptedAssets => encrypted_assets(dexes, assets)?,
+                Signal::DexClassLoader => dex_class_loader(dexes)?,
+            })))
+            .collect();
+        Self { classes, application: manifest.and_then(|manifest| manifest.application.as_deref()), assets, signals }
+    }
+
+    /// Evidence of every condition of `rule`, `None` unless they all hold
+    fn evidence(&self, rule: &Rule) -> Option<Vec<Evidence>> {
+        let mut evidence = vec![];
+        if !rule.classes.is_empty() {
+            let descriptor = self.classes.iter().find(|class| rule.classes.iter().any(|prefix| class.starts_with(prefix.as_str())))?;
+            evidence.push(Evidence::Class { descriptor: descriptor.clone() });
+        }
+        if !rule.application.is_empty() {
+            let name = self.application.filter(|name| rule.application.iter().any(|application| application == name))?;
+            evidence.push(Evidence::Application { name: name.to_string() });
+        }
+        if !rule.assets.is_empty() {
+            let asset = self.assets.iter().find(|asset| rule.assets.iter().any(|prefix| asset.name.starts_with(prefix.as_str())))?;
+            evidence.push(Evidence::Asset { name: asset.name.clone() });
+        }
+        for signal in &rule.signals {
+            evidence.push(self.signals.get(signal)?.clone());
+        }
+        Some(evidence)
+    }
+}
+
+
+fn short_package_names(classes: &[String]) -> Option<Evidence> {
+    let share = share(classes, |class| {
+        let path = class.trim_start_matches('L').trim_end_matches(';');
+        path.rsplit_once('/').is_some_and(|(package, _)| package.split('/').all(|name| name.chars().count() == 1))
+    });
+    (share >= SHORT_PACKAGE_SHARE).then_some(Evidence::ShortPackageNames { share })
+}
+
+
+fn unicode_class_names(classes: &[String]) -> Option<Evidence> {
+    let share = share(classes, |class| !class.is_ascii());
+    (share >= UNICODE_NAME_SHARE).then_some(Evidence::UnicodeClassNames { share })
+}
+
+
+fn share(classes: &[String], predicate: impl Fn(&str) -> bool) -> f64 {
+    if classes.is_empty() {
+        return 0.0;
+    }
+    classes.iter().filter(|class| predicate(class)).count() as f64 / classes.len() as f64
+}
+
+
+fn dead_code<T: AsRef<[u8]>>(dexes: &[Dex<T>], max_cfg_depth: usize, warnings: &mut Vec<Warning>) -> Option<Evidence> {
+    let (mut unreached, mut total) = (0, 0);
+    for class in dexes.iter().flat_map(|dex| dex.classes().filter_map(Result::ok)) {
+        for method in class.methods() {
+            let Some(code) = method.code() else { continue };
+            let handlers = code.tries().iter()
+                .flat_map(|try_block| try_block.catch_handlers().iter().map(|handler| handler.addr() as usize));
+            match unreachable_instructions(code.insns(), handlers, max_cfg_depth) {
+                Some((method_unreached, method_total)) => {
+                    unreached += method_unreached;
+                    total += method_total;
+                },
+                None => warnings.push(Warning::new(WarningKind::CfgDepthExceeded, format!("Reachability analysis cut at depth {}", max_cfg_depth))
+                    .class(class.jtype().type_descriptor().as_str())
+                    .method(method.name().as_str())),
+            }
+        }
+    }
+    let share = if total == 0 { 0.0 } else { unreached as f64 / total as f64 };
+    (share >= DEAD_CODE_SHARE).then_some(Evidence::DeadCode { share })
+}
+
+
+fn encrypted_assets<T: AsRef<[u8]>>(dexes: &[Dex<T>], assets: &[Asset]) -> Option<Evidence> {
+    let dex_classes = dexes.iter().map(|dex| dex.header().class_defs_size()).sum();
+    if dex_classes > TINY_DEX_CLASSES {
+        return None;
+    }
+    let asset = assets.iter()
+        .filter(|asset| asset.size >= ENCRYPTED_ASSET_SIZE && asset.entropy >= ENCRYPTED_ASSET_ENTROPY)
+        .max_by_key(|asset| asset.size)?;
+    Some(Evidence::EncryptedAssets { dex_classes, asset: asset.name.clone(), size: asset.size, entropy: asset.entropy })
+}
+
+
+fn dex_class_loader<T: AsRef<[u8]>>(dexes: &[Dex<T>]) -> Option<Evidence> {
+    dexes.iter()
+        .flat_map(|dex| (0..dex.header().type_ids_size()).filter_map(|idx| dex.get_type(idx).ok()))
+        .map(|jtype| jtype.type_descriptor().to_string())
+        .find(|descriptor| CLASS_LOADERS.contains(&descriptor.as_str()))
+        .map(|descriptor| Evidence::DexClassLoader { descriptor })
+}
+
+
+/// Shannon entropy of `bytes` in bits per byte, 0 for no bytes
+fn entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    -counts.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| count as f64 / len)
+        .map(|p| p * p.log2())
+        .sum::<f64>()
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    use crate::testing::{DexBuilder, ClassDef, MethodDef, CodeDef};
+    use super::*;
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert_eq!(entropy(&(0..=255).collect::<Vec<u8>>()), 8.0);
+    }
+
+    #[test]
+    fn test_rules_parsing() {
+        let mut rules = PackerRules::default();
+        let default_count = rules.rules.len();
+        rules.extend_from_toml("[[rule]]\nlabel = \"Custom\"\nclasses = [\"Lcom/custom/\"]\nsignals = [\"dead_code\"]\n").unwrap();
+        assert_eq!(rules.rules.len(), default_count + 1);
+        assert_eq!(rules.rules[default_count].signals, [Signal::DeadCode]);
+        assert!(rules.extend_from_toml("[[rule]]\nlabel = \"Empty\"\n").is_err());
+        assert!(rules.extend_from_toml("[[rule]]\nlabel = \"Typo\"\nsignals = [\"dead_blocks\"]\n").is_err());
+        assert_eq!(rules.rules.len(), default_count + 1);
+    }
+
+    #[test]
+    fn test_detect_rules() {
+        let rules = PackerRules::default();
+        let labels = |matches: &[PackerMatch]| matches.iter().map(|packer| packer.label.clone()).collect::<Vec<_>>();
+
+        // A Jiagu stub application with its shell class
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/stub/StubApp;").method(MethodDef::new("onCreate", "V", &[]).code(CodeDef::new(1, 1, 0, &[0x000E]))));
+        let dexes = [DexReader::from_vec(builder.build()).unwrap()];
+        let manifest = Manifest { permissions: vec![], application: Some("com.stub.StubApp".to_string()), launcher_activity: None, components: vec![] };
+        let matches = rules.detect(&dexes, Some(&manifest), &[], 0, &mut vec![]);
+        assert_eq!(labels(&matches), ["Jiagu"]);
+        assert_eq!(matches[0].evidence, [Evidence::Application { name: "com.stub.StubApp".to_string() }]);
+
+        // A tiny dex loading code from a large random asset
+        let mut builder = DexBuilder::new();
+        let init = builder.method("Ldalvik/system/DexClassLoader;", "<init>", "V", &["Ljava/lang/String;", "Ljava/lang/String;", "Ljava/lang/String;", "Ljava/lang/ClassLoader;"]) as u16;
+        builder.class(ClassDef::new("Lcom/example/Loader;")
+            .method(MethodDef::new("load", "V", &[]).code(CodeDef::new(5, 1, 5, &[0x5070, init, 0x3210, 0x000E]))));
+        let dexes = [DexReader::from_vec(builder.build()).unwrap()];
+        let mut payload = vec![0; 128 * 1024];
+        StdRng::seed_from_u64(0).fill_bytes(&mut payload);
+        let assets = [Asset::new("assets/icon.png", &[0; 1024]), Asset::new("assets/payload.bin", &payload)];
+        let matches = rules.detect(&dexes, None, &assets, 0, &mut vec![]);
+        assert_eq!(labels(&matches), ["generic packer"]);
+        let Evidence::EncryptedAssets { asset, size, .. } = &matches[0].evidence[0] else { panic!("{:?}", matches[0].evidence) };
+        assert_eq!((asset.as_str(), *size), ("assets/payload.bin", 128 * 1024));
+        assert_eq!(matches[0].evidence[1], Evidence::DexClassLoader { descriptor: "Ldalvik/system/DexClassLoader;".to_string() });
+        // Without the asset only the class loader is left, which is not enough
+        assert!(rules.detect(&dexes, None, &assets[..1], 0, &mut vec![]).is_empty());
+    }
+}
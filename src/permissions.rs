@@ -0,0 +1,148 @@
+use dex::Dex;
+use serde::Serialize;
+
+use crate::{extension::for_each_method, options::AnalysisOptions};
+
+
+/// Framework APIs guarded by a permission, as `(class, method, permissions)`: a call succeeds with any of the permissions
+pub const PERMISSION_APIS: &[(&str, &str, &[&str])] = &[
+    ("Landroid/telephony/TelephonyManager;", "getDeviceId", &["READ_PHONE_STATE"]),
+    ("Landroid/telephony/TelephonyManager;", "getImei", &["READ_PHONE_STATE"]),
+    ("Landroid/telephony/TelephonyManager;", "getMeid", &["READ_PHONE_STATE"]),
+    ("Landroid/telephony/TelephonyManager;", "getSubscriberId", &["READ_PHONE_STATE"]),
+    ("Landroid/telephony/TelephonyManager;", "getSimSerialNumber", &["READ_PHONE_STATE"]),
+    ("Landroid/telephony/TelephonyManager;", "getDeviceSoftwareVersion", &["READ_PHONE_STATE"]),
+    ("Landroid/telephony/TelephonyManager;", "getLine1Number", &["READ_PHONE_STATE", "READ_PHONE_NUMBERS", "READ_SMS"]),
+    ("Landroid/location/LocationManager;", "getLastKnownLocation", &["ACCESS_FINE_LOCATION", "ACCESS_COARSE_LOCATION"]),
+    ("Landroid/location/LocationManager;", "requestLocationUpdates", &["ACCESS_FINE_LOCATION", "ACCESS_COARSE_LOCATION"]),
+    ("Landroid/location/LocationManager;", "requestSingleUpdate", &["ACCESS_FINE_LOCATION", "ACCESS_COARSE_LOCATION"]),
+    ("Landroid/telephony/SmsManager;", "sendTextMessage", &["SEND_SMS"]),
+    ("Landroid/telephony/SmsManager;", "sendMultipartTextMessage", &["SEND_SMS"]),
+    ("Landroid/telephony/SmsManager;", "sendDataMessage", &["SEND_SMS"]),
+    ("Landroid/hardware/Camera;", "open", &["CAMERA"]),
+    ("Landroid/media/AudioRecord;", "<init>", &["RECORD_AUDIO"]),
+    ("Landroid/media/MediaRecorder;", "setAudioSource", &["RECORD_AUDIO"]),
+    ("Landroid/accounts/AccountManager;", "getAccounts", &["GET_ACCOUNTS"]),
+    ("Landroid/accounts/AccountManager;", "getAccountsByType", &["GET_ACCOUNTS"]),
+    ("Ljava/net/URL;", "openConnection", &["INTERNET"]),
+    ("Ljava/net/Socket;", "<init>", &["INTERNET"]),
+    ("Landroid/net/ConnectivityManager;", "getActiveNetworkInfo", &["ACCESS_NETWORK_STATE"]),
+    ("Landroid/net/wifi/WifiManager;", "getConnectionInfo", &["ACCESS_WIFI_STATE"]),
+    ("Landroid/net/wifi/WifiManager;", "getScanResults", &["ACCESS_WIFI_STATE"]),
+    ("Landroid/net/wifi/WifiManager;", "setWifiEnabled", &["CHANGE_WIFI_STATE"]),
+    ("Landroid/bluetooth/BluetoothAdapter;", "getAddress", &["BLUETOOTH"]),
+    ("Landroid/bluetooth/BluetoothAdapter;", "enable", &["BLUETOOTH_ADMIN"]),
+    ("Landroid/os/Vibrator;", "vibrate", &["VIBRATE"]),
+    ("Landroid/os/PowerManager$WakeLock;", "acquire", &["WAKE_LOCK"]),
+    ("Landroid/app/ActivityManager;", "killBackgroundProcesses", &["KILL_BACKGROUND_PROCESSES"]),
+    ("Landroid/app/WallpaperManager;", "setBitmap", &["SET_WALLPAPER"]),
+    ("Landroid/nfc/NfcAdapter;", "enableForegroundDispatch", &["NFC"]),
+];
+
+/// Permissions of `PERMISSION_APIS` with the dangerous protection level, granted at runtime rather than at install time
+pub const RUNTIME_PERMISSIONS: &[&str] = &[
+    "READ_PHONE_STATE", "READ_PHONE_NUMBERS", "READ_SMS", "ACCESS_FINE_LOCATION", "ACCESS_COARSE_LOCATION", "SEND_SMS", "CAMERA",
+    "RECORD_AUDIO", "GET_ACCOUNTS",
+];
+
+
+/// Call of a permission-guarded API that can never succeed, none of its permissions being requested by the manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeadApiCall {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the calling class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub method: String,
+    /// Code unit offset of the invoke
+    pub offset: usize,
+    /// Invoked API, e.g. `Landroid/telephony/TelephonyManager;->getDeviceId`
+    pub api: String,
+    /// Permissions allowing the call, without the `android.permission.` prefix
+    pub missing_permissions: Vec<String>,
+    /// Whether they are dangerous permissions. These are requested at runtime, but only once declared in the manifest
+    pub runtime: bool,
+}
+
+
+/// Protection of a guarded API, `None` for APIs outside `PERMISSION_APIS`
+pub fn required_permissions(class: &str, method: &str) -> Option<&'static [&'static str]> {
+    PERMISSION_APIS.iter().find(|(api_class, api_method, _)| *api_class == class && *api_method == method).map(|(_, _, permissions)| *permissions)
+}
+
+
+/// Calls of the selected classes of `dex` to guarded APIs whose permissions are all missing from `permissions`,
+/// the requested permissions of the manifest
+pub fn dead_api_calls<T: AsRef<[u8]>>(dex_index: usize, dex: &Dex<T>, permissions: &[String], options: &AnalysisOptions) -> Vec<DeadApiCall> {
+    let mut calls = vec![];
+    for_each_method(dex_index, dex, options, |ctx| {
+        for inst in ctx.instructions().iter().filter(|inst| inst.opcode().is_invoke()) {
+            let Some(api) = (*inst.reference()).and_then(|method_idx| ctx.dex().resolve_method(method_idx)) else { continue };
+            let Some(required) = required_permissions(&api.class, &api.name) else { continue };
+            if required.iter().any(|permission| permissions.iter().any(|requested| requested == permission)) {
+                continue;
+            }
+            calls.push(DeadApiCall {
+                dex: dex_index,
+                class: ctx.class().to_string(),
+                method: ctx.name().to_string(),
+                offset: *inst.offset(),
+                api: format!("{}->{}", api.class, api.name),
+                missing_permissions: required.iter().map(|permission| permission.to_string()).collect(),
+                runtime: required.iter().all(|permission| RUNTIME_PERMISSIONS.contains(permission)),
+            });
+        }
+    });
+    calls
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::{
+        analysis::analyze_dexes,
+        dex_parsing::NamedDex,
+        manifest_parsing::Manifest,
+        testing::{ClassDef, CodeDef, DexBuilder, MethodDef},
+    };
+    use super::*;
+
+    fn dex() -> Dex<Vec<u8>> {
+        let mut builder = DexBuilder::new();
+        let get_device_id = builder.method("Landroid/telephony/TelephonyManager;", "getDeviceId", "Ljava/lang/String;", &[]) as u16;
+        let open_connection = builder.method("Ljava/net/URL;", "openConnection", "Ljava/net/URLConnection;", &[]) as u16;
+        // invoke-virtual {v0}, getDeviceId; invoke-virtual {v0}, openConnection; return-void
+        let body = [0x106E, get_device_id, 0x0000, 0x106E, open_connection, 0x0000, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Main;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 1, &body))));
+        DexReader::from_vec(builder.build()).unwrap()
+    }
+
+    #[test]
+    fn test_dead_api_calls() {
+        let calls = dead_api_calls(0, &dex(), &["INTERNET".to_string()], &AnalysisOptions::default());
+        assert_eq!(calls, [DeadApiCall {
+            dex: 0,
+            class: "Lcom/example/Main;".to_string(),
+            method: "run".to_string(),
+            offset: 0,
+            api: "Landroid/telephony/TelephonyManager;->getDeviceId".to_string(),
+            missing_permissions: vec!["READ_PHONE_STATE".to_string()],
+            runtime: true,
+        }]);
+        let calls = dead_api_calls(0, &dex(), &[], &AnalysisOptions::default());
+        assert_eq!(calls.iter().map(|call| (call.offset, call.runtime)).collect::<Vec<_>>(), [(0, true), (3, false)]);
+        assert!(dead_api_calls(0, &dex(), &["READ_PHONE_STATE".to_string(), "INTERNET".to_string()], &AnalysisOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn test_report_needs_a_manifest() {
+        let options = AnalysisOptions::default().dead_api_calls(true);
+        let manifest = Manifest { permissions: vec!["INTERNET".to_string()], application: None, launcher_activity: None, components: vec![] };
+        let report = analyze_dexes(NamedDex::multidex([dex()]), Some(manifest), &options);
+        assert_eq!(report.dead_api_calls.unwrap()[0].api, "Landroid/telephony/TelephonyManager;->getDeviceId");
+        assert!(analyze_dexes(NamedDex::multidex([dex()]), None, &options).dead_api_calls.is_none());
+        assert!(analyze_dexes(NamedDex::multidex([dex()]), Some(Manifest::default()), &AnalysisOptions::default()).dead_api_calls.is_none());
+    }
+}
@@ -0,0 +1,247 @@
+use std::{io::{IsTerminal, Write}, sync::{Arc, Mutex, OnceLock, PoisonError}, thread, time::Duration};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::stats::RunStats;
+
+
+/// Seconds between two lines of the plain progress, without bars
+pub const PLAIN_INTERVAL: u64 = 10;
+
+/// Characters of the input name shown on the status line
+const NAME_WIDTH: usize = 60;
+
+
+/// Bars of the run, for `eprintln_above!` to print above them
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+
+/// `eprintln!` clearing the progress bars while it prints, so that the line doesn't tear them
+macro_rules! eprintln_above {
+    ($($arg:tt)*) => {
+        $crate::progress::suspend(|| eprintln!($($arg)*))
+    };
+}
+pub(crate) use eprintln_above;
+
+
+/// Runs `f` with the progress bars cleared, they are drawn again afterwards
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    match BARS.get() {
+        Some(bars) => bars.suspend(f),
+        None => f(),
+    }
+}
+
+
+/// How the progress of a run is shown on stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A bar and a status line with the input started last and the failures
+    Bars,
+    /// A plain text line every interval, for logs and other non-terminals
+    Plain(Duration),
+}
+
+
+impl Mode {
+    /// Bars on a terminal, plain lines every `PLAIN_INTERVAL` seconds with `--no-progress` or when stderr isn't a terminal
+    pub fn detect(no_progress: bool) -> Self {
+        if no_progress || !std::io::stderr().is_terminal() {
+            Mode::Plain(Duration::from_secs(PLAIN_INTERVAL))
+        } else {
+            Mode::Bars
+        }
+    }
+}
+
+
+/// Progress of a run over a known number of inputs
+#[derive(Clone)]
+pub struct Progress {
+    bar: ProgressBar,
+    status: ProgressBar,
+    stats: Arc<RunStats>,
+    /// Name of the input started last
+    current: Arc<Mutex<String>>,
+    /// Destination of the plain lines, `None` with bars
+    plain: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+}
+
+
+impl Progress {
+    /// Progress over `len` inputs whose failures are counted by `stats`. With `Mode::Plain`, the lines are written to
+    /// `plain` by a background thread, and a last one by `finish`
+    pub fn new(len: u64, stats: Arc<RunStats>, mode: Mode, plain: impl Write + Send + 'static) -> Self {
+        let (bar, status) = match mode {
+            Mode::Bars => {
+                let bars = BARS.get_or_init(MultiProgress::new);
+                let bar = bars.add(ProgressBar::new(len)
+                    .with_style(ProgressStyle::with_template("{wide_bar} {pos}/{len} [{elapsed_precise}] {msg}").unwrap()));
+                let status = bars.add(ProgressBar::new_spinner().with_style(ProgressStyle::with_template("{msg}").unwrap()));
+                (bar, status)
+            },
+            Mode::Plain(_) => (ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::hidden()), ProgressBar::hidden()),
+        };
+        let plain = matches!(mode, Mode::Plain(_)).then(|| Arc::new(Mutex::new(Box::new(plain) as Box<dyn Write + Send>)));
+        let progress = Self { bar, status, stats, current: Arc::new(Mutex::new(String::new())), plain };
+        if let Mode::Plain(interval) = mode {
+            progress.spawn_plain(interval);
+        }
+        progress
+    }
+
+    /// Main bar, advanced by the iterators over the inputs
+    pub fn bar(&self) -> ProgressBar {
+        self.bar.clone()
+    }
+
+    /// Sets the message after the main bar, e.g. the bytes in flight
+    pub fn set_message(&self, message: String) {
+        self.bar.set_message(message);
+    }
+
+    /// Shows `name` as the input being processed
+    pub fn start(&self, name: &str) {
+        *self.current.lock().unwrap_or_else(PoisonError::into_inner) = truncate_start(name, NAME_WIDTH);
+        self.refresh_status();
+    }
+
+    /// Counts a failed input, shown on the status line right away
+    pub fn failed(&self) {
+        self.stats.failed();
+        self.refresh_status();
+    }
+
+    fn refresh_status(&self) {
+        let current = self.current.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        self.status.set_message(format!("{} | {} failed", current, self.stats.failures()));
+    }
+
+    /// Line of the plain progress, e.g. `12/100 inputs (12%), 1 failed, 34s elapsed, at app.apk`
+    pub fn plain_line(&self) -> String {
+        let (position, len) = (self.bar.position(), self.bar.length().unwrap_or(0));
+        let percent = if len > 0 { position as f64 / len as f64 * 100.0 } else { 100.0 };
+        let mut line = format!("{}/{} inputs ({:.0}%), {} failed, {}s elapsed", position, len, percent, self.stats.failures(), self.bar.elapsed().as_secs());
+        let current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        if !current.is_empty() && position < len {
+            line.push_str(", at ");
+            line.push_str(&current);
+        }
+        line
+    }
+
+    /// Writes a plain line every `interval` until the progress is finished
+    fn spawn_plain(&self, interval: Duration) {
+        let progress = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if !progress.write_plain(|_| ()) {
+                break;
+            }
+        });
+    }
+
+    /// Writes a plain line after `before` runs, unless the progress is already finished. The destination stays locked
+    /// throughout, so that no line follows the last one. Returns whether a line was written
+    fn write_plain(&self, before: impl FnOnce(&Self)) -> bool {
+        let Some(plain) = &self.plain else { return false };
+        let mut out = plain.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.bar.is_finished() {
+            return false;
+        }
+        before(self);
+        writeln!(out, "Progress: {}", self.plain_line()).and_then(|_| out.flush()).is_ok()
+    }
+
+    /// Finishes the main bar and clears the status line, or writes the last plain line
+    pub fn finish(&self) {
+        if !self.write_plain(|progress| progress.bar.finish()) {
+            self.bar.finish();
+        }
+        self.status.finish_and_clear();
+    }
+}
+
+
+/// Last `width` characters of `name`, with an ellipsis in front of truncated names
+fn truncate_start(name: &str, width: usize) -> String {
+    let count = name.chars().count();
+    if count <= width {
+        return name.to_string();
+    }
+    let tail: String = name.chars().skip(count - width + 1).collect();
+    format!("…{}", tail)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writer into a buffer shared with the test
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap().lines().map(str::to_string).collect()
+        }
+    }
+
+    #[test]
+    fn test_truncate_start() {
+        assert_eq!(truncate_start("app.apk", 10), "app.apk");
+        assert_eq!(truncate_start("/data/apks/app.apk", 10), "…s/app.apk");
+        assert_eq!(truncate_start("/data/apks/app.apk", 10).chars().count(), 10);
+    }
+
+    #[test]
+    fn test_status_counts_failures() {
+        let progress = Progress::new(2, Arc::new(RunStats::new()), Mode::Plain(Duration::from_secs(60)), std::io::sink());
+        progress.start("first.apk");
+        assert_eq!(progress.status.message(), "first.apk | 0 failed");
+        progress.failed();
+        assert_eq!(progress.status.message(), "first.apk | 1 failed");
+        progress.finish();
+    }
+
+    #[test]
+    fn test_plain_lines() {
+        let stats = Arc::new(RunStats::new());
+        let out = Shared::default();
+        let progress = Progress::new(4, stats.clone(), Mode::Plain(Duration::from_millis(20)), out.clone());
+        progress.start("/data/apks/first.apk");
+        progress.bar().inc(1);
+        progress.failed();
+        assert_eq!(stats.failures(), 1);
+        progress.start("/data/apks/second.apk");
+        assert!(progress.plain_line().starts_with("1/4 inputs (25%), 1 failed, 0s elapsed"));
+        assert!(progress.plain_line().ends_with(", at /data/apks/second.apk"));
+
+        thread::sleep(Duration::from_millis(150));
+        let lines = out.lines();
+        assert!(lines.len() >= 2, "{:?}", lines);
+        assert!(lines.iter().all(|line| line.starts_with("Progress: 1/4 inputs (25%), 1 failed")));
+
+        // One last line once finished, without the input, and no more afterwards
+        progress.bar().inc(3);
+        progress.finish();
+        let lines = out.lines();
+        assert!(lines.last().unwrap().starts_with("Progress: 4/4 inputs (100%), 1 failed"), "{:?}", lines);
+        assert!(!lines.last().unwrap().contains(" at "));
+        let count = lines.len();
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(out.lines().len(), count);
+    }
+}
@@ -0,0 +1,219 @@
+use std::path::PathBuf;
+
+use num_traits::FromPrimitive;
+use pyo3::{exceptions::{PyIOError, PyTypeError, PyValueError}, prelude::*, types::{PyDict, PyList}};
+use serde_json::Value;
+
+use crate::{obfuscation::DecryptorThresholds, packer::PackerRules, AnalysisOptions, CapStrategy, DecodeMode, DedupKey, DedupScope, Error, ClassFilter, InvalidStrings, Normalization, Opcode, opcode_map::OpcodeMap, ReportField, Sampling, Strictness, watchlist::Watchlist};
+
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(_) => PyIOError::new_err(err.to_string()),
+            _ => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+
+/// Opcodes of a method, from its code units as little-endian bytes
+#[pyfunction]
+fn decode_method(py: Python, bytes: &[u8]) -> PyResult<Vec<u8>> {
+    if bytes.len() % 2 != 0 {
+        return Err(PyValueError::new_err("method code must be a whole number of 16-bit code units"));
+    }
+    let raw_bytecode: Vec<u16> = bytes.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+    Ok(py.allow_threads(|| {
+        crate::decode_method(&raw_bytecode).instructions.iter().map(|inst| *inst.opcode() as u8).collect()
+    }))
+}
+
+
+/// Analyzes an APK, the returned dict follows the JSON output of the command line tool.
+/// Options are the command line flags: `sequence_cap`, `method_cap`, `lenient`, `dedup_methods`, `dedup_scope`, `dedup_key`, `metrics`,
+/// `include_class`, `exclude_class`, `watchlist`, `sample_rate` and `seed`
+#[pyfunction]
+#[pyo3(signature = (path, **options))]
+fn analyze_apk(py: Python, path: PathBuf, options: Option<&PyDict>) -> PyResult<PyObject> {
+    let options = options_from_kwargs(options)?;
+    let report = py.allow_threads(|| crate::analyze_apk(&path, &options))?;
+    let value = report.to_value(options.writes_mnemonics()).map_err(|err| PyValueError::new_err(err.to_string()))?;
+    to_py(py, &value)
+}
+
+
+/// Smali mnemonic of an opcode, e.g. `invoke-virtual` for 0x6e
+#[pyfunction]
+fn opcode_name(opcode: u8) -> PyResult<String> {
+    match Opcode::from_u8(opcode) {
+        Some(opcode) => Ok(opcode.mnemonic().to_string()),
+        None => Err(PyValueError::new_err(format!("unused opcode {:#04x}", opcode))),
+    }
+}
+
+
+fn options_from_kwargs(kwargs: Option<&PyDict>) -> PyResult<AnalysisOptions> {
+    let mut options = AnalysisOptions::default();
+    let mut class_filter = ClassFilter::default();
+    let mut sampling = Sampling { rate: 1.0, seed: 0 };
+    let mut obfuscation = false;
+    let mut thresholds = DecryptorThresholds::default();
+    let mut packer = None;
+    for (key, value) in kwargs.into_iter().flatten() {
+        let key: &str = key.extract()?;
+        options = match key {
+            "sequence_cap" => options.sequence_cap(value.extract()?),
+            "cap_strategy" => options.cap_strategy(match value.extract::<&str>()? {
+                "truncate-methods" => CapStrategy::TruncateMethods,
+                "truncate-exact" => CapStrategy::TruncateExact,
+                "per-dex" => CapStrategy::PerDex,
+                strategy => return Err(PyValueError::new_err(format!("unknown cap strategy: {}", strategy))),
+            }),
+            "method_cap" => options.method_cap(value.extract()?),
+            "lenient" => options.strictness(if value.extract()? { Strictness::Lenient } else { Strictness::Strict }),
+            "dedup_methods" => options.dedup_methods(value.extract()?),
+            "dedup_scope" => options.dedup_scope(match value.extract::<&str>()? {
+                "input" => DedupScope::Input,
+                "dex" => DedupScope::Dex,
+                "run" => DedupScope::Run,
+                scope => return Err(PyValueError::new_err(format!("unknown dedup scope: {}", scope))),
+            }),
+            "dedup_key" => options.dedup_key(match value.extract::<&str>()? {
+                "exact" => DedupKey::Bytecode,
+                "opcodes" => DedupKey::Opcodes,
+                key => return Err(PyValueError::new_err(format!("unknown dedup key: {}", key))),
+            }),
+            "shallow" => options.shallow(value.extract()?),
+            "decode_mode" => options.decode_mode(match value.extract::<&str>()? {
+                "linear" => DecodeMode::Linear,
+                "recursive" => DecodeMode::Recursive,
+                mode => return Err(PyValueError::new_err(format!("unknown decode mode: {}", mode))),
+            }),
+            "with_offsets" => options.with_offsets(value.extract()?),
+            "with_op_stats" => options.op_stats(value.extract()?),
+            "instructions_lite" => options.instructions_lite(value.extract()?),
+            "metrics" => options.call_graph_metrics(value.extract()?),
+            "string_pool" => options.string_pool(value.extract()?),
+            "invalid_strings" => options.invalid_strings(match value.extract::<&str>()? {
+                "hex" => InvalidStrings::Hex,
+                "escaped" => InvalidStrings::Escaped,
+                "base64" => InvalidStrings::Base64,
+                encoding => return Err(PyValueError::new_err(format!("unknown invalid string encoding: {}", encoding))),
+            }),
+            "sections" => options.sections(value.extract()?),
+            "fields" => options.fields(value.extract()?),
+            "api_sequences" => options.api_sequences(value.extract()?),
+            "dead_api_calls" => options.dead_api_calls(value.extract()?),
+            "intents" => options.intents(value.extract()?),
+            "network_indicators" => options.network_indicators(value.extract()?),
+            "kotlin" => options.kotlin(value.extract()?),
+            "debug_info" => options.debug_info(value.extract()?),
+            "exclude_synthetic" => options.exclude_synthetic(value.extract()?),
+            "verify" => options.verify(value.extract()?),
+            "strict_classes" => options.strict_classes(value.extract()?),
+            "report_fields" => options.report_fields(value.extract::<Vec<&str>>()?.into_iter()
+                .map(|name| ReportField::from_name(name).ok_or_else(|| PyValueError::new_err(format!("unknown report field: {}", name))))
+                .collect::<PyResult<Vec<_>>>()?),
+            "mnemonics" => options.mnemonics(value.extract()?),
+            "max_cfg_depth" => options.max_cfg_depth(value.extract()?),
+            "obfuscation" => {
+                obfuscation = value.extract()?;
+                options
+            },
+            "decryptor_min_score" => {
+                thresholds.min_score = value.extract()?;
+                options
+            },
+            "decryptor_min_callers" => {
+                thresholds.min_caller_classes = value.extract()?;
+                options
+            },
+            "packer" => {
+                if value.extract()? {
+                    packer.get_or_insert_with(PackerRules::default);
+                }
+                options
+            },
+            "packer_rules" => {
+                packer.get_or_insert_with(PackerRules::default).extend_from_file(value.extract::<PathBuf>()?)?;
+                options
+            },
+            "include_class" => {
+                class_filter = value.extract::<Vec<String>>()?.into_iter().fold(class_filter, ClassFilter::include);
+                options
+            },
+            "exclude_class" => {
+                class_filter = value.extract::<Vec<String>>()?.into_iter().fold(class_filter, ClassFilter::exclude);
+                options
+            },
+            "watchlist" => {
+                let mut watchlist = Watchlist::default();
+                watchlist.extend_from_file(value.extract::<PathBuf>()?)?;
+                options.watchlist(watchlist)
+            },
+            "sample_rate" => {
+                sampling.rate = value.extract()?;
+                if !(0.0..=1.0).contains(&sampling.rate) {
+                    return Err(PyValueError::new_err("sample_rate must be between 0 and 1"));
+                }
+                options
+            },
+            "normalize" => options.normalization(match value.extract::<&str>()? {
+                "none" => Normalization::None,
+                "invoke-merged" => Normalization::InvokeMerged,
+                "category" => Normalization::Category,
+                scheme => return Err(PyValueError::new_err(format!("unknown normalization: {}", scheme))),
+            }),
+            "opcode_map" => options.normalization(Normalization::Map(Box::new(OpcodeMap::from_file(value.extract::<PathBuf>()?)?))),
+            "preset" => options.normalization(Normalization::Map(Box::new(match value.extract::<&str>()? {
+                "kinds" => OpcodeMap::kinds(),
+                "full" => OpcodeMap::full(),
+                preset => return Err(PyValueError::new_err(format!("unknown preset: {}", preset))),
+            }))),
+            "seed" => {
+                sampling.seed = value.extract()?;
+                options
+            },
+            _ => return Err(PyTypeError::new_err(format!("unexpected option: {}", key))),
+        };
+    }
+    if obfuscation {
+        options = options.string_decryptors(thresholds);
+    }
+    if let Some(rules) = packer {
+        options = options.packer(rules);
+    }
+    Ok(options.class_filter(class_filter).sampling(sampling).build())
+}
+
+
+fn to_py(py: Python, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(value) => value.into_py(py),
+        Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+            (Some(value), _) => value.into_py(py),
+            (None, Some(value)) => value.into_py(py),
+            _ => number.as_f64().into_py(py),
+        },
+        Value::String(value) => value.into_py(py),
+        Value::Array(items) => PyList::new(py, items.iter().map(|item| to_py(py, item)).collect::<PyResult<Vec<_>>>()?).into(),
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, value) in map {
+                dict.set_item(key, to_py(py, value)?)?;
+            }
+            dict.into()
+        },
+    })
+}
+
+
+#[pymodule]
+fn dexompiler(_py: Python, module: &PyModule) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(decode_method, module)?)?;
+    module.add_function(wrap_pyfunction!(analyze_apk, module)?)?;
+    module.add_function(wrap_pyfunction!(opcode_name, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use dex::Dex;
+use serde::Serialize;
+
+use crate::{dex_parsing::{Instruction, Opcode}, options::InvalidStrings, string_pool::{decode_mutf8, encode_invalid, raw_string}};
+
+
+/// A method reference resolved from the method ids of a dex
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct MethodRef {
+    /// Descriptor of the defining class, e.g. `Ljava/lang/Class;`
+    pub class: String,
+    pub name: String,
+    /// Descriptors of the parameter types, e.g. `["Ljava/lang/String;", "I"]`
+    pub params: Vec<String>,
+    /// Descriptor of the return type, e.g. `[B`
+    pub return_type: String,
+}
+
+
+/// Resolves a method index, as referenced by an invoke instruction, to its class, name and prototype.
+/// Returns `None` for indices outside the method ids
+pub fn resolve_method<T: AsRef<[u8]>>(dex: &Dex<T>, method_idx: u32) -> Option<MethodRef> {
+    if method_idx >= dex.header().method_ids_size() {
+        return None;
+    }
+    let item = dex.get_method_item(method_idx as u64).ok()?;
+    let class = dex.get_type(item.class_idx() as u32).ok()?;
+    let name = dex.get_string(item.name_idx()).ok()?;
+    let proto = dex.get_proto_item(item.proto_idx() as u64).ok()?;
+    let return_type = dex.get_type(proto.return_type()).ok()?;
+    // A prototype without parameters has no type list
+    let params = match proto.params_off() {
+        0 => vec![],
+        params_off => dex.get_interfaces(params_off).ok()?,
+    };
+    Some(MethodRef {
+        class: class.type_descriptor().to_string(),
+        name: name.to_string(),
+        params: params.iter().map(|param| param.type_descriptor().to_string()).collect(),
+        return_type: return_type.type_descriptor().to_string(),
+    })
+}
+
+
+/// Resolves a string index, as referenced by `const-string`, to its contents, decoded from MUTF-8 with its embedded NULs
+/// and supplementary characters. Returns `None` for indices outside the string ids and strings that aren't valid
+/// MUTF-8, which `resolve_string_encoded` writes when the bytes of the dex are at hand
+pub fn resolve_string<T: AsRef<[u8]>>(dex: &Dex<T>, string_idx: u32) -> Option<String> {
+    if string_idx >= dex.header().string_ids_size() {
+        return None;
+    }
+    dex.get_string(string_idx).ok().map(|string| string.to_string())
+}
+
+
+/// `resolve_string` reading the strings that aren't valid MUTF-8 from `bytes`, the dex parsed as `dex`, and writing
+/// them as set by `invalid` like the string pool does
+pub fn resolve_string_encoded<T: AsRef<[u8]>>(dex: &Dex<T>, bytes: &[u8], string_idx: u32, invalid: InvalidStrings) -> Option<String> {
+    resolve_string(dex, string_idx).or_else(|| {
+        let raw = raw_string(bytes, string_idx)?;
+        Some(decode_mutf8(raw).unwrap_or_else(|| encode_invalid(raw, invalid)))
+    })
+}
+
+
+/// Resolves a type index to its Java name, e.g. `dalvik.system.DexClassLoader`.
+/// Returns `None` for indices outside the type ids
+pub fn resolve_type<T: AsRef<[u8]>>(dex: &Dex<T>, type_idx: u32) -> Option<String> {
+    if type_idx >= dex.header().type_ids_size() {
+        return None;
+    }
+    dex.get_type(type_idx).ok().map(|jtype| jtype.to_java_type())
+}
+
+
+/// Resolves the type operand of `new-instance`, `check-cast`, `const-class`, `instance-of`,
+/// `new-array` and `filled-new-array`, `None` for any other instruction
+pub fn resolve_instruction_type<T: AsRef<[u8]>>(dex: &Dex<T>, inst: &Instruction) -> Option<String> {
+    match inst.opcode() {
+        Opcode::NewInstance | Opcode::CheckCast | Opcode::ConstClass | Opcode::InstanceOf
+        | Opcode::NewArray | Opcode::FilledNewArray | Opcode::FilledNewArrayRange => resolve_type(dex, (*inst.reference())?),
+        _ => None,
+    }
+}
+
+
+/// Descriptor of the class defining the type, field or method an instruction references, e.g. `Ljava/lang/String;` for
+/// `invoke-virtual String.length()`. Arrays stand for their element class, `None` for primitives and other references
+pub fn resolve_referenced_class<T: AsRef<[u8]>>(dex: &Dex<T>, inst: &Instruction) -> Option<String> {
+    let reference = (*inst.reference())?;
+    let type_idx = match inst.reference_kind()? {
+        "type" if reference < dex.header().type_ids_size() => reference,
+        "field" if reference < dex.header().field_ids_size() => dex.get_field_item(reference as u64).ok()?.class_idx() as u32,
+        "method" if reference < dex.header().method_ids_size() => dex.get_method_item(reference as u64).ok()?.class_idx() as u32,
+        _ => return None,
+    };
+    let jtype = dex.get_type(type_idx).ok()?;
+    let element = jtype.type_descriptor().as_str().trim_start_matches('[');
+    element.starts_with('L').then(|| element.to_string())
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{DexBuilder, ClassDef, MethodDef, CodeDef};
+    use super::*;
+
+    #[test]
+    fn test_resolve_string_mutf8() {
+        let mut builder = DexBuilder::new();
+        let string = builder.string("key\0\u{1F511}");
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let resolved = resolve_string(&dex, string).unwrap();
+        assert_eq!(resolved, "key\0\u{1F511}");
+        assert_eq!(resolved.chars().count(), 5);
+        assert_eq!(serde_json::to_string(&resolved).unwrap(), "\"key\\u0000\u{1F511}\"");
+        assert_eq!(resolve_string(&dex, 100), None);
+    }
+
+    #[test]
+    fn test_resolve_string_encoded() {
+        let mut builder = DexBuilder::new();
+        let valid = builder.string("key\0\u{1F511}");
+        let invalid = builder.string("invalid");
+        let mut bytes = builder.build();
+        // Replaces the last byte of "invalid" by a lone continuation byte
+        let end = bytes.windows(7).position(|window| window == b"invalid").unwrap() + 6;
+        bytes[end] = 0x80;
+        let dex = DexReader::from_vec(bytes.as_slice()).unwrap();
+        assert_eq!(resolve_string(&dex, invalid), None);
+        assert_eq!(resolve_string_encoded(&dex, &bytes, valid, InvalidStrings::Hex).unwrap(), "key\0\u{1F511}");
+        assert_eq!(resolve_string_encoded(&dex, &bytes, invalid, InvalidStrings::Escaped).unwrap(), r"invali\x80");
+        assert_eq!(resolve_string_encoded(&dex, &bytes, invalid, InvalidStrings::Base64).unwrap(), "aW52YWxpgA==");
+        assert_eq!(resolve_string_encoded(&dex, &bytes, 100, InvalidStrings::Hex), None);
+    }
+
+    #[test]
+    fn test_resolve_method_proto() {
+        let mut builder = DexBuilder::new();
+        let get_bytes = builder.method("Ljava/lang/String;", "getBytes", "[B", &["Ljava/lang/String;"]);
+        let length = builder.method("Ljava/lang/String;", "length", "I", &[]);
+        builder.class(ClassDef::new("Lcom/example/Main;"));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+
+        let method = resolve_method(&dex, get_bytes).unwrap();
+        assert_eq!((method.class.as_str(), method.name.as_str()), ("Ljava/lang/String;", "getBytes"));
+        assert_eq!((method.params, method.return_type), (vec!["Ljava/lang/String;".to_string()], "[B".to_string()));
+        let method = resolve_method(&dex, length).unwrap();
+        assert_eq!((method.params, method.return_type), (vec![], "I".to_string()));
+        assert_eq!(resolve_method(&dex, 100), None);
+    }
+
+    #[test]
+    fn test_resolve_new_instance_type() {
+        let mut builder = DexBuilder::new();
+        let loader = builder.type_idx("Ldalvik/system/DexClassLoader;") as u16;
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("load", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0022, loader, 0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+
+        let (new_instance, _) = Instruction::try_from_raw_bytecode(&[0x0022, loader], 0).unwrap().unwrap();
+        assert_eq!(resolve_instruction_type(&dex, &new_instance).as_deref(), Some("dalvik.system.DexClassLoader"));
+        let (out_of_range, _) = Instruction::try_from_raw_bytecode(&[0x0022, 0xFFFF], 0).unwrap().unwrap();
+        assert_eq!(resolve_instruction_type(&dex, &out_of_range), None);
+        let (return_void, _) = Instruction::try_from_raw_bytecode(&[0x000E], 0).unwrap().unwrap();
+        assert_eq!(resolve_instruction_type(&dex, &return_void), None);
+    }
+}
@@ -0,0 +1,237 @@
+use serde::Serialize;
+
+
+const HEADER_SIZE: u32 = 0x70;
+const FILE_SIZE_OFFSET: usize = 0x20;
+const LINK_SIZE_OFFSET: usize = 0x2C;
+const LINK_OFF_OFFSET: usize = 0x30;
+const MAP_OFF_OFFSET: usize = 0x34;
+const MAP_LIST: u16 = 0x1000;
+
+
+/// Section of a dex, as declared by an entry of its map_list or, for the link data, by its header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DexSection {
+    /// Name of the item type, e.g. `code_item`, `link_data` for the link data and `unknown` for unknown item types
+    pub kind: &'static str,
+    /// Item type code of the map_list entry, absent for the link data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item_type: Option<u16>,
+    pub offset: u32,
+    /// Number of items, bytes for the link data
+    pub items: u32,
+    /// Size in bytes. Sections of variable-sized items are taken to run until the next section or the end of the dex
+    pub bytes: u32,
+}
+
+
+/// Unusual layout of a dex, which compilers don't produce but packers and hand-crafted dexes do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SectionAnomaly {
+    /// The header declares link data, which only statically linked dexes have and the runtime never reads
+    LinkData { offset: u32, size: u32 },
+    /// Bytes past the `file_size` of the header, which the runtime ignores
+    AppendedData { offset: u32, size: u32 },
+    /// Bytes between the end of the last section and the `file_size` of the header
+    UnclaimedTail { offset: u32, size: u32 },
+    /// Two sections claiming the same bytes
+    OverlappingSections { first: &'static str, second: &'static str, offset: u32 },
+    /// Section running past the `file_size` of the header
+    OutOfBounds { section: &'static str, offset: u32 },
+    /// The map_list lies outside the dex
+    MissingMap,
+}
+
+
+/// Sections of a dex sorted by offset, and the anomalies of their layout
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DexLayout {
+    pub sections: Vec<DexSection>,
+    pub anomalies: Vec<SectionAnomaly>,
+}
+
+
+/// Name of a map_list item type, `unknown` for types the dex format doesn't define
+pub fn item_type_name(item_type: u16) -> &'static str {
+    match item_type {
+        0x0000 => "header_item",
+        0x0001 => "string_id_item",
+        0x0002 => "type_id_item",
+        0x0003 => "proto_id_item",
+        0x0004 => "field_id_item",
+        0x0005 => "method_id_item",
+        0x0006 => "class_def_item",
+        0x0007 => "call_site_id_item",
+        0x0008 => "method_handle_item",
+        MAP_LIST => "map_list",
+        0x1001 => "type_list",
+        0x1002 => "annotation_set_ref_list",
+        0x1003 => "annotation_set_item",
+        0x2000 => "class_data_item",
+        0x2001 => "code_item",
+        0x2002 => "string_data_item",
+        0x2003 => "debug_info_item",
+        0x2004 => "annotation_item",
+        0x2005 => "encoded_array_item",
+        0x2006 => "annotations_directory_item",
+        0xF000 => "hiddenapi_class_data_item",
+        _ => "unknown",
+    }
+}
+
+
+/// Reads the map_list and the header of a dex from its bytes, without the dex crate which neither exposes them nor
+/// looks past `file_size`. The bytes are those of the whole archive entry, so that appended data is seen
+pub fn dex_layout(bytes: &[u8]) -> DexLayout {
+    let read_u32 = |offset: usize| bytes.get(offset..offset + 4).map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+    let read_u16 = |offset: usize| bytes.get(offset..offset + 2).map(|half| u16::from_le_bytes(half.try_into().unwrap()));
+    let mut layout = DexLayout::default();
+    let Some(file_size) = read_u32(FILE_SIZE_OFFSET) else {
+        layout.anomalies.push(SectionAnomaly::MissingMap);
+        return layout;
+    };
+
+    // Sections along with their exact end, unknown for variable-sized items
+    let mut sections: Vec<(DexSection, Option<u64>)> = vec![];
+    let map_off = read_u32(MAP_OFF_OFFSET).unwrap_or(0) as usize;
+    match read_u32(map_off).filter(|_| map_off >= HEADER_SIZE as usize) {
+        Some(entries) => {
+            for entry in (0..entries as usize).map_while(|index| map_off.checked_add(4 + 12 * index)) {
+                let (Some(item_type), Some(items), Some(offset)) = (read_u16(entry), read_u32(entry + 4), read_u32(entry + 8)) else { break };
+                let end = match item_type {
+                    MAP_LIST => Some(4 + 12 * entries as u64),
+                    _ => item_size(item_type).map(|size| size as u64 * items as u64),
+                }.map(|size| offset as u64 + size);
+                sections.push((DexSection { kind: item_type_name(item_type), item_type: Some(item_type), offset, items, bytes: 0 }, end));
+            }
+        }
+        None => layout.anomalies.push(SectionAnomaly::MissingMap),
+    }
+    let (link_size, link_off) = (read_u32(LINK_SIZE_OFFSET).unwrap_or(0), read_u32(LINK_OFF_OFFSET).unwrap_or(0));
+    if link_size != 0 {
+        layout.anomalies.push(SectionAnomaly::LinkData { offset: link_off, size: link_size });
+        sections.push((DexSection { kind: "link_data", item_type: None, offset: link_off, items: link_size, bytes: link_size }, Some(link_off as u64 + link_size as u64)));
+    }
+    sections.sort_by_key(|(section, _)| section.offset);
+
+    for index in 0..sections.len() {
+        let (section, end) = &sections[index];
+        let next = sections[index + 1..].iter().map(|(next, _)| next.offset).find(|&offset| offset > section.offset);
+        let end = end.unwrap_or(next.unwrap_or(file_size) as u64);
+        if end > file_size as u64 || section.offset > file_size {
+            layout.anomalies.push(SectionAnomaly::OutOfBounds { section: section.kind, offset: section.offset });
+        }
+        // Any of the following sections may start before the end, not only the next one
+        let overlapping = sections[index + 1..].iter().take_while(|(following, _)| end > following.offset as u64);
+        for (following, _) in overlapping.filter(|(following, _)| section.items > 0 && following.items > 0) {
+            layout.anomalies.push(SectionAnomaly::OverlappingSections { first: section.kind, second: following.kind, offset: following.offset });
+        }
+        let size = end.saturating_sub(section.offset as u64).min(u32::MAX as u64) as u32;
+        sections[index].0.bytes = size;
+    }
+
+    // Only a last section of fixed-size items, usually the map_list, tells where the declared content ends
+    if let Some((last, Some(end))) = sections.last() {
+        if *end < file_size as u64 && last.kind != "link_data" {
+            layout.anomalies.push(SectionAnomaly::UnclaimedTail { offset: *end as u32, size: file_size - *end as u32 });
+        }
+    }
+    if bytes.len() > file_size as usize {
+        layout.anomalies.push(SectionAnomaly::AppendedData { offset: file_size, size: (bytes.len() - file_size as usize) as u32 });
+    }
+    layout.sections = sections.into_iter().map(|(section, _)| section).collect();
+    layout
+}
+
+
+/// Size of the items of the fixed-size item types, `None` for variable-sized ones
+fn item_size(item_type: u16) -> Option<u32> {
+    match item_type {
+        0x0000 => Some(HEADER_SIZE),
+        0x0001 | 0x0002 | 0x0007 => Some(4),
+        0x0004 | 0x0005 | 0x0008 => Some(8),
+        0x0003 => Some(12),
+        0x0006 => Some(32),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::testing::{ClassDef, CodeDef, DexBuilder, MethodDef};
+    use super::*;
+
+    fn dex() -> Vec<u8> {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;").method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x000E]))));
+        builder.build()
+    }
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_normal_dex() {
+        let bytes = dex();
+        let layout = dex_layout(&bytes);
+        assert_eq!(layout.anomalies, []);
+        let kinds: Vec<_> = layout.sections.iter().map(|section| section.kind).collect();
+        assert_eq!(kinds.first(), Some(&"header_item"));
+        assert_eq!(kinds.last(), Some(&"map_list"));
+        assert!(kinds.contains(&"code_item") && kinds.contains(&"string_data_item"));
+        let map = layout.sections.last().unwrap();
+        assert_eq!((map.offset + map.bytes) as usize, bytes.len());
+        assert_eq!(layout.sections[0].bytes, HEADER_SIZE);
+    }
+
+    #[test]
+    fn test_appended_data() {
+        let mut bytes = dex();
+        let file_size = bytes.len() as u32;
+        bytes.extend(b"hidden payload");
+        assert_eq!(dex_layout(&bytes).anomalies, [SectionAnomaly::AppendedData { offset: file_size, size: 14 }]);
+    }
+
+    #[test]
+    fn test_link_data_and_unclaimed_tail() {
+        let mut bytes = dex();
+        let end = bytes.len() as u32;
+        bytes.extend([0; 16]);
+        write_u32(&mut bytes, FILE_SIZE_OFFSET, end + 16);
+        assert_eq!(dex_layout(&bytes).anomalies, [SectionAnomaly::UnclaimedTail { offset: end, size: 16 }]);
+
+        write_u32(&mut bytes, LINK_SIZE_OFFSET, 16);
+        write_u32(&mut bytes, LINK_OFF_OFFSET, end);
+        let layout = dex_layout(&bytes);
+        assert_eq!(layout.anomalies, [SectionAnomaly::LinkData { offset: end, size: 16 }]);
+        assert_eq!(layout.sections.last().map(|section| (section.kind, section.bytes)), Some(("link_data", 16)));
+    }
+
+    #[test]
+    fn test_overlapping_sections() {
+        let mut bytes = dex();
+        // Points the string ids at the header
+        let map_off = u32::from_le_bytes(bytes[MAP_OFF_OFFSET..MAP_OFF_OFFSET + 4].try_into().unwrap()) as usize;
+        let string_ids = (0..).map(|index| map_off + 4 + 12 * index).find(|&entry| bytes[entry..entry + 2] == [1, 0]).unwrap();
+        write_u32(&mut bytes, string_ids + 8, 0x10);
+        assert!(dex_layout(&bytes).anomalies.contains(&SectionAnomaly::OverlappingSections { first: "header_item", second: "string_id_item", offset: 0x10 }));
+    }
+
+    #[test]
+    fn test_section_overlapping_several() {
+        let mut bytes = dex();
+        let map_off = u32::from_le_bytes(bytes[MAP_OFF_OFFSET..MAP_OFF_OFFSET + 4].try_into().unwrap()) as usize;
+        let entry = |bytes: &[u8], item_type: u8| (0..).map(|index| map_off + 4 + 12 * index).find(|&entry| bytes[entry..entry + 2] == [item_type, 0]).unwrap();
+        let offset = |bytes: &[u8], item_type: u8| u32::from_le_bytes(bytes[entry(bytes, item_type) + 8..entry(bytes, item_type) + 12].try_into().unwrap());
+        // Enough string ids to run over the type ids and the proto ids that follow them
+        let (string_ids, type_ids, proto_ids) = (offset(&bytes, 1), offset(&bytes, 2), offset(&bytes, 3));
+        let string_ids_entry = entry(&bytes, 1);
+        write_u32(&mut bytes, string_ids_entry + 4, (proto_ids + 1 - string_ids).div_ceil(4));
+        let anomalies = dex_layout(&bytes).anomalies;
+        assert!(anomalies.contains(&SectionAnomaly::OverlappingSections { first: "string_id_item", second: "type_id_item", offset: type_ids }));
+        assert!(anomalies.contains(&SectionAnomaly::OverlappingSections { first: "string_id_item", second: "proto_id_item", offset: proto_ids }));
+    }
+}
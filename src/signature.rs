@@ -0,0 +1,147 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+use serde::Serialize;
+
+
+/// Magic closing the APK Signing Block, right before the central directory
+const SIGNING_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+
+/// IDs of the signature scheme blocks in the APK Signing Block
+const V2_BLOCK_ID: u32 = 0x7109871A;
+const V3_BLOCK_ID: u32 = 0xF05368C0;
+const V31_BLOCK_ID: u32 = 0x1B93AD61;
+
+/// Extensions of the JAR signature block files in `META-INF/`
+const V1_SIGNATURE_EXTENSIONS: &[&str] = &[".RSA", ".DSA", ".EC"];
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningScheme {
+    /// JAR signing, signature block files in `META-INF/`
+    V1,
+    V2,
+    /// v3 and v3.1, which share the signer layout
+    V3,
+}
+
+
+/// Which signature schemes an APK is signed with and by how many signers, certificates are not parsed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Signatures {
+    pub signed: bool,
+    /// Most signers found in a single scheme
+    pub signer_count: usize,
+    pub schemes: Vec<SigningScheme>,
+    /// Signature block files of the v1 scheme, e.g. `META-INF/CERT.RSA`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub v1_files: Vec<String>,
+}
+
+
+impl Signatures {
+    /// Records an archive entry if it is a v1 signature block file
+    pub fn add_entry(&mut self, name: &str) {
+        let Some(file_name) = name.strip_prefix("META-INF/") else { return };
+        if !file_name.contains('/') && V1_SIGNATURE_EXTENSIONS.iter().any(|extension| file_name.to_ascii_uppercase().ends_with(extension)) {
+            self.v1_files.push(name.to_string());
+            self.add_scheme(SigningScheme::V1, self.v1_files.len());
+        }
+    }
+
+    /// Reads the v2 and v3 signers from the APK Signing Block preceding the central directory at `central_directory_start`.
+    /// A missing or malformed block leaves the signatures unchanged
+    pub fn read_signing_block(&mut self, reader: &mut (impl Read + Seek), central_directory_start: u64) -> io::Result<()> {
+        let Some(footer_start) = central_directory_start.checked_sub(24) else { return Ok(()) };
+        let mut footer = [0; 24];
+        reader.seek(SeekFrom::Start(footer_start))?;
+        reader.read_exact(&mut footer)?;
+        if &footer[8..] != SIGNING_BLOCK_MAGIC {
+            return Ok(());
+        }
+        // The size excludes the leading size field and covers the pairs, the trailing size field and the magic
+        let block_size = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        let Some(block_start) = central_directory_start.checked_sub(block_size.saturating_add(8)) else { return Ok(()) };
+        let Some(pairs_len) = block_size.checked_sub(24) else { return Ok(()) };
+        let mut pairs = vec![0; pairs_len as usize];
+        reader.seek(SeekFrom::Start(block_start + 8))?;
+        reader.read_exact(&mut pairs)?;
+
+        let mut rest = pairs.as_slice();
+        while rest.len() >= 12 {
+            let pair_len = u64::from_le_bytes(rest[..8].try_into().unwrap());
+            let Some(pair) = usize::try_from(pair_len).ok().and_then(|len| rest.get(8..len.checked_add(8)?)).filter(|pair| pair.len() >= 4) else { break };
+            let id = u32::from_le_bytes(pair[..4].try_into().unwrap());
+            let scheme = match id {
+                V2_BLOCK_ID => SigningScheme::V2,
+                V3_BLOCK_ID | V31_BLOCK_ID => SigningScheme::V3,
+                _ => {
+                    rest = &rest[8 + pair.len()..];
+                    continue;
+                }
+            };
+            if let Some(signers) = count_signers(&pair[4..]) {
+                self.add_scheme(scheme, signers);
+            }
+            rest = &rest[8 + pair.len()..];
+        }
+        Ok(())
+    }
+
+    fn add_scheme(&mut self, scheme: SigningScheme, signers: usize) {
+        if !self.schemes.contains(&scheme) {
+            self.schemes.push(scheme);
+        }
+        self.signed = true;
+        self.signer_count = self.signer_count.max(signers);
+    }
+}
+
+
+/// Number of signers in a scheme block: a length-prefixed sequence of length-prefixed signers
+fn count_signers(value: &[u8]) -> Option<usize> {
+    let len = u32::from_le_bytes(value.get(..4)?.try_into().ok()?) as usize;
+    let mut signers = value.get(4..len.checked_add(4)?)?;
+    let mut count = 0;
+    while !signers.is_empty() {
+        let signer_len = u32::from_le_bytes(signers.get(..4)?.try_into().ok()?) as usize;
+        signers = signers.get(signer_len.checked_add(4)?..)?;
+        count += 1;
+    }
+    Some(count)
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_v1_entries() {
+        let mut signatures = Signatures::default();
+        for name in ["classes.dex", "META-INF/MANIFEST.MF", "META-INF/CERT.SF", "META-INF/CERT.RSA", "META-INF/other/KEY.EC", "META-INF/ALT.ec"] {
+            signatures.add_entry(name);
+        }
+        assert!(signatures.signed);
+        assert_eq!(signatures.schemes, vec![SigningScheme::V1]);
+        assert_eq!(signatures.v1_files, vec!["META-INF/CERT.RSA", "META-INF/ALT.ec"]);
+        assert_eq!(signatures.signer_count, 2);
+    }
+
+    #[test]
+    fn test_count_signers() {
+        let value = [12, 0, 0, 0, 2, 0, 0, 0, 0xAA, 0xBB, 2, 0, 0, 0, 0xCC, 0xDD];
+        assert_eq!(count_signers(&value), Some(2));
+        assert_eq!(count_signers(&value[..10]), None);
+    }
+
+    #[test]
+    fn test_no_signing_block() {
+        let mut signatures = Signatures::default();
+        signatures.read_signing_block(&mut Cursor::new(vec![0; 64]), 64).unwrap();
+        signatures.read_signing_block(&mut Cursor::new(vec![0; 8]), 8).unwrap();
+        assert_eq!(signatures, Signatures::default());
+    }
+}
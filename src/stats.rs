@@ -0,0 +1,355 @@
+use std::{fmt, fs, io::{self, Write}, ops::Add, sync::{Arc, atomic::{AtomicU64, Ordering}}, time::Instant};
+
+use dexompiler::Sequences;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+
+
+/// Methods by share of register moves in their instructions, in buckets 10% wide: 0 to 10%, 10 to 20%... 90 to 100%
+pub type MoveDensity = [u64; 10];
+
+
+/// Stage of the analysis of an input timed by `RunStats::time`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading the archive: the dexes, the manifest, the signatures and the assets
+    ZipRead,
+    /// Decoding the dexes into the report
+    Decode,
+    /// Turning the reports into output records and writing them
+    Serialization,
+}
+
+
+/// Counters of a run, bumped by the workers as inputs complete
+pub struct RunStats {
+    started: Instant,
+    processed: AtomicU64,
+    failed: AtomicU64,
+    skipped: AtomicU64,
+    dex_bytes: AtomicU64,
+    instructions: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    /// Methods of the deduplicated sequences and their unique bodies
+    deduplicated_methods: AtomicU64,
+    unique_methods: AtomicU64,
+    /// Nanoseconds spent in every `Stage`, in declaration order
+    stage_nanos: [AtomicU64; 3],
+    move_density: [AtomicU64; 10],
+    written: Arc<AtomicU64>,
+}
+
+
+impl RunStats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            processed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            dex_bytes: AtomicU64::new(0),
+            instructions: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            deduplicated_methods: AtomicU64::new(0),
+            unique_methods: AtomicU64::new(0),
+            stage_nanos: Default::default(),
+            move_density: Default::default(),
+            written: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Counts an input analyzed, of `dex_bytes` bytes of dexes and `instructions` emitted opcodes
+    pub fn processed(&self, dex_bytes: u64, instructions: u64) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        self.dex_bytes.fetch_add(dex_bytes, Ordering::Relaxed);
+        self.instructions.fetch_add(instructions, Ordering::Relaxed);
+    }
+
+    /// Counts an input that couldn't be downloaded or analyzed
+    pub fn failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an input left out of the output without an analysis error, quarantined by `--isolate`
+    pub fn skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the lookups of `--cache-dir` made by a child process
+    pub fn cache_lookups(&self, hits: u64, misses: u64) {
+        self.cache_hits.fetch_add(hits, Ordering::Relaxed);
+        self.cache_misses.fetch_add(misses, Ordering::Relaxed);
+    }
+
+    /// Counts a lookup of `--cache-dir`
+    pub fn cache_lookup(&self, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the `methods` of the deduplicated sequences of an input, of which `unique` have a body of their own
+    pub fn deduplicated(&self, methods: u64, unique: u64) {
+        self.deduplicated_methods.fetch_add(methods, Ordering::Relaxed);
+        self.unique_methods.fetch_add(unique, Ordering::Relaxed);
+    }
+
+    /// Inputs that couldn't be downloaded or analyzed so far
+    pub fn failures(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Adds the move densities of the methods of an input, see `move_density`
+    pub fn moves(&self, density: &MoveDensity) {
+        for (total, methods) in self.move_density.iter().zip(density) {
+            total.fetch_add(*methods, Ordering::Relaxed);
+        }
+    }
+
+    /// Runs `f`, adding the time it takes to `stage`
+    pub fn time<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.elapsed(stage, started);
+        result
+    }
+
+    /// Adds the time since `started` to `stage`, for a stage that ends halfway through a call, e.g. the serialization of
+    /// the output before its summary footer
+    pub fn elapsed(&self, stage: Stage, started: Instant) {
+        self.stage_nanos[stage as usize].fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Wraps the writer of the output to count the bytes written to it
+    pub fn counting<W: Write>(&self, inner: W) -> CountingWriter<W> {
+        CountingWriter { inner, written: self.written.clone() }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let wall_time_secs = self.started.elapsed().as_secs_f64();
+        let instructions = self.instructions.load(Ordering::Relaxed);
+        let (cache_hits, cache_misses) = (self.cache_hits.load(Ordering::Relaxed), self.cache_misses.load(Ordering::Relaxed));
+        let cached = cache_hits + cache_misses > 0;
+        let (methods, unique_methods) = (self.deduplicated_methods.load(Ordering::Relaxed), self.unique_methods.load(Ordering::Relaxed));
+        let [zip_read, decode, serialization] = self.stage_nanos.each_ref().map(|nanos| nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let timed = zip_read + decode + serialization > 0.0;
+        let move_density = self.move_density.each_ref().map(|methods| methods.load(Ordering::Relaxed));
+        Summary {
+            wall_time_secs,
+            inputs_processed: self.processed.load(Ordering::Relaxed),
+            inputs_failed: self.failed.load(Ordering::Relaxed),
+            inputs_skipped: self.skipped.load(Ordering::Relaxed),
+            dex_bytes: self.dex_bytes.load(Ordering::Relaxed),
+            instructions,
+            instructions_per_sec: if wall_time_secs > 0.0 { instructions as f64 / wall_time_secs } else { 0.0 },
+            peak_rss_bytes: peak_rss(),
+            bytes_written: self.written.load(Ordering::Relaxed),
+            cache_hits: cached.then_some(cache_hits),
+            cache_misses: cached.then_some(cache_misses),
+            deduplicated_methods: (methods > 0).then_some(methods),
+            unique_methods: (methods > 0).then_some(unique_methods),
+            dedup_ratio: dedup_ratio(methods, unique_methods),
+            stage_secs: timed.then_some(StageSecs { zip_read, decode, serialization }),
+            move_density: move_density.iter().any(|&methods| methods > 0).then_some(move_density),
+        }
+    }
+}
+
+
+/// Snapshot of the counters of a run, printed at the end and written as the `summary` footer of the output
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Summary {
+    pub wall_time_secs: f64,
+    pub inputs_processed: u64,
+    pub inputs_failed: u64,
+    pub inputs_skipped: u64,
+    /// Total size of the dexes of the processed inputs, as announced by their headers
+    pub dex_bytes: u64,
+    /// Opcodes emitted in the sequences of the processed inputs
+    pub instructions: u64,
+    pub instructions_per_sec: f64,
+    /// Peak resident set size of the process, only known on Linux
+    pub peak_rss_bytes: Option<u64>,
+    /// Bytes of output written before the summary
+    pub bytes_written: u64,
+    /// Dexes found in and missing from `--cache-dir`, absent without lookups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_hits: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_misses: Option<u64>,
+    /// Methods of the deduplicated sequences and their unique bodies, absent without deduplication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deduplicated_methods: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unique_methods: Option<u64>,
+    /// Share of the deduplicated methods whose body is a duplicate, see `dedup_ratio`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup_ratio: Option<f64>,
+    /// Time spent in every stage, absent when the inputs were all analyzed by child processes of `--isolate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage_secs: Option<StageSecs>,
+    /// Distribution of the share of register moves of the methods, telling apart dx output from the sparser D8 output.
+    /// Only with `--with-op-stats`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub move_density: Option<MoveDensity>,
+}
+
+
+/// Seconds spent in every `Stage`, summed over the worker threads so that they can add up to more than the wall time
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StageSecs {
+    pub zip_read: f64,
+    pub decode: f64,
+    pub serialization: f64,
+}
+
+
+impl Add for StageSecs {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self { zip_read: self.zip_read + other.zip_read, decode: self.decode + other.decode, serialization: self.serialization + other.serialization }
+    }
+}
+
+
+impl Summary {
+    /// Share of the lookups of `--cache-dir` that found the input, `None` without lookups
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let (hits, misses) = (self.cache_hits?, self.cache_misses?);
+        (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64)
+    }
+}
+
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Processed {} inputs, {} failed and {} skipped, {} of dexes and {} instructions in {:.1}s ({:.0} instructions/s), {} written",
+            self.inputs_processed, self.inputs_failed, self.inputs_skipped, HumanBytes(self.dex_bytes), self.instructions,
+            self.wall_time_secs, self.instructions_per_sec, HumanBytes(self.bytes_written))?;
+        if let Some(peak_rss) = self.peak_rss_bytes {
+            write!(f, ", peak RSS {}", HumanBytes(peak_rss))?;
+        }
+        if let Some(stages) = self.stage_secs {
+            write!(f, ", {:.1}s reading zips, {:.1}s decoding and {:.1}s serializing", stages.zip_read, stages.decode, stages.serialization)?;
+        }
+        if let (Some(rate), Some(hits)) = (self.cache_hit_rate(), self.cache_hits) {
+            write!(f, ", {} cache hits ({:.1}%)", hits, rate * 100.0)?;
+        }
+        if let (Some(ratio), Some(methods), Some(unique)) = (self.dedup_ratio, self.deduplicated_methods, self.unique_methods) {
+            write!(f, ", deduplicated {} of {} methods ({:.2}%)", methods - unique, methods, ratio * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Share of `methods` whose body is the duplicate of another one, `unique` of them being unique. `None` without methods
+pub fn dedup_ratio(methods: u64, unique: u64) -> Option<f64> {
+    (methods > 0).then(|| 1.0 - unique as f64 / methods as f64)
+}
+
+
+/// Move density of the methods of an input with op stats, `--with-op-stats` being incompatible with deduplication
+pub fn move_density(sequences: &Sequences) -> MoveDensity {
+    let mut density = MoveDensity::default();
+    if let Sequences::Flat { methods, .. } = sequences {
+        for ratio in methods.iter().filter_map(|method| method.op_stats()?.move_ratio()) {
+            density[((ratio * 10.0) as usize).min(9)] += 1;
+        }
+    }
+    density
+}
+
+
+/// Writer counting the bytes that go through it into its `RunStats`
+pub struct CountingWriter<W> {
+    inner: W,
+    written: Arc<AtomicU64>,
+}
+
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+/// Peak resident set size of the process, `VmHWM` of /proc/self/status. `None` where there is no such file
+pub fn peak_rss() -> Option<u64> {
+    parse_vm_hwm(&fs::read_to_string("/proc/self/status").ok()?)
+}
+
+
+fn parse_vm_hwm(status: &str) -> Option<u64> {
+    let kilobytes = status.lines().find_map(|line| line.strip_prefix("VmHWM:"))?.trim().strip_suffix("kB")?.trim();
+    Some(kilobytes.parse::<u64>().ok()? * 1024)
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_hwm() {
+        let status = "Name:\tdexompiler\nVmPeak:\t  204800 kB\nVmHWM:\t   51200 kB\nVmRSS:\t   40960 kB\n";
+        assert_eq!(parse_vm_hwm(status), Some(51200 * 1024));
+        assert_eq!(parse_vm_hwm("Name:\tdexompiler\n"), None);
+    }
+
+    #[test]
+    fn test_counters() {
+        let stats = RunStats::new();
+        stats.processed(1000, 40);
+        stats.processed(500, 2);
+        stats.failed();
+        let mut writer = stats.counting(vec![]);
+        writer.write_all(b"{\"meta\":{}}\n").unwrap();
+        let summary = stats.summary();
+        assert_eq!((summary.inputs_processed, summary.inputs_failed, summary.inputs_skipped), (2, 1, 0));
+        assert_eq!((summary.dex_bytes, summary.instructions, summary.bytes_written), (1500, 42, 12));
+        assert_eq!(summary.cache_hit_rate(), None);
+        assert_eq!(summary.stage_secs, None);
+
+        stats.cache_lookup(false);
+        stats.cache_lookup(true);
+        stats.cache_lookup(true);
+        let summary = stats.summary();
+        assert_eq!((summary.cache_hits, summary.cache_misses), (Some(2), Some(1)));
+        assert!(summary.to_string().ends_with(", 2 cache hits (66.7%)"));
+        assert_eq!(stats.failures(), 1);
+
+        stats.time(Stage::Decode, || std::thread::sleep(std::time::Duration::from_millis(20)));
+        let stages = stats.summary().stage_secs.unwrap();
+        assert!(stages.decode >= 0.02);
+        assert_eq!((stages.zip_read, stages.serialization), (0.0, 0.0));
+        assert!(stats.summary().to_string().contains("s decoding and 0.0s serializing"));
+        let started = Instant::now();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        stats.elapsed(Stage::Serialization, started);
+        assert!(stats.summary().stage_secs.unwrap().serialization >= 0.02);
+
+        assert_eq!(stats.summary().move_density, None);
+        stats.moves(&[3, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        stats.moves(&[1, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(stats.summary().move_density, Some([4, 0, 0, 0, 0, 1, 0, 0, 0, 1]));
+
+        assert_eq!(stats.summary().dedup_ratio, None);
+        stats.deduplicated(6, 4);
+        stats.deduplicated(2, 1);
+        let summary = stats.summary();
+        assert_eq!((summary.deduplicated_methods, summary.unique_methods, summary.dedup_ratio), (Some(8), Some(5), Some(0.375)));
+        assert!(summary.to_string().ends_with(", deduplicated 3 of 8 methods (37.50%)"));
+    }
+}
@@ -0,0 +1,234 @@
+use std::ops::ControlFlow;
+
+use dex::Dex;
+use serde::Serialize;
+
+use crate::{dex_parsing::{walk_dex, DecodedInstruction, InstructionVisitor, MethodInfo}, options::{InvalidStrings, Strictness}};
+
+
+/// Offsets of the string_ids size and offset fields in the dex header
+const STRING_IDS_SIZE_OFFSET: usize = 0x38;
+const STRING_IDS_OFF_OFFSET: usize = 0x3C;
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+
+#[cfg(test)]
+thread_local! {
+    /// Number of string pools read on this thread, for the tests checking that unselected sections aren't computed
+    pub(crate) static POOLS_READ: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+
+/// Entry of the string_ids section of a dex
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PoolString {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    pub index: u32,
+    /// Decoded string, or its bytes written as set by the options when they are not valid MUTF-8
+    pub value: String,
+    /// Length of the MUTF-8 encoded string in bytes
+    pub byte_length: usize,
+    pub valid_utf8: bool,
+    /// Number of instructions referencing the string, e.g. `const-string`
+    pub uses: u32,
+}
+
+
+/// Every string of the dex `bytes`, `dex` being the same dex parsed, the invalid ones written as `invalid`.
+/// Entries pointing outside the file are left out
+pub fn string_pool<T: AsRef<[u8]>>(dex_index: usize, bytes: &[u8], dex: &Dex<T>, invalid: InvalidStrings) -> Vec<PoolString> {
+    #[cfg(test)]
+    POOLS_READ.with(|read| read.set(read.get() + 1));
+    let raw_strings = raw_strings(bytes);
+    let mut visitor = UsesVisitor { uses: vec![0; raw_strings.len()] };
+    walk_dex(dex, &mut visitor);
+    raw_strings.into_iter()
+        .enumerate()
+        .filter_map(|(index, raw)| {
+            let raw = raw?;
+            let decoded = decode_mutf8(raw);
+            Some(PoolString {
+                dex: dex_index,
+                index: index as u32,
+                valid_utf8: decoded.is_some(),
+                value: decoded.unwrap_or_else(|| encode_invalid(raw, invalid)),
+                byte_length: raw.len(),
+                uses: visitor.uses[index],
+            })
+        })
+        .collect()
+}
+
+
+/// Counts the instructions referencing every string index
+struct UsesVisitor {
+    uses: Vec<u32>,
+}
+
+impl InstructionVisitor for UsesVisitor {
+    fn visit_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        if method.code().is_some() { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+    }
+
+    fn visit_instruction(&mut self, inst: &DecodedInstruction) {
+        if inst.instruction.reference_kind() == Some("string") {
+            if let Some(uses) = inst.instruction.reference().and_then(|index| self.uses.get_mut(index as usize)) {
+                *uses += 1;
+            }
+        }
+    }
+
+    fn strictness(&self) -> Strictness {
+        Strictness::Lenient
+    }
+}
+
+
+/// MUTF-8 bytes of every entry of the string_ids section, `None` for entries pointing outside the file
+fn raw_strings(bytes: &[u8]) -> Vec<Option<&[u8]>> {
+    let (Some(size), Some(ids_off)) = (read_u32(bytes, STRING_IDS_SIZE_OFFSET), read_u32(bytes, STRING_IDS_OFF_OFFSET)) else { return vec![] };
+    (0..size)
+        .map_while(|index| ids_off.checked_add(index * 4).and_then(|offset| read_u32(bytes, offset)))
+        .map(|data_off| string_data(bytes, data_off))
+        .collect()
+}
+
+
+/// MUTF-8 bytes of the entry `index` of the string_ids section, `None` for indices and entries outside the file
+pub(crate) fn raw_string(bytes: &[u8], index: u32) -> Option<&[u8]> {
+    if index as usize >= read_u32(bytes, STRING_IDS_SIZE_OFFSET)? {
+        return None;
+    }
+    let data_off = read_u32(bytes, read_u32(bytes, STRING_IDS_OFF_OFFSET)?.checked_add(index as usize * 4)?)?;
+    string_data(bytes, data_off)
+}
+
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<usize> {
+    bytes.get(offset..offset.checked_add(4)?).map(|word| u32::from_le_bytes(word.try_into().unwrap()) as usize)
+}
+
+
+/// Bytes of the string_data_item at `data_off`: uleb128 length in UTF-16 code units, then the NUL-terminated MUTF-8 bytes
+fn string_data(bytes: &[u8], data_off: usize) -> Option<&[u8]> {
+    let data = bytes.get(data_off..)?;
+    let length_size = data.iter().position(|byte| byte & 0x80 == 0)? + 1;
+    let data = &data[length_size..];
+    Some(&data[..data.iter().position(|&byte| byte == 0)?])
+}
+
+
+/// Decodes MUTF-8: NUL as two bytes and supplementary characters as surrogate pairs of three bytes each.
+/// `None` for malformed sequences and unpaired surrogates
+pub fn decode_mutf8(bytes: &[u8]) -> Option<String> {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&first) = bytes.next() {
+        let mut continuation = || bytes.next().filter(|&&byte| byte & 0xC0 == 0x80).map(|&byte| (byte & 0x3F) as u16);
+        let unit = match first {
+            0x01..=0x7F => first as u16,
+            0xC0..=0xDF => ((first & 0x1F) as u16) << 6 | continuation()?,
+            0xE0..=0xEF => ((first & 0x0F) as u16) << 12 | continuation()? << 6 | continuation()?,
+            _ => return None,
+        };
+        units.push(unit);
+    }
+    String::from_utf16(&units).ok()
+}
+
+
+/// Bytes of a string that isn't valid MUTF-8 as text that any JSON or CSV output can hold
+pub fn encode_invalid(bytes: &[u8], encoding: InvalidStrings) -> String {
+    match encoding {
+        InvalidStrings::Hex => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        InvalidStrings::Escaped => bytes.escape_ascii().to_string(),
+        InvalidStrings::Base64 => bytes.chunks(3)
+            .flat_map(|chunk| {
+                let word = chunk.iter().enumerate().fold(0u32, |word, (index, &byte)| word | (byte as u32) << (16 - 8 * index));
+                // A chunk of n bytes gives n + 1 symbols, padded to 4
+                (0..4).map(move |index| if index <= chunk.len() { BASE64_ALPHABET[((word >> (18 - 6 * index)) & 0x3F) as usize] as char } else { '=' })
+            })
+            .collect(),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{DexBuilder, ClassDef, MethodDef, CodeDef};
+    use super::*;
+
+    #[test]
+    fn test_raw_strings_count() {
+        let mut builder = DexBuilder::new();
+        for value in ["first", "second", "third"] {
+            builder.string(value);
+        }
+        builder.type_idx("Lcom/example/Main;");
+        let bytes = builder.build();
+        let strings = raw_strings(&bytes);
+        assert_eq!(strings, [Some(&b"first"[..]), Some(b"second"), Some(b"third"), Some(b"Lcom/example/Main;")]);
+        assert!(raw_strings(&bytes[..0x40]).is_empty());
+        assert_eq!((0..4).map(|index| raw_string(&bytes, index)).collect::<Vec<_>>(), strings);
+        assert_eq!(raw_string(&bytes, 4), None);
+    }
+
+    #[test]
+    fn test_string_pool_uses() {
+        let mut builder = DexBuilder::new();
+        let key = builder.string("secret-key") as u16;
+        builder.string("unused");
+        // const-string v0, "secret-key" twice; return-void
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x001A, key, 0x001A, key, 0x000E]))));
+        let bytes = builder.build();
+        let dex = DexReader::from_vec(bytes.as_slice()).unwrap();
+        let pool = string_pool(1, &bytes, &dex, InvalidStrings::Hex);
+        assert_eq!(pool.len(), raw_strings(&bytes).len());
+        assert_eq!(pool[0], PoolString { dex: 1, index: 0, value: "secret-key".to_string(), byte_length: 10, valid_utf8: true, uses: 2 });
+        assert_eq!((pool[1].value.as_str(), pool[1].uses), ("unused", 0));
+    }
+
+    #[test]
+    fn test_decode_mutf8() {
+        // Embedded NUL as C0 80 and U+1F600 as the surrogate pair D83D DE00
+        assert_eq!(decode_mutf8(b"a\xC0\x80b\xED\xA0\xBD\xED\xB8\x80").as_deref(), Some("a\0b\u{1F600}"));
+        assert_eq!(decode_mutf8("é€".as_bytes()).as_deref(), Some("é€"));
+        // Unpaired surrogate, truncated sequence and bare NUL
+        assert_eq!(decode_mutf8(b"\xED\xA0\xBD"), None);
+        assert_eq!(decode_mutf8(b"\xE2\x82"), None);
+        assert_eq!(decode_mutf8(b"\x00"), None);
+    }
+
+    #[test]
+    fn test_string_pool_mutf8() {
+        let mut builder = DexBuilder::new();
+        builder.string("a\0b\u{1F600}");
+        builder.string("invalid");
+        let mut bytes = builder.build();
+        // Replaces the last byte of "invalid" by a lone continuation byte
+        let end = bytes.windows(7).position(|window| window == b"invalid").unwrap() + 6;
+        bytes[end] = 0x80;
+        let dex = DexReader::from_vec(bytes.as_slice()).unwrap();
+        let pool = string_pool(0, &bytes, &dex, InvalidStrings::Base64);
+        assert_eq!((pool[0].value.as_str(), pool[0].byte_length, pool[0].valid_utf8), ("a\0b\u{1F600}", 10, true));
+        assert_eq!((pool[1].value.as_str(), pool[1].valid_utf8), ("aW52YWxpgA==", false));
+        let json = serde_json::to_string(&pool).unwrap();
+        assert!(json.contains(r"a\u0000b"));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap()[0]["value"], "a\0b\u{1F600}");
+    }
+
+    #[test]
+    fn test_encode_invalid() {
+        let bytes = b"a\x80\xED\xA0";
+        assert_eq!(encode_invalid(bytes, InvalidStrings::Hex), "6180eda0");
+        assert_eq!(encode_invalid(bytes, InvalidStrings::Escaped), r"a\x80\xed\xa0");
+        assert_eq!(encode_invalid(bytes, InvalidStrings::Base64), "YYDtoA==");
+        assert_eq!(encode_invalid(b"abc", InvalidStrings::Base64), "YWJj");
+        assert_eq!(encode_invalid(b"ab", InvalidStrings::Base64), "YWI=");
+        assert_eq!(encode_invalid(b"", InvalidStrings::Base64), "");
+    }
+}
@@ -0,0 +1,863 @@
+//! Assembles small dex files in memory, used as fixtures by the tests and benchmarks.
+//!
+//! Ids are assigned in insertion order instead of the sorted order the format asks for,
+//! which the decoder does not rely on, so callers can embed an index in bytecode as soon as it's interned.
+
+use std::collections::HashMap;
+
+use crate::dex_parsing::Opcode;
+
+const NO_INDEX: u32 = 0xffff_ffff;
+const HEADER_SIZE: u32 = 0x70;
+
+pub const ACC_PUBLIC: u32 = 0x1;
+pub const ACC_PRIVATE: u32 = 0x2;
+pub const ACC_STATIC: u32 = 0x8;
+pub const ACC_FINAL: u32 = 0x10;
+pub const ACC_NATIVE: u32 = 0x100;
+pub const ACC_INTERFACE: u32 = 0x200;
+pub const ACC_ABSTRACT: u32 = 0x400;
+pub const ACC_SYNTHETIC: u32 = 0x1000;
+pub const ACC_CONSTRUCTOR: u32 = 0x10000;
+
+
+/// A class definition to add to a `DexBuilder`
+pub struct ClassDef {
+    descriptor: String,
+    access_flags: u32,
+    superclass: Option<String>,
+    source_file: Option<String>,
+    fields: Vec<FieldDef>,
+    methods: Vec<MethodDef>,
+}
+
+impl ClassDef {
+    pub fn new(descriptor: &str) -> Self {
+        Self {
+            descriptor: descriptor.to_string(),
+            access_flags: ACC_PUBLIC,
+            superclass: Some("Ljava/lang/Object;".to_string()),
+            source_file: None,
+            fields: vec![],
+            methods: vec![],
+        }
+    }
+
+    pub fn access_flags(mut self, access_flags: u32) -> Self {
+        self.access_flags = access_flags;
+        self
+    }
+
+    pub fn superclass(mut self, superclass: Option<&str>) -> Self {
+        self.superclass = superclass.map(str::to_string);
+        self
+    }
+
+    pub fn source_file(mut self, source_file: &str) -> Self {
+        self.source_file = Some(source_file.to_string());
+        self
+    }
+
+    pub fn field(mut self, field: FieldDef) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    pub fn method(mut self, method: MethodDef) -> Self {
+        self.methods.push(method);
+        self
+    }
+}
+
+
+/// A field of a `ClassDef`, static fields may have an initial value
+pub struct FieldDef {
+    name: String,
+    field_type: String,
+    access_flags: u32,
+    value: Option<ValueDef>,
+}
+
+impl FieldDef {
+    pub fn new(name: &str, field_type: &str) -> Self {
+        Self { name: name.to_string(), field_type: field_type.to_string(), access_flags: ACC_PUBLIC, value: None }
+    }
+
+    pub fn access_flags(mut self, access_flags: u32) -> Self {
+        self.access_flags = access_flags;
+        self
+    }
+
+    pub fn value(mut self, value: ValueDef) -> Self {
+        self.value = Some(value);
+        self
+    }
+}
+
+
+/// Initial value of a static `FieldDef`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueDef {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Boolean(bool),
+    Null,
+    /// Enum constant as its declaring class, name and type
+    Enum(String, String, String),
+}
+
+
+/// A method of a `ClassDef`, without code unless `code` is set
+pub struct MethodDef {
+    name: String,
+    return_type: String,
+    params: Vec<String>,
+    access_flags: u32,
+    code: Option<CodeDef>,
+}
+
+impl MethodDef {
+    pub fn new(name: &str, return_type: &str, params: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            return_type: return_type.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            access_flags: ACC_PUBLIC,
+            code: None,
+        }
+    }
+
+    pub fn access_flags(mut self, access_flags: u32) -> Self {
+        self.access_flags = access_flags;
+        self
+    }
+
+    pub fn code(mut self, code: CodeDef) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    fn is_direct(&self) -> bool {
+        self.access_flags & (ACC_STATIC | ACC_PRIVATE | ACC_CONSTRUCTOR) != 0
+    }
+}
+
+
+/// The code item of a `MethodDef`
+pub struct CodeDef {
+    registers_size: u16,
+    ins_size: u16,
+    outs_size: u16,
+    insns: Vec<u16>,
+    tries: Vec<TryDef>,
+    debug_line: Option<u32>,
+}
+
+impl CodeDef {
+    pub fn new(registers_size: u16, ins_size: u16, outs_size: u16, insns: &[u16]) -> Self {
+        Self { registers_size, ins_size, outs_size, insns: insns.to_vec(), tries: vec![], debug_line: None }
+    }
+
+    pub fn try_block(mut self, try_block: TryDef) -> Self {
+        self.tries.push(try_block);
+        self
+    }
+
+    /// Gives the code a debug_info_item starting at `line_start`, without parameter names nor line entries
+    pub fn debug_info(mut self, line_start: u32) -> Self {
+        self.debug_line = Some(line_start);
+        self
+    }
+}
+
+
+/// A try block of a `CodeDef` with its typed handlers and optional catch-all handler
+pub struct TryDef {
+    start_addr: u32,
+    insn_count: u16,
+    handlers: Vec<(String, u32)>,
+    catch_all: Option<u32>,
+}
+
+impl TryDef {
+    pub fn new(start_addr: u32, insn_count: u16) -> Self {
+        Self { start_addr, insn_count, handlers: vec![], catch_all: None }
+    }
+
+    pub fn catch(mut self, exception_type: &str, handler_addr: u32) -> Self {
+        self.handlers.push((exception_type.to_string(), handler_addr));
+        self
+    }
+
+    pub fn catch_all(mut self, handler_addr: u32) -> Self {
+        self.catch_all = Some(handler_addr);
+        self
+    }
+}
+
+
+struct InternedClass {
+    type_idx: u32,
+    access_flags: u32,
+    superclass_idx: u32,
+    source_file_idx: u32,
+    /// Field index, access flags and whether the field is static, for every field
+    fields: Vec<(u32, u32, bool)>,
+    /// Initial values of the static fields, ordered by field index, with indices resolved
+    static_values: Vec<InternedValue>,
+    /// Method index, access flags and code of every method
+    methods: Vec<(u32, u32, bool, Option<InternedCode>)>,
+}
+
+/// A `ValueDef` whose string or field is replaced by its index
+enum InternedValue {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(u32),
+    Boolean(bool),
+    Null,
+    Enum(u32),
+}
+
+struct InternedCode {
+    code: CodeDef,
+    /// Type index of every typed handler of every try block, `NO_INDEX` never occurs here
+    handler_types: Vec<Vec<u32>>,
+}
+
+
+/// Builds a dex file from class definitions and interned string/type/proto/method ids
+#[derive(Default)]
+pub struct DexBuilder {
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    types: Vec<u32>,
+    type_ids: HashMap<String, u32>,
+    /// Shorty string index, return type index and parameter type indices
+    protos: Vec<(u32, u32, Vec<u32>)>,
+    proto_ids: HashMap<(String, Vec<String>), u32>,
+    /// Class type index, type index and name string index
+    fields: Vec<(u32, u32, u32)>,
+    field_ids: HashMap<(String, String, String), u32>,
+    /// Class type index, proto index and name string index
+    methods: Vec<(u32, u32, u32)>,
+    method_ids: HashMap<(String, String, String, Vec<String>), u32>,
+    classes: Vec<InternedClass>,
+}
+
+impl DexBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of `value` in the string ids, adding it if needed
+    pub fn string(&mut self, value: &str) -> u32 {
+        if let Some(&idx) = self.string_ids.get(value) {
+            return idx;
+        }
+        self.strings.push(value.to_string());
+        let idx = self.strings.len() as u32 - 1;
+        self.string_ids.insert(value.to_string(), idx);
+        idx
+    }
+
+    /// Index of the type `descriptor` in the type ids, adding it if needed
+    pub fn type_idx(&mut self, descriptor: &str) -> u32 {
+        if let Some(&idx) = self.type_ids.get(descriptor) {
+            return idx;
+        }
+        let string_idx = self.string(descriptor);
+        self.types.push(string_idx);
+        let idx = self.types.len() as u32 - 1;
+        self.type_ids.insert(descriptor.to_string(), idx);
+        idx
+    }
+
+    /// Index of the prototype in the proto ids, adding it if needed
+    pub fn proto(&mut self, return_type: &str, params: &[&str]) -> u32 {
+        let key = (return_type.to_string(), params.iter().map(|param| param.to_string()).collect::<Vec<_>>());
+        if let Some(&idx) = self.proto_ids.get(&key) {
+            return idx;
+        }
+        let shorty = std::iter::once(return_type).chain(params.iter().copied())
+            .map(|descriptor| match descriptor.as_bytes()[0] {
+                b'[' => 'L',
+                c => c as char,
+            })
+            .collect::<String>();
+        let shorty_idx = self.string(&shorty);
+        let return_type_idx = self.type_idx(return_type);
+        let param_idxs = params.iter().map(|param| self.type_idx(param)).collect();
+        self.protos.push((shorty_idx, return_type_idx, param_idxs));
+        let idx = self.protos.len() as u32 - 1;
+        self.proto_ids.insert(key, idx);
+        idx
+    }
+
+    /// Index of the field reference in the field ids, adding it if needed.
+    /// The class does not have to be defined in this dex
+    pub fn field(&mut self, class: &str, name: &str, field_type: &str) -> u32 {
+        let key = (class.to_string(), name.to_string(), field_type.to_string());
+        if let Some(&idx) = self.field_ids.get(&key) {
+            return idx;
+        }
+        let class_idx = self.type_idx(class);
+        let type_idx = self.type_idx(field_type);
+        let name_idx = self.string(name);
+        self.fields.push((class_idx, type_idx, name_idx));
+        let idx = self.fields.len() as u32 - 1;
+        self.field_ids.insert(key, idx);
+        idx
+    }
+
+    /// Index of the method reference in the method ids, adding it if needed.
+    /// The class does not have to be defined in this dex
+    pub fn method(&mut self, class: &str, name: &str, return_type: &str, params: &[&str]) -> u32 {
+        let key = (
+            class.to_string(),
+            name.to_string(),
+            return_type.to_string(),
+            params.iter().map(|param| param.to_string()).collect::<Vec<_>>(),
+        );
+        if let Some(&idx) = self.method_ids.get(&key) {
+            return idx;
+        }
+        let class_idx = self.type_idx(class);
+        let proto_idx = self.proto(return_type, params);
+        let name_idx = self.string(name);
+        self.methods.push((class_idx, proto_idx, name_idx));
+        let idx = self.methods.len() as u32 - 1;
+        self.method_ids.insert(key, idx);
+        idx
+    }
+
+    /// Defines a class, interning every id it refers to
+    pub fn class(&mut self, class: ClassDef) -> &mut Self {
+        let type_idx = self.type_idx(&class.descriptor);
+        let superclass_idx = match &class.superclass {
+            Some(superclass) => self.type_idx(superclass),
+            None => NO_INDEX,
+        };
+        let source_file_idx = match &class.source_file {
+            Some(source_file) => self.string(source_file),
+            None => NO_INDEX,
+        };
+        let mut fields = vec![];
+        let mut static_values = vec![];
+        let has_values = class.fields.iter().any(|field| field.value.is_some());
+        for field in class.fields {
+            let field_idx = self.field(&class.descriptor, &field.name, &field.field_type);
+            let is_static = field.access_flags & ACC_STATIC != 0;
+            fields.push((field_idx, field.access_flags, is_static));
+            if is_static && has_values {
+                // Static fields without a value get the default of their type, as the array covers every field before the last value
+                let value = field.value.unwrap_or_else(|| default_value(&field.field_type));
+                static_values.push((field_idx, self.intern_value(value)));
+            }
+        }
+        static_values.sort_by_key(|(field_idx, _)| *field_idx);
+        let static_values = static_values.into_iter().map(|(_, value)| value).collect();
+        let mut methods = vec![];
+        for method in class.methods {
+            let params = method.params.iter().map(String::as_str).collect::<Vec<_>>();
+            let method_idx = self.method(&class.descriptor, &method.name, &method.return_type, &params);
+            let is_direct = method.is_direct();
+            let code = method.code.map(|code| {
+                let handler_types = code.tries.iter()
+                    .map(|try_block| try_block.handlers.iter().map(|(exception_type, _)| self.type_idx(exception_type)).collect())
+                    .collect();
+                InternedCode { code, handler_types }
+            });
+            methods.push((method_idx, method.access_flags, is_direct, code));
+        }
+        self.classes.push(InternedClass { type_idx, access_flags: class.access_flags, superclass_idx, source_file_idx, fields, static_values, methods });
+        self
+    }
+
+    fn intern_value(&mut self, value: ValueDef) -> InternedValue {
+        match value {
+            ValueDef::Int(value) => InternedValue::Int(value),
+            ValueDef::Long(value) => InternedValue::Long(value),
+            ValueDef::Float(value) => InternedValue::Float(value),
+            ValueDef::Double(value) => InternedValue::Double(value),
+            ValueDef::String(value) => InternedValue::String(self.string(&value)),
+            ValueDef::Boolean(value) => InternedValue::Boolean(value),
+            ValueDef::Null => InternedValue::Null,
+            ValueDef::Enum(class, name, field_type) => InternedValue::Enum(self.field(&class, &name, &field_type)),
+        }
+    }
+
+    /// Lays out the dex file
+    pub fn build(&self) -> Vec<u8> {
+        let string_ids_off = HEADER_SIZE;
+        let type_ids_off = string_ids_off + 4 * self.strings.len() as u32;
+        let proto_ids_off = type_ids_off + 4 * self.types.len() as u32;
+        let field_ids_off = proto_ids_off + 12 * self.protos.len() as u32;
+        let method_ids_off = field_ids_off + 8 * self.fields.len() as u32;
+        let class_defs_off = method_ids_off + 8 * self.methods.len() as u32;
+        let data_off = class_defs_off + 32 * self.classes.len() as u32;
+
+        let mut data = Section::new(data_off);
+        let mut map = vec![(0x0000u16, 1u32, 0u32)];
+        let mut push_map = |item_type: u16, size: usize, offset: u32| if size > 0 {
+            map.push((item_type, size as u32, offset));
+        };
+        push_map(0x0001, self.strings.len(), string_ids_off);
+        push_map(0x0002, self.types.len(), type_ids_off);
+        push_map(0x0003, self.protos.len(), proto_ids_off);
+        push_map(0x0004, self.fields.len(), field_ids_off);
+        push_map(0x0005, self.methods.len(), method_ids_off);
+        push_map(0x0006, self.classes.len(), class_defs_off);
+
+        let string_data_off = data.offset();
+        let string_offs = self.strings.iter().map(|string| {
+            let offset = data.offset();
+            write_uleb128(&mut data.bytes, string.encode_utf16().count() as u32);
+            data.bytes.extend(encode_mutf8(string));
+            data.bytes.push(0);
+            offset
+        }).collect::<Vec<_>>();
+        push_map(0x2002, self.strings.len(), string_data_off);
+
+        data.align(4);
+        let type_lists_off = data.offset();
+        let mut type_lists = 0;
+        let params_offs = self.protos.iter().map(|(_, _, params)| {
+            if params.is_empty() {
+                return 0;
+            }
+            data.align(4);
+            let offset = data.offset();
+            data.bytes.extend((params.len() as u32).to_le_bytes());
+            for param in params {
+                data.bytes.extend((*param as u16).to_le_bytes());
+            }
+            type_lists += 1;
+            offset
+        }).collect::<Vec<_>>();
+        push_map(0x1001, type_lists, type_lists_off);
+
+        data.align(4);
+        let debug_info_off = data.offset();
+        let mut debug_infos = 0;
+        let debug_info_offs = self.classes.iter().map(|class| {
+            class.methods.iter().map(|(_, _, _, code)| match code.as_ref().and_then(|code| code.code.debug_line) {
+                Some(line_start) => {
+                    debug_infos += 1;
+                    let offset = data.offset();
+                    write_uleb128(&mut data.bytes, line_start);
+                    write_uleb128(&mut data.bytes, 0);
+                    // DBG_END_SEQUENCE
+                    data.bytes.push(0);
+                    offset
+                },
+                None => 0,
+            }).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        push_map(0x2003, debug_infos, debug_info_off);
+
+        data.align(4);
+        let code_items_off = data.offset();
+        let mut code_items = 0;
+        let code_offs = self.classes.iter().zip(debug_info_offs).map(|(class, debug_info_offs)| {
+            class.methods.iter().zip(debug_info_offs).map(|((_, _, _, code), debug_info_off)| match code {
+                Some(code) => {
+                    code_items += 1;
+                    write_code_item(&mut data, code, debug_info_off)
+                },
+                None => 0,
+            }).collect::<Vec<_>>()
+        }).collect::<Vec<_>>();
+        push_map(0x2001, code_items, code_items_off);
+
+        let class_data_off = data.offset();
+        let class_data_offs = self.classes.iter().zip(code_offs.iter()).map(|(class, code_offs)| {
+            let offset = data.offset();
+            let mut direct = vec![];
+            let mut virtual_ = vec![];
+            for ((method_idx, access_flags, is_direct, _), code_off) in class.methods.iter().zip(code_offs.iter()) {
+                let entry = (*method_idx, *access_flags, *code_off);
+                if *is_direct { direct.push(entry) } else { virtual_.push(entry) }
+            }
+            direct.sort();
+            virtual_.sort();
+            let mut static_fields = vec![];
+            let mut instance_fields = vec![];
+            for &(field_idx, access_flags, is_static) in class.fields.iter() {
+                if is_static { static_fields.push((field_idx, access_flags)) } else { instance_fields.push((field_idx, access_flags)) }
+            }
+            static_fields.sort();
+            instance_fields.sort();
+            write_uleb128(&mut data.bytes, static_fields.len() as u32);
+            write_uleb128(&mut data.bytes, instance_fields.len() as u32);
+            write_uleb128(&mut data.bytes, direct.len() as u32);
+            write_uleb128(&mut data.bytes, virtual_.len() as u32);
+            for fields in [static_fields, instance_fields] {
+                let mut previous = 0;
+                for (field_idx, access_flags) in fields {
+                    write_uleb128(&mut data.bytes, field_idx - previous);
+                    write_uleb128(&mut data.bytes, access_flags);
+                    previous = field_idx;
+                }
+            }
+            for methods in [direct, virtual_] {
+                let mut previous = 0;
+                for (method_idx, access_flags, code_off) in methods {
+                    write_uleb128(&mut data.bytes, method_idx - previous);
+                    write_uleb128(&mut data.bytes, access_flags);
+                    write_uleb128(&mut data.bytes, code_off);
+                    previous = method_idx;
+                }
+            }
+            offset
+        }).collect::<Vec<_>>();
+        push_map(0x2000, self.classes.len(), class_data_off);
+
+        let encoded_arrays_off = data.offset();
+        let mut encoded_arrays = 0;
+        let static_values_offs = self.classes.iter().map(|class| {
+            if class.static_values.is_empty() {
+                return 0;
+            }
+            encoded_arrays += 1;
+            let offset = data.offset();
+            write_uleb128(&mut data.bytes, class.static_values.len() as u32);
+            for value in class.static_values.iter() {
+                write_encoded_value(&mut data.bytes, value);
+            }
+            offset
+        }).collect::<Vec<_>>();
+        push_map(0x2005, encoded_arrays, encoded_arrays_off);
+
+        data.align(4);
+        let map_off = data.offset();
+        map.push((0x1000, 1, map_off));
+        data.bytes.extend((map.len() as u32).to_le_bytes());
+        for (item_type, size, offset) in map.iter() {
+            data.bytes.extend(item_type.to_le_bytes());
+            data.bytes.extend(0u16.to_le_bytes());
+            data.bytes.extend(size.to_le_bytes());
+            data.bytes.extend(offset.to_le_bytes());
+        }
+
+        let file_size = data.offset();
+        let mut out = Vec::with_capacity(file_size as usize);
+        out.extend(b"dex\n035\0");
+        out.extend([0; 4 + 20]);
+        for value in [
+            file_size, HEADER_SIZE, 0x1234_5678, 0, 0, map_off,
+            self.strings.len() as u32, string_ids_off,
+            self.types.len() as u32, type_ids_off,
+            self.protos.len() as u32, proto_ids_off,
+            self.fields.len() as u32, if self.fields.is_empty() { 0 } else { field_ids_off },
+            self.methods.len() as u32, method_ids_off,
+            self.classes.len() as u32, class_defs_off,
+            file_size - data_off, data_off,
+        ] {
+            out.extend(value.to_le_bytes());
+        }
+        for offset in string_offs {
+            out.extend(offset.to_le_bytes());
+        }
+        for string_idx in self.types.iter() {
+            out.extend(string_idx.to_le_bytes());
+        }
+        for ((shorty_idx, return_type_idx, _), params_off) in self.protos.iter().zip(params_offs) {
+            for value in [*shorty_idx, *return_type_idx, params_off] {
+                out.extend(value.to_le_bytes());
+            }
+        }
+        for (class_idx, type_idx, name_idx) in self.fields.iter() {
+            out.extend((*class_idx as u16).to_le_bytes());
+            out.extend((*type_idx as u16).to_le_bytes());
+            out.extend(name_idx.to_le_bytes());
+        }
+        for (class_idx, proto_idx, name_idx) in self.methods.iter() {
+            out.extend((*class_idx as u16).to_le_bytes());
+            out.extend((*proto_idx as u16).to_le_bytes());
+            out.extend(name_idx.to_le_bytes());
+        }
+        for ((class, class_data_off), static_values_off) in self.classes.iter().zip(class_data_offs).zip(static_values_offs) {
+            for value in [
+                class.type_idx, class.access_flags, class.superclass_idx, 0,
+                class.source_file_idx, 0, class_data_off, static_values_off,
+            ] {
+                out.extend(value.to_le_bytes());
+            }
+        }
+        debug_assert_eq!(out.len() as u32, data_off);
+        out.extend(data.bytes);
+
+        let checksum = adler32(&out[12..]);
+        out[8..12].copy_from_slice(&checksum.to_le_bytes());
+        out
+    }
+}
+
+
+/// Bytes of the data section together with the file offset they start at
+struct Section {
+    base: u32,
+    bytes: Vec<u8>,
+}
+
+impl Section {
+    fn new(base: u32) -> Self {
+        Self { base, bytes: vec![] }
+    }
+
+    fn offset(&self) -> u32 {
+        self.base + self.bytes.len() as u32
+    }
+
+    fn align(&mut self, alignment: u32) {
+        while self.offset() % alignment != 0 {
+            self.bytes.push(0);
+        }
+    }
+}
+
+
+fn write_code_item(data: &mut Section, interned: &InternedCode, debug_info_off: u32) -> u32 {
+    let code = &interned.code;
+    data.align(4);
+    let offset = data.offset();
+    for value in [code.registers_size, code.ins_size, code.outs_size, code.tries.len() as u16] {
+        data.bytes.extend(value.to_le_bytes());
+    }
+    data.bytes.extend(debug_info_off.to_le_bytes());
+    data.bytes.extend((code.insns.len() as u32).to_le_bytes());
+    for word in code.insns.iter() {
+        data.bytes.extend(word.to_le_bytes());
+    }
+    if code.tries.is_empty() {
+        return offset;
+    }
+    if code.insns.len() % 2 == 1 {
+        data.bytes.extend(0u16.to_le_bytes());
+    }
+
+    let mut handlers = vec![];
+    write_uleb128(&mut handlers, code.tries.len() as u32);
+    let handler_offs = code.tries.iter().zip(interned.handler_types.iter()).map(|(try_block, types)| {
+        let handler_off = handlers.len() as u16;
+        let size = try_block.handlers.len() as i32;
+        write_sleb128(&mut handlers, if try_block.catch_all.is_some() { -size } else { size });
+        for (type_idx, (_, addr)) in types.iter().zip(try_block.handlers.iter()) {
+            write_uleb128(&mut handlers, *type_idx);
+            write_uleb128(&mut handlers, *addr);
+        }
+        if let Some(addr) = try_block.catch_all {
+            write_uleb128(&mut handlers, addr);
+        }
+        handler_off
+    }).collect::<Vec<_>>();
+    for (try_block, handler_off) in code.tries.iter().zip(handler_offs) {
+        data.bytes.extend(try_block.start_addr.to_le_bytes());
+        data.bytes.extend(try_block.insn_count.to_le_bytes());
+        data.bytes.extend(handler_off.to_le_bytes());
+    }
+    data.bytes.extend(handlers);
+    offset
+}
+
+
+/// Default value of a static field of type `descriptor` without an initial value
+fn default_value(descriptor: &str) -> ValueDef {
+    match descriptor.as_bytes()[0] {
+        b'Z' => ValueDef::Boolean(false),
+        b'J' => ValueDef::Long(0),
+        b'F' => ValueDef::Float(0.0),
+        b'D' => ValueDef::Double(0.0),
+        b'L' | b'[' => ValueDef::Null,
+        _ => ValueDef::Int(0),
+    }
+}
+
+
+/// Writes an encoded_value, numbers and indices at their full width
+fn write_encoded_value(out: &mut Vec<u8>, value: &InternedValue) {
+    let (value_type, bytes) = match value {
+        InternedValue::Int(value) => (0x04, value.to_le_bytes().to_vec()),
+        InternedValue::Long(value) => (0x06, value.to_le_bytes().to_vec()),
+        InternedValue::Float(value) => (0x10, value.to_bits().to_le_bytes().to_vec()),
+        InternedValue::Double(value) => (0x11, value.to_bits().to_le_bytes().to_vec()),
+        InternedValue::String(idx) => (0x17, idx.to_le_bytes().to_vec()),
+        InternedValue::Enum(idx) => (0x1b, idx.to_le_bytes().to_vec()),
+        InternedValue::Null => {
+            out.push(0x1e);
+            return;
+        },
+        InternedValue::Boolean(value) => {
+            out.push((*value as u8) << 5 | 0x1f);
+            return;
+        },
+    };
+    out.push(((bytes.len() - 1) as u8) << 5 | value_type);
+    out.extend(bytes);
+}
+
+
+fn write_uleb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+
+fn write_sleb128(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+
+/// Encodes a string as MUTF-8: nulls take two bytes and supplementary characters are encoded as surrogate pairs
+fn encode_mutf8(value: &str) -> Vec<u8> {
+    let mut out = vec![];
+    for unit in value.encode_utf16() {
+        match unit {
+            0x01..=0x7f => out.push(unit as u8),
+            0x00 | 0x80..=0x7ff => {
+                out.push(0xc0 | (unit >> 6) as u8);
+                out.push(0x80 | (unit & 0x3f) as u8);
+            },
+            _ => {
+                out.push(0xe0 | (unit >> 12) as u8);
+                out.push(0x80 | ((unit >> 6) & 0x3f) as u8);
+                out.push(0x80 | (unit & 0x3f) as u8);
+            }
+        }
+    }
+    out
+}
+
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+
+/// Encodes an instruction in the format of its opcode. `reference` fills the index operand, `branch` is the branch offset
+/// relative to the instruction and `registers` fills the register operands, truncated to the width of each operand
+pub fn encode_instruction(opcode: Opcode, reference: u32, branch: i32, registers: u16) -> Vec<u16> {
+    let op = opcode as u16;
+    let aa = op | (registers << 8);
+    // The argument count in the upper nibble of 35c and 45cc instructions is at most 5
+    let args = op | (((registers >> 4) % 6) << 12) | ((registers & 0xF) << 8);
+    let (low, high) = (branch as u32 as u16, (branch as u32 >> 16) as u16);
+    match opcode as u8 {
+        // A nop with a non-zero high byte would be a payload, and the high byte of return-void is unused
+        0x00 | 0x0E => vec![op],
+        0x28 => vec![op | ((branch as i8 as u8 as u16) << 8)],
+        0x01 | 0x04 | 0x07 | 0x0A..=0x0D | 0x0F..=0x12 | 0x1D | 0x1E | 0x21 | 0x27 | 0x7B..=0x8F | 0xB0..=0xCF => vec![aa],
+        0x29 => vec![op, low],
+        0x32..=0x3D => vec![aa, low],
+        0x26 | 0x2A..=0x2C => vec![aa, low, high],
+        0x1B => vec![aa, reference as u16, (reference >> 16) as u16],
+        0x02 | 0x05 | 0x08 | 0x13 | 0x15 | 0x16 | 0x19 | 0x1A | 0x1C | 0x1F | 0x20 | 0x22 | 0x23 | 0x2D..=0x31 | 0x44..=0x6D | 0x90..=0xAF | 0xD0..=0xE2 | 0xFE | 0xFF => vec![aa, reference as u16],
+        0xFA => vec![args, reference as u16, registers, registers],
+        0xFB => vec![aa, reference as u16, registers, registers],
+        0x24 | 0x6E..=0x72 | 0xFC => vec![args, reference as u16, registers],
+        0x18 => vec![aa, registers, registers, registers, registers],
+        _ => vec![aa, reference as u16, registers],
+    }
+}
+
+
+/// Method bodies taken from F-Droid and Bouncy Castle, used by the decoder tests and the benchmarks
+pub const SAMPLE_METHODS: [(&str, &[u16]); 3] = [
+    // Lorg/fdroid/fdroid/views/main/MainActivity;onStart
+    ("onStart", &[4207, 743, 2, 96, 57, 275, 33, 4148, 15, 26, 21033, 8305, 855, 2, 266, 312, 7, 8532, 22998, 8302, 714, 1, 14]),
+    // Lorg/bouncycastle/dvcs/DVCSRequestInfo;getRequestTime
+    ("getRequestTime", &[
+        16468, 2726, 4206, 3408, 0, 12, 57, 4, 18, 17,
+        4206, 3424, 0, 268, 312, 11, 4206, 3424, 0, 12,
+        4206, 3167, 0, 12, 17, 290, 5100, 4206, 3425, 0,
+        12, 8304, 23643, 1, 4206, 23653, 1, 12, 4206,
+        23682, 0, 12, 17, 13, 290, 2002, 546, 643, 4208,
+        1799, 2, 794, 37138, 8302, 1810, 50, 4206, 1847,
+        0, 780, 8302, 1810, 50, 4206, 1818, 2, 524, 12400,
+        7759, 33, 295
+    ]),
+    // Lorg/fdroid/download/Mirror;hashCode
+    ("hashCode", &[
+        8276, 11170, 4206, 1757, 0, 10, 218, 7936, 8532, 11172,
+        313, 4, 274, 1320, 4206, 1757, 1, 266, 4272, 218, 7936,
+        8533, 11171, 312, 3, 4370, 4272, 15
+    ]),
+];
+
+
+/// A dex with `classes` classes, each holding the `SAMPLE_METHODS`
+pub fn sample_dex(classes: usize) -> Vec<u8> {
+    let mut builder = DexBuilder::new();
+    for i in 0..classes {
+        let mut class = ClassDef::new(&format!("Lorg/example/Sample{};", i));
+        for (name, insns) in SAMPLE_METHODS {
+            class = class.method(MethodDef::new(name, "V", &[]).code(CodeDef::new(8, 1, 4, insns)));
+        }
+        builder.class(class);
+    }
+    builder.build()
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use super::*;
+
+    #[test]
+    fn test_sample_dex_round_trip() {
+        let dex = DexReader::from_vec(sample_dex(2)).unwrap();
+        let classes = dex.classes().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(classes.len(), 2);
+        assert_eq!(classes[1].jtype().type_descriptor().to_string(), "Lorg/example/Sample1;");
+        let methods = classes[0].methods().collect::<Vec<_>>();
+        assert_eq!(methods.len(), 3);
+        for (method, (name, insns)) in methods.iter().zip(SAMPLE_METHODS) {
+            assert_eq!(method.name().to_string(), name);
+            assert_eq!(method.code().unwrap().insns(), insns);
+        }
+    }
+
+    #[test]
+    fn test_write_encoded_value() {
+        let mut out = vec![];
+        for value in [InternedValue::Int(-2), InternedValue::Boolean(true), InternedValue::Null, InternedValue::String(5)] {
+            write_encoded_value(&mut out, &value);
+        }
+        assert_eq!(out, [0x64, 0xfe, 0xff, 0xff, 0xff, 0x3f, 0x1e, 0x77, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_encode_mutf8() {
+        assert_eq!(encode_mutf8("a\0b"), vec![b'a', 0xc0, 0x80, b'b']);
+        // U+1F600 as the surrogate pair D83D DE00
+        assert_eq!(encode_mutf8("\u{1F600}"), vec![0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80]);
+    }
+}
@@ -0,0 +1,321 @@
+use std::ops::ControlFlow;
+
+use dex::Dex;
+use serde::Serialize;
+
+use crate::{
+    dex_parsing::{is_selected, walk_dex, ClassInfo, DecodedInstruction, Instruction, InstructionIndex, InstructionParsingError, InstructionVisitor, MethodInfo, OpcodeCategory, Registers},
+    options::{AnalysisOptions, DecodeMode, Strictness},
+    warning::{Warning, WarningKind},
+};
+
+
+/// Register operand outside the frame of its method, which ART's verifier would reject
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RegisterError {
+    /// Register at or past the register count
+    Register { register: u16 },
+    /// Wide pair whose low register is the last one of the frame
+    WidePair { register: u16 },
+    /// Range of registers running past the register count
+    Range { first: u16, count: u8 },
+}
+
+
+/// Register error of an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VerifyError {
+    /// Code unit offset of the instruction
+    pub offset: usize,
+    pub opcode: &'static str,
+    #[serde(flatten)]
+    pub error: RegisterError,
+}
+
+
+/// Register errors of a method
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MethodVerifyErrors {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub name: String,
+    pub registers_size: u16,
+    pub errors: Vec<VerifyError>,
+}
+
+
+/// Checks the register operands of every decoded instruction of the methods of the selected classes of `dex` against
+/// the register count of its method. Only the methods with errors are reported, and decoding goes on past them.
+/// Branches into the middle of an instruction are added to `warnings` as `OverlappingCode`
+pub fn verify_dex<T: AsRef<[u8]>>(dex_index: usize, dex: &Dex<T>, options: &AnalysisOptions, warnings: &mut Vec<Warning>) -> Vec<MethodVerifyErrors> {
+    let mut visitor = VerifyVisitor { options, registers_size: 0, instructions: vec![], errors: vec![], methods: vec![], warnings };
+    walk_dex(dex, &mut visitor);
+    visitor.methods.into_iter()
+        .map(|(class, name, registers_size, errors)| MethodVerifyErrors { dex: dex_index, class, name, registers_size, errors })
+        .collect()
+}
+
+
+/// Code unit of a method that doesn't decode as an instruction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DecodeFailure {
+    /// Index of the dex in the APK
+    pub dex: usize,
+    /// Descriptor of the declaring class, e.g. `Lcom/example/Main;`
+    pub class: String,
+    pub method: String,
+    /// Code unit offset in the method
+    pub offset: usize,
+    pub opcode_byte: u8,
+}
+
+
+/// Decodes every method of `dex`, whatever the class filter, and reports every code unit that can't be decoded, going
+/// on past it as lenient decoding does. Only the decode mode of the options applies. Meant to find the instructions
+/// the decoder still can't handle, without building any sequence
+pub fn decode_failures<T: AsRef<[u8]>>(dex_index: usize, dex: &Dex<T>, options: &AnalysisOptions) -> Vec<DecodeFailure> {
+    let mut visitor = DecodeFailureVisitor { dex_index, decode_mode: options.decode_mode, class: String::new(), method: String::new(), failures: vec![] };
+    walk_dex(dex, &mut visitor);
+    visitor.failures
+}
+
+
+/// Branch or switch target inside a decoded instruction rather than at its start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverlappingTarget {
+    /// Offset of the branch or switch
+    pub source: usize,
+    pub target: usize,
+    /// Offset and opcode of the instruction the target falls inside
+    pub inside: (usize, &'static str),
+    /// Opcode decoded from the target, which the linear sweep never sees. `None` when nothing decodes there
+    pub decoded: Option<&'static str>,
+}
+
+
+/// Targets of the branches and switches of a method that fall inside one of its `instructions`, decoded by a linear sweep
+/// of `raw_bytecode`
+pub fn overlapping_targets(raw_bytecode: &[u16], instructions: &[Instruction]) -> Vec<OverlappingTarget> {
+    let index = InstructionIndex::new(instructions);
+    let mut overlapping = vec![];
+    for inst in instructions {
+        let mut targets: Vec<usize> = inst.absolute_target(raw_bytecode.len()).into_iter().collect();
+        if inst.opcode().category() == OpcodeCategory::Switch {
+            targets.extend(inst.switch_targets(raw_bytecode).into_iter().flatten());
+        }
+        for target in targets {
+            let Some(inside) = index.containing(target).filter(|inside| *inside.offset() != target) else { continue };
+            let decoded = Instruction::try_opcode_from_raw_bytecode(raw_bytecode, target).ok().flatten().map(|(opcode, _)| opcode.mnemonic());
+            overlapping.push(OverlappingTarget { source: *inst.offset(), target, inside: (*inside.offset(), inside.opcode().mnemonic()), decoded });
+        }
+    }
+    overlapping
+}
+
+
+/// Register errors of an instruction of a method of `registers_size` registers
+pub fn register_errors(registers: &Registers, widths: impl IntoIterator<Item = u16>, registers_size: u16) -> Vec<RegisterError> {
+    let size = registers_size as u32;
+    match *registers {
+        Registers::Range { first, count } => (first as u32 + count as u32 > size)
+            .then_some(RegisterError::Range { first, count })
+            .into_iter()
+            .collect(),
+        Registers::List { .. } => registers.iter().zip(widths).filter_map(|(register, width)| {
+            if register as u32 >= size {
+                Some(RegisterError::Register { register })
+            } else if register as u32 + width as u32 > size {
+                Some(RegisterError::WidePair { register })
+            } else {
+                None
+            }
+        }).collect(),
+    }
+}
+
+
+struct VerifyVisitor<'a> {
+    options: &'a AnalysisOptions,
+    registers_size: u16,
+    /// Decoded instructions of the current method, for the branch targets
+    instructions: Vec<Instruction>,
+    errors: Vec<VerifyError>,
+    /// Class, name, register count and errors of every method with errors
+    methods: Vec<(String, String, u16, Vec<VerifyError>)>,
+    warnings: &'a mut Vec<Warning>,
+}
+
+impl InstructionVisitor for VerifyVisitor<'_> {
+    fn visit_class(&mut self, class: &ClassInfo) -> ControlFlow<()> {
+        if is_selected(class.class(), self.options) { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+    }
+
+    fn visit_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        match method.code() {
+            Some(code) => {
+                self.registers_size = code.registers_size();
+                ControlFlow::Continue(())
+            },
+            None => ControlFlow::Break(()),
+        }
+    }
+
+    fn visit_instruction(&mut self, inst: &DecodedInstruction) {
+        let inst = &inst.instruction;
+        let widths = inst.operands().into_iter().map(|(_, width)| width);
+        for error in register_errors(inst.registers(), widths, self.registers_size) {
+            self.errors.push(VerifyError { offset: *inst.offset(), opcode: inst.opcode().mnemonic(), error });
+        }
+        self.instructions.push(inst.clone());
+    }
+
+    fn leave_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        let class = method.class().jtype().type_descriptor();
+        if let Some(code) = method.code() {
+            for overlap in overlapping_targets(code.insns(), &self.instructions) {
+                let (inside, opcode) = overlap.inside;
+                let mut message = format!("Target {} of the branch at {} is inside the {} at {}", overlap.target, overlap.source, opcode, inside);
+                if let Some(decoded) = overlap.decoded {
+                    message += &format!(", where it decodes as {}", decoded);
+                }
+                self.warnings.push(Warning::new(WarningKind::OverlappingCode, message).class(class.as_str()).method(method.method().name().as_str()).offset(overlap.source));
+            }
+        }
+        self.instructions.clear();
+        if !self.errors.is_empty() {
+            self.methods.push((class.to_string(), method.method().name().to_string(), self.registers_size, std::mem::take(&mut self.errors)));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn strictness(&self) -> Strictness {
+        Strictness::Lenient
+    }
+}
+
+
+/// Collects the undecodable code units of every method for `decode_failures`
+struct DecodeFailureVisitor {
+    dex_index: usize,
+    decode_mode: DecodeMode,
+    /// Class and name of the method being walked
+    class: String,
+    method: String,
+    failures: Vec<DecodeFailure>,
+}
+
+impl InstructionVisitor for DecodeFailureVisitor {
+    fn visit_method(&mut self, method: &MethodInfo) -> ControlFlow<()> {
+        if method.code().is_none() {
+            return ControlFlow::Break(());
+        }
+        self.class = method.class().jtype().type_descriptor().to_string();
+        self.method = method.method().name().to_string();
+        ControlFlow::Continue(())
+    }
+
+    fn visit_instruction(&mut self, _inst: &DecodedInstruction) {}
+
+    fn visit_error(&mut self, err: &InstructionParsingError) {
+        self.failures.push(DecodeFailure { dex: self.dex_index, class: self.class.clone(), method: self.method.clone(), offset: err.offset(), opcode_byte: err.byte() });
+    }
+
+    fn strictness(&self) -> Strictness {
+        Strictness::Lenient
+    }
+
+    fn decode_mode(&self) -> DecodeMode {
+        self.decode_mode
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::{dex_parsing::decode_method_lenient, options::ClassFilter, testing::{ClassDef, CodeDef, DexBuilder, MethodDef}};
+    use super::*;
+
+    #[test]
+    fn test_invoke_past_register_count() {
+        let mut builder = DexBuilder::new();
+        let helper = builder.method("Lcom/example/Main;", "helper", "V", &["I"]) as u16;
+        // invoke-static/range {v200}, helper; return-void
+        let corrupted = [0x0177, helper, 200, 0x000E];
+        // const-wide/16 v3, 1; const/4 v4, 0; invoke-static/range {v2 .. v5}, helper; return-void
+        let wide = [0x0316, 0x0001, 0x0412, 0x0477, helper, 0x0002, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("corrupted", "V", &[]).code(CodeDef::new(5, 0, 1, &corrupted)))
+            .method(MethodDef::new("wide", "V", &[]).code(CodeDef::new(4, 0, 4, &wide)))
+            .method(MethodDef::new("helper", "V", &["I"]).code(CodeDef::new(1, 1, 0, &[0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let errors = verify_dex(0, &dex, &AnalysisOptions::default(), &mut vec![]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].name.as_str(), errors[0].registers_size), ("corrupted", 5));
+        assert_eq!(errors[0].errors, [VerifyError { offset: 0, opcode: "invoke-static/range", error: RegisterError::Range { first: 200, count: 1 } }]);
+        assert_eq!(errors[1].name, "wide");
+        assert_eq!(errors[1].errors, [
+            VerifyError { offset: 0, opcode: "const-wide/16", error: RegisterError::WidePair { register: 3 } },
+            VerifyError { offset: 2, opcode: "const/4", error: RegisterError::Register { register: 4 } },
+            VerifyError { offset: 3, opcode: "invoke-static/range", error: RegisterError::Range { first: 2, count: 4 } },
+        ]);
+    }
+
+    #[test]
+    fn test_branch_into_instruction() {
+        // const-wide/16 v0, 0x0012; goto -1; return-void
+        // The goto lands on the literal of the const-wide, which decodes as const/4 v0, 0
+        let raw_bytecode = [0x0016, 0x0012, 0xFF28, 0x000E];
+        let instructions = decode_method_lenient(&raw_bytecode).instructions;
+        assert_eq!(overlapping_targets(&raw_bytecode, &instructions), [
+            OverlappingTarget { source: 2, target: 1, inside: (0, "const-wide/16"), decoded: Some("const/4") },
+        ]);
+        // Branches to instruction starts don't overlap: const/4 v0, 0; if-eqz v0, +3; nop; return-void
+        let raw_bytecode = [0x0012, 0x0038, 0x0003, 0x0000, 0x000E];
+        assert!(overlapping_targets(&raw_bytecode, &decode_method_lenient(&raw_bytecode).instructions).is_empty());
+
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("overlapping", "V", &[]).code(CodeDef::new(2, 0, 0, &[0x0016, 0x0012, 0xFF28, 0x000E]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        let mut warnings = vec![];
+        assert!(verify_dex(0, &dex, &AnalysisOptions::default(), &mut warnings).is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, WarningKind::OverlappingCode);
+        assert_eq!((warnings[0].method.as_deref(), warnings[0].offset), (Some("overlapping"), Some(2)));
+    }
+
+    #[test]
+    fn test_decode_failures() {
+        let mut builder = DexBuilder::new();
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000E])))
+            // const/4 v0, 0; unused opcode 0x3e; return-void
+            .method(MethodDef::new("broken", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x003E, 0x000E]))));
+        builder.class(ClassDef::new("Lcom/example/Skipped;").method(MethodDef::new("also_broken", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0073]))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+        // Classes left out by the filter are decoded too
+        let options = AnalysisOptions::default().class_filter(ClassFilter::default().exclude("Lcom/example/Skipped;"));
+        assert_eq!(decode_failures(1, &dex, &options), [
+            DecodeFailure { dex: 1, class: "Lcom/example/Main;".to_string(), method: "broken".to_string(), offset: 1, opcode_byte: 0x3E },
+            DecodeFailure { dex: 1, class: "Lcom/example/Skipped;".to_string(), method: "also_broken".to_string(), offset: 0, opcode_byte: 0x73 },
+        ]);
+    }
+
+    #[test]
+    fn test_register_errors() {
+        let list = |named: &[u16]| {
+            let mut registers = [0; 5];
+            registers[..named.len()].copy_from_slice(named);
+            Registers::List { registers, len: named.len() as u8 }
+        };
+        assert!(register_errors(&list(&[0, 4]), [2, 1], 5).is_empty());
+        assert_eq!(register_errors(&list(&[4, 5]), [2, 1], 5), [RegisterError::WidePair { register: 4 }, RegisterError::Register { register: 5 }]);
+        assert!(register_errors(&Registers::Range { first: 1, count: 4 }, [], 5).is_empty());
+        assert_eq!(register_errors(&Registers::Range { first: 2, count: 4 }, [], 5), [RegisterError::Range { first: 2, count: 4 }]);
+    }
+}
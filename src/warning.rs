@@ -0,0 +1,130 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+
+/// What went wrong while analyzing an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// The input or one of its entries could not be read
+    Io,
+    /// The input is not a valid zip archive
+    InvalidArchive,
+    /// A dex entry of the APK could not be parsed and was skipped
+    InvalidDex,
+    /// A class definition could not be parsed and was skipped
+    InvalidClass,
+    /// None of the class definitions of a dex could be parsed, its empty sequence means broken rather than empty
+    NoValidClasses,
+    /// A method contains an undecodable instruction, in strict mode the method is dropped
+    InvalidInstruction,
+    /// The branches of a method could not be linked into basic blocks
+    InvalidControlFlow,
+    /// The manifest of the APK could not be decoded, its permissions are unknown
+    InvalidManifest,
+    /// A traversal of the control flow of a method went past the max depth of the options, the results it feeds leave the method out
+    CfgDepthExceeded,
+    /// A branch or switch of a method targets the middle of a decoded instruction, so its code decodes differently depending on the entry
+    OverlappingCode,
+    /// The decoding of a dex panicked, its methods are missing
+    DecodePanicked,
+}
+
+
+/// A problem met during the analysis of an input that didn't prevent the rest of it from being analyzed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// Descriptor of the class involved, e.g. `Lcom/example/Main;`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    /// Index of the class definition involved in its dex, for the classes that can't be parsed and have no descriptor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// Offset in code units of the offending instruction in the method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+
+impl Warning {
+    pub fn new(kind: WarningKind, message: impl ToString) -> Self {
+        Self { kind, class: None, class_index: None, method: None, offset: None, message: message.to_string() }
+    }
+
+    pub fn class(mut self, class: impl Into<String>) -> Self {
+        self.class = Some(class.into());
+        self
+    }
+
+    pub fn class_index(mut self, class_index: usize) -> Self {
+        self.class_index = Some(class_index);
+        self
+    }
+
+    pub fn method(mut self, method: impl Into<String>) -> Self {
+        self.method = Some(method.into());
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+}
+
+
+impl From<Error> for Warning {
+    /// Warning for an error that only affects part of the input, carrying over the location of an `InstructionDecode`
+    fn from(err: Error) -> Self {
+        let kind = match err {
+            Error::Io(_) => WarningKind::Io,
+            Error::Zip(_) => WarningKind::InvalidArchive,
+            Error::Dex(_) | Error::MethodNotFound { .. } | Error::NoCode { .. } => WarningKind::InvalidDex,
+            Error::DecodePanicked { .. } => WarningKind::DecodePanicked,
+            Error::Manifest(_) => WarningKind::InvalidManifest,
+            Error::InvalidClass { .. } => WarningKind::InvalidClass,
+            Error::InstructionDecode { .. } => WarningKind::InvalidInstruction,
+            Error::CfgConstruction { .. } => WarningKind::InvalidControlFlow,
+        };
+        let warning = Warning::new(kind, &err);
+        match err {
+            Error::InstructionDecode { class, method, offset, .. } => Warning { class, method, offset: Some(offset), ..warning },
+            Error::InvalidClass { index, .. } => warning.class_index(index),
+            _ => warning,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.class, &self.method) {
+            (Some(class), Some(method)) => write!(f, "{}->{}: ", class, method)?,
+            (Some(class), None) => write!(f, "{}: ", class)?,
+            _ => (),
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_warning_display() {
+        let warning = Warning::new(WarningKind::InvalidInstruction, "Invalid instruction at offset 5: 62")
+            .class("Lcom/example/Main;")
+            .method("run")
+            .offset(5);
+        assert_eq!(warning.to_string(), "Lcom/example/Main;->run: Invalid instruction at offset 5: 62");
+        assert_eq!(serde_json::to_value(&warning).unwrap()["kind"], "invalid_instruction");
+    }
+}
@@ -0,0 +1,14 @@
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::{analyze_dex, AnalysisOptions, Strictness};
+
+
+/// Analyzes a dex buffer and returns its report as a plain JS object, following the JSON output of the command line tool.
+/// Undecodable instructions are skipped as with `--lenient`
+#[wasm_bindgen(js_name = decodeDex)]
+pub fn decode_dex(bytes: &[u8]) -> Result<JsValue, JsError> {
+    let options = AnalysisOptions::default().strictness(Strictness::Lenient).call_graph_metrics(true).build();
+    let report = analyze_dex(bytes.to_vec(), &options)?;
+    Ok(report.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
@@ -0,0 +1,163 @@
+use std::{collections::{BTreeMap, HashMap}, io::{self, BufRead}};
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufReader, path::Path};
+
+use dex::Dex;
+use serde::Serialize;
+
+use crate::{dex_parsing::decode_method_lenient, reference::resolve_method};
+
+
+/// APIs used for reflection and dynamic code loading, as `(class, method, tag)`
+pub const DEFAULT_WATCHLIST: &[(&str, &str, &str)] = &[
+    ("Ljava/lang/Class;", "forName", "reflection"),
+    ("Ljava/lang/Class;", "getMethod", "reflection"),
+    ("Ljava/lang/Class;", "getDeclaredMethod", "reflection"),
+    ("Ljava/lang/Class;", "getField", "reflection"),
+    ("Ljava/lang/Class;", "getDeclaredField", "reflection"),
+    ("Ljava/lang/Class;", "newInstance", "reflection"),
+    ("Ljava/lang/reflect/Method;", "invoke", "reflection"),
+    ("Ljava/lang/reflect/Constructor;", "newInstance", "reflection"),
+    ("Ljava/lang/reflect/AccessibleObject;", "setAccessible", "reflection"),
+    ("Ljava/lang/ClassLoader;", "loadClass", "dynamic-loading"),
+    ("Ldalvik/system/DexClassLoader;", "<init>", "dynamic-loading"),
+    ("Ldalvik/system/PathClassLoader;", "<init>", "dynamic-loading"),
+    ("Ldalvik/system/InMemoryDexClassLoader;", "<init>", "dynamic-loading"),
+    ("Ldalvik/system/DexFile;", "loadDex", "dynamic-loading"),
+    ("Ldalvik/system/DexFile;", "loadClass", "dynamic-loading"),
+    ("Ljava/lang/System;", "load", "native-loading"),
+    ("Ljava/lang/System;", "loadLibrary", "native-loading"),
+    ("Ljava/lang/Runtime;", "exec", "process-execution"),
+];
+
+/// Tag of the entries loaded with `Watchlist::extend_from_file`
+pub const USER_TAG: &str = "user";
+
+
+/// Number of calls of a watched API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WatchlistHit {
+    pub class: String,
+    pub method: String,
+    pub tag: String,
+    pub count: usize,
+}
+
+
+/// Set of watched `(class, method)` APIs, the embedded defaults plus any user-supplied entries
+#[derive(Debug, Clone)]
+pub struct Watchlist {
+    /// Ordered, so that the `Debug` form of the options is the same from one run to the next
+    entries: BTreeMap<(String, String), String>,
+}
+
+
+impl Default for Watchlist {
+    fn default() -> Self {
+        let entries = DEFAULT_WATCHLIST.iter()
+            .map(|(class, method, tag)| ((class.to_string(), method.to_string()), tag.to_string()))
+            .collect();
+        Self { entries }
+    }
+}
+
+
+impl Watchlist {
+    /// Merges entries from a file with one `Lclass;->method` per line, blank lines and `#` comments are skipped
+    #[cfg(feature = "fs")]
+    pub fn extend_from_file(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.extend_from_reader(BufReader::new(File::open(path)?))
+    }
+
+    pub fn extend_from_reader(&mut self, reader: impl BufRead) -> io::Result<()> {
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once("->") {
+                Some((class, method)) if class.starts_with('L') && class.ends_with(';') && !method.is_empty() => {
+                    self.entries.insert((class.to_string(), method.to_string()), USER_TAG.to_string());
+                },
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid watchlist entry at line {}: {}", i + 1, line))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Tag of the API if it is watched
+    pub fn matches(&self, class: &str, method: &str) -> Option<&str> {
+        self.entries.get(&(class.to_string(), method.to_string())).map(String::as_str)
+    }
+
+    /// Counts the calls of watched APIs across the dexes of an APK.
+    /// Every method reference of a dex is resolved and matched once, however many times it is invoked
+    pub fn scan<T: AsRef<[u8]>>(&self, dexes: &[Dex<T>]) -> Vec<WatchlistHit> {
+        let mut hits: Vec<WatchlistHit> = vec![];
+        let mut hit_index = HashMap::new();
+        for dex in dexes {
+            let mut resolved: HashMap<u32, Option<usize>> = HashMap::new();
+            for class in dex.classes().flatten() {
+                for method in class.methods() {
+                    let Some(code) = method.code() else { continue };
+                    for inst in decode_method_lenient(code.insns()).instructions {
+                        let (Some(method_idx), true) = (*inst.reference(), inst.opcode().is_invoke()) else { continue };
+                        let hit = *resolved.entry(method_idx).or_insert_with(|| {
+                            let method_ref = resolve_method(dex, method_idx)?;
+                            let tag = self.matches(&method_ref.class, &method_ref.name)?;
+                            let key = (method_ref.class, method_ref.name);
+                            Some(*hit_index.entry(key.clone()).or_insert_with(|| {
+                                hits.push(WatchlistHit { class: key.0, method: key.1, tag: tag.to_string(), count: 0 });
+                                hits.len() - 1
+                            }))
+                        });
+                        if let Some(hit) = hit {
+                            hits[hit].count += 1;
+                        }
+                    }
+                }
+            }
+        }
+        hits
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use dex::DexReader;
+
+    use crate::testing::{DexBuilder, ClassDef, MethodDef, CodeDef, ACC_STATIC};
+    use super::*;
+
+    #[test]
+    fn test_user_entry_matched() {
+        let mut watchlist = Watchlist::default();
+        let entries = "# packer entry points\n\nLcom/packer/Stub;->decrypt\n";
+        watchlist.extend_from_reader(entries.as_bytes()).unwrap();
+        assert_eq!(watchlist.matches("Lcom/packer/Stub;", "decrypt"), Some(USER_TAG));
+        assert_eq!(watchlist.matches("Ljava/lang/Class;", "forName"), Some("reflection"));
+        assert_eq!(watchlist.matches("Lcom/packer/Stub;", "encrypt"), None);
+        assert!(watchlist.extend_from_reader("com.packer.Stub.decrypt".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_scan_counts_user_entry() {
+        let mut builder = DexBuilder::new();
+        let decrypt = builder.method("Lcom/packer/Stub;", "decrypt", "V", &[]) as u16;
+        let to_string = builder.method("Ljava/lang/Object;", "toString", "Ljava/lang/String;", &[]) as u16;
+        // invoke-static {} decrypt; invoke-static {} decrypt; invoke-virtual {v0} toString; return-void
+        let insns = [0x0071, decrypt, 0, 0x0071, decrypt, 0, 0x106E, to_string, 0, 0x000E];
+        builder.class(ClassDef::new("Lcom/example/Main;")
+            .method(MethodDef::new("main", "V", &[]).access_flags(ACC_STATIC).code(CodeDef::new(1, 0, 1, &insns))));
+        let dex = DexReader::from_vec(builder.build()).unwrap();
+
+        let mut watchlist = Watchlist::default();
+        watchlist.extend_from_reader("Lcom/packer/Stub;->decrypt".as_bytes()).unwrap();
+        let hits = watchlist.scan(&[dex]);
+        assert_eq!(hits, vec![WatchlistHit {
+            class: "Lcom/packer/Stub;".to_string(), method: "decrypt".to_string(), tag: USER_TAG.to_string(), count: 2
+        }]);
+    }
+}
@@ -0,0 +1,73 @@
+#![cfg(all(not(target_arch = "wasm32"), feature = "cli"))]
+//! Ndjson output over many tiny inputs, with every record sent to the writer on its own and in per-worker batches
+
+use std::{fs, path::{Path, PathBuf}, process::Command, time::{Duration, Instant}};
+
+use dexompiler::testing::{ClassDef, CodeDef, DexBuilder, MethodDef};
+
+mod common;
+
+/// Number of inputs, each an APK holding a dex with a single method
+const INPUTS: usize = 10_000;
+
+
+fn scratch(name: &str) -> PathBuf {
+    common::scratch("batching", name)
+}
+
+
+fn tiny_apk(index: usize) -> Vec<u8> {
+    let mut builder = DexBuilder::new();
+    // const/4 v0, 0; return-void
+    builder.class(ClassDef::new(&format!("Lcom/example/Tiny{};", index)).method(MethodDef::new("run", "V", &[]).code(CodeDef::new(1, 0, 0, &[0x0012, 0x000E]))));
+    common::apk(&builder.build(), &[])
+}
+
+
+/// Runs the command line over the inputs of `list` with `batch_records` records per batch, returning the time it took
+/// and the records written, sorted
+fn run(list: &Path, batch_records: usize, output: &Path) -> (Duration, Vec<String>) {
+    let started = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_dexompiler"))
+        .args(["--format", "ndjson", "--granularity", "method", "--no-progress", "--input-list", list.to_str().unwrap()])
+        .args(["--batch-records", &batch_records.to_string(), "-o", output.to_str().unwrap()])
+        .status()
+        .unwrap();
+    let elapsed = started.elapsed();
+    assert!(status.success());
+    let written = fs::read_to_string(output).unwrap();
+    fs::remove_file(output).unwrap();
+    let lines: Vec<&str> = written.lines().collect();
+    // Between the meta header and the summary footer
+    let mut records: Vec<String> = lines[1..lines.len() - 1].iter().map(|line| line.to_string()).collect();
+    records.sort();
+    (elapsed, records)
+}
+
+
+#[test]
+#[ignore = "writes 10k inputs and runs the command line over them twice, run with --ignored"]
+fn test_batching_tiny_inputs() {
+    let dir = scratch("inputs");
+    fs::create_dir_all(&dir).unwrap();
+    let paths: Vec<String> = (0..INPUTS).map(|index| {
+        let path = dir.join(format!("tiny{}.apk", index));
+        fs::write(&path, tiny_apk(index)).unwrap();
+        path.to_string_lossy().into_owned()
+    }).collect();
+    let list = scratch("inputs.txt");
+    fs::write(&list, paths.join("\n")).unwrap();
+
+    let output = scratch("out.ndjson");
+    let (unbatched, records) = run(&list, 1, &output);
+    let (batched, batched_records) = run(&list, 64, &output);
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_file(&list).unwrap();
+
+    assert_eq!(records.len(), INPUTS);
+    assert_eq!(batched_records, records);
+    eprintln!(
+        "{} inputs: {:.0} inputs/s with a record per batch, {:.0} inputs/s with 64",
+        INPUTS, INPUTS as f64 / unbatched.as_secs_f64(), INPUTS as f64 / batched.as_secs_f64(),
+    );
+}
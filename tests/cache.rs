@@ -0,0 +1,40 @@
+#![cfg(all(not(target_arch = "wasm32"), feature = "cli"))]
+
+use std::{fs, path::PathBuf, process::Command};
+
+mod common;
+
+use common::{apk, sample_apk, SAMPLE_DEX};
+
+
+fn scratch(name: &str) -> PathBuf {
+    common::scratch("cache", name)
+}
+
+
+#[test]
+fn test_same_dex_is_a_cache_hit() {
+    let (first, second, output, cache) = (scratch("first.apk"), scratch("second.apk"), scratch("out.json"), scratch("dir"));
+    fs::write(&first, sample_apk()).unwrap();
+    fs::write(&second, apk(SAMPLE_DEX, &[("res/raw/readme.txt", b"repackaged")])).unwrap();
+
+    // One thread, so that the second input is looked up after the first is stored
+    let status = Command::new(env!("CARGO_BIN_EXE_dexompiler"))
+        .args(["--lenient", "--threads", "1", "-i", first.to_str().unwrap(), second.to_str().unwrap()])
+        .args(["-o", output.to_str().unwrap(), "--cache-dir", cache.to_str().unwrap()])
+        .status()
+        .unwrap();
+    let written: serde_json::Value = serde_json::from_slice(&fs::read(&output).unwrap()).unwrap();
+    let entries = fs::read_dir(&cache).unwrap().count();
+    for path in [&first, &second, &output] {
+        fs::remove_file(path).unwrap();
+    }
+    fs::remove_dir_all(&cache).unwrap();
+
+    assert!(status.success());
+    assert_eq!(entries, 1);
+    assert_eq!((written["summary"]["cache_hits"].as_u64(), written["summary"]["cache_misses"].as_u64()), (Some(1), Some(1)));
+    let (first, second) = (&written["inputs"][first.to_str().unwrap()], &written["inputs"][second.to_str().unwrap()]);
+    assert!(!first["op_seq"].as_array().unwrap().is_empty());
+    assert_eq!(first, second);
+}
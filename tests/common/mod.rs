@@ -0,0 +1,31 @@
+//! Fixtures shared by the command line tests, each of which uses only some of them
+#![allow(dead_code)]
+
+use std::{io::{Cursor, Write}, path::PathBuf};
+
+use zip::{write::FileOptions, ZipWriter};
+
+pub const SAMPLE_DEX: &[u8] = include_bytes!("../fixtures/sample.dex");
+
+
+/// Path in the temporary directory for the file `name` of the tests of `suite`, unique to the test process
+pub fn scratch(suite: &str, name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dexompiler-{}-{}-{}", suite, std::process::id(), name))
+}
+
+
+/// APK holding `dex` as its `classes.dex`, followed by the `extra` entries as `(name, contents)`
+pub fn apk(dex: &[u8], extra: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in std::iter::once(("classes.dex", dex)).chain(extra.iter().copied()) {
+        writer.start_file(name, FileOptions::default()).unwrap();
+        writer.write_all(contents).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+
+/// APK holding only `tests/fixtures/sample.dex`
+pub fn sample_apk() -> Vec<u8> {
+    apk(SAMPLE_DEX, &[])
+}
@@ -0,0 +1,84 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use dexompiler::{decode_method, testing::encode_instruction, Opcode};
+use num_traits::FromPrimitive;
+use proptest::prelude::*;
+
+
+/// An instruction with random operands and what decoding it must give back
+#[derive(Debug, Clone)]
+struct Generated {
+    opcode: Opcode,
+    reference: u32,
+    branch: i32,
+    registers: u16,
+}
+
+impl Generated {
+    /// Width of the reference operand, `None` for opcodes without one
+    fn reference_width(&self) -> Option<u32> {
+        match self.opcode as u8 {
+            0x1B => Some(32),
+            0x1A | 0x1C | 0x1F | 0x20 | 0x22..=0x25 | 0x52..=0x72 | 0x74..=0x78 | 0xFA..=0xFF => Some(16),
+            _ => None,
+        }
+    }
+
+    /// Branch offset as the opcode's format stores it, `None` for opcodes that don't branch
+    fn stored_branch(&self) -> Option<i32> {
+        match self.opcode as u8 {
+            0x28 => Some(self.branch as i8 as i32),
+            0x29 | 0x32..=0x3D => Some(self.branch as i16 as i32),
+            0x26 | 0x2A..=0x2C => Some(self.branch),
+            _ => None,
+        }
+    }
+}
+
+fn instruction() -> impl Strategy<Value = Generated> {
+    any::<u8>().prop_filter_map("unused opcode", Opcode::from_u8).prop_flat_map(|opcode| {
+        // Forward branches that fit the offset operand, a branch before the start of the method is invalid
+        let max_branch = match opcode as u8 {
+            0x28 => i8::MAX as i32,
+            0x29 | 0x32..=0x3D => i16::MAX as i32,
+            _ => i32::MAX,
+        };
+        (any::<u32>(), 0..=max_branch, any::<u16>())
+            .prop_map(move |(reference, branch, registers)| Generated { opcode, reference, branch, registers })
+    })
+}
+
+
+proptest! {
+    #[test]
+    fn test_encoded_stream_round_trips(instructions in prop::collection::vec(instruction(), 0..64)) {
+        let mut raw_bytecode = vec![];
+        let mut offsets = vec![];
+        for inst in &instructions {
+            offsets.push(raw_bytecode.len());
+            raw_bytecode.extend(encode_instruction(inst.opcode, inst.reference, inst.branch, inst.registers));
+        }
+
+        let decoded = decode_method(&raw_bytecode);
+        prop_assert!(decoded.undecoded.is_empty());
+        prop_assert_eq!(decoded.instructions.len(), instructions.len());
+        for ((inst, expected), &offset) in decoded.instructions.iter().zip(&instructions).zip(&offsets) {
+            prop_assert_eq!(*inst.opcode(), expected.opcode);
+            prop_assert_eq!(*inst.offset(), offset);
+            let reference = expected.reference_width().map(|width| if width == 32 { expected.reference } else { expected.reference & 0xFFFF });
+            prop_assert_eq!(*inst.reference(), reference);
+            let branch_target = expected.stored_branch().map(|branch| (offset as i64 + branch as i64) as usize);
+            prop_assert_eq!(*inst.branch_target(), branch_target);
+        }
+        // The lengths of the instructions add up to the whole buffer
+        let end = decoded.instructions.last().map_or(0, |inst| inst.offset() + encode_instruction(*inst.opcode(), 0, 0, 0).len());
+        prop_assert_eq!(end, raw_bytecode.len());
+    }
+
+    #[test]
+    fn test_lenient_decode_bounded_by_code_units(raw_bytecode in prop::collection::vec(any::<u16>(), 0..256)) {
+        let decoded = decode_method(&raw_bytecode);
+        prop_assert!(decoded.instructions.len() <= raw_bytecode.len());
+        prop_assert!(decoded.instructions.len() + decoded.undecoded.len() <= raw_bytecode.len());
+    }
+}
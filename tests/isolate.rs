@@ -0,0 +1,40 @@
+#![cfg(all(not(target_arch = "wasm32"), feature = "cli", debug_assertions))]
+
+use std::{fs, path::PathBuf, process::Command};
+
+mod common;
+
+use common::sample_apk;
+
+
+fn scratch(name: &str) -> PathBuf {
+    common::scratch("isolate", name)
+}
+
+
+#[test]
+fn test_isolate_quarantines_crashing_input() {
+    let apk = sample_apk();
+    let (good, crashing) = (scratch("good.apk"), scratch("crashing.apk"));
+    fs::write(&good, &apk).unwrap();
+    fs::write(&crashing, &apk).unwrap();
+    let (output, quarantine) = (scratch("out.json"), scratch("quarantine.txt"));
+
+    // Every worker given the crashing input panics, retries included
+    let status = Command::new(env!("CARGO_BIN_EXE_dexompiler"))
+        .args(["--isolate", "--lenient", "-i", good.to_str().unwrap(), crashing.to_str().unwrap()])
+        .args(["-o", output.to_str().unwrap(), "--quarantine", quarantine.to_str().unwrap()])
+        .env("DEXOMPILER_TEST_WORKER_PANIC", "crashing.apk")
+        .status()
+        .unwrap();
+    let written: serde_json::Value = serde_json::from_slice(&fs::read(&output).unwrap()).unwrap();
+    let quarantined = fs::read_to_string(&quarantine).unwrap();
+    for path in [&good, &crashing, &output, &quarantine] {
+        fs::remove_file(path).unwrap();
+    }
+
+    assert!(status.success());
+    assert_eq!(written["inputs"][good.to_str().unwrap()]["methods"].as_array().unwrap().len(), 6);
+    assert!(written["inputs"].get(crashing.to_str().unwrap()).is_none());
+    assert_eq!(quarantined, format!("{}\n", crashing.to_str().unwrap()));
+}
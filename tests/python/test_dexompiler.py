@@ -0,0 +1,56 @@
+# Run with `maturin develop --features python && pytest tests/python`
+import zipfile
+from pathlib import Path
+
+import pytest
+
+import dexompiler
+
+FIXTURE = Path(__file__).parent.parent / "fixtures" / "sample.dex"
+
+
+@pytest.fixture
+def apk(tmp_path):
+    path = tmp_path / "sample.apk"
+    with zipfile.ZipFile(path, "w") as archive:
+        archive.write(FIXTURE, "classes.dex")
+    return path
+
+
+def test_analyze_apk(apk):
+    report = dexompiler.analyze_apk(str(apk), lenient=True, metrics=True)
+    assert isinstance(report["op_seq"], list)
+    assert len(report["methods"]) == 6
+    method = report["methods"][-1]
+    assert set(method) == {"dex_name", "start", "end", "registers_size", "ins_size", "outs_size", "access_flags"}
+    assert method["dex_name"] == "classes.dex"
+    assert method["end"] + 1 == len(report["op_seq"])
+    assert report["permissions"] is None
+    assert report["watchlist"] == []
+    assert report["metrics"][0]["methods"] == 6
+
+
+def test_analyze_apk_dedup(apk):
+    report = dexompiler.analyze_apk(str(apk), lenient=True, dedup_methods=True)
+    assert len(report["unique_sequences"]) == 3
+    assert len(report["methods"]) == 6
+
+
+def test_analyze_apk_options(apk):
+    with pytest.raises(TypeError):
+        dexompiler.analyze_apk(str(apk), no_such_option=True)
+    with pytest.raises(OSError):
+        dexompiler.analyze_apk(str(apk.parent / "missing.apk"))
+
+
+def test_decode_method():
+    # const/4 v0, 0; return v0
+    assert dexompiler.decode_method(bytes([0x12, 0x00, 0x0F, 0x00])) == [0x12, 0x0F]
+    with pytest.raises(ValueError):
+        dexompiler.decode_method(b"\x12")
+
+
+def test_opcode_name():
+    assert dexompiler.opcode_name(0x6E) == "invoke-virtual"
+    with pytest.raises(ValueError):
+        dexompiler.opcode_name(0x3E)
@@ -0,0 +1,51 @@
+#![cfg(all(not(target_arch = "wasm32"), feature = "cli"))]
+
+use std::{fs, io::Write, path::{Path, PathBuf}, process::{Command, Stdio}};
+
+mod common;
+
+use common::sample_apk;
+
+
+fn scratch(name: &str) -> PathBuf {
+    common::scratch("stdin", name)
+}
+
+
+/// Runs the binary on `args`, feeding `stdin` if any, and returns its JSON output
+fn run(args: &[&str], output: &Path, stdin: Option<&[u8]>) -> serde_json::Value {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dexompiler"))
+        .args(args)
+        .args(["--lenient", "-o", output.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.unwrap_or_default()).unwrap();
+    assert!(child.wait().unwrap().success());
+    let written = fs::read(output).unwrap();
+    fs::remove_file(output).unwrap();
+    serde_json::from_slice(&written).unwrap()
+}
+
+
+#[test]
+fn test_stdin_matches_file() {
+    let apk = sample_apk();
+    let path = scratch("sample.apk");
+    fs::write(&path, &apk).unwrap();
+    let output = scratch("out.json");
+
+    let from_file = run(&["-i", path.to_str().unwrap()], &output, None);
+    let from_stdin = run(&["-i", "-", "--stdin-name", "sample"], &output, Some(&apk));
+    let hashed = run(&["-i", "-"], &output, Some(&apk));
+    fs::remove_file(&path).unwrap();
+
+    let report = &from_file["inputs"][path.to_str().unwrap()];
+    assert_eq!(report["methods"].as_array().unwrap().len(), 6);
+    assert_eq!(&from_stdin["inputs"]["sample"], report);
+    let (key, hashed_report) = hashed["inputs"].as_object().unwrap().iter().next().unwrap();
+    assert_eq!(key.len(), 32);
+    assert!(key.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(hashed_report, report);
+}
@@ -0,0 +1,40 @@
+#![cfg(all(not(target_arch = "wasm32"), feature = "cli"))]
+
+use std::{fs, path::PathBuf, process::Command};
+
+mod common;
+
+use common::sample_apk;
+
+
+fn scratch(name: &str) -> PathBuf {
+    common::scratch("summary", name)
+}
+
+
+#[test]
+fn test_summary_counts_emitted_instructions() {
+    let (apk, missing, output) = (scratch("sample.apk"), scratch("missing.apk"), scratch("out.ndjson"));
+    fs::write(&apk, sample_apk()).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_dexompiler"))
+        .args(["--lenient", "--format", "ndjson", "--granularity", "method", "-i", apk.to_str().unwrap(), missing.to_str().unwrap()])
+        .args(["-o", output.to_str().unwrap()])
+        .status()
+        .unwrap();
+    let written = fs::read_to_string(&output).unwrap();
+    fs::remove_file(&apk).unwrap();
+    fs::remove_file(&output).unwrap();
+    assert!(status.success());
+
+    let lines = written.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()).collect::<Vec<_>>();
+    let (summary, records) = lines[1..].split_last().unwrap();
+    let summary = &summary["summary"];
+    let instructions: usize = records.iter().map(|record| record["op_seq"].as_array().unwrap().len()).sum();
+    assert!(instructions > 0);
+    assert_eq!(summary["instructions"], instructions);
+    assert_eq!((summary["inputs_processed"].as_u64(), summary["inputs_failed"].as_u64()), (Some(1), Some(1)));
+    assert_eq!(summary["dex_bytes"], common::SAMPLE_DEX.len());
+    // Everything before the summary line
+    assert_eq!(summary["bytes_written"], written.rfind("{\"summary\"").unwrap());
+}
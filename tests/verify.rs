@@ -0,0 +1,40 @@
+#![cfg(all(not(target_arch = "wasm32"), feature = "cli"))]
+
+use std::{fs, path::PathBuf, process::Command};
+
+mod common;
+
+use common::sample_apk;
+
+
+fn scratch(name: &str) -> PathBuf {
+    common::scratch("verify", name)
+}
+
+
+#[test]
+fn test_unreadable_inputs_fail_the_verification() {
+    let (apk, missing, output) = (scratch("sample.apk"), scratch("missing.apk"), scratch("out.json"));
+    fs::write(&apk, sample_apk()).unwrap();
+
+    let verify = |inputs: &[&PathBuf]| {
+        let status = Command::new(env!("CARGO_BIN_EXE_dexompiler"))
+            .args(["--verify-only", "--lenient", "-o", output.to_str().unwrap(), "-i"])
+            .args(inputs)
+            .status()
+            .unwrap();
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&output).unwrap()).unwrap();
+        (status.success(), written)
+    };
+    let (verified, written) = verify(&[&apk]);
+    let (verified_with_missing, written_with_missing) = verify(&[&apk, &missing]);
+    fs::remove_file(&apk).unwrap();
+    fs::remove_file(&output).unwrap();
+
+    assert!(verified);
+    assert_eq!(written["meta"]["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(written["inputs"][apk.to_str().unwrap()], serde_json::json!([]));
+    // The missing input has no failures to write, but it wasn't verified either
+    assert!(!verified_with_missing);
+    assert!(written_with_missing["inputs"].get(missing.to_str().unwrap()).is_none());
+}
@@ -0,0 +1,22 @@
+//! Run with `wasm-pack test --node --no-default-features --features wasm`
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use dexompiler::{analyze_dex, AnalysisOptions, Sequences, Strictness};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+const SAMPLE_DEX: &[u8] = include_bytes!("fixtures/sample.dex");
+
+#[wasm_bindgen_test]
+fn test_decode_sample_dex() {
+    let options = AnalysisOptions::default().strictness(Strictness::Lenient).build();
+    let report = analyze_dex(SAMPLE_DEX.to_vec(), &options).unwrap();
+    let Sequences::Flat { op_seq, methods } = report.sequences else { panic!("expected a flat report") };
+    assert_eq!(methods.len(), 6);
+    assert_eq!(methods.last().unwrap().end() + 1, op_seq.len());
+}
+
+#[wasm_bindgen_test]
+fn test_decode_dex_binding() {
+    let report = dexompiler::wasm::decode_dex(SAMPLE_DEX).unwrap();
+    assert!(report.is_object());
+}